@@ -0,0 +1,115 @@
+use std::iter;
+use interpolate::Interpolation;
+use super::Frame;
+
+#[derive(Debug, Clone)]
+/// A circular buffer of frames, which can be read at any (possibly
+/// fractional) delay up to it's capacity.
+pub struct DelayLine {
+    frames: Box<[Frame]>,
+    // index of the last pushed frame
+    position: usize
+}
+
+impl DelayLine {
+    /// Create a silent delay line with maximum delay of `capacity` frames.
+    pub fn new(capacity: usize) -> Self {
+        let frames = iter::repeat(Frame::default()).take(capacity + 1);
+        Self { frames: frames.collect::<Vec<_>>().into_boxed_slice(), position: 0 }
+    }
+
+    #[inline]
+    /// Get maximum delay in frames.
+    pub fn capacity(&self) -> usize { self.frames.len() - 1 }
+
+    /// Push `frame` as the most recent frame, dropping the oldest one.
+    #[inline]
+    pub fn push(&mut self, frame: Frame) {
+        self.position += 1;
+        if self.position == self.frames.len() { self.position = 0; }
+        self.frames[self.position] = frame;
+    }
+
+    /// Get the frame pushed `delay` frames ago; 0 is the last pushed frame.
+    /// Delays out of the capacity are clamped.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::audio::DelayLine;
+    ///
+    /// let mut line = DelayLine::new(4);
+    /// line.push((1.).into());
+    /// line.push((2.).into());
+    /// assert_eq!(line.get(0), (2.).into());
+    /// assert_eq!(line.get(1), (1.).into());
+    /// assert_eq!(line.get(2), (0.).into());
+    /// ```
+    #[inline]
+    pub fn get(&self, delay: usize) -> Frame {
+        let len = self.frames.len();
+        let delay = delay.min(len - 1);
+        let index = if delay > self.position { self.position + len - delay }
+                    else { self.position - delay };
+        self.frames[index]
+    }
+
+    /// Read the frame at fractional `delay`, interpolating the neighbour
+    /// frames with `interpolation`.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::audio::DelayLine;
+    /// use overcore::interpolate::Interpolation;
+    ///
+    /// let mut line = DelayLine::new(4);
+    /// line.push((1.).into());
+    /// line.push((2.).into());
+    /// assert_eq!(line.read(0.25, Interpolation::Linear), (1.75).into());
+    /// ```
+    pub fn read(&self, delay: f64, interpolation: Interpolation) -> Frame {
+        let delay = delay.max(0.);
+        interpolation.apply(delay, |i| self.get(if i < 0 { 0 } else { i as usize }))
+    }
+
+    /// Silence the delay line.
+    pub fn clear(&mut self) {
+        self.frames.iter_mut().for_each(|f| *f = Default::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(length: usize) -> DelayLine {
+        let mut line = DelayLine::new(length);
+        (0..length + 1).for_each(|i| line.push((i as f64).into()));
+        line
+    }
+
+    #[test]
+    fn test_delay_line_wrap() {
+        let mut line = ramp(3);
+        assert_eq!(line.get(0), (3.).into());
+        assert_eq!(line.get(3), (0.).into());
+        assert_eq!(line.get(10), (0.).into());
+
+        line.push((4.).into());
+        assert_eq!(line.get(0), (4.).into());
+        assert_eq!(line.get(3), (1.).into());
+
+        line.clear();
+        assert_eq!(line.get(0), Frame::default());
+    }
+
+    #[test]
+    fn test_delay_line_read() {
+        let line = ramp(8);
+        assert_eq!(line.read(2., Interpolation::Floor), (6.).into());
+        assert_eq!(line.read(2.4, Interpolation::Nearest), (6.).into());
+        assert_eq!(line.read(2.5, Interpolation::Linear), (5.5).into());
+
+        let [left, right] = line.read(3.25, Interpolation::Poly).0;
+        assert!((left - 4.75).abs() < 1e-10 && (right - 4.75).abs() < 1e-10);
+    }
+}
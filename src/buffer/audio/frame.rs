@@ -24,6 +24,36 @@ impl Frame {
         cos, acos, cosh, acosh,
         tan, atan, tanh, atanh
     );
+
+    /// Pan the frame with a balance law. `panning` is in range of -100 to
+    /// 100; positive values attenuate the right channel and negative values
+    /// attenuate the left one, the same as [`NoteParams::velocities()`][0].
+    /// The attenuated channel follows a quarter cosine, down to silence at
+    /// the ends, and the other one is unchanged; so 0 leaves the frame
+    /// unchanged and small pannings change it only slightly.
+    /// [0]: ../control/struct.NoteParams.html#method.velocities
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::audio::Frame;
+    /// let frame = Frame::from(1.).pan(-100.).round();
+    /// assert_eq!(frame, (0., 1.).into());
+    ///
+    /// let [left, right] = Frame::from(1.).pan(1.).0;
+    /// assert_eq!(left, 1.);
+    /// assert!(right < 1. && right > 0.999);
+    /// ```
+    pub fn pan(self, panning: f64) -> Self {
+        use std::f64::consts::FRAC_PI_2;
+        let gain = (panning.abs() * (FRAC_PI_2 / 100.)).cos();
+
+        let [left, right] = self.0;
+        if panning.is_sign_positive() {
+            (left, gain * right).into()
+        } else {
+            (gain * left, right).into()
+        }
+    }
 }
 
 impl From<Sample> for Frame {
@@ -102,8 +132,17 @@ impl DerefMut for Frame {
 
 impl<T: Into<Frame>> Sum<T> for Frame {
     #[inline]
+    /// Add up `frames`, starting from silence.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::audio::Frame;
+    /// let frames = [Frame::from((0.5, -0.25)), Frame::from(0.25)];
+    /// assert_eq!(frames.iter().cloned().sum::<Frame>(), (0.75, 0.).into());
+    /// assert_eq!(Vec::<Frame>::new().into_iter().sum::<Frame>(), Frame::default());
+    /// ```
     fn sum<I: Iterator<Item = T>>(frames: I) -> Self {
-        frames.fold((1.).into(), |p, f| p + f.into())
+        frames.fold(Frame::default(), |p, f| p + f.into())
     }
 }
 
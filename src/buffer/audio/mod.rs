@@ -9,6 +9,9 @@ pub mod prelude {
 mod frame;
 pub use self::frame::*;
 
+mod delay;
+pub use self::delay::*;
+
 /// A single sample representing signal amplitude.
 pub type Sample = f64;
 
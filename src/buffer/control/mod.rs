@@ -3,7 +3,7 @@ use std::ops::{Deref, DerefMut};
 use super::Buffer;
 
 pub mod prelude {
-    pub use super::{NoteAlphabet, NoteName, NoteRef};
+    pub use super::{NoteAlphabet, NoteName, NoteRef, NoteRefAllocator};
     pub use super::{NoteParam, NoteParams, Note};
     pub use super::{Event, Moment, ControlBuffer};
 }
@@ -14,10 +14,13 @@ pub use self::note::*;
 mod param;
 pub use self::param::*;
 
+mod mpe;
+pub use self::mpe::*;
+
 #[derive(Debug, PartialEq, Clone)]
 /// A message containing control state changes.
 pub enum Event {
-    NoteOn(Note, Option<usize>),
+    NoteOn(Note, NoteRef),
     NoteSet(NoteRef, NoteParam),
     NoteOff(NoteRef),
     ParamSet(usize, ParamValue),
@@ -0,0 +1,558 @@
+use std::collections::HashMap;
+use super::{Event, Note, NoteName, NoteParam, NoteParams};
+use super::{NoteRef, NoteRefAllocator};
+
+/// Number of channels in a MIDI group.
+const CHANNELS: usize = 16;
+
+/// Default pitch bend range of a channel which is not in any zone.
+const DEFAULT_BEND_RANGE: f64 = 2.;
+
+#[inline]
+/// Scale an unsigned `value` with `bits` bits to [0, 100].
+fn unipolar(value: u32, bits: u32) -> f64 {
+    value as f64 * 100. / ((1u64 << bits) - 1) as f64
+}
+
+#[inline]
+/// Scale an unsigned `value` with `bits` bits and centered at it's half to
+/// [-1, 1].
+fn bipolar(value: u32, bits: u32) -> f64 {
+    let center = (1u64 << (bits - 1)) as f64;
+    let value = value as f64 - center;
+    if value < 0. { value / center } else { value / (center - 1.) }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// An MPE zone, configured by an MPE Configuration Message (RPN 6).
+pub struct MpeZone {
+    /// Master channel of the zone, 0 for a lower and 15 for an upper zone.
+    pub master: u8,
+
+    /// Number of member channels, next to the master channel.
+    pub members: u8,
+
+    /// Pitch bend range of member channels in semitones.
+    pub member_bend_range: f64,
+
+    /// Pitch bend range of the master channel in semitones.
+    pub master_bend_range: f64
+}
+
+impl MpeZone {
+    /// Create a lower zone with `members` member channels.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::MpeZone;
+    /// let zone = MpeZone::lower(3);
+    /// assert_eq!(zone.master, 0);
+    /// assert_eq!(zone.is_member(3), true);
+    /// assert_eq!(zone.is_member(4), false);
+    /// ```
+    pub fn lower(members: u8) -> Self {
+        Self::with_master(0, members)
+    }
+
+    /// Create an upper zone with `members` member channels.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::MpeZone;
+    /// let zone = MpeZone::upper(3);
+    /// assert_eq!(zone.master, 15);
+    /// assert_eq!(zone.is_member(12), true);
+    /// assert_eq!(zone.is_member(11), false);
+    /// ```
+    pub fn upper(members: u8) -> Self {
+        Self::with_master(15, members)
+    }
+
+    fn with_master(master: u8, members: u8) -> Self {
+        let members = members.min(CHANNELS as u8 - 1);
+        Self { master, members, member_bend_range: 48., master_bend_range: 2. }
+    }
+
+    #[inline]
+    /// Check whether `channel` is a member channel of this zone.
+    pub fn is_member(&self, channel: u8) -> bool {
+        if self.master == 0 {
+            channel > 0 && channel <= self.members
+        } else {
+            channel < self.master && channel >= self.master - self.members
+        }
+    }
+
+    #[inline]
+    /// Check whether `channel` is the master or a member channel of this
+    /// zone.
+    pub fn contains(&self, channel: u8) -> bool {
+        channel == self.master || self.is_member(channel)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ChannelState {
+    /// Pitch bend, in range [-1, 1].
+    bend: f64,
+
+    /// Pitch bend range in semitones, for channels outside of zones.
+    bend_range: f64,
+
+    /// Channel pressure in range [0, 100].
+    pressure: f64,
+
+    /// Timbre (CC 74) in range [0, 100].
+    timbre: f64,
+
+    /// Selected registered parameter number as (MSB, LSB).
+    rpn: (u8, u8),
+
+    /// Note numbers which are currently on, with their references.
+    notes: Vec<(u8, NoteRef)>
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        let defaults = NoteParams::default();
+        Self {
+            bend: 0.,
+            bend_range: DEFAULT_BEND_RANGE,
+            pressure: defaults.pressure,
+            timbre: defaults.timbre,
+            rpn: (0x7f, 0x7f),
+            notes: Vec::new()
+        }
+    }
+}
+
+/// Translates MPE (MIDI 1.0) and MIDI 2.0 channel voice messages to
+/// [`Event`]s, mapping per-note expressions onto [`NoteParam`]s.
+///
+/// Pitch bend is mapped to [`NoteParam::Cents`], pressure (channel pressure
+/// or polyphonic key pressure) to [`NoteParam::Pressure`] and CC 74 to
+/// [`NoteParam::Timbre`]. Expressions on the master channel of a zone reach
+/// the notes of all it's member channels.
+///
+/// [`Event`]: enum.Event.html
+/// [`NoteParam`]: enum.NoteParam.html
+/// [`NoteParam::Cents`]: enum.NoteParam.html#variant.Cents
+/// [`NoteParam::Pressure`]: enum.NoteParam.html#variant.Pressure
+/// [`NoteParam::Timbre`]: enum.NoteParam.html#variant.Timbre
+pub struct MpeDecoder {
+    zones: Vec<MpeZone>,
+    channels: Vec<ChannelState>,
+    refs: NoteRefAllocator,
+    /// Per-note pitch bend or pitch of MIDI 2.0, in semitones.
+    note_bends: HashMap<(u8, u8), f64>
+}
+
+impl MpeDecoder {
+    /// Create a decoder with no zones, so each channel acts on it's own.
+    pub fn new() -> Self {
+        let channels = (0..CHANNELS).map(|_| Default::default()).collect();
+        let (zones, refs, note_bends) = (Vec::new(), Default::default(), HashMap::new());
+        Self { zones, channels, refs, note_bends }
+    }
+
+    /// Add `zone` to the decoder, shrinking the opposite zone if they
+    /// overlap.
+    pub fn with_zone(mut self, zone: MpeZone) -> Self {
+        self.configure(zone);
+        self
+    }
+
+    /// Get configured zones.
+    pub fn zones(&self) -> &[MpeZone] { &self.zones }
+
+    fn configure(&mut self, zone: MpeZone) {
+        self.zones.retain(|z| z.master != zone.master);
+
+        if zone.members == 0 {
+            return;
+        }
+
+        let free = (CHANNELS as u8 - 2).saturating_sub(zone.members);
+        for other in self.zones.iter_mut() {
+            other.members = other.members.min(free);
+        }
+        self.zones.retain(|z| z.members != 0);
+
+        self.zones.push(zone);
+    }
+
+    fn zone(&self, channel: u8) -> Option<&MpeZone> {
+        self.zones.iter().find(|z| z.contains(channel))
+    }
+
+    /// Get detuning of `note` on `channel` in cents, according to pitch bends.
+    fn cents(&self, channel: u8, note: u8) -> f64 {
+        let state = &self.channels[channel as usize];
+        let note_bend = self.note_bends.get(&(channel, note)).cloned();
+        let note_bend = note_bend.unwrap_or(0.);
+
+        let semitones = match self.zone(channel) {
+            Some(zone) if zone.master == channel => {
+                state.bend * zone.master_bend_range
+            },
+            Some(zone) => {
+                let master = &self.channels[zone.master as usize];
+                state.bend * zone.member_bend_range
+                    + master.bend * zone.master_bend_range
+            },
+            None => state.bend * state.bend_range
+        };
+
+        (semitones + note_bend) * 100.
+    }
+
+    /// Get channels which are affected by a change on `channel`.
+    fn affected(&self, channel: u8) -> Vec<u8> {
+        match self.zone(channel) {
+            Some(zone) if zone.master == channel => {
+                (0..CHANNELS as u8).filter(|&c| zone.contains(c)).collect()
+            },
+            _ => vec![channel]
+        }
+    }
+
+    fn note_on(&mut self, channel: u8, note: u8, velocity: f64,
+               events: &mut Vec<Event>) {
+        self.note_off(channel, note, events);
+
+        let params = {
+            let state = &self.channels[channel as usize];
+            NoteParams {
+                velocity,
+                cents: self.cents(channel, note),
+                pressure: state.pressure,
+                timbre: state.timbre,
+                ..Default::default()
+            }
+        };
+
+        let reference = self.refs.allocate();
+        self.channels[channel as usize].notes.push((note, reference));
+        let note = Note { name: NoteName::from_midi(note), params };
+        events.push(Event::NoteOn(note, reference));
+    }
+
+    fn note_off(&mut self, channel: u8, note: u8, events: &mut Vec<Event>) {
+        let notes = &mut self.channels[channel as usize].notes;
+        if let Some(i) = notes.iter().position(|&(n, _)| n == note) {
+            let (_, reference) = notes.remove(i);
+            self.note_bends.remove(&(channel, note));
+            events.push(Event::NoteOff(reference));
+        }
+    }
+
+    /// Find reference of `note` on `channel`, if it's on.
+    fn find(&self, channel: u8, note: u8) -> Option<NoteRef> {
+        let notes = self.channels[channel as usize].notes.iter();
+        notes.filter(|&&(n, _)| n == note).map(|&(_, r)| r).next()
+    }
+
+    fn bend(&mut self, channel: u8, bend: f64, events: &mut Vec<Event>) {
+        self.channels[channel as usize].bend = bend;
+
+        for c in self.affected(channel) {
+            for &(note, reference) in self.channels[c as usize].notes.iter() {
+                let cents = NoteParam::Cents(self.cents(c, note));
+                events.push(Event::NoteSet(reference, cents));
+            }
+        }
+    }
+
+    fn note_bend(&mut self, channel: u8, note: u8, bend: f64,
+                 events: &mut Vec<Event>) {
+        let range = match self.zone(channel) {
+            Some(zone) => zone.member_bend_range,
+            None => self.channels[channel as usize].bend_range
+        };
+        self.note_pitch(channel, note, bend * range, events)
+    }
+
+    /// Detune `note` on `channel` by `semitones`, on top of channel pitch
+    /// bends.
+    fn note_pitch(&mut self, channel: u8, note: u8, semitones: f64,
+                  events: &mut Vec<Event>) {
+        let reference = match self.find(channel, note) {
+            Some(reference) => reference,
+            None => { return; }
+        };
+        self.note_bends.insert((channel, note), semitones);
+
+        let cents = NoteParam::Cents(self.cents(channel, note));
+        events.push(Event::NoteSet(reference, cents));
+    }
+
+    /// Emit `param` for every note affected by a change on `channel`.
+    fn set_all(&self, channel: u8, param: NoteParam, events: &mut Vec<Event>) {
+        for c in self.affected(channel) {
+            for &(_, reference) in self.channels[c as usize].notes.iter() {
+                events.push(Event::NoteSet(reference, param.clone()));
+            }
+        }
+    }
+
+    /// Emit `param` for `note` on `channel`, if it's on.
+    fn set(&self, channel: u8, note: u8, param: NoteParam,
+           events: &mut Vec<Event>) {
+        if let Some(reference) = self.find(channel, note) {
+            events.push(Event::NoteSet(reference, param));
+        }
+    }
+
+    /// Handle a registered parameter with 7-bit `value`.
+    fn registered(&mut self, channel: u8, rpn: (u8, u8), value: u8) {
+        match rpn {
+            (0, 0) => {
+                let range = value as f64;
+                let zone = self.zones.iter_mut().find(|z| z.contains(channel));
+                match zone {
+                    Some(zone) => if zone.master == channel {
+                        zone.master_bend_range = range
+                    } else {
+                        zone.member_bend_range = range
+                    },
+                    None => self.channels[channel as usize].bend_range = range
+                }
+            },
+            (0, 6) if channel == 0 => self.configure(MpeZone::lower(value)),
+            (0, 6) if channel == 15 => self.configure(MpeZone::upper(value)),
+            _ => {}
+        }
+    }
+
+    fn control(&mut self, channel: u8, index: u8, value: u32, bits: u32,
+               events: &mut Vec<Event>) {
+        let msb = (value >> (bits - 7)) as u8;
+        match index {
+            74 => {
+                let timbre = unipolar(value, bits);
+                self.channels[channel as usize].timbre = timbre;
+                self.set_all(channel, NoteParam::Timbre(timbre), events);
+            },
+            101 => { self.channels[channel as usize].rpn.0 = msb },
+            100 => { self.channels[channel as usize].rpn.1 = msb },
+            6 => {
+                let rpn = self.channels[channel as usize].rpn;
+                self.registered(channel, rpn, msb)
+            },
+            _ => {}
+        }
+    }
+
+    fn pressure(&mut self, channel: u8, pressure: f64,
+                events: &mut Vec<Event>) {
+        self.channels[channel as usize].pressure = pressure;
+        self.set_all(channel, NoteParam::Pressure(pressure), events);
+    }
+
+    /// Decode a MIDI 1.0 channel voice `message`, returning resulting events.
+    /// Unsupported or malformed messages are ignored.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::{Event, MpeDecoder, MpeZone, NoteParam};
+    ///
+    /// let mut decoder = MpeDecoder::new().with_zone(MpeZone::lower(15));
+    ///
+    /// let events = decoder.decode(&[0x91, 69, 127]);
+    /// let reference = match events[0] {
+    ///     Event::NoteOn(ref note, reference) => {
+    ///         assert_eq!(note.freq(), 440.);
+    ///         reference
+    ///     },
+    ///     _ => unreachable!()
+    /// };
+    ///
+    /// // the same pitch on another member channel is a distinct note
+    /// match decoder.decode(&[0x92, 69, 127])[0] {
+    ///     Event::NoteOn(_, other) => assert_ne!(other, reference),
+    ///     _ => unreachable!()
+    /// }
+    ///
+    /// // the whole pitch bend range (48 semitones) downwards
+    /// let events = decoder.decode(&[0xe1, 0x00, 0x00]);
+    /// assert_eq!(events, vec![Event::NoteSet(reference, NoteParam::Cents(-4800.))]);
+    ///
+    /// // channel pressure on another member channel affects no notes
+    /// assert_eq!(decoder.decode(&[0xd3, 127]), vec![]);
+    ///
+    /// let events = decoder.decode(&[0x81, 69, 0]);
+    /// assert_eq!(events, vec![Event::NoteOff(reference)]);
+    /// ```
+    pub fn decode(&mut self, message: &[u8]) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        let (status, channel) = match message.first() {
+            Some(&byte) => (byte >> 4, byte & 0x0f),
+            None => { return events; }
+        };
+        let data = |i: usize| message.get(i).map(|&b| b & 0x7f);
+
+        match (status, data(1), data(2)) {
+            (0x9, Some(note), Some(0)) | (0x8, Some(note), Some(_)) => {
+                self.note_off(channel, note, &mut events)
+            },
+            (0x9, Some(note), Some(velocity)) => {
+                let velocity = unipolar(velocity as u32, 7);
+                self.note_on(channel, note, velocity, &mut events)
+            },
+            (0xa, Some(note), Some(pressure)) => {
+                let pressure = NoteParam::Pressure(unipolar(pressure as u32, 7));
+                self.set(channel, note, pressure, &mut events)
+            },
+            (0xb, Some(index), Some(value)) => {
+                self.control(channel, index, value as u32, 7, &mut events)
+            },
+            (0xd, Some(pressure), _) => {
+                let pressure = unipolar(pressure as u32, 7);
+                self.pressure(channel, pressure, &mut events)
+            },
+            (0xe, Some(lsb), Some(msb)) => {
+                let bend = bipolar((msb as u32) << 7 | lsb as u32, 14);
+                self.bend(channel, bend, &mut events)
+            },
+            _ => {}
+        }
+
+        events
+    }
+
+    /// Decode a 64-bit MIDI 2.0 channel voice message (UMP message type 4),
+    /// returning resulting events. Registered per-note controllers 3 (pitch
+    /// 7.25), 7 (volume), 10 (pan) and 74 (timbre) are mapped onto
+    /// [`NoteParam`]s. Unsupported messages are ignored.
+    ///
+    /// [`NoteParam`]: enum.NoteParam.html
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::{Event, MpeDecoder, NoteParam};
+    ///
+    /// let mut decoder = MpeDecoder::new();
+    ///
+    /// let events = decoder.decode_ump([0x4090_4500, 0xffff_0000]);
+    /// let reference = match events[0] {
+    ///     Event::NoteOn(_, reference) => reference,
+    ///     _ => unreachable!()
+    /// };
+    ///
+    /// // per-note pan, fully to the right
+    /// let events = decoder.decode_ump([0x4000_450a, 0xffff_ffff]);
+    /// assert_eq!(events, vec![Event::NoteSet(reference, NoteParam::Panning(100.))]);
+    /// ```
+    pub fn decode_ump(&mut self, packet: [u32; 2]) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        let [head, data] = packet;
+        if head >> 28 != 0x4 {
+            return events;
+        }
+
+        let status = (head >> 20 & 0xf) as u8;
+        let channel = (head >> 16 & 0xf) as u8;
+        let (index, extra) = ((head >> 8 & 0x7f) as u8, (head & 0xff) as u8);
+
+        match status {
+            0x8 => self.note_off(channel, index, &mut events),
+            0x9 => {
+                let velocity = unipolar(data >> 16, 16);
+                self.note_on(channel, index, velocity, &mut events)
+            },
+            0xa => {
+                let pressure = NoteParam::Pressure(unipolar(data, 32));
+                self.set(channel, index, pressure, &mut events)
+            },
+            0x0 if extra == 3 => {
+                let pitch = data as f64 / (1u32 << 25) as f64;
+                let semitones = pitch - index as f64;
+                self.note_pitch(channel, index, semitones, &mut events)
+            },
+            0x0 => {
+                let param = match extra {
+                    7 => Some(NoteParam::Velocity(unipolar(data, 32))),
+                    10 => Some(NoteParam::Panning(bipolar(data, 32) * 100.)),
+                    74 => Some(NoteParam::Timbre(unipolar(data, 32))),
+                    _ => None
+                };
+                if let Some(param) = param {
+                    self.set(channel, index, param, &mut events)
+                }
+            },
+            0x6 => {
+                let bend = bipolar(data, 32);
+                self.note_bend(channel, index, bend, &mut events)
+            },
+            0xf if extra & 0x1 != 0 => {
+                self.note_bend(channel, index, 0., &mut events)
+            },
+            0xb => self.control(channel, index, data, 32, &mut events),
+            0x2 => {
+                let rpn = (index, extra & 0x7f);
+                self.registered(channel, rpn, (data >> 25) as u8)
+            },
+            0xd => self.pressure(channel, unipolar(data, 32), &mut events),
+            0xe => self.bend(channel, bipolar(data, 32), &mut events),
+            _ => {}
+        }
+
+        events
+    }
+}
+
+impl Default for MpeDecoder {
+    fn default() -> Self { Self::new() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference(events: &[Event]) -> NoteRef {
+        match events[0] {
+            Event::NoteOn(_, reference) => reference,
+            _ => panic!("a note should be on")
+        }
+    }
+
+    #[test]
+    fn test_mpe_master_expression() {
+        let mut decoder = MpeDecoder::new().with_zone(MpeZone::lower(15));
+        let first = reference(&decoder.decode(&[0x91, 60, 127]));
+        let second = reference(&decoder.decode(&[0x92, 64, 127]));
+
+        // the master channel reaches notes of every member channel
+        let events = decoder.decode(&[0xd0, 127]);
+        assert_eq!(events, vec![
+            Event::NoteSet(first, NoteParam::Pressure(100.)),
+            Event::NoteSet(second, NoteParam::Pressure(100.))
+        ]);
+        let events = decoder.decode(&[0xb0, 74, 0]);
+        assert_eq!(events, vec![
+            Event::NoteSet(first, NoteParam::Timbre(0.)),
+            Event::NoteSet(second, NoteParam::Timbre(0.))
+        ]);
+
+        // a member channel only reaches it's own notes
+        let events = decoder.decode(&[0xd2, 0]);
+        assert_eq!(events, vec![Event::NoteSet(second, NoteParam::Pressure(0.))]);
+    }
+
+    #[test]
+    fn test_mpe_note_pitch() {
+        let mut decoder = MpeDecoder::new();
+        let note = reference(&decoder.decode_ump([0x4090_4500, 0xffff_0000]));
+
+        // a quarter tone above the note, 69.5 in pitch 7.25
+        let pitch = 139 << 24;
+        let events = decoder.decode_ump([0x4000_4503, pitch]);
+        assert_eq!(events, vec![Event::NoteSet(note, NoteParam::Cents(50.))]);
+
+        // the per-note pitch is kept under the channel pitch bend
+        let events = decoder.decode(&[0xe0, 0x7f, 0x7f]);
+        assert_eq!(events, vec![Event::NoteSet(note, NoteParam::Cents(250.))]);
+    }
+}
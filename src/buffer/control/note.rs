@@ -1,6 +1,7 @@
+use std::fmt;
 use std::convert::TryFrom;
 use buffer::prelude::Frame;
-use hardconf::TWO_POW_ONE_TWELFTH;
+use tuning::{self, Tuning, EqualTemperament};
 
 #[derive(Debug, PartialEq, Clone, Eq, Hash)]
 /// Alphabets of a [`NoteName`] (e.g. E in "E3").
@@ -58,9 +59,7 @@ impl NoteAlphabet {
     /// assert_eq!(NoteAlphabet::A.detune(-900.), NoteAlphabet::C.freq());
     /// ```
     pub fn detune<T: Into<f64>>(&self, cents: T) -> f64 {
-        let mut half_steps = cents.into() / 100.;
-        half_steps += self.index() as f64 - NoteAlphabet::A.index() as f64;
-        440. * TWO_POW_ONE_TWELFTH.powf(half_steps)
+        NoteName(self.clone(), 4).detune(cents)
     }
 
     #[inline]
@@ -202,7 +201,9 @@ pub struct NoteName(pub NoteAlphabet, pub i8);
 
 impl NoteName {
     #[inline]
-    /// Get the note's frequency.
+    /// Get the note's frequency in the [default tuning].
+    ///
+    /// [default tuning]: ../../tuning/fn.default_tuning.html
     ///
     /// # Examples
     /// ```
@@ -213,7 +214,7 @@ impl NoteName {
     pub fn freq(&self) -> f64 { self.detune(0.) }
 
     #[inline]
-    /// Detune frequency by `cents`.
+    /// Detune frequency by `cents`, in the [default tuning].
     ///
     /// # Examples
     /// ```
@@ -222,15 +223,252 @@ impl NoteName {
     /// assert_eq!(NoteAlphabet::A.detune(100.), NoteAlphabet::As.freq());
     /// assert_eq!(NoteAlphabet::A.detune(-900.), NoteAlphabet::C.freq());
     /// ```
+    ///
+    /// [default tuning]: ../../tuning/fn.default_tuning.html
     pub fn detune<T: Into<f64>>(&self, cents: T) -> f64 {
-        let half_steps = (self.1 as isize - 4) * NoteAlphabet::len() as isize;
-        let cents = cents.into() + (half_steps * 100) as f64;
-        self.0.detune(cents)
+        self.detune_with(&*tuning::default_tuning(), cents)
+    }
+
+    #[inline]
+    /// Detune frequency by `cents`, according to `tuning`.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::{NoteAlphabet, NoteName};
+    /// use overcore::tuning::EqualTemperament;
+    /// let name = NoteName(NoteAlphabet::A, 4);
+    /// let tuning = EqualTemperament::with_pitch(442.);
+    /// assert_eq!(name.detune_with(&tuning, 0.), 442.);
+    /// assert_eq!(name.detune_with(&tuning, 1200.), 884.);
+    /// ```
+    pub fn detune_with<T, U>(&self, tuning: &T, cents: U) -> f64
+        where T: Tuning + ?Sized, U: Into<f64> {
+        tuning.freq(self, cents.into())
+    }
+
+    #[inline]
+    /// Transpose a new note from self, `n` half steps higher (or lower).
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::{NoteAlphabet, NoteName};
+    /// let name = NoteName(NoteAlphabet::A, 4);
+    /// assert_eq!(name.transpose(3), NoteName(NoteAlphabet::C, 5));
+    /// assert_eq!(name.transpose(-10), NoteName(NoteAlphabet::B, 3));
+    /// assert_eq!(name.transpose(-22), NoteName(NoteAlphabet::B, 2));
+    /// ```
+    pub fn transpose(&self, n: isize) -> Self {
+        Self::from_index(self.index() + n)
+    }
+
+    #[inline]
+    /// Get number of half steps from C-1 to this note. It's equal to the
+    /// MIDI note number for notes in MIDI range.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::{NoteAlphabet, NoteName};
+    /// assert_eq!(NoteName(NoteAlphabet::C, -1).index(), 0);
+    /// assert_eq!(NoteName(NoteAlphabet::A, 4).index(), 69);
+    /// assert_eq!(NoteName(NoteAlphabet::B, -2).index(), -1);
+    /// ```
+    pub fn index(&self) -> isize {
+        (self.1 as isize + 1) * NoteAlphabet::len() as isize + self.0.index() as isize
+    }
+
+    #[inline]
+    /// Get the note which is `index` half steps from C-1. See
+    /// [`index()`](#method.index).
+    ///
+    /// # Panics
+    /// Panics if octave of the note does not fit in an `i8`.
+    pub fn from_index(index: isize) -> Self {
+        Self::checked_from_index(index).expect("octave out of range")
+    }
+
+    fn checked_from_index(index: isize) -> Option<Self> {
+        let len = NoteAlphabet::len() as isize;
+        let octave = if index < 0 { (index + 1) / len - 1 } else { index / len };
+        let octave = octave - 1;
+        if octave < i8::min_value() as isize || octave > i8::max_value() as isize {
+            return None;
+        }
+        Some(NoteName(From::from(index), octave as i8))
+    }
+
+    #[inline]
+    /// Get the note with MIDI note number `note`.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::{NoteAlphabet, NoteName};
+    /// assert_eq!(NoteName::from_midi(60), NoteName(NoteAlphabet::C, 4));
+    /// assert_eq!(NoteName::from_midi(127), NoteName(NoteAlphabet::G, 9));
+    /// ```
+    pub fn from_midi(note: u8) -> Self { Self::from_index(note as isize) }
+
+    #[inline]
+    /// Get MIDI note number of the note, if it's in MIDI range (C-1 to G9).
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::{NoteAlphabet, NoteName};
+    /// assert_eq!(NoteName(NoteAlphabet::A, 4).midi(), Some(69));
+    /// assert_eq!(NoteName(NoteAlphabet::Gs, 9).midi(), None);
+    /// assert_eq!(NoteName(NoteAlphabet::B, -2).midi(), None);
+    /// ```
+    pub fn midi(&self) -> Option<u8> {
+        let index = self.index();
+        if index >= 0 && index < 128 { Some(index as u8) } else { None }
+    }
+
+    /// Get the nearest note to `freq` in the default 12-TET tuning, with
+    /// the difference in cents (in range [-50, 50]). Returns `None` if
+    /// `freq` is not a positive frequency, or the note's octave does not
+    /// fit in an `i8`.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::{NoteAlphabet, NoteName};
+    ///
+    /// let (name, cents) = NoteName::from_freq(440.).unwrap();
+    /// assert_eq!((name, cents), (NoteName(NoteAlphabet::A, 4), 0.));
+    ///
+    /// let (name, cents) = NoteName::from_freq(270.).unwrap();
+    /// assert_eq!(name, NoteName(NoteAlphabet::Cs, 4));
+    /// assert_eq!(cents.round(), -45.);
+    ///
+    /// assert_eq!(NoteName::from_freq(0.), None);
+    /// ```
+    pub fn from_freq(freq: f64) -> Option<(Self, f64)> {
+        if !(freq > 0.) || freq.is_infinite() {
+            return None;
+        }
+
+        let reference = EqualTemperament::default();
+        let steps = 12. * (freq / reference.freq).log2();
+        let nearest = steps.round();
+        let name = Self::checked_from_index(reference.reference.index() + nearest as isize)?;
+        Some((name, (steps - nearest) * 100.))
+    }
+}
+
+impl fmt::Display for NoteAlphabet {
+    /// Format the alphabet using sharps (e.g. "C", "F#").
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let alphabet = match self {
+            &NoteAlphabet::C  => "C",
+            &NoteAlphabet::Cs => "C#",
+            &NoteAlphabet::D  => "D",
+            &NoteAlphabet::Ds => "D#",
+            &NoteAlphabet::E  => "E",
+            &NoteAlphabet::F  => "F",
+            &NoteAlphabet::Fs => "F#",
+            &NoteAlphabet::G  => "G",
+            &NoteAlphabet::Gs => "G#",
+            &NoteAlphabet::A  => "A",
+            &NoteAlphabet::As => "A#",
+            &NoteAlphabet::B  => "B",
+        };
+        f.write_str(alphabet)
+    }
+}
+
+impl fmt::Display for NoteName {
+    /// Format the note name like "C#4" or "B-1".
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::{NoteAlphabet, NoteName};
+    /// assert_eq!(NoteName(NoteAlphabet::Cs, 4).to_string(), "C#4");
+    /// assert_eq!(NoteName(NoteAlphabet::B, -1).to_string(), "B-1");
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.0, self.1)
+    }
+}
+
+impl<'a> TryFrom<&'a str> for NoteName {
+    type Error = ();
+
+    /// Parse a note name like "C#4", "Bb-1" or "e3". Accidentals may move
+    /// the note to the neighbour octave (e.g. "Cb4" is B3).
+    ///
+    /// # Examples
+    /// ```
+    /// #![feature(try_from)]
+    ///
+    /// use std::convert::TryFrom;
+    /// use overcore::buffer::control::{NoteAlphabet, NoteName};
+    ///
+    /// assert_eq!(NoteName::try_from("C#4"), Ok(NoteName(NoteAlphabet::Cs, 4)));
+    /// assert_eq!(NoteName::try_from("Bb-1"), Ok(NoteName(NoteAlphabet::As, -1)));
+    /// assert_eq!(NoteName::try_from("Cb4"), Ok(NoteName(NoteAlphabet::B, 3)));
+    /// assert_eq!(NoteName::try_from("B#4"), Ok(NoteName(NoteAlphabet::C, 5)));
+    /// assert_eq!(NoteName::try_from("C"), Err(()));
+    /// assert_eq!(NoteName::try_from("H4"), Err(()));
+    /// assert_eq!(NoteName::try_from("C#+4"), Err(()));
+    /// assert_eq!(NoteName::try_from("C200"), Err(()));
+    /// ```
+    fn try_from(name: &'a str) -> Result<Self, Self::Error> {
+        let split = name.char_indices().skip(1)
+            .find(|&(_, c)| c != '#' && c != 'b')
+            .map(|(i, _)| i).ok_or(())?;
+        let (alphabet, octave) = name.split_at(split);
+
+        ensure!(octave.starts_with('-') || octave.starts_with(char::is_numeric));
+        let octave: i8 = octave.parse().map_err(|_| ())?;
+
+        // alphabets of "Cb" and "B#" are "B" and "C", so the octave changes
+        let natural = NoteAlphabet::try_from(alphabet.get(..1).ok_or(())?)?;
+        let natural = natural.index() as isize;
+        let shift = match NoteAlphabet::try_from(alphabet)?.index() as isize - natural {
+            11 => -1,
+            -11 => 1,
+            shift => shift
+        };
+
+        let index = NoteName(NoteAlphabet::from(natural), octave).index() + shift;
+        NoteName::checked_from_index(index).ok_or(())
     }
 }
 
-/// Reference for comparing notes.
-pub type NoteRef = NoteName;
+#[derive(Debug, PartialEq, Clone, Copy, Eq, Hash)]
+/// Reference for addressing a playing note. It's assigned by the host when
+/// the note is started by [`Event::NoteOn`] and is unique among notes of a
+/// control stream, so multiple notes with the same [`NoteName`] can be
+/// played simultaneously.
+///
+/// [`Event::NoteOn`]: enum.Event.html#variant.NoteOn
+/// [`NoteName`]: struct.NoteName.html
+pub struct NoteRef(pub usize);
+
+#[derive(Debug, Default, Clone)]
+/// Allocates unique [`NoteRef`]s for a control stream.
+///
+/// [`NoteRef`]: struct.NoteRef.html
+pub struct NoteRefAllocator(usize);
+
+impl NoteRefAllocator {
+    /// Create a new allocator.
+    pub fn new() -> Self { Default::default() }
+
+    #[inline]
+    /// Allocate a reference which is not returned by this allocator before.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::NoteRefAllocator;
+    /// let mut refs = NoteRefAllocator::new();
+    /// let (a, b) = (refs.allocate(), refs.allocate());
+    /// assert_ne!(a, b);
+    /// ```
+    pub fn allocate(&mut self) -> NoteRef {
+        let note = NoteRef(self.0);
+        self.0 = self.0.wrapping_add(1);
+        note
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
 /// Value for a specific parameter of a note.
@@ -242,7 +480,13 @@ pub enum NoteParam {
     Panning(f64),
 
     /// Note's detuning.
-    Cents(f64)
+    Cents(f64),
+
+    /// Note's pressure (i.e. polyphonic aftertouch).
+    Pressure(f64),
+
+    /// Note's timbre (e.g. brightness of the sound).
+    Timbre(f64)
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -256,20 +500,27 @@ pub struct NoteParams {
 
     /// Cents in unit cents. 0 means no detuning, 1200 means one octave
     /// higher.
-    pub cents: f64
+    pub cents: f64,
+
+    /// Pressure. 0 means no pressure and 100 means maximum pressure.
+    pub pressure: f64,
+
+    /// Timbre. 0 means the darkest and 100 means the brightest sound.
+    pub timbre: f64
 }
 
 impl NoteParams {
-    /// Create from left velocity, right velocity and cents.
+    /// Create from left velocity, right velocity and cents. Other parameters
+    /// are set to their defaults.
     ///
     /// # Examples
     /// ```
     /// use overcore::buffer::control::NoteParams;
     /// let params = NoteParams::from_velocities([100.; 2].into(), 0.);
-    /// assert_eq!(params, NoteParams { velocity: 100., panning: 0., cents: 0. });
+    /// assert_eq!(params, NoteParams { panning: 0., ..Default::default() });
     ///
     /// let params = NoteParams::from_velocities([0., 100.].into(), 0.);
-    /// assert_eq!(params, NoteParams { velocity: 100., panning: -100., cents: 0. });
+    /// assert_eq!(params, NoteParams { panning: -100., ..Default::default() });
     /// ```
     pub fn from_velocities(frame: Frame, cents: f64) -> Self {
         let [left, right]: [f64; 2] = frame.into();
@@ -284,7 +535,7 @@ impl NoteParams {
             unimplemented!()
         };
 
-        Self { velocity, panning, cents }
+        Self { velocity, panning, cents, ..Default::default() }
     }
 
     #[inline]
@@ -293,16 +544,22 @@ impl NoteParams {
     /// # Examples
     /// ```
     /// use overcore::buffer::control::{NoteParam, NoteParams};
-    /// let mut params = NoteParams { velocity: 100., panning: 0., cents: 0., };
+    /// let mut params = NoteParams::default();
     /// params.apply(&NoteParam::Velocity(50.));
     /// params.apply(&NoteParam::Panning(-30.));
     /// params.apply(&NoteParam::Cents(100.));
-    /// assert_eq!(params, NoteParams { velocity: 50., panning: -30., cents: 100. });
+    /// params.apply(&NoteParam::Pressure(20.));
+    /// params.apply(&NoteParam::Timbre(75.));
+    /// assert_eq!(params, NoteParams {
+    ///     velocity: 50., panning: -30., cents: 100., pressure: 20., timbre: 75.
+    /// });
     pub fn apply(&mut self, param: &NoteParam) {
         match param {
             &NoteParam::Velocity(v) => { self.velocity = v },
             &NoteParam::Panning(p) => { self.panning = p },
-            &NoteParam::Cents(c) => { self.cents = c }
+            &NoteParam::Cents(c) => { self.cents = c },
+            &NoteParam::Pressure(p) => { self.pressure = p },
+            &NoteParam::Timbre(t) => { self.timbre = t }
         }
     }
 
@@ -312,9 +569,9 @@ impl NoteParams {
     /// # Examples
     /// ```
     /// use overcore::buffer::control::{NoteParam, NoteParams};
-    /// let mut params = NoteParams { velocity: 100., panning: 0., cents: 0., };
+    /// let mut params = NoteParams::default();
     /// params.gain(2);
-    /// assert_eq!(params, NoteParams { velocity: 200., panning: 0., cents: 0. });
+    /// assert_eq!(params, NoteParams { velocity: 200., ..Default::default() });
     pub fn gain<T: Into<f64>>(&mut self, gain: T) {
         let amplitude = (self.velocity as f64) * gain.into();
         self.velocity = amplitude as f64;
@@ -326,37 +583,22 @@ impl NoteParams {
     /// # Examples
     /// ```
     /// use overcore::buffer::control::NoteParams;
-    /// let params = NoteParams { velocity: 80., panning: 0., cents: 0. };
+    /// let params = NoteParams { velocity: 80., ..Default::default() };
     /// assert_eq!(params.velocities(), [80.; 2].into());
     ///
-    /// let params = NoteParams { velocity: 100., panning: 100., cents: 0. };
+    /// let params = NoteParams { panning: 100., ..Default::default() };
     /// let velocities: [f64; 2] = params.velocities().round().into();
     /// assert_eq!(velocities, [100., 0.]);
     /// ```
     pub fn velocities(&self) -> Frame {
-        let v = self.velocity as f64;
-
-        if self.panning == 0. {
-            return v.into();
-        }
-
-        use std::f64::consts::{FRAC_PI_4, SQRT_2};
-        let t = self.panning.abs() as f64 * (FRAC_PI_4 / 100.);
-        let (c, s) = (SQRT_2 * t.cos() / 2., SQRT_2 * t.sin() / 2.);
-        let (h, l) = (c + s, c - s);
-
-        if self.panning.is_sign_positive() {
-            (h * v, l * v).into()
-        } else {
-            (l * v, h * v).into()
-        }
+        Frame::from(self.velocity as f64).pan(self.panning as f64)
     }
 }
 
 impl Default for NoteParams {
     #[inline]
     fn default() -> Self {
-        Self { velocity: 100., panning: 0., cents: 0. }
+        Self { velocity: 100., panning: 0., cents: 0., pressure: 0., timbre: 50. }
     }
 }
 
@@ -369,16 +611,35 @@ pub struct Note {
 
 impl Note {
     #[inline]
-    /// Get frequency of the note.
+    /// Get frequency of the note in the [default tuning].
+    ///
+    /// [default tuning]: ../../tuning/fn.default_tuning.html
     pub fn freq(&self) -> f64 {
         self.detune(0.)
     }
 
     #[inline]
-    /// Get frequency of the note, detuning `cents` cents.
+    /// Get frequency of the note in the [default tuning], detuning `cents`
+    /// cents.
+    ///
+    /// [default tuning]: ../../tuning/fn.default_tuning.html
     pub fn detune<T: Into<f64>>(&self, cents: T) -> f64 {
+        self.detune_with(&*tuning::default_tuning(), cents)
+    }
+
+    #[inline]
+    /// Get frequency of the note according to `tuning`.
+    pub fn freq_with<T: Tuning + ?Sized>(&self, tuning: &T) -> f64 {
+        self.detune_with(tuning, 0.)
+    }
+
+    #[inline]
+    /// Get frequency of the note according to `tuning`, detuning `cents`
+    /// cents.
+    pub fn detune_with<T, U>(&self, tuning: &T, cents: U) -> f64
+        where T: Tuning + ?Sized, U: Into<f64> {
         let cents = self.params.cents as f64 + cents.into();
-        self.name.detune(cents)
+        self.name.detune_with(tuning, cents)
     }
 
     #[inline]
@@ -387,3 +648,66 @@ impl Note {
         self.params.gain(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_note_name_string_round_trip() {
+        for index in -200..200 {
+            let name = NoteName::from_index(index);
+            let string = name.to_string();
+            assert_eq!(NoteName::try_from(string.as_str()), Ok(name), "{}", string);
+        }
+    }
+
+    #[test]
+    fn test_note_name_flats() {
+        let names = ["Db3", "Eb3", "Gb3", "Ab3", "Bb3"];
+        let sharps = ["C#3", "D#3", "F#3", "G#3", "A#3"];
+        for (flat, sharp) in names.iter().zip(sharps.iter()) {
+            let name = NoteName::try_from(*flat).unwrap();
+            assert_eq!(&name.to_string(), sharp);
+        }
+    }
+
+    #[test]
+    fn test_note_name_midi_round_trip() {
+        for note in 0..128 {
+            let name = NoteName::from_midi(note);
+            assert_eq!(name.midi(), Some(note));
+            let (nearest, cents) = NoteName::from_freq(name.freq()).unwrap();
+            assert_eq!(nearest, name);
+            assert!(cents.abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_note_name_from_freq_cents() {
+        let a4 = NoteName(NoteAlphabet::A, 4);
+        for &cents in [-49., -25., -1., 1., 25., 49.].iter() {
+            let (name, offset) = NoteName::from_freq(a4.detune(cents)).unwrap();
+            assert_eq!(name, a4);
+            assert!((offset - cents).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_note_name_from_invalid_freq() {
+        for &freq in [0., -440., ::std::f64::NAN, ::std::f64::INFINITY].iter() {
+            assert_eq!(NoteName::from_freq(freq), None);
+        }
+        // out of the octaves of a note name, though they're finite
+        assert_eq!(NoteName::from_freq(::std::f64::MAX), None);
+        assert_eq!(NoteName::from_freq(::std::f64::MIN_POSITIVE), None);
+        assert_eq!(NoteName::from_freq(1e-300), None);
+    }
+
+    #[test]
+    fn test_note_name_octave_overflow() {
+        assert_eq!(NoteName::try_from("Cb-128"), Err(()));
+        assert_eq!(NoteName::try_from("B#127"), Err(()));
+        assert_eq!(NoteName::try_from("Db-128"), Ok(NoteName(NoteAlphabet::Cs, -128)));
+    }
+}
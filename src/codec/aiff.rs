@@ -0,0 +1,123 @@
+use super::{AudioFile, Bytes, CodecError, CodecResult, SampleFormat};
+
+/// Parse an 80 bit IEEE 754 extended precision float.
+fn extended(bytes: &[u8]) -> f64 {
+    let exponent = ((bytes[0] as i32 & 0x7F) << 8) | bytes[1] as i32;
+    let mantissa = bytes[2..10].iter().fold(0u64, |m, b| (m << 8) | *b as u64);
+    let value = mantissa as f64 * 2f64.powi(exponent - 16383 - 63);
+    if bytes[0] & 0x80 != 0 { -value } else { value }
+}
+
+/// Decode an AIFF or uncompressed AIFF-C file.
+pub fn decode(data: &[u8]) -> CodecResult<AudioFile> {
+    let mut bytes = Bytes::new(data);
+    bytes.skip(4)?;
+    let size = bytes.u32_be()? as usize;
+    let compressed = match bytes.take(4)? {
+        b"AIFF" => false,
+        b"AIFC" => true,
+        _ => { return Err(CodecError::Unsupported("FORM file is not AIFF")); }
+    };
+
+    let mut bytes = Bytes::new(bytes.take_max(size.saturating_sub(4)));
+    let mut format = None;
+    let mut sound = None;
+
+    while bytes.remaining() >= 8 {
+        let id = bytes.take(4)?;
+        let size = bytes.u32_be()? as usize;
+        let chunk = bytes.take_max(size);
+        if size % 2 == 1 { bytes.take_max(1); }
+
+        match id {
+            b"COMM" => {
+                let mut chunk = Bytes::new(chunk);
+                let channels = chunk.u16_be()? as usize;
+                let frames = chunk.u32_be()? as usize;
+                let bits = chunk.u16_be()? as usize;
+                let sample_rate = extended(chunk.take(10)?).round() as u32;
+                let size = (bits + 7) / 8;
+
+                let (sample, little_endian) = if !compressed {
+                    (SampleFormat::Signed(size), false)
+                } else {
+                    match chunk.take(4)? {
+                        b"NONE" | b"twos" => (SampleFormat::Signed(size), false),
+                        b"sowt" => (SampleFormat::Signed(size), true),
+                        b"fl32" | b"FL32" => (SampleFormat::Float(4), false),
+                        b"fl64" | b"FL64" => (SampleFormat::Float(8), false),
+                        _ => { return Err(CodecError::Unsupported("AIFF-C compression")); }
+                    }
+                };
+                format = Some((sample_rate, channels, frames, sample, little_endian));
+            },
+            b"SSND" => {
+                let mut chunk = Bytes::new(chunk);
+                let offset = chunk.u32_be()? as usize;
+                chunk.skip(4)?;
+                chunk.skip(offset)?;
+                sound = Some(chunk.take_max(chunk.remaining()));
+            },
+            _ => {}
+        }
+    }
+
+    match (format, sound) {
+        (Some((sample_rate, channels, frames, sample, little_endian)), Some(sound)) => {
+            let length = (frames * channels * sample.size()).min(sound.len());
+            let samples = sample.decode(&sound[..length], little_endian)?;
+            AudioFile::from_samples(sample_rate, channels, &samples)
+        },
+        (None, _) => Err(CodecError::Malformed("no COMM chunk")),
+        (_, None) => Err(CodecError::Malformed("no SSND chunk"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 44100 as an 80 bit extended float
+    const RATE: [u8; 10] = [0x40, 0x0E, 0xAC, 0x44, 0, 0, 0, 0, 0, 0];
+
+    fn aiff(kind: &[u8], compression: &[u8], channels: u8, bits: u8, samples: &[u8])
+        -> Vec<u8> {
+        let frames = samples.len() / (channels as usize * ((bits as usize + 7) / 8));
+        let mut data = Vec::new();
+        data.extend_from_slice(b"FORM\xFF\xFF\x00\x00");
+        data.extend_from_slice(kind);
+
+        data.extend_from_slice(b"COMM");
+        data.extend_from_slice(&[0, 0, 0, 18 + compression.len() as u8]);
+        data.extend_from_slice(&[0, channels, 0, 0, 0, frames as u8, 0, bits]);
+        data.extend_from_slice(&RATE);
+        data.extend_from_slice(compression);
+
+        data.extend_from_slice(b"SSND");
+        data.extend_from_slice(&[0, 0, 0, 8 + samples.len() as u8, 0, 0, 0, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(samples);
+        data
+    }
+
+    #[test]
+    fn test_aiff_extended() {
+        assert_eq!(extended(&RATE), 44100.);
+        assert_eq!(extended(&[0xBF, 0xFF, 0x80, 0, 0, 0, 0, 0, 0, 0]), -1.);
+    }
+
+    #[test]
+    fn test_aiff_decode() {
+        let file = AudioFile::decode(&aiff(b"AIFF", b"", 2, 16, &[0x40, 0, 0xC0, 0])).unwrap();
+        assert_eq!(file.sample_rate, 44100);
+        assert_eq!(&file.frames[..], &[(0.5, -0.5).into()]);
+
+        let file = AudioFile::decode(&aiff(b"AIFC", b"sowt", 1, 16, &[0, 0x40])).unwrap();
+        assert_eq!(&file.frames[..], &[(0.5).into()]);
+
+        let file = AudioFile::decode(&aiff(b"AIFC", b"fl32", 1, 32, &[0x3E, 0x80, 0, 0]))
+            .unwrap();
+        assert_eq!(&file.frames[..], &[(0.25).into()]);
+
+        assert!(AudioFile::decode(&aiff(b"AIFC", b"ulaw", 1, 8, &[0])).is_err());
+    }
+}
@@ -0,0 +1,448 @@
+use super::{AudioFile, Bytes, CodecError, CodecResult};
+
+const BLOCK_STREAMINFO: u8 = 0;
+
+const MALFORMED: CodecError = CodecError::Malformed("invalid FLAC stream");
+
+/// A reader of big endian bit fields.
+struct Bits<'a> {
+    data: &'a [u8],
+    // position in bits
+    position: usize
+}
+
+impl<'a> Bits<'a> {
+    fn new(data: &'a [u8]) -> Self { Self { data, position: 0 } }
+
+    fn is_empty(&self) -> bool { self.position >= self.data.len() * 8 }
+
+    fn bit(&mut self) -> CodecResult<u64> {
+        let byte = match self.data.get(self.position / 8) {
+            Some(byte) => *byte,
+            None => { return Err(CodecError::Malformed("unexpected end of file")); }
+        };
+        let bit = (byte >> (7 - self.position % 8)) & 1;
+        self.position += 1;
+        Ok(bit as u64)
+    }
+
+    fn read(&mut self, n: usize) -> CodecResult<u64> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = (value << 1) | self.bit()?;
+        }
+        Ok(value)
+    }
+
+    fn signed(&mut self, n: usize) -> CodecResult<i64> {
+        if n == 0 { return Ok(0); }
+        let value = self.read(n)?;
+        Ok(((value << (64 - n)) as i64) >> (64 - n))
+    }
+
+    fn unary(&mut self) -> CodecResult<u64> {
+        let mut count = 0;
+        while self.bit()? == 0 {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn align(&mut self) {
+        self.position = (self.position + 7) / 8 * 8;
+    }
+
+    /// Read an UTF-8 like coded number, used for frame and sample numbers.
+    fn utf8(&mut self) -> CodecResult<u64> {
+        let first = self.read(8)?;
+        let extra = (!(first as u8)).leading_zeros() as usize;
+        let mut value = match extra {
+            0 => return Ok(first),
+            1 | 8 => return Err(MALFORMED),
+            _ => first & (0x7F >> extra)
+        };
+        for _ in 1..extra {
+            value = (value << 6) | (self.read(8)? & 0x3F);
+        }
+        Ok(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct StreamInfo {
+    sample_rate: u32,
+    channels: usize,
+    bits: usize
+}
+
+fn residual(bits: &mut Bits, block: usize, order: usize, samples: &mut Vec<i64>)
+    -> CodecResult<()> {
+    let parameter_bits = match bits.read(2)? {
+        0 => 4,
+        1 => 5,
+        _ => { return Err(CodecError::Unsupported("FLAC residual coding")); }
+    };
+    let escape = (1 << parameter_bits) - 1;
+
+    let partition_order = bits.read(4)? as usize;
+    let partitions = 1 << partition_order;
+    if block % partitions != 0 || block / partitions < order {
+        return Err(MALFORMED);
+    }
+
+    for partition in 0..partitions {
+        let count = block / partitions - if partition == 0 { order } else { 0 };
+        let parameter = bits.read(parameter_bits)?;
+
+        if parameter == escape {
+            let size = bits.read(5)? as usize;
+            for _ in 0..count {
+                samples.push(bits.signed(size)?);
+            }
+        } else {
+            for _ in 0..count {
+                let value = (bits.unary()? << parameter) | bits.read(parameter as usize)?;
+                // zigzag decoding
+                samples.push(((value >> 1) as i64) ^ -((value & 1) as i64));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Restore samples of a predicted subframe, which start with warm-up samples
+/// followed by residuals. Overflows can only be caused by a malformed stream.
+fn predict(samples: &mut [i64], coefficients: &[i64], shift: u32) -> CodecResult<()> {
+    let order = coefficients.len();
+    for i in order..samples.len() {
+        let mut prediction = 0i64;
+        for (j, c) in coefficients.iter().enumerate() {
+            prediction = c.checked_mul(samples[i - 1 - j])
+                .and_then(|p| prediction.checked_add(p)).ok_or(MALFORMED)?;
+        }
+        samples[i] = samples[i].checked_add(prediction >> shift).ok_or(MALFORMED)?;
+    }
+    Ok(())
+}
+
+fn subframe(bits: &mut Bits, block: usize, depth: usize) -> CodecResult<Vec<i64>> {
+    if bits.bit()? != 0 {
+        return Err(MALFORMED);
+    }
+
+    let kind = bits.read(6)?;
+    let wasted = if bits.bit()? == 1 { bits.unary()? as usize + 1 } else { 0 };
+    let depth = depth.checked_sub(wasted).ok_or(MALFORMED)?;
+
+    let mut samples = Vec::with_capacity(block);
+    match kind {
+        0 => {
+            let value = bits.signed(depth)?;
+            samples.resize(block, value);
+        },
+        1 => {
+            for _ in 0..block {
+                samples.push(bits.signed(depth)?);
+            }
+        },
+        8...12 => {
+            let order = kind as usize - 8;
+            for _ in 0..order {
+                samples.push(bits.signed(depth)?);
+            }
+            residual(bits, block, order, &mut samples)?;
+
+            let coefficients: &[i64] = match order {
+                0 => &[], 1 => &[1], 2 => &[2, -1], 3 => &[3, -3, 1],
+                _ => &[4, -6, 4, -1]
+            };
+            predict(&mut samples, coefficients, 0)?;
+        },
+        32...63 => {
+            let order = kind as usize - 31;
+            for _ in 0..order {
+                samples.push(bits.signed(depth)?);
+            }
+
+            let precision = bits.read(4)? as usize + 1;
+            if precision == 16 {
+                return Err(MALFORMED);
+            }
+            let shift = bits.signed(5)?;
+            if shift < 0 {
+                return Err(CodecError::Unsupported("FLAC negative LPC shift"));
+            }
+
+            let mut coefficients = Vec::with_capacity(order);
+            for _ in 0..order {
+                coefficients.push(bits.signed(precision)?);
+            }
+            residual(bits, block, order, &mut samples)?;
+            predict(&mut samples, &coefficients, shift as u32)?;
+        },
+        _ => { return Err(MALFORMED); }
+    }
+
+    if wasted > 0 {
+        samples.iter_mut().for_each(|s| *s <<= wasted);
+    }
+    Ok(samples)
+}
+
+/// Decode a frame, appending it's interleaved samples normalized to [-1, 1].
+fn frame(bits: &mut Bits, info: &StreamInfo, output: &mut Vec<f64>) -> CodecResult<()> {
+    if bits.read(14)? != 0x3FFE || bits.bit()? != 0 {
+        return Err(MALFORMED);
+    }
+    bits.bit()?;
+
+    let block_code = bits.read(4)?;
+    let rate_code = bits.read(4)?;
+    let assignment = bits.read(4)? as usize;
+    let depth = match bits.read(3)? {
+        0 => info.bits,
+        1 => 8, 2 => 12, 4 => 16, 5 => 20, 6 => 24, 7 => 32,
+        _ => { return Err(MALFORMED); }
+    };
+    bits.bit()?;
+    bits.utf8()?;
+
+    let block = match block_code {
+        0 => { return Err(MALFORMED); },
+        1 => 192,
+        2...5 => 576 << (block_code - 2),
+        6 => bits.read(8)? as usize + 1,
+        7 => bits.read(16)? as usize + 1,
+        _ => 256 << (block_code - 8)
+    };
+    match rate_code {
+        12 => { bits.read(8)?; },
+        13 | 14 => { bits.read(16)?; },
+        15 => { return Err(MALFORMED); },
+        _ => {}
+    }
+    // header CRC
+    bits.read(8)?;
+
+    let channels = match assignment {
+        0...7 => assignment + 1,
+        8...10 => 2,
+        _ => { return Err(MALFORMED); }
+    };
+    if channels != info.channels {
+        return Err(CodecError::Unsupported("FLAC channel count change"));
+    }
+
+    let mut subframes = Vec::with_capacity(channels);
+    for channel in 0..channels {
+        // side channel has an extra bit
+        let side = match (assignment, channel) {
+            (8, 1) | (9, 0) | (10, 1) => 1,
+            _ => 0
+        };
+        subframes.push(subframe(bits, block, depth + side)?);
+    }
+
+    match assignment {
+        8 => {
+            for i in 0..block {
+                subframes[1][i] = subframes[0][i].checked_sub(subframes[1][i]).ok_or(MALFORMED)?;
+            }
+        },
+        9 => {
+            for i in 0..block {
+                subframes[0][i] = subframes[0][i].checked_add(subframes[1][i]).ok_or(MALFORMED)?;
+            }
+        },
+        10 => {
+            for i in 0..block {
+                let (mid, side) = (subframes[0][i], subframes[1][i]);
+                let mid = mid.checked_mul(2).ok_or(MALFORMED)? | (side & 1);
+                subframes[0][i] = mid.checked_add(side).ok_or(MALFORMED)? >> 1;
+                subframes[1][i] = mid.checked_sub(side).ok_or(MALFORMED)? >> 1;
+            }
+        },
+        _ => {}
+    }
+
+    bits.align();
+    // footer CRC
+    bits.read(16)?;
+
+    let scale = (1u64 << (depth - 1)) as f64;
+    for i in 0..block {
+        for channel in subframes.iter() {
+            output.push(channel[i] as f64 / scale);
+        }
+    }
+    Ok(())
+}
+
+/// Decode a FLAC stream.
+pub fn decode(data: &[u8]) -> CodecResult<AudioFile> {
+    let mut bytes = Bytes::new(data);
+    bytes.skip(4)?;
+
+    let mut info = None;
+    loop {
+        let header = bytes.take(4)?;
+        let size = (header[1] as usize) << 16 | (header[2] as usize) << 8 | header[3] as usize;
+        let block = bytes.take(size)?;
+
+        if header[0] & 0x7F == BLOCK_STREAMINFO {
+            let mut bits = Bits::new(block);
+            bits.read(80)?;
+            info = Some(StreamInfo {
+                sample_rate: bits.read(20)? as u32,
+                channels: bits.read(3)? as usize + 1,
+                bits: bits.read(5)? as usize + 1
+            });
+        }
+
+        if header[0] & 0x80 != 0 { break; }
+    }
+
+    let info = info.ok_or(CodecError::Malformed("no FLAC stream info"))?;
+    let mut bits = Bits::new(bytes.take_max(bytes.remaining()));
+    let mut samples = Vec::new();
+    while !bits.is_empty() {
+        frame(&mut bits, &info, &mut samples)?;
+    }
+
+    AudioFile::from_samples(info.sample_rate, info.channels, &samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A writer of big endian bit fields, to make test streams.
+    struct Writer(Vec<u8>, usize);
+
+    impl Writer {
+        fn write(&mut self, n: usize, value: i64) -> &mut Self {
+            for i in (0..n).rev() {
+                if self.1 % 8 == 0 { self.0.push(0); }
+                let bit = ((value >> i) & 1) as u8;
+                *self.0.last_mut().unwrap() |= bit << (7 - self.1 % 8);
+                self.1 += 1;
+            }
+            self
+        }
+
+        fn rice(&mut self, parameter: usize, value: i64) -> &mut Self {
+            let value = ((value << 1) ^ (value >> 63)) as u64;
+            for _ in 0..(value >> parameter) { self.write(1, 0); }
+            self.write(1, 1).write(parameter, value as i64)
+        }
+
+        fn align(&mut self) -> &mut Self {
+            while self.1 % 8 != 0 { self.write(1, 0); }
+            self
+        }
+    }
+
+    /// Make a stream of 16 bit stereo frames, given subframe writers.
+    fn stream(frames: &[(usize, usize, &Fn(&mut Writer))]) -> Vec<u8> {
+        let mut w = Writer(b"fLaC".to_vec(), 32);
+        w.write(8, 0x80).write(24, 34);
+        w.write(40, 0).write(40, 0).write(20, 48000).write(3, 1).write(5, 15).write(36, 0);
+        w.write(64, 0).write(64, 0);
+
+        for (n, &(assignment, block, ref subframes)) in frames.iter().enumerate() {
+            w.write(14, 0x3FFE).write(2, 0).write(4, 7).write(4, 0);
+            w.write(4, assignment as i64).write(3, 4).write(1, 0);
+            w.write(8, n as i64).write(16, block as i64 - 1).write(8, 0);
+            subframes(&mut w);
+            w.align().write(16, 0);
+        }
+        w.0
+    }
+
+    fn decoded(file: &AudioFile) -> Vec<(i64, i64)> {
+        file.frames.iter().map(|f| {
+            ((f[0] * 32768.).round() as i64, (f[1] * 32768.).round() as i64)
+        }).collect()
+    }
+
+    #[test]
+    fn test_flac_constant_and_verbatim() {
+        let data = stream(&[(1, 3, &|w: &mut Writer| {
+            w.write(8, 0).write(16, -5);
+            w.write(8, 2).write(16, 1).write(16, 2).write(16, -32768);
+        })]);
+
+        let file = AudioFile::decode(&data).unwrap();
+        assert_eq!(file.sample_rate, 48000);
+        assert_eq!(file.channels, 2);
+        assert_eq!(decoded(&file), vec![(-5, 1), (-5, 2), (-5, -32768)]);
+    }
+
+    #[test]
+    fn test_flac_fixed_and_lpc() {
+        let left = [10i64, 20, 31, 43, 54, 66, 80, 91];
+        let right = [-3i64, 7, 12, 20, 25, 31, 30, 28];
+
+        let data = stream(&[(1, 8, &|w: &mut Writer| {
+            // fixed order 2 with rice parameter 2 and one partition
+            w.write(8, 0x14).write(16, left[0]).write(16, left[1]);
+            w.write(2, 0).write(4, 0).write(4, 2);
+            for i in 2..8 {
+                w.rice(2, left[i] - (2 * left[i - 1] - left[i - 2]));
+            }
+
+            // lpc order 1, coefficient 3 with shift 2, and escaped partitions
+            w.write(8, 0x40).write(16, right[0]);
+            w.write(4, 4).write(5, 2).write(5, 3);
+            w.write(2, 1).write(4, 1);
+            w.write(5, 31).write(5, 8);
+            for i in 1..4 { w.write(8, right[i] - ((3 * right[i - 1]) >> 2)); }
+            w.write(5, 3);
+            for i in 4..8 { w.rice(3, right[i] - ((3 * right[i - 1]) >> 2)); }
+        })]);
+
+        let file = AudioFile::decode(&data).unwrap();
+        let expected = left.iter().cloned().zip(right.iter().cloned()).collect::<Vec<_>>();
+        assert_eq!(decoded(&file), expected);
+    }
+
+    #[test]
+    fn test_flac_stereo_decorrelation() {
+        let (left, right) = (1000i64, -24i64);
+        let side = left - right;
+        let mid = (left + right) >> 1;
+
+        let data = stream(&[
+            (8, 1, &|w: &mut Writer| { w.write(8, 2).write(16, left).write(8, 2).write(17, side); }),
+            (9, 1, &|w: &mut Writer| { w.write(8, 2).write(17, side).write(8, 2).write(16, right); }),
+            (10, 1, &|w: &mut Writer| { w.write(8, 2).write(16, mid).write(8, 2).write(17, side); }),
+            // wasted bits: constant of 3 bits shifted by 2
+            (1, 1, &|w: &mut Writer| {
+                w.write(8, 1).write(1, 0).write(1, 1).write(16 - 2, 3);
+                w.write(8, 0).write(16, 0);
+            })
+        ]);
+
+        let file = AudioFile::decode(&data).unwrap();
+        assert_eq!(decoded(&file), vec![(left, right), (left, right), (left, right), (12, 0)]);
+
+        assert!(AudioFile::decode(&data[..data.len() - 3]).is_err());
+    }
+
+    #[test]
+    fn test_flac_overflow() {
+        // lpc order 1 with the largest coefficient, which grows past i64
+        let data = stream(&[(1, 8, &|w: &mut Writer| {
+            w.write(8, 0x40).write(16, 16384);
+            w.write(4, 14).write(5, 0).write(15, 16383);
+            w.write(2, 0).write(4, 0).write(4, 0);
+            for _ in 1..8 { w.rice(0, 0); }
+            w.write(8, 0).write(16, 0);
+        })]);
+
+        match AudioFile::decode(&data) {
+            Err(CodecError::Malformed(_)) => {},
+            _ => panic!("overflow isn't detected")
+        }
+    }
+}
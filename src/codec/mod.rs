@@ -0,0 +1,257 @@
+//! The Codec Module
+//!
+//! Decoding audio files into [`AudioBuffer`]s. Supported formats are WAV
+//! (PCM and float), AIFF (and uncompressed AIFF-C) and FLAC. Frames can be
+//! written to WAV files by [`WavWriter`].
+//!
+//! [`AudioBuffer`]: ../buffer/audio/struct.AudioBuffer.html
+//! [`WavWriter`]: struct.WavWriter.html
+
+use std::{fmt, io, error};
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use buffer::Buffer;
+use buffer::audio::{Frame, AudioBuffer};
+use resample::{Quality, Resampler};
+
+mod wav;
+pub use self::wav::{Encoding, WavWriter};
+mod aiff;
+mod flac;
+
+#[derive(Debug)]
+/// An error occurred while decoding or encoding a file.
+pub enum CodecError {
+    /// Reading or writing the file failed.
+    Io(io::Error),
+
+    /// The file is valid, but uses a feature which is not supported.
+    Unsupported(&'static str),
+
+    /// The file is not valid.
+    Malformed(&'static str)
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CodecError::Io(ref e) => write!(f, "io error: {}", e),
+            CodecError::Unsupported(s) => write!(f, "unsupported: {}", s),
+            CodecError::Malformed(s) => write!(f, "malformed: {}", s)
+        }
+    }
+}
+
+impl error::Error for CodecError {
+    fn description(&self) -> &str {
+        match *self {
+            CodecError::Io(_) => "io error",
+            CodecError::Unsupported(s) => s,
+            CodecError::Malformed(s) => s
+        }
+    }
+}
+
+impl From<io::Error> for CodecError {
+    fn from(e: io::Error) -> Self { CodecError::Io(e) }
+}
+
+pub type CodecResult<T> = Result<T, CodecError>;
+
+#[derive(Debug, Clone)]
+/// Decoded audio of a file. Mono files are played on both channels and
+/// only the first two channels of multichannel files are kept.
+pub struct AudioFile {
+    /// Sample rate of the audio.
+    pub sample_rate: u32,
+
+    /// Number of channels of the original file.
+    pub channels: usize,
+
+    /// Decoded frames.
+    pub frames: AudioBuffer
+}
+
+impl AudioFile {
+    /// Decode a file from it's contents, detecting the format.
+    pub fn decode(data: &[u8]) -> CodecResult<Self> {
+        if data.starts_with(b"RIFF") {
+            wav::decode(data)
+        } else if data.starts_with(b"FORM") {
+            aiff::decode(data)
+        } else if data.starts_with(b"fLaC") {
+            flac::decode(data)
+        } else {
+            Err(CodecError::Unsupported("unknown file format"))
+        }
+    }
+
+    /// Read and decode the file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> CodecResult<Self> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        Self::decode(&data)
+    }
+
+    /// Encode audio as a stereo WAV file of 64-bit floats, which decodes
+    /// to the same frames.
+    pub fn encode(&self) -> CodecResult<Vec<u8>> {
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), self.sample_rate,
+                                        Encoding::Float64)?;
+        writer.write(&self.frames)?;
+        Ok(writer.finish()?.into_inner())
+    }
+
+    /// Get duration in seconds.
+    pub fn duration(&self) -> f64 {
+        self.frames.len() as f64 / self.sample_rate as f64
+    }
+
+    /// Convert audio to `sample_rate`, using a windowed sinc
+    /// [`Resampler`].
+    ///
+    /// [`Resampler`]: ../resample/struct.Resampler.html
+    pub fn resample(&self, sample_rate: u32) -> Self {
+        if sample_rate == self.sample_rate {
+            return self.clone();
+        }
+
+        let mut resampler = Resampler::new(self.sample_rate as f64, sample_rate as f64,
+                                           Quality::Sinc);
+        let frames = resampler.resample(&self.frames);
+        Self { sample_rate, channels: self.channels, frames }
+    }
+
+    fn from_samples(sample_rate: u32, channels: usize, samples: &[f64])
+        -> CodecResult<Self> {
+        if channels == 0 || sample_rate == 0 {
+            return Err(CodecError::Malformed("no channels or zero sample rate"));
+        }
+
+        let mut frames = AudioBuffer::with_length(samples.len() / channels);
+        for (frame, samples) in frames.iter_mut().zip(samples.chunks(channels)) {
+            *frame = if channels == 1 { Frame::from(samples[0]) }
+                     else { (samples[0], samples[1]).into() };
+        }
+
+        Ok(Self { sample_rate, channels, frames })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Encoding of PCM samples, with size of each sample in bytes.
+enum SampleFormat { Unsigned(usize), Signed(usize), Float(usize) }
+
+impl SampleFormat {
+    fn size(&self) -> usize {
+        match *self {
+            SampleFormat::Unsigned(s) | SampleFormat::Signed(s) | SampleFormat::Float(s) => s
+        }
+    }
+
+    /// Decode all samples of `data`, normalized to [-1, 1].
+    fn decode(&self, data: &[u8], little_endian: bool) -> CodecResult<Vec<f64>> {
+        let size = self.size();
+        match *self {
+            SampleFormat::Unsigned(1) | SampleFormat::Signed(1...4) |
+            SampleFormat::Float(4) | SampleFormat::Float(8) => {},
+            _ => { return Err(CodecError::Unsupported("sample format")); }
+        }
+
+        Ok(data.chunks(size).filter(|c| c.len() == size).map(|bytes| {
+            // assemble as big endian, left justified in 64 bits
+            let mut value = 0u64;
+            for i in 0..size {
+                let byte = if little_endian { bytes[size - 1 - i] } else { bytes[i] };
+                value |= (byte as u64) << (56 - 8 * i);
+            }
+
+            match *self {
+                SampleFormat::Unsigned(_) => (value >> 56) as f64 / 128. - 1.,
+                SampleFormat::Signed(_) => value as i64 as f64 / 9223372036854775808.,
+                SampleFormat::Float(4) => f32::from_bits((value >> 32) as u32) as f64,
+                _ => f64::from_bits(value)
+            }
+        }).collect())
+    }
+}
+
+/// A cursor over bytes of a file.
+struct Bytes<'a> {
+    data: &'a [u8],
+    position: usize
+}
+
+impl<'a> Bytes<'a> {
+    fn new(data: &'a [u8]) -> Self { Self { data, position: 0 } }
+
+    fn remaining(&self) -> usize { self.data.len() - self.position }
+
+    fn take(&mut self, n: usize) -> CodecResult<&'a [u8]> {
+        if n > self.remaining() {
+            return Err(CodecError::Malformed("unexpected end of file"));
+        }
+        let bytes = &self.data[self.position..self.position + n];
+        self.position += n;
+        Ok(bytes)
+    }
+
+    /// Take at most `n` bytes.
+    fn take_max(&mut self, n: usize) -> &'a [u8] {
+        let n = n.min(self.remaining());
+        self.take(n).unwrap()
+    }
+
+    fn skip(&mut self, n: usize) -> CodecResult<()> { self.take(n).map(|_| ()) }
+
+    fn u16_le(&mut self) -> CodecResult<u16> {
+        self.take(2).map(|b| b[0] as u16 | (b[1] as u16) << 8)
+    }
+
+    fn u32_le(&mut self) -> CodecResult<u32> {
+        Ok(self.u16_le()? as u32 | (self.u16_le()? as u32) << 16)
+    }
+
+    fn u16_be(&mut self) -> CodecResult<u16> {
+        self.take(2).map(|b| (b[0] as u16) << 8 | b[1] as u16)
+    }
+
+    fn u32_be(&mut self) -> CodecResult<u32> {
+        Ok((self.u16_be()? as u32) << 16 | self.u16_be()? as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_format_decode() {
+        let decode = |format: SampleFormat, data: &[u8], le| format.decode(data, le).unwrap();
+
+        assert_eq!(decode(SampleFormat::Unsigned(1), &[0, 128, 192], true), vec![-1., 0., 0.5]);
+        assert_eq!(decode(SampleFormat::Signed(2), &[0x00, 0x40, 0x00, 0x80], true),
+                   vec![0.5, -1.]);
+        assert_eq!(decode(SampleFormat::Signed(3), &[0xC0, 0x00, 0x00], false), vec![-0.5]);
+        assert_eq!(decode(SampleFormat::Float(4), &[0x00, 0x00, 0x80, 0x3E], true), vec![0.25]);
+        assert!(SampleFormat::Float(2).decode(&[0, 0], true).is_err());
+    }
+
+    #[test]
+    fn test_audio_file_resample() {
+        let mut frames = AudioBuffer::with_length(100);
+        for (i, frame) in frames.iter_mut().enumerate() {
+            *frame = (i as f64 / 10.).sin().into();
+        }
+        let file = AudioFile { sample_rate: 100, channels: 1, frames };
+
+        let resampled = file.resample(200);
+        assert_eq!(resampled.sample_rate, 200);
+        assert_eq!(resampled.frames.len(), 200);
+        assert!((resampled.frames[101][0] - 5.05f64.sin()).abs() < 1e-3);
+        assert_eq!(resampled.duration(), file.duration());
+
+        assert!(AudioFile::decode(b"OggS").is_err());
+    }
+}
@@ -0,0 +1,295 @@
+use std::io::{Seek, SeekFrom, Write};
+use buffer::audio::Frame;
+use super::{AudioFile, Bytes, CodecError, CodecResult, SampleFormat};
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Decode a RIFF WAVE file.
+pub fn decode(data: &[u8]) -> CodecResult<AudioFile> {
+    let mut bytes = Bytes::new(data);
+    bytes.skip(4)?;
+    let size = bytes.u32_le()? as usize;
+    if bytes.take(4)? != b"WAVE" {
+        return Err(CodecError::Unsupported("RIFF file is not WAVE"));
+    }
+
+    let mut bytes = Bytes::new(bytes.take_max(size.saturating_sub(4)));
+    let mut format = None;
+
+    while bytes.remaining() >= 8 {
+        let id = bytes.take(4)?;
+        let size = bytes.u32_le()? as usize;
+        let chunk = bytes.take_max(size);
+        // chunks are word aligned
+        if size % 2 == 1 { bytes.take_max(1); }
+
+        match id {
+            b"fmt " => {
+                let mut chunk = Bytes::new(chunk);
+                let mut tag = chunk.u16_le()?;
+                let channels = chunk.u16_le()? as usize;
+                let sample_rate = chunk.u32_le()?;
+                chunk.skip(4)?;
+                let align = chunk.u16_le()? as usize;
+                let bits = chunk.u16_le()?;
+
+                if tag == FORMAT_EXTENSIBLE {
+                    // extension size, valid bits, channel mask, subformat guid
+                    chunk.skip(8)?;
+                    tag = chunk.u16_le()?;
+                }
+
+                if channels == 0 || align % channels != 0 {
+                    return Err(CodecError::Malformed("invalid block align"));
+                }
+
+                let size = align / channels;
+                let sample = match (tag, bits) {
+                    (FORMAT_PCM, 1...8) => SampleFormat::Unsigned(size),
+                    (FORMAT_PCM, _) => SampleFormat::Signed(size),
+                    (FORMAT_FLOAT, _) => SampleFormat::Float(size),
+                    _ => { return Err(CodecError::Unsupported("WAVE encoding")); }
+                };
+                format = Some((sample_rate, channels, sample));
+            },
+            b"data" => {
+                let (sample_rate, channels, sample) = match format {
+                    Some(format) => format,
+                    None => { return Err(CodecError::Malformed("data before fmt chunk")); }
+                };
+                let samples = sample.decode(chunk, true)?;
+                return AudioFile::from_samples(sample_rate, channels, &samples);
+            },
+            _ => {}
+        }
+    }
+
+    Err(CodecError::Malformed("no data chunk"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Sample encoding of written WAVE files.
+pub enum Encoding { Int16, Int24, Float32, Float64 }
+
+impl Encoding {
+    fn bits(&self) -> u16 {
+        match *self {
+            Encoding::Int16 => 16,
+            Encoding::Int24 => 24,
+            Encoding::Float32 => 32,
+            Encoding::Float64 => 64
+        }
+    }
+
+    fn is_float(&self) -> bool {
+        *self == Encoding::Float32 || *self == Encoding::Float64
+    }
+}
+
+/// A xorshift generator, used for dither noise.
+struct Noise(u32);
+
+impl Noise {
+    /// Get a random number in [0, 1).
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as f64 / 4294967296.
+    }
+}
+
+/// Writes frames to a WAVE file. Sizes in the header are updated by
+/// [`finish()`], so the file is not valid before calling it.
+///
+/// Integer encodings are dithered by default, with triangular noise of
+/// 1 LSB peak amplitude.
+///
+/// [`finish()`]: #method.finish
+///
+/// # Examples
+/// ```
+/// use std::io::Cursor;
+/// use overcore::codec::{AudioFile, Encoding, WavWriter};
+///
+/// let mut writer = WavWriter::new(Cursor::new(Vec::new()), 48000, Encoding::Float32)
+///     .unwrap();
+/// writer.write(&[(0.5, -0.25).into()]).unwrap();
+/// let data = writer.finish().unwrap().into_inner();
+///
+/// let file = AudioFile::decode(&data).unwrap();
+/// assert_eq!(file.sample_rate, 48000);
+/// assert_eq!(file.frames[0], (0.5, -0.25).into());
+/// ```
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    encoding: Encoding,
+    dither: bool,
+    noise: Noise,
+    // written bytes of samples
+    length: u32
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Start writing a stereo file to `writer`.
+    pub fn new(mut writer: W, sample_rate: u32, encoding: Encoding) -> CodecResult<Self> {
+        let bits = encoding.bits();
+        let align = 2 * bits / 8;
+        let tag = if encoding.is_float() { FORMAT_FLOAT } else { FORMAT_PCM };
+
+        let mut header = Vec::with_capacity(44);
+        header.extend_from_slice(b"RIFF\x00\x00\x00\x00WAVEfmt \x10\x00\x00\x00");
+        for &(value, size) in [(tag as u32, 2), (2, 2), (sample_rate, 4),
+                               (sample_rate * align as u32, 4), (align as u32, 2),
+                               (bits as u32, 2)].iter() {
+            for i in 0..size { header.push((value >> (8 * i)) as u8); }
+        }
+        header.extend_from_slice(b"data\x00\x00\x00\x00");
+        writer.write_all(&header)?;
+
+        let dither = !encoding.is_float();
+        Ok(Self { writer, encoding, dither, noise: Noise(0x9E3779B9), length: 0 })
+    }
+
+    /// Enable or disable dithering of integer encodings.
+    pub fn with_dither(mut self, dither: bool) -> Self {
+        self.dither = dither && !self.encoding.is_float();
+        self
+    }
+
+    /// Append `frames` to the file. Samples out of [-1, 1] are clipped in
+    /// integer encodings.
+    pub fn write(&mut self, frames: &[Frame]) -> CodecResult<()> {
+        let size = self.encoding.bits() as usize / 8;
+        let mut data = Vec::with_capacity(frames.len() * 2 * size);
+
+        for frame in frames.iter() {
+            for &sample in frame.0.iter() {
+                let value = match self.encoding {
+                    Encoding::Float32 => (sample as f32).to_bits() as u64,
+                    Encoding::Float64 => sample.to_bits(),
+                    _ => {
+                        let scale = (1 << (self.encoding.bits() - 1)) as f64;
+                        let dither = if self.dither { self.noise.next() - self.noise.next() }
+                                     else { 0. };
+                        let value = (sample * scale + dither).round();
+                        value.max(-scale).min(scale - 1.) as i32 as u32 as u64
+                    }
+                };
+                for i in 0..size { data.push((value >> (8 * i)) as u8); }
+            }
+        }
+
+        self.writer.write_all(&data)?;
+        self.length = self.length.saturating_add(data.len() as u32);
+        Ok(())
+    }
+
+    /// Update the header to the frames written so far and flush the
+    /// writer, so it's a complete file until more frames are written.
+    pub fn flush(&mut self) -> CodecResult<()> {
+        let length = self.length;
+        for &(position, size) in [(4, length.saturating_add(36)), (40, length)].iter() {
+            self.writer.seek(SeekFrom::Start(position))?;
+            self.writer.write_all(&[size as u8, (size >> 8) as u8,
+                                    (size >> 16) as u8, (size >> 24) as u8])?;
+        }
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Update the header and get the underlying writer back.
+    pub fn finish(mut self) -> CodecResult<W> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make a WAVE file of given format and sample bytes.
+    fn wave(tag: u16, channels: u16, bits: u16, samples: &[u8]) -> Vec<u8> {
+        let align = channels * ((bits + 7) / 8);
+        let mut data = Vec::new();
+        data.extend_from_slice(b"RIFF");
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(b"WAVE");
+
+        // an unknown chunk with odd size, to test skipping
+        data.extend_from_slice(b"LIST\x03\x00\x00\x00abc\x00");
+
+        data.extend_from_slice(b"fmt \x10\x00\x00\x00");
+        for &(value, size) in [(tag as u32, 2), (channels as u32, 2), (44100, 4),
+                               (44100 * align as u32, 4), (align as u32, 2),
+                               (bits as u32, 2)].iter() {
+            for i in 0..size { data.push((value >> (8 * i)) as u8); }
+        }
+
+        data.extend_from_slice(b"data");
+        let size = samples.len() as u32;
+        data.extend_from_slice(&[size as u8, (size >> 8) as u8, (size >> 16) as u8, 0]);
+        data.extend_from_slice(samples);
+
+        let size = data.len() as u32 - 8;
+        data[4..8].copy_from_slice(&[size as u8, (size >> 8) as u8, (size >> 16) as u8, 0]);
+        data
+    }
+
+    #[test]
+    fn test_wav_decode_pcm() {
+        let file = AudioFile::decode(&wave(FORMAT_PCM, 2, 16, &[0, 0x40, 0, 0xC0])).unwrap();
+        assert_eq!(file.sample_rate, 44100);
+        assert_eq!(file.channels, 2);
+        assert_eq!(&file.frames[..], &[(0.5, -0.5).into()]);
+
+        let file = AudioFile::decode(&wave(FORMAT_PCM, 1, 24, &[0, 0, 0x20, 0, 0, 0x80]))
+            .unwrap();
+        assert_eq!(&file.frames[..], &[(0.25).into(), (-1.).into()]);
+
+        let file = AudioFile::decode(&wave(FORMAT_PCM, 1, 8, &[64])).unwrap();
+        assert_eq!(&file.frames[..], &[(-0.5).into()]);
+    }
+
+    #[test]
+    fn test_wav_decode_float() {
+        let samples = [0x00, 0x00, 0x80, 0x3E, 0x00, 0x00, 0x80, 0xBF];
+        let file = AudioFile::decode(&wave(FORMAT_FLOAT, 2, 32, &samples)).unwrap();
+        assert_eq!(&file.frames[..], &[(0.25, -1.).into()]);
+
+        assert!(AudioFile::decode(&wave(2, 1, 4, &[0])).is_err());
+        assert!(AudioFile::decode(&wave(FORMAT_PCM, 1, 16, &[])[..40]).is_err());
+    }
+
+    fn round_trip(encoding: Encoding, dither: bool, frames: &[Frame]) -> AudioFile {
+        let cursor = ::std::io::Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(cursor, 44100, encoding).unwrap().with_dither(dither);
+        writer.write(&frames[..1]).unwrap();
+        writer.write(&frames[1..]).unwrap();
+        AudioFile::decode(&writer.finish().unwrap().into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_wav_writer() {
+        let frames = [(0.5, -0.25).into(), (1.5, -1.5).into(), (0.1, 0.).into()];
+
+        let file = round_trip(Encoding::Int16, false, &frames);
+        assert_eq!(file.sample_rate, 44100);
+        assert_eq!(file.frames.len(), 3);
+        assert_eq!(file.frames[0], (0.5, -0.25).into());
+        assert_eq!(file.frames[1], (32767. / 32768., -1.).into());
+        assert_eq!(file.frames[2], ((0.1f64 * 32768.).round() / 32768., 0.).into());
+
+        // dither noise is at most 1 LSB
+        let file = round_trip(Encoding::Int24, true, &frames);
+        let lsb = 1. / 8388608.;
+        assert!((file.frames[2][0] - 0.1).abs() <= 1.5 * lsb);
+
+        let file = round_trip(Encoding::Float32, true, &frames);
+        assert_eq!(file.frames[1], (1.5, -1.5).into());
+    }
+}
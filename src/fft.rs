@@ -0,0 +1,201 @@
+//! The FFT Module
+//!
+//! A small radix-2 fast Fourier transform, used by the spectral processors
+//! of the core.
+
+use std::ops::{Add, Sub, Mul};
+use std::f64::consts::PI;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+/// A complex number.
+pub struct Complex {
+    /// Real part.
+    pub re: f64,
+
+    /// Imaginary part.
+    pub im: f64
+}
+
+impl Complex {
+    #[inline]
+    /// Create a complex number from it's parts.
+    pub fn new(re: f64, im: f64) -> Self { Self { re, im } }
+
+    #[inline]
+    /// Get complex conjugate.
+    pub fn conj(self) -> Self { Self::new(self.re, -self.im) }
+
+    #[inline]
+    /// Get magnitude.
+    pub fn abs(self) -> f64 { self.re.hypot(self.im) }
+}
+
+impl From<f64> for Complex {
+    #[inline]
+    fn from(re: f64) -> Self { Self::new(re, 0.) }
+}
+
+impl Add for Complex {
+    type Output = Self;
+
+    #[inline]
+    fn add(self, other: Self) -> Self {
+        Self::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+
+    #[inline]
+    fn mul(self, other: Self) -> Self {
+        Self::new(self.re * other.re - self.im * other.im,
+                  self.re * other.im + self.im * other.re)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A precomputed FFT of a fixed size.
+pub struct Fft {
+    twiddles: Box<[Complex]>,
+    reversed: Box<[usize]>
+}
+
+impl Fft {
+    /// Prepare an FFT of `size` points.
+    ///
+    /// # Panics
+    /// Panics if `size` is not a power of two.
+    pub fn new(size: usize) -> Self {
+        assert!(size.is_power_of_two());
+
+        let twiddles = (0..size / 2).map(|k| {
+            let angle = -2. * PI * k as f64 / size as f64;
+            Complex::new(angle.cos(), angle.sin())
+        });
+
+        let bits = size.trailing_zeros();
+        let reversed = (0..size).map(|i| {
+            (0..bits).fold(0, |r, bit| (r << 1) | ((i >> bit) & 1))
+        });
+
+        Self {
+            twiddles: twiddles.collect::<Vec<_>>().into_boxed_slice(),
+            reversed: reversed.collect::<Vec<_>>().into_boxed_slice()
+        }
+    }
+
+    #[inline]
+    /// Get number of points.
+    pub fn size(&self) -> usize { self.reversed.len() }
+
+    /// Transform `data` to frequency domain, in place.
+    ///
+    /// # Panics
+    /// Panics if length of `data` is not equal to the size.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::fft::{Fft, Complex};
+    ///
+    /// let mut data = [Complex::from(1.), Complex::default(), Complex::default(),
+    ///                 Complex::default()];
+    /// Fft::new(4).forward(&mut data);
+    /// assert_eq!(data, [Complex::from(1.); 4]);
+    /// ```
+    pub fn forward(&self, data: &mut [Complex]) {
+        self.transform(data, false);
+    }
+
+    /// Transform `data` back to time domain, in place. It's scaled, so it's
+    /// the exact inverse of [`forward()`](#method.forward).
+    ///
+    /// # Panics
+    /// Panics if length of `data` is not equal to the size.
+    pub fn inverse(&self, data: &mut [Complex]) {
+        self.transform(data, true);
+        let scale = 1. / data.len() as f64;
+        data.iter_mut().for_each(|c| *c = Complex::new(c.re * scale, c.im * scale));
+    }
+
+    fn transform(&self, data: &mut [Complex], inverse: bool) {
+        let n = self.size();
+        assert_eq!(data.len(), n);
+
+        for i in 0..n {
+            let j = self.reversed[i];
+            if i < j { data.swap(i, j); }
+        }
+
+        let mut len = 2;
+        while len <= n {
+            let (half, step) = (len / 2, n / len);
+            let mut start = 0;
+            while start < n {
+                for k in 0..half {
+                    let w = self.twiddles[k * step];
+                    let w = if inverse { w.conj() } else { w };
+                    let (a, b) = (data[start + k], data[start + k + half] * w);
+                    data[start + k] = a + b;
+                    data[start + k + half] = a - b;
+                }
+                start += len;
+            }
+            len *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dft(data: &[Complex]) -> Vec<Complex> {
+        let n = data.len();
+        (0..n).map(|k| data.iter().enumerate().fold(Complex::default(), |sum, (t, x)| {
+            let angle = -2. * PI * (k * t) as f64 / n as f64;
+            sum + *x * Complex::new(angle.cos(), angle.sin())
+        })).collect()
+    }
+
+    fn signal(n: usize) -> Vec<Complex> {
+        (0..n).map(|i| Complex::new((i as f64 * 0.7).sin(), (i as f64 * 1.3).cos()))
+            .collect()
+    }
+
+    #[test]
+    fn test_fft_forward() {
+        for &n in [1, 2, 8, 64].iter() {
+            let mut data = signal(n);
+            let expected = dft(&data);
+            Fft::new(n).forward(&mut data);
+
+            for (a, b) in data.iter().zip(expected.iter()) {
+                assert!((*a - *b).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fft_inverse() {
+        let fft = Fft::new(256);
+        let original = signal(256);
+        let mut data = original.clone();
+
+        fft.forward(&mut data);
+        fft.inverse(&mut data);
+
+        for (a, b) in data.iter().zip(original.iter()) {
+            assert!((*a - *b).abs() < 1e-12);
+        }
+    }
+}
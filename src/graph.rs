@@ -1,9 +1,1334 @@
-use Core;
+//! The Graph Module
+//!
+//! Plugins are processed as nodes of a directed acyclic graph, which it's
+//! connections feed outputs of a node to inputs of another. Connections to
+//! the same input are summed.
+//!
+//! Latency reported by plugins is compensated: when paths of different
+//! latencies join, the earlier ones are delayed so all signals arriving at
+//! a node are aligned.
+//!
+//! A cycle is only allowed through a feedback connection, which feeds the
+//! output of it's source from the previous buffer, i.e. delayed by one
+//! buffer. Feedback connections are not considered in latency compensation.
+//!
+//! With [`CoreConfig::threads`] above one, independent branches are
+//! processed concurrently on a pool of workers, with the same output as on
+//! a single thread.
+//!
+//! [`CoreConfig::threads`]: ../struct.CoreConfig.html#structfield.threads
 
-pub struct Graph {}
+use std::{fmt, error, mem};
+use std::collections::{HashMap, VecDeque};
+use buffer::Buffer;
+use buffer::prelude::*;
+use buffer::control::ParamValue;
+use plugins::prelude::*;
+use plugins::Factory;
+use pool::{Pool, Task};
+use {Core, CoreConfig};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+/// Identifies a node of a [`Graph`](struct.Graph.html).
+pub struct NodeId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// A connection from an output of a node to an input of another.
+pub struct Connection {
+    /// The source node.
+    pub from: NodeId,
+
+    /// Index of the source's output.
+    pub output: usize,
+
+    /// The destination node.
+    pub to: NodeId,
+
+    /// Index of the destination's input.
+    pub input: usize
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// An error in some operation done on a graph.
+pub enum GraphError {
+    /// There is no node with this id.
+    NoSuchNode(NodeId),
+
+    /// The node has no such input or output.
+    NoSuchPort(NodeId, usize),
+
+    /// The output and input of a connection have different kinds.
+    KindMismatch,
+
+    /// The connection already exists.
+    AlreadyConnected,
+
+    /// The connection does not exist.
+    NotConnected,
+
+    /// The connection would make a cycle, passing the given nodes.
+    Cycle(Vec<NodeId>),
+
+    /// No factory provides a plugin with this uuid.
+    UnknownPlugin(String),
+
+    /// A plugin returned an error.
+    Plugin(NodeId, PluginError)
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GraphError::NoSuchNode(id) => write!(f, "no node with id {}", id.0),
+            GraphError::NoSuchPort(id, port) => {
+                write!(f, "node {} has no port {}", id.0, port)
+            },
+            GraphError::KindMismatch => write!(f, "kinds of ports mismatch"),
+            GraphError::AlreadyConnected => write!(f, "already connected"),
+            GraphError::NotConnected => write!(f, "not connected"),
+            GraphError::Cycle(ref nodes) => {
+                let nodes = nodes.iter().map(|id| id.0.to_string()).collect::<Vec<_>>();
+                write!(f, "cycle through nodes {}, which needs a feedback connection",
+                       nodes.join(", "))
+            },
+            GraphError::UnknownPlugin(ref uuid) => write!(f, "unknown plugin {}", uuid),
+            GraphError::Plugin(id, ref e) => write!(f, "node {} failed: {:?}", id.0, e)
+        }
+    }
+}
+
+impl error::Error for GraphError {
+    fn description(&self) -> &str { "graph error" }
+}
+
+pub type GraphResult<T> = Result<T, GraphError>;
+
+/// Kinds of inputs and outputs described by `desc`.
+pub(crate) fn port_kinds(desc: &PluginIoDesc) -> (Vec<PluginIoKind>, Vec<PluginIoKind>) {
+    match desc.mode {
+        PluginIoMode::Inplace(ref io) => {
+            let outputs = io.mapping.iter().map(|&i| io.buffers[i].clone());
+            (io.buffers.to_vec(), outputs.collect())
+        },
+        PluginIoMode::Complex(ref io) => (io.inputs.to_vec(), io.outputs.to_vec())
+    }
+}
+
+/// Make a buffer of `kind`, or a disconnected one if `kind` is `None`.
+fn allocate(kind: Option<&PluginIoKind>, length: usize) -> PluginIoBuffer {
+    match kind {
+        Some(&PluginIoKind::Control) => PluginIoBuffer::Control(ControlBuffer::with_length(length)),
+        Some(_) => PluginIoBuffer::Audio(AudioBuffer::with_length(length)),
+        None => PluginIoBuffer::Disconnected
+    }
+}
+
+/// Add content of `source` to `buffer`.
+fn mix(buffer: &mut PluginIoBuffer, source: &PluginIoBuffer) {
+    match (buffer, source) {
+        (&mut PluginIoBuffer::Audio(ref mut audio), &PluginIoBuffer::Audio(ref source)) => {
+            audio.iter_mut().zip(source.iter()).for_each(|(a, s)| *a += *s);
+        },
+        (&mut PluginIoBuffer::Control(ref mut control), &PluginIoBuffer::Control(ref source)) => {
+            for (moment, events) in control.iter_mut().zip(source.iter()) {
+                if let Some(ref events) = *events {
+                    merge(moment, events);
+                }
+            }
+        },
+        _ => {}
+    }
+}
+
+/// Add `events` to `moment`. Only moments with events are touched, and
+/// events of several sources are put together at once.
+fn merge(moment: &mut Moment, events: &[Event]) {
+    *moment = Some(match moment.take() {
+        None => events.to_vec().into_boxed_slice(),
+        Some(current) => {
+            let mut merged = Vec::with_capacity(current.len() + events.len());
+            merged.extend(current.into_vec());
+            merged.extend_from_slice(events);
+            merged.into_boxed_slice()
+        }
+    });
+}
+
+/// Clear content of `buffer`.
+fn clear(buffer: &mut PluginIoBuffer) {
+    match *buffer {
+        PluginIoBuffer::Audio(ref mut audio) => audio.clear(),
+        PluginIoBuffer::Control(ref mut control) => control.iter_mut().for_each(|m| *m = None),
+        PluginIoBuffer::Disconnected => {}
+    }
+}
+
+/// Replace content of `buffer` with `source`, if they're of the same kind.
+fn copy(buffer: &mut PluginIoBuffer, source: &PluginIoBuffer) {
+    clear(buffer);
+    mix(buffer, source);
+}
+
+/// A delay which aligns a connection with the latest path to it's input.
+struct Compensation {
+    audio: VecDeque<Frame>,
+    control: VecDeque<Moment>
+}
+
+impl Compensation {
+    fn new(frames: usize) -> Self {
+        let mut audio = VecDeque::with_capacity(frames);
+        let mut control = VecDeque::with_capacity(frames);
+        for _ in 0..frames {
+            audio.push_back(Frame::default());
+            control.push_back(None);
+        }
+        Self { audio, control }
+    }
+
+    fn frames(&self) -> usize { self.audio.len() }
+
+    /// Add content of `source` to `buffer`, delayed. The delay line keeps
+    /// it's length, so it never grows.
+    fn mix(&mut self, buffer: &mut PluginIoBuffer, source: &PluginIoBuffer) {
+        match (buffer, source) {
+            (&mut PluginIoBuffer::Audio(ref mut audio), &PluginIoBuffer::Audio(ref source)) => {
+                for (frame, input) in audio.iter_mut().zip(source.iter()) {
+                    *frame += self.audio.pop_front().unwrap();
+                    self.audio.push_back(*input);
+                }
+            },
+            (&mut PluginIoBuffer::Control(ref mut control),
+             &PluginIoBuffer::Control(ref source)) => {
+                for (moment, input) in control.iter_mut().zip(source.iter()) {
+                    let delayed = self.control.pop_front().unwrap();
+                    self.control.push_back(input.clone());
+                    match (moment.is_some(), delayed) {
+                        (false, delayed) => { *moment = delayed; },
+                        (true, Some(ref events)) => merge(moment, events),
+                        (true, None) => {}
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+#[derive(Clone)]
+/// What is known about a node without touching it's plugin.
+pub(crate) struct Shape {
+    pub uuid: Option<String>,
+    pub desc: PluginIoDesc,
+    /// ranges of the parameters, to check values without the plugin
+    pub params: Box<[PluginParamRange]>,
+    /// parameters which allocate when they're set
+    pub allocating: Box<[bool]>,
+    pub latency: usize
+}
+
+impl Shape {
+    pub fn new(plugin: &Plugin, uuid: Option<String>) -> Self {
+        let params = plugin.get_params().iter().map(|desc| desc.kind.range())
+            .collect::<Box<[_]>>();
+        let allocating = (0..params.len()).map(|i| plugin.param_allocates(i)).collect();
+        Self {
+            uuid, desc: plugin.get_io_descriptor(), params, allocating,
+            latency: plugin.latency()
+        }
+    }
+
+    /// Fetch everything from `plugin` again, keeping the uuid.
+    pub fn update(&mut self, plugin: &Plugin) {
+        let uuid = self.uuid.take();
+        *self = Shape::new(plugin, uuid);
+    }
+}
+
+#[derive(Clone, Default)]
+/// Nodes and connections of a graph, which are enough to check edits and
+/// compile a [`Plan`](struct.Plan.html).
+pub(crate) struct Layout {
+    pub nodes: Vec<Option<Shape>>,
+    pub connections: Vec<Connection>,
+    pub feedback: Vec<Connection>
+}
+
+impl Layout {
+    pub fn ids<'a>(&'a self) -> impl Iterator<Item = NodeId> + 'a {
+        self.nodes.iter().enumerate().filter(|&(_, n)| n.is_some()).map(|(i, _)| NodeId(i))
+    }
+
+    pub fn shape(&self, id: NodeId) -> GraphResult<&Shape> {
+        match self.nodes.get(id.0) {
+            Some(&Some(ref shape)) => Ok(shape),
+            _ => Err(GraphError::NoSuchNode(id))
+        }
+    }
+
+    pub fn shape_mut(&mut self, id: NodeId) -> GraphResult<&mut Shape> {
+        match self.nodes.get_mut(id.0) {
+            Some(&mut Some(ref mut shape)) => Ok(shape),
+            _ => Err(GraphError::NoSuchNode(id))
+        }
+    }
+
+    /// Put `shape` as node `id`, which must not be in use.
+    pub fn insert(&mut self, id: NodeId, shape: Shape) {
+        debug_assert!(self.nodes.get(id.0).map_or(true, |n| n.is_none()));
+        while self.nodes.len() <= id.0 {
+            self.nodes.push(None);
+        }
+        self.nodes[id.0] = Some(shape);
+    }
+
+    /// Find a path from `from` to `to`, following connections. Each node is
+    /// visited once, remembering the node it's reached from, and targets of
+    /// each node are listed once, so the search is linear in the size of the
+    /// graph.
+    fn path(&self, from: NodeId, to: NodeId) -> Option<Vec<NodeId>> {
+        let mut targets: HashMap<NodeId, Vec<NodeId>> = HashMap::new();
+        for connection in self.connections.iter() {
+            targets.entry(connection.from).or_insert_with(Vec::new).push(connection.to);
+        }
+
+        let mut parents = HashMap::new();
+        let mut queue = VecDeque::new();
+        parents.insert(from, from);
+        queue.push_back(from);
+
+        while let Some(id) = queue.pop_front() {
+            if id == to {
+                let mut path = vec![id];
+                while *path.last().unwrap() != from {
+                    let parent = parents[path.last().unwrap()];
+                    path.push(parent);
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            for &target in targets.get(&id).into_iter().flat_map(|t| t.iter()) {
+                if !parents.contains_key(&target) {
+                    parents.insert(target, id);
+                    queue.push_back(target);
+                }
+            }
+        }
+        None
+    }
+
+    /// Check whether `connection` can be added as a feedback connection.
+    pub fn check_feedback(&self, connection: Connection) -> GraphResult<()> {
+        let output = {
+            let kinds = port_kinds(&self.shape(connection.from)?.desc).1;
+            kinds.get(connection.output).cloned()
+                .ok_or(GraphError::NoSuchPort(connection.from, connection.output))?
+        };
+        let input = {
+            let kinds = port_kinds(&self.shape(connection.to)?.desc).0;
+            kinds.get(connection.input).cloned()
+                .ok_or(GraphError::NoSuchPort(connection.to, connection.input))?
+        };
+
+        match (output, input) {
+            (PluginIoKind::Either, _) | (_, PluginIoKind::Either) => {},
+            (ref o, ref i) if o == i => {},
+            _ => { return Err(GraphError::KindMismatch); }
+        }
+
+        if self.connections.contains(&connection) || self.feedback.contains(&connection) {
+            return Err(GraphError::AlreadyConnected);
+        }
+        Ok(())
+    }
+
+    /// Check whether `connection` can be added.
+    pub fn check(&self, connection: Connection) -> GraphResult<()> {
+        self.check_feedback(connection)?;
+        if let Some(mut path) = self.path(connection.to, connection.from) {
+            path.push(connection.to);
+            return Err(GraphError::Cycle(path));
+        }
+        Ok(())
+    }
+
+    /// Remove `connection`, whether it's a feedback connection or not.
+    pub fn remove(&mut self, connection: Connection) -> GraphResult<()> {
+        if let Some(index) = self.connections.iter().position(|c| *c == connection) {
+            self.connections.remove(index);
+        } else if let Some(index) = self.feedback.iter().position(|c| *c == connection) {
+            self.feedback.remove(index);
+        } else {
+            return Err(GraphError::NotConnected);
+        }
+        Ok(())
+    }
+
+    /// Get all connections from or to node `id`.
+    pub fn connections_of(&self, id: NodeId) -> Vec<Connection> {
+        self.connections.iter().chain(self.feedback.iter()).cloned()
+            .filter(|c| c.from == id || c.to == id).collect()
+    }
+
+    /// Get the change to inform the plugin of node `id` about the status of
+    /// a port.
+    pub fn port_change(&self, id: NodeId, is_output: bool, index: usize)
+        -> GraphResult<PluginIoChange> {
+        let connected = self.connections.iter().chain(self.feedback.iter()).any(|c| {
+            if is_output { c.from == id && c.output == index }
+            else { c.to == id && c.input == index }
+        });
+
+        let (inputs, outputs) = port_kinds(&self.shape(id)?.desc);
+        let kinds = if is_output { outputs } else { inputs };
+        let status = if connected { kinds.get(index).cloned() } else { None };
+        Ok(PluginIoChange { is_output, index, status })
+    }
+
+    /// Sort nodes, compute latency compensation and allocate buffers.
+    pub fn compile(&self, config: &CoreConfig) -> Plan {
+        // topological sort
+        let mut incoming: HashMap<NodeId, usize> = self.ids().map(|id| (id, 0)).collect();
+        for connection in self.connections.iter() {
+            *incoming.get_mut(&connection.to).unwrap() += 1;
+        }
+        let mut ready = self.ids().filter(|id| incoming[id] == 0).collect::<VecDeque<_>>();
+        let mut order = Vec::new();
+        while let Some(id) = ready.pop_front() {
+            order.push(id);
+            for connection in self.connections.iter().filter(|c| c.from == id) {
+                let count = incoming.get_mut(&connection.to).unwrap();
+                *count -= 1;
+                if *count == 0 { ready.push_back(connection.to); }
+            }
+        }
+
+        // arrival time of signals at each node, and delays to align them
+        let mut arrival: HashMap<NodeId, usize> = HashMap::new();
+        let mut compensations = HashMap::new();
+        let mut latency = 0;
+        for &id in order.iter() {
+            let shape = self.nodes[id.0].as_ref().unwrap();
+            let inputs = self.connections.iter().filter(|c| c.to == id);
+            let ready = inputs.clone().map(|c| arrival[&c.from]).max().unwrap_or(0);
+
+            for connection in inputs {
+                let delay = ready - arrival[&connection.from];
+                if delay > 0 {
+                    compensations.insert(*connection, Compensation::new(delay));
+                }
+            }
+
+            arrival.insert(id, ready + shape.latency);
+            latency = latency.max(ready + shape.latency);
+        }
+
+        // buffers of connected ports
+        let length = config.buffer_size;
+        let mut slots = self.nodes.iter().map(|_| None).collect::<Vec<_>>();
+        for &id in order.iter() {
+            let shape = self.nodes[id.0].as_ref().unwrap();
+            let (inputs, outputs) = port_kinds(&shape.desc);
+            let all = || self.connections.iter().chain(self.feedback.iter());
+            let input = |i| all().any(|c| c.to == id && c.input == i);
+            let output = |o| all().any(|c| c.from == id && c.output == o);
+
+            let slot = match shape.desc.mode {
+                PluginIoMode::Inplace(ref io) => {
+                    let buffers = (0..inputs.len()).map(|i| {
+                        let used = input(i) || (0..outputs.len())
+                            .any(|o| io.mapping[o] == i && output(o));
+                        allocate(if used { Some(&inputs[i]) } else { None }, length)
+                    }).collect::<Vec<_>>();
+                    let inputs: PluginIo = box [];
+                    Slot {
+                        inputs,
+                        outputs: buffers.into_boxed_slice(),
+                        mapping: io.mapping.clone(),
+                        inplace: true,
+                        latency: shape.latency
+                    }
+                },
+                PluginIoMode::Complex(_) => {
+                    let buffers = (0..inputs.len()).map(|i| {
+                        allocate(if input(i) { Some(&inputs[i]) } else { None }, length)
+                    });
+                    let inputs = buffers.collect::<Vec<_>>().into_boxed_slice();
+                    let buffers = (0..outputs.len()).map(|o| {
+                        allocate(if output(o) { Some(&outputs[o]) } else { None }, length)
+                    });
+                    let outputs = buffers.collect::<Vec<_>>().into_boxed_slice();
+                    let mapping = (0..outputs.len()).collect::<Vec<_>>().into_boxed_slice();
+                    Slot { inputs, outputs, mapping, inplace: false, latency: shape.latency }
+                }
+            };
+            slots[id.0] = Some(slot);
+        }
+
+        let mut dependencies = self.nodes.iter().map(|_| 0).collect::<Vec<_>>();
+        for connection in self.connections.iter() {
+            dependencies[connection.to.0] += 1;
+        }
+        let waiting = dependencies.clone();
+        let ready = VecDeque::with_capacity(order.len());
+
+        // outputs of sources of feedback connections in the previous cycle
+        let feedback = self.feedback.iter().map(|&connection| {
+            let kinds = port_kinds(&self.nodes[connection.from.0].as_ref().unwrap().desc).1;
+            (connection, allocate(kinds.get(connection.output), length))
+        }).collect();
+
+        let mut times = self.nodes.iter().map(|_| 0).collect::<Vec<_>>();
+        for (id, time) in arrival {
+            times[id.0] = time;
+        }
+
+        let connections = self.connections.clone();
+        Plan {
+            order, connections, feedback, compensations, slots, latency, arrival: times,
+            dependencies, waiting, ready
+        }
+    }
+}
+
+/// Buffers of a node in a plan.
+struct Slot {
+    inputs: PluginIo,
+    outputs: PluginIo,
+    /// index of the buffer holding each output
+    mapping: Box<[usize]>,
+    inplace: bool,
+    latency: usize
+}
+
+/// Processing of a node, which may be run on a worker of the pool.
+pub(crate) struct Job {
+    id: NodeId,
+    plugin: Box<Plugin>,
+    slot: Slot,
+    result: PluginResult<()>
+}
+
+impl Task for Job {
+    fn run(&mut self) {
+        self.result = self.plugin.process(&self.slot.inputs, &mut self.slot.outputs);
+        if self.result.is_err() {
+            self.slot.outputs.iter_mut().for_each(clear);
+        }
+    }
+}
+
+/// A change of a node noticed while processing a plan.
+pub(crate) enum Change<'a> {
+    /// The plugin failed with `InvalidArgument`, so it's IO configuration
+    /// may be changed.
+    Invalid(&'a Plugin),
+
+    /// The plugin reports a new latency.
+    Latency(usize)
+}
+
+#[derive(Default)]
+/// A compiled graph: the processing order, latency compensation and buffers
+/// of the nodes. A plan doesn't own the plugins, so it can be compiled away
+/// from them.
+pub(crate) struct Plan {
+    order: Vec<NodeId>,
+    connections: Vec<Connection>,
+    feedback: Vec<(Connection, PluginIoBuffer)>,
+    compensations: HashMap<Connection, Compensation>,
+    slots: Vec<Option<Slot>>,
+    latency: usize,
+    /// arrival time of signals at each node
+    arrival: Vec<usize>,
+    /// number of connections to each node
+    dependencies: Vec<usize>,
+    /// connections to each node from nodes which are not processed yet
+    waiting: Vec<usize>,
+    /// nodes which all of their sources are processed
+    ready: VecDeque<NodeId>
+}
+
+impl Plan {
+    /// Get the total latency of the graph, which is the latency of the
+    /// latest path.
+    pub fn latency(&self) -> usize { self.latency }
+
+    /// Get buffer of `output` of node `id`.
+    fn output_mut(&mut self, id: NodeId, output: usize) -> Option<&mut PluginIoBuffer> {
+        let slot = self.slots.get_mut(id.0)?.as_mut()?;
+        let index = *slot.mapping.get(output)?;
+        slot.outputs.get_mut(index)
+    }
+
+    /// Get buffer of `input` of node `id`.
+    fn input(&self, id: NodeId, input: usize) -> Option<&PluginIoBuffer> {
+        let slot = self.slots.get(id.0)?.as_ref()?;
+        if slot.inplace { slot.outputs.get(input) } else { slot.inputs.get(input) }
+    }
+
+    /// Take over delayed content of connections from the `old` plan, where
+    /// the delay is not changed.
+    pub fn inherit(&mut self, old: &mut Plan) {
+        for (connection, compensation) in self.compensations.iter_mut() {
+            if let Some(old) = old.compensations.get_mut(connection) {
+                if old.frames() == compensation.frames() {
+                    mem::swap(old, compensation);
+                }
+            }
+        }
+        for &mut (connection, ref mut buffer) in self.feedback.iter_mut() {
+            if let Some(old) = old.feedback.iter_mut().find(|f| f.0 == connection) {
+                mem::swap(&mut old.1, buffer);
+            }
+        }
+    }
+
+    /// Update the total latency to the latencies of nodes in `layout`,
+    /// without allocating. Connections keep the compensation they're
+    /// compiled with.
+    fn retime(&mut self, layout: &Layout) {
+        let Plan { ref order, ref connections, ref mut arrival, ref mut latency, .. } = *self;
+        *latency = 0;
+        for &id in order.iter() {
+            let inputs = connections.iter().filter(|c| c.to == id);
+            let ready = inputs.map(|c| arrival[c.from.0]).max().unwrap_or(0);
+            arrival[id.0] = ready + layout.shape(id).map_or(0, |shape| shape.latency);
+            *latency = (*latency).max(arrival[id.0]);
+        }
+    }
+
+    /// Process one buffer of `plugins`, indexed by node id. Nodes missing in
+    /// `plugins` are skipped, with silent outputs. `changed` is told about
+    /// changes of plugins, and returns whether it's taken the change; if
+    /// not, it's told again in the next cycle. Errors of plugins don't stop processing, but
+    /// the outputs of the failed node are cleared and the error of the
+    /// earliest failed node is returned.
+    ///
+    /// Nodes which all of their sources are processed are handed to workers
+    /// of `pool`, or processed on this thread if none is idle. Inputs are
+    /// always gathered on this thread in the order of connections, so the
+    /// output doesn't depend on the number of threads.
+    pub fn process(&mut self, plugins: &mut [Option<Box<Plugin>>], pool: &mut Pool<Job>,
+                   changed: &mut FnMut(NodeId, Change) -> bool) -> GraphResult<()> {
+        self.waiting.clone_from(&self.dependencies);
+        self.ready.clear();
+        for &id in self.order.iter() {
+            if self.dependencies[id.0] == 0 {
+                self.ready.push_back(id);
+            }
+        }
+
+        let mut failed = None;
+        let mut running = 0;
+        loop {
+            while let Some(job) = pool.finished() {
+                running -= 1;
+                self.finish(job, plugins, changed, &mut failed);
+            }
+
+            let id = match self.ready.pop_front() {
+                Some(id) => id,
+                None if running == 0 => break,
+                None => {
+                    // nothing is ready until a worker is done
+                    if let Some(job) = pool.wait() {
+                        running -= 1;
+                        self.finish(job, plugins, changed, &mut failed);
+                    }
+                    continue;
+                }
+            };
+            let plugin = match plugins.get_mut(id.0).and_then(Option::take) {
+                Some(plugin) => plugin,
+                None => {
+                    self.slots[id.0].as_mut().unwrap().outputs.iter_mut().for_each(clear);
+                    self.release(id);
+                    continue;
+                }
+            };
+            let mut slot = self.slots[id.0].take().unwrap();
+            self.gather(id, &mut slot);
+
+            match pool.submit(Job { id, plugin, slot, result: Ok(()) }) {
+                Ok(()) => running += 1,
+                Err(mut job) => {
+                    job.run();
+                    self.finish(job, plugins, changed, &mut failed);
+                }
+            }
+        }
+
+        // keep outputs for feedback connections of the next cycle
+        for &mut (connection, ref mut buffer) in self.feedback.iter_mut() {
+            if let Some(source) = self.slots[connection.from.0].as_ref() {
+                copy(buffer, &source.outputs[source.mapping[connection.output]]);
+            }
+        }
+
+        match failed {
+            Some((_, e)) => Err(e),
+            None => Ok(())
+        }
+    }
+
+    /// Mix outputs of the sources of node `id` into it's input buffers.
+    fn gather(&mut self, id: NodeId, slot: &mut Slot) {
+        let count = if slot.inplace { slot.outputs.len() } else { slot.inputs.len() };
+        for i in 0..count {
+            let buffer = if slot.inplace { &mut slot.outputs[i] } else { &mut slot.inputs[i] };
+            clear(buffer);
+
+            for connection in self.connections.iter().filter(|c| c.to == id && c.input == i) {
+                let source = self.slots[connection.from.0].as_ref().unwrap();
+                let output = &source.outputs[source.mapping[connection.output]];
+                match self.compensations.get_mut(connection) {
+                    Some(compensation) => compensation.mix(buffer, output),
+                    None => mix(buffer, output)
+                }
+            }
+
+            let feedback = self.feedback.iter().filter(|f| f.0.to == id && f.0.input == i);
+            for &(_, ref delayed) in feedback {
+                mix(buffer, delayed);
+            }
+        }
+    }
+
+    /// Put back a processed node, and see which nodes are ready after it.
+    fn finish(&mut self, job: Job, plugins: &mut [Option<Box<Plugin>>],
+              changed: &mut FnMut(NodeId, Change) -> bool,
+              failed: &mut Option<(usize, GraphError)>) {
+        let Job { id, plugin, mut slot, result } = job;
+
+        if let Err(e) = result {
+            if e == PluginError::InvalidArgument {
+                changed(id, Change::Invalid(&*plugin));
+            }
+            let position = self.order.iter().position(|&n| n == id).unwrap();
+            if failed.as_ref().map_or(true, |&(p, _)| position < p) {
+                *failed = Some((position, GraphError::Plugin(id, e)));
+            }
+        }
+
+        // latency changes are compensated from the next cycle
+        let latency = plugin.latency();
+        if latency != slot.latency && changed(id, Change::Latency(latency)) {
+            slot.latency = latency;
+        }
+
+        plugins[id.0] = Some(plugin);
+        self.slots[id.0] = Some(slot);
+        self.release(id);
+    }
+
+    fn release(&mut self, id: NodeId) {
+        for connection in self.connections.iter().filter(|c| c.from == id) {
+            let waiting = &mut self.waiting[connection.to.0];
+            *waiting -= 1;
+            if *waiting == 0 {
+                self.ready.push_back(connection.to);
+            }
+        }
+    }
+}
+
+/// A graph of plugins, which is processed one buffer at a time.
+pub struct Graph {
+    config: CoreConfig,
+    layout: Layout,
+    plugins: Vec<Option<Box<Plugin>>>,
+    plan: Plan,
+    pool: Pool<Job>,
+    dirty: bool
+}
 
 impl Graph {
-    pub fn new(_: &Core) -> Self {
-        Self {}
+    pub fn new(core: &Core) -> Self {
+        Self {
+            config: core.config.clone(),
+            layout: Layout::default(),
+            plugins: Vec::new(),
+            plan: Plan::default(),
+            pool: Pool::new(core.config.threads),
+            dirty: true
+        }
+    }
+
+    /// Get config of the core the graph is processed in.
+    pub fn config(&self) -> &CoreConfig { &self.config }
+
+    /// Add `plugin` as a new node. The plugin is initialized and informed
+    /// of the core config.
+    pub fn add_node(&mut self, plugin: Box<Plugin>) -> GraphResult<NodeId> {
+        self.insert(plugin, None)
+    }
+
+    /// Create the plugin of `uuid` from `factory` and add it as a new
+    /// node. Unlike [`add_node()`], the node remembers it's plugin uuid, so
+    /// it can be saved in a project.
+    ///
+    /// [`add_node()`]: #method.add_node
+    pub fn create_node(&mut self, factory: &mut Factory, uuid: &str) -> GraphResult<NodeId> {
+        let id = NodeId(self.layout.nodes.len());
+        let desc = factory.get_plugins().iter().find(|desc| desc.uuid == uuid).cloned()
+            .ok_or(GraphError::UnknownPlugin(uuid.to_owned()))?;
+        let plugin = factory.create_plugin(desc.id).map_err(|e| GraphError::Plugin(id, e))?;
+        self.insert(plugin, Some(desc.uuid))
+    }
+
+    fn insert(&mut self, plugin: Box<Plugin>, uuid: Option<String>) -> GraphResult<NodeId> {
+        let id = NodeId(self.layout.nodes.len());
+        self.restore_node(id, plugin, uuid).map(|_| id).map_err(|(e, _)| e)
+    }
+
+    /// Put a removed plugin back as node `id`, which must not be in use.
+    /// The plugin is initialized again, since it's terminated on removal.
+    /// If it fails to initialize, it's given back with the error.
+    pub(crate) fn restore_node(&mut self, id: NodeId, mut plugin: Box<Plugin>,
+                               uuid: Option<String>)
+        -> Result<(), (GraphError, Box<Plugin>)> {
+        if let Err(e) = plugin.initialize() {
+            return Err((GraphError::Plugin(id, e), plugin));
+        }
+        plugin.core_changed(&self.config);
+
+        self.layout.insert(id, Shape::new(&*plugin, uuid));
+        while self.plugins.len() <= id.0 {
+            self.plugins.push(None);
+        }
+        self.plugins[id.0] = Some(plugin);
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Remove node `id` with all of it's connections, and get it's plugin
+    /// back, terminated.
+    pub fn remove_node(&mut self, id: NodeId) -> GraphResult<Box<Plugin>> {
+        self.layout.shape(id)?;
+
+        for connection in self.layout.connections_of(id) {
+            self.disconnect(connection)?;
+        }
+
+        self.layout.nodes[id.0] = None;
+        let mut plugin = self.plugins[id.0].take().unwrap();
+        plugin.terminate();
+        self.dirty = true;
+        Ok(plugin)
+    }
+
+    /// Get ids of all nodes.
+    pub fn nodes<'a>(&'a self) -> impl Iterator<Item = NodeId> + 'a {
+        self.layout.ids()
+    }
+
+    /// Get plugin of node `id`.
+    pub fn plugin(&self, id: NodeId) -> GraphResult<&(Plugin + 'static)> {
+        match self.plugins.get(id.0) {
+            Some(&Some(ref plugin)) => Ok(&**plugin),
+            _ => Err(GraphError::NoSuchNode(id))
+        }
+    }
+
+    /// Get plugin of node `id` mutably. If it's IO configuration or
+    /// latency is changed, [`refresh()`] should be called.
+    ///
+    /// [`refresh()`]: #method.refresh
+    pub fn plugin_mut(&mut self, id: NodeId) -> GraphResult<&mut (Plugin + 'static)> {
+        self.dirty = true;
+        match self.plugins.get_mut(id.0) {
+            Some(&mut Some(ref mut plugin)) => Ok(&mut **plugin),
+            _ => Err(GraphError::NoSuchNode(id))
+        }
+    }
+
+    /// Get uuid of the plugin of node `id`, if it was created by
+    /// [`create_node()`].
+    ///
+    /// [`create_node()`]: #method.create_node
+    pub fn uuid(&self, id: NodeId) -> GraphResult<Option<&str>> {
+        self.layout.shape(id).map(|shape| shape.uuid.as_ref().map(|uuid| &uuid[..]))
+    }
+
+    /// Get IO descriptor of node `id`.
+    pub fn io_descriptor(&self, id: NodeId) -> GraphResult<&PluginIoDesc> {
+        self.layout.shape(id).map(|shape| &shape.desc)
+    }
+
+    /// Fetch IO descriptor and latency of node `id` again.
+    pub fn refresh(&mut self, id: NodeId) -> GraphResult<()> {
+        let Graph { ref mut layout, ref plugins, .. } = *self;
+        match plugins.get(id.0) {
+            Some(&Some(ref plugin)) => layout.shape_mut(id)?.update(&**plugin),
+            _ => { return Err(GraphError::NoSuchNode(id)); }
+        }
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Set parameter `index` of node `id` to `value`. Unlike editing the
+    /// plugin by [`plugin_mut()`], this doesn't make the graph compile
+    /// again, since parameters don't change IO configurations.
+    ///
+    /// [`plugin_mut()`]: #method.plugin_mut
+    pub fn set_param(&mut self, id: NodeId, index: usize, value: ParamValue)
+        -> GraphResult<()> {
+        match self.plugins.get_mut(id.0) {
+            Some(&mut Some(ref mut plugin)) => {
+                plugin.set_param(index, value).map_err(|e| GraphError::Plugin(id, e))
+            },
+            _ => Err(GraphError::NoSuchNode(id))
+        }
+    }
+
+    /// Inform all plugins about a new core config.
+    pub(crate) fn core_changed(&mut self, config: &CoreConfig) {
+        self.config = config.clone();
+        let ids = self.nodes().collect::<Vec<_>>();
+        for id in ids {
+            self.plugins[id.0].as_mut().unwrap().core_changed(config);
+            self.refresh(id).unwrap();
+        }
+        self.dirty = true;
+    }
+
+    /// Process the graph on `threads` threads from now on.
+    pub(crate) fn set_threads(&mut self, threads: usize) {
+        self.config.threads = threads;
+        self.pool = Pool::new(threads);
+        self.dirty = true;
+    }
+
+    /// Fill buffer of `output` of node `id` with `source`, for the next
+    /// cycle of the compiled plan. Nothing is done if the output is not
+    /// connected.
+    pub(crate) fn set_output(&mut self, id: NodeId, output: usize, source: &PluginIoBuffer) {
+        if let Some(buffer) = self.plan.output_mut(id, output) {
+            copy(buffer, source);
+        }
+    }
+
+    /// Fill `buffer` with what `input` of node `id` received in the last
+    /// cycle.
+    pub(crate) fn get_input(&self, id: NodeId, input: usize, buffer: &mut PluginIoBuffer) {
+        match self.plan.input(id, input) {
+            Some(source) => copy(buffer, source),
+            None => clear(buffer)
+        }
+    }
+
+    /// Get all connections, except feedback connections.
+    pub fn connections(&self) -> &[Connection] { &self.layout.connections }
+
+    /// Get all feedback connections.
+    pub fn feedback(&self) -> &[Connection] { &self.layout.feedback }
+
+    /// Get whether `connection` is a feedback connection.
+    pub fn is_feedback(&self, connection: Connection) -> bool {
+        self.layout.feedback.contains(&connection)
+    }
+
+    /// Inform the plugin of node `id` about the status of a port.
+    fn port_changed(&mut self, id: NodeId, is_output: bool, index: usize)
+        -> GraphResult<()> {
+        let change = self.layout.port_change(id, is_output, index)?;
+        let plugin = self.plugins[id.0].as_mut().unwrap();
+        match plugin.io_changed(change) {
+            Ok(()) => Ok(()),
+            Err(PluginError::InvalidArgument) => {
+                self.layout.shape_mut(id)?.update(&**plugin);
+                Ok(())
+            },
+            Err(e) => Err(GraphError::Plugin(id, e))
+        }
+    }
+
+    /// Add `connection` to the graph.
+    pub fn connect(&mut self, connection: Connection) -> GraphResult<()> {
+        self.layout.check(connection)?;
+        self.layout.connections.push(connection);
+        self.dirty = true;
+
+        let result = self.port_changed(connection.from, true, connection.output)
+            .and_then(|_| self.port_changed(connection.to, false, connection.input));
+        if result.is_err() {
+            self.layout.connections.retain(|c| *c != connection);
+        }
+        result
+    }
+
+    /// Add `connection` as a feedback connection, which may make a cycle.
+    /// It's destination receives output of the source in the previous
+    /// cycle.
+    pub fn connect_feedback(&mut self, connection: Connection) -> GraphResult<()> {
+        self.layout.check_feedback(connection)?;
+        self.layout.feedback.push(connection);
+        self.dirty = true;
+
+        let result = self.port_changed(connection.from, true, connection.output)
+            .and_then(|_| self.port_changed(connection.to, false, connection.input));
+        if result.is_err() {
+            self.layout.feedback.retain(|c| *c != connection);
+        }
+        result
+    }
+
+    /// Remove `connection` from the graph, whether it's a feedback
+    /// connection or not.
+    pub fn disconnect(&mut self, connection: Connection) -> GraphResult<()> {
+        self.layout.remove(connection)?;
+        self.dirty = true;
+
+        self.port_changed(connection.from, true, connection.output)?;
+        self.port_changed(connection.to, false, connection.input)
+    }
+
+    /// Get the frames `connection` is delayed by to compensate latency.
+    pub fn compensation(&mut self, connection: Connection) -> Option<usize> {
+        self.compile();
+        self.plan.compensations.get(&connection).map(Compensation::frames)
+    }
+
+    /// Get the total latency of the graph, which is the latency of the
+    /// latest path.
+    pub fn latency(&mut self) -> usize {
+        self.compile();
+        self.plan.latency
+    }
+
+    /// Compile the plan again, if the graph is changed.
+    pub(crate) fn compile(&mut self) {
+        if self.dirty {
+            self.dirty = false;
+            let mut plan = self.layout.compile(&self.config);
+            plan.inherit(&mut self.plan);
+            self.plan = plan;
+        }
+    }
+
+    /// Take the graph apart into it's config, layout, plugins, compiled
+    /// plan and pool, without terminating the plugins.
+    pub(crate) fn split(mut self)
+        -> (CoreConfig, Layout, Vec<Option<Box<Plugin>>>, Plan, Pool<Job>) {
+        self.compile();
+        let layout = mem::replace(&mut self.layout, Layout::default());
+        let plugins = mem::replace(&mut self.plugins, Vec::new());
+        let plan = mem::replace(&mut self.plan, Plan::default());
+        let pool = mem::replace(&mut self.pool, Pool::new(1));
+        (self.config.clone(), layout, plugins, plan, pool)
+    }
+
+    /// Finish a run of the graph, waiting once more for each plugin which
+    /// is a [`WaitablePlugin`], so it can flush what it has rendered. The
+    /// first error is returned.
+    ///
+    /// [`WaitablePlugin`]: ../plugins/trait.WaitablePlugin.html
+    pub fn stop(&mut self) -> GraphResult<()> {
+        let mut result = Ok(());
+        for (i, plugin) in self.plugins.iter_mut().enumerate() {
+            let plugin = match plugin.as_mut().and_then(|p| p.as_waitable()) {
+                Some(plugin) => plugin,
+                None => { continue; }
+            };
+            let waited = plugin.wait().map_err(|e| GraphError::Plugin(NodeId(i), e));
+            result = result.and(waited);
+        }
+        result
+    }
+
+    /// Process one buffer of all nodes. Errors of plugins don't stop
+    /// processing, but the outputs of the failed node are cleared and the
+    /// first error is returned.
+    pub fn process(&mut self) -> GraphResult<()> {
+        self.compile();
+        self.process_compiled()
+    }
+
+    /// Process one buffer with the plan compiled last, so no buffers are
+    /// allocated; only control events of several sources, or of delayed
+    /// connections, are put into new event lists. Changes since then are
+    /// not processed yet, except that latency changes of plugins are added
+    /// to the [`compiled_latency()`] right away.
+    ///
+    /// [`compiled_latency()`]: #method.compiled_latency
+    pub(crate) fn process_compiled(&mut self) -> GraphResult<()> {
+        let Graph {
+            ref mut layout, ref mut plugins, ref mut plan, ref mut pool, ref mut dirty, ..
+        } = *self;
+        let mut retime = false;
+        let result = plan.process(plugins, pool, &mut |id, change| {
+            let shape = layout.shape_mut(id).unwrap();
+            match change {
+                Change::Invalid(plugin) => shape.update(plugin),
+                Change::Latency(latency) => { shape.latency = latency; retime = true; }
+            }
+            *dirty = true;
+            true
+        });
+        if retime {
+            plan.retime(layout);
+        }
+        result
+    }
+
+    /// Get the total latency of the plan compiled last, without compiling
+    /// the graph again.
+    pub(crate) fn compiled_latency(&self) -> usize { self.plan.latency }
+
+    /// Terminate all plugins and release them. The graph stays with
+    /// empty nodes, which are skipped.
+    pub(crate) fn terminate(&mut self) {
+        for slot in self.plugins.iter_mut() {
+            if let Some(mut plugin) = slot.take() {
+                plugin.terminate();
+            }
+        }
+    }
+}
+
+impl Drop for Graph {
+    fn drop(&mut self) {
+        self.terminate();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use plugins::core::{CorePlugin, Mixer, Reverb};
+    use plugins::special::{Effect, Generator};
+    use super::*;
+
+    /// Generates an impulse on the first frame.
+    struct Impulse(bool);
+
+    impl Generator for Impulse {
+        fn generate(&mut self, _control: Option<&ControlBuffer>,
+                    audio: &mut AudioBuffer) -> PluginResult<()> {
+            audio.clear();
+            if !self.0 { audio[0] = (1.).into(); self.0 = true; }
+            Ok(())
+        }
+    }
+
+    impl_generator_for!(Impulse);
+
+    /// Delays audio by a number of frames, which are reported as latency.
+    struct Latent(VecDeque<Frame>);
+
+    impl Effect for Latent {
+        fn process_audio(&mut self, audio: &mut AudioBuffer) -> PluginResult<()> {
+            for frame in audio.iter_mut() {
+                self.0.push_back(*frame);
+                *frame = self.0.pop_front().unwrap();
+            }
+            Ok(())
+        }
+    }
+
+    impl_effect_for!(Latent, {
+        fn latency(&self) -> usize { self.0.len() }
+    });
+
+    fn latent(frames: usize) -> Box<Plugin> {
+        let delay = (0..frames).map(|_| Frame::default()).collect();
+        box Latent(delay)
+    }
+
+    /// Records audio of it's input.
+    struct Capture(Arc<Mutex<Vec<Sample>>>);
+
+    impl Plugin for Capture {
+        fn get_io_descriptor(&self) -> PluginIoDesc {
+            PluginIoDesc {
+                mode: PluginIoMode::Complex(PluginComplexIo {
+                    inputs: box [PluginIoKind::Audio],
+                    outputs: box []
+                }),
+                inputs: box ["Audio".to_owned()],
+                outputs: box []
+            }
+        }
+
+        fn process(&mut self, inputs: &PluginIo, _outputs: &mut PluginIo)
+            -> PluginResult<()> {
+            if let PluginIoBuffer::Audio(ref audio) = inputs[0] {
+                self.0.lock().unwrap().extend(audio.iter().map(|f| f[0]));
+            }
+            Ok(())
+        }
+    }
+
+    fn graph() -> (Graph, Arc<Mutex<Vec<Sample>>>) {
+        let core = Core::new(CoreConfig { buffer_size: 8, ..Default::default() });
+        (Graph::new(&core), Arc::new(Mutex::new(Vec::new())))
+    }
+
+    fn connect(graph: &mut Graph, from: NodeId, to: NodeId, input: usize) -> Connection {
+        let connection = Connection { from, output: 0, to, input };
+        graph.connect(connection).unwrap();
+        connection
+    }
+
+    #[test]
+    fn test_graph_cycle_search() {
+        // layers of two nodes, each connected to both nodes of the next one;
+        // connecting from the last layer, the nodes below each connection
+        // are reached through 2^layers paths
+        let (mut graph, _) = graph();
+        let layers = (0..40).map(|_| {
+            [graph.add_node(latent(0)).unwrap(), graph.add_node(latent(0)).unwrap()]
+        }).collect::<Vec<_>>();
+        for pair in layers.windows(2).rev() {
+            for &from in pair[0].iter() {
+                for &to in pair[1].iter() {
+                    connect(&mut graph, from, to, 0);
+                }
+            }
+        }
+
+        let (first, last) = (layers[0][0], layers[39][1]);
+        match graph.connect(Connection { from: last, output: 0, to: first, input: 0 }) {
+            Err(GraphError::Cycle(path)) => {
+                assert_eq!(path.len(), 41);
+                assert_eq!((path[0], path[39], path[40]), (first, last, first));
+            },
+            _ => panic!("cycle isn't found")
+        }
+    }
+
+    #[test]
+    fn test_graph_summing() {
+        let (mut graph, captured) = graph();
+        let impulse = graph.add_node(box Impulse(false)).unwrap();
+        let delayed = graph.add_node(latent(3)).unwrap();
+        let capture = graph.add_node(box Capture(captured.clone())).unwrap();
+
+        let direct = connect(&mut graph, impulse, capture, 0);
+        connect(&mut graph, impulse, delayed, 0);
+        connect(&mut graph, delayed, capture, 0);
+
+        assert_eq!(graph.latency(), 3);
+        assert_eq!(graph.compensation(direct), Some(3));
+
+        graph.process().unwrap();
+        assert_eq!(&captured.lock().unwrap()[..], &[0., 0., 0., 2., 0., 0., 0., 0.]);
+    }
+
+    #[test]
+    fn test_graph_mixer_compensation() {
+        let (mut graph, captured) = graph();
+        let core = Core::new(Default::default());
+        let impulse = graph.add_node(box Impulse(false)).unwrap();
+        let first = graph.add_node(latent(2)).unwrap();
+        let second = graph.add_node(latent(5)).unwrap();
+        let mixer = graph.add_node(box Mixer::new(&core)).unwrap();
+        let capture = graph.add_node(box Capture(captured.clone())).unwrap();
+
+        connect(&mut graph, impulse, first, 0);
+        connect(&mut graph, impulse, second, 0);
+        let early = connect(&mut graph, first, mixer, 0);
+        connect(&mut graph, second, mixer, 1);
+        connect(&mut graph, mixer, capture, 0);
+
+        assert_eq!(graph.compensation(early), Some(3));
+        graph.process().unwrap();
+
+        // both paths arrive at the same frame
+        let captured = captured.lock().unwrap();
+        let peaks = captured.iter().enumerate().filter(|&(_, s)| *s != 0.)
+            .map(|(i, _)| i).collect::<Vec<_>>();
+        assert_eq!(peaks, [5]);
+        assert!(captured[5] > 1.);
+    }
+
+    #[test]
+    fn test_graph_latency_change() {
+        let (mut graph, captured) = graph();
+        let impulse = graph.add_node(box Impulse(false)).unwrap();
+        let delayed = graph.add_node(latent(0)).unwrap();
+        let capture = graph.add_node(box Capture(captured.clone())).unwrap();
+
+        let direct = connect(&mut graph, impulse, capture, 0);
+        connect(&mut graph, impulse, delayed, 0);
+        connect(&mut graph, delayed, capture, 0);
+        assert_eq!(graph.compensation(direct), None);
+
+        graph.remove_node(delayed).unwrap();
+        assert_eq!(graph.connections(), &[direct]);
+
+        let delayed = graph.add_node(latent(2)).unwrap();
+        connect(&mut graph, impulse, delayed, 0);
+        connect(&mut graph, delayed, capture, 0);
+        assert_eq!(graph.compensation(direct), Some(2));
+
+        let cycle = graph.connect(Connection { from: capture, output: 0, to: impulse, input: 0 });
+        assert_eq!(cycle, Err(GraphError::NoSuchPort(capture, 0)));
+        let cycle = graph.connect(Connection { from: delayed, output: 0, to: delayed, input: 0 });
+        assert_eq!(cycle, Err(GraphError::Cycle(vec![delayed, delayed])));
+    }
+
+    #[test]
+    fn test_graph_parallel() {
+        let render = |threads| {
+            let core = Core::new(CoreConfig { buffer_size: 64, threads, ..Default::default() });
+            let mut graph = Graph::new(&core);
+            let captured = Arc::new(Mutex::new(Vec::new()));
+            let impulse = graph.add_node(box Impulse(false)).unwrap();
+            let mixer = graph.add_node(box Mixer::new(&core)).unwrap();
+            for i in 0..8 {
+                let reverb = graph.add_node(box Reverb::new(&core)).unwrap();
+                let delayed = graph.add_node(latent(i)).unwrap();
+                connect(&mut graph, impulse, reverb, 0);
+                connect(&mut graph, reverb, delayed, 0);
+                connect(&mut graph, delayed, mixer, i);
+            }
+            let capture = graph.add_node(box Capture(captured.clone())).unwrap();
+            connect(&mut graph, mixer, capture, 0);
+
+            for _ in 0..32 {
+                graph.process().unwrap();
+            }
+            let captured = captured.lock().unwrap();
+            captured.clone()
+        };
+
+        let single = render(1);
+        assert_eq!(single.len(), 32 * 64);
+        assert!(single.iter().filter(|&&s| s != 0.).count() > 8);
+        assert_eq!(render(4), single);
+    }
+
+    #[test]
+    fn test_graph_feedback() {
+        let (mut graph, captured) = graph();
+        let impulse = graph.add_node(box Impulse(false)).unwrap();
+        let first = graph.add_node(latent(0)).unwrap();
+        let second = graph.add_node(latent(0)).unwrap();
+        let capture = graph.add_node(box Capture(captured.clone())).unwrap();
+
+        connect(&mut graph, impulse, first, 0);
+        connect(&mut graph, first, second, 0);
+        connect(&mut graph, second, capture, 0);
+
+        let back = Connection { from: second, output: 0, to: first, input: 0 };
+        assert_eq!(graph.connect(back), Err(GraphError::Cycle(vec![first, second, first])));
+        graph.connect_feedback(back).unwrap();
+        assert_eq!(graph.connect_feedback(back), Err(GraphError::AlreadyConnected));
+        assert_eq!(graph.feedback(), &[back]);
+
+        // the impulse circulates, one buffer later each time
+        for _ in 0..3 {
+            graph.process().unwrap();
+        }
+        let expected = (0..24).map(|i| if i % 8 == 0 { 1. } else { 0. }).collect::<Vec<_>>();
+        assert_eq!(&captured.lock().unwrap()[..], &expected[..]);
+
+        graph.disconnect(back).unwrap();
+        assert!(graph.feedback().is_empty());
+        graph.process().unwrap();
+        assert!(captured.lock().unwrap()[24..].iter().all(|&s| s == 0.));
     }
 }
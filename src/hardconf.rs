@@ -1,3 +1,5 @@
 pub const TWO_POW_ONE_TWELFTH: f64 = 1.05946309435929526456182529494634170;
 
+pub const REFERENCE_PITCH: f64 = 440.;
+
 pub const OVERDAW_URL: &'static str = "https://mamins1376.github.io/overdaw/";
@@ -0,0 +1,573 @@
+//! The History Module
+//!
+//! Edits of a [`Graph`] as reversible [`Command`]s. A [`History`] applies
+//! commands, keeps what's needed to revert them in an undo stack, and
+//! records everything in a [`Journal`].
+//!
+//! The journal is written one entry per line, so a host can append each
+//! entry to a file as it happens. After a crash, replaying the journal onto
+//! the graph of a fresh core rebuilds the session, including it's undo
+//! history.
+//!
+//! [`Graph`]: ../graph/struct.Graph.html
+//! [`Command`]: enum.Command.html
+//! [`History`]: struct.History.html
+//! [`Journal`]: struct.Journal.html
+
+use std::fmt;
+use buffer::control::ParamValue;
+use graph::{Connection, Graph, GraphError, GraphResult, NodeId};
+use json::Value;
+use plugins::prelude::*;
+use plugins::Factory;
+use plugins::preset::{read_value, write_value};
+
+#[derive(Debug, Clone, PartialEq)]
+/// An edit of a graph.
+pub enum Command {
+    /// Add a node of the plugin of the given uuid.
+    AddNode(String),
+
+    /// Remove a node with it's connections.
+    RemoveNode(NodeId),
+
+    /// Add a connection.
+    Connect(Connection),
+
+    /// Add a feedback connection.
+    ConnectFeedback(Connection),
+
+    /// Remove a connection.
+    Disconnect(Connection),
+
+    /// Set value of a parameter of a node.
+    SetParam(NodeId, usize, ParamValue)
+}
+
+/// Parameter values of some nodes.
+type Snapshot = Vec<(NodeId, usize, ParamValue)>;
+
+/// Get values of all parameters of `ids`. Plugins may drop parameters when
+/// a port is disconnected (e.g. channels of the mixer), so they are
+/// restored after connecting it back.
+fn snapshot(graph: &Graph, ids: &[NodeId]) -> Snapshot {
+    let mut snapshot = Vec::new();
+    for &id in ids.iter() {
+        if let Ok(plugin) = graph.plugin(id) {
+            let count = plugin.get_params().len();
+            let values = (0..count).filter_map(|i| plugin.get_param(i).map(|v| (id, i, v)));
+            snapshot.extend(values);
+        }
+    }
+    snapshot
+}
+
+fn restore(graph: &mut Graph, snapshot: &Snapshot) {
+    for &(id, index, ref value) in snapshot.iter() {
+        let _ = set_param(graph, id, index, value.clone());
+    }
+}
+
+/// A removed node, which can be put back.
+struct Stash {
+    plugin: Box<Plugin>,
+    uuid: Option<String>,
+    // connections of the node, and whether they're feedback connections
+    connections: Vec<(Connection, bool)>,
+    // parameters of the nodes it was connected to
+    neighbors: Snapshot
+}
+
+fn take(graph: &mut Graph, id: NodeId) -> GraphResult<Stash> {
+    let uuid = graph.uuid(id)?.map(|uuid| uuid.to_owned());
+    let connections = graph.connections().iter().chain(graph.feedback().iter()).cloned()
+        .filter(|c| c.from == id || c.to == id)
+        .map(|c| (c, graph.is_feedback(c))).collect::<Vec<_>>();
+    let neighbors = connections.iter().map(|&(c, _)| if c.from == id { c.to } else { c.from })
+        .filter(|&n| n != id).collect::<Vec<_>>();
+    let neighbors = snapshot(graph, &neighbors);
+    let plugin = graph.remove_node(id)?;
+    Ok(Stash { plugin, uuid, connections, neighbors })
+}
+
+/// Put the stashed node back. On failure, the node is taken out again and
+/// stays in `stash`.
+fn put(graph: &mut Graph, id: NodeId, stash: &mut Option<Stash>) -> GraphResult<()> {
+    let Stash { plugin, uuid, connections, neighbors } = stash.take().unwrap();
+    if let Err((e, plugin)) = graph.restore_node(id, plugin, uuid.clone()) {
+        *stash = Some(Stash { plugin, uuid, connections, neighbors });
+        return Err(e);
+    }
+
+    let result = connections.iter()
+        .map(|&(connection, feedback)| connect(graph, connection, feedback))
+        .collect::<GraphResult<()>>();
+    if let Err(e) = result {
+        // removing the node drops the connections made so far
+        let plugin = graph.remove_node(id)?;
+        restore(graph, &neighbors);
+        *stash = Some(Stash { plugin, uuid, connections, neighbors });
+        return Err(e);
+    }
+    restore(graph, &neighbors);
+    Ok(())
+}
+
+fn connect(graph: &mut Graph, connection: Connection, feedback: bool) -> GraphResult<()> {
+    if feedback { graph.connect_feedback(connection) } else { graph.connect(connection) }
+}
+
+fn set_param(graph: &mut Graph, id: NodeId, index: usize, value: ParamValue)
+    -> GraphResult<()> {
+    graph.plugin_mut(id)?.set_param(index, value).map_err(|e| GraphError::Plugin(id, e))
+}
+
+/// An applied command, with what's needed to revert it. Plugins of nodes
+/// which are out of the graph are kept in their stash.
+enum Edit {
+    Added(NodeId, Option<Stash>),
+    Removed(NodeId, Option<Stash>),
+    Connected(Connection, bool),
+    Disconnected(Connection, bool, Snapshot),
+    ParamSet(NodeId, usize, ParamValue, ParamValue)
+}
+
+impl Edit {
+    fn revert(&mut self, graph: &mut Graph) -> GraphResult<()> {
+        match *self {
+            Edit::Added(id, ref mut stash) => { *stash = Some(take(graph, id)?); Ok(()) },
+            Edit::Removed(id, ref mut stash) => put(graph, id, stash),
+            Edit::Connected(connection, _) => graph.disconnect(connection),
+            Edit::Disconnected(connection, feedback, ref params) => {
+                connect(graph, connection, feedback)?;
+                restore(graph, params);
+                Ok(())
+            },
+            Edit::ParamSet(id, index, ref old, _) => set_param(graph, id, index, old.clone())
+        }
+    }
+
+    fn reapply(&mut self, graph: &mut Graph) -> GraphResult<()> {
+        match *self {
+            Edit::Added(id, ref mut stash) => put(graph, id, stash),
+            Edit::Removed(id, ref mut stash) => { *stash = Some(take(graph, id)?); Ok(()) },
+            Edit::Connected(connection, feedback) => connect(graph, connection, feedback),
+            Edit::Disconnected(connection, _, _) => graph.disconnect(connection),
+            Edit::ParamSet(id, index, _, ref new) => set_param(graph, id, index, new.clone())
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// An entry of a [`Journal`](struct.Journal.html).
+pub enum Entry {
+    /// A command was applied.
+    Apply(Command),
+
+    /// The last step was undone.
+    Undo,
+
+    /// The last undone step was redone.
+    Redo,
+
+    /// A group was started.
+    Begin,
+
+    /// A group was ended.
+    End
+}
+
+impl Entry {
+    /// Read an entry from a line of a journal.
+    pub fn parse(line: &str) -> Option<Self> {
+        let value = Value::parse(line).ok()?;
+        match value.as_str() {
+            Some("undo") => return Some(Entry::Undo),
+            Some("redo") => return Some(Entry::Redo),
+            Some("begin") => return Some(Entry::Begin),
+            Some("end") => return Some(Entry::End),
+            Some(_) => return None,
+            None => {}
+        }
+
+        let (kind, args) = match value.as_object() {
+            Some(&[(ref kind, ref args)]) => (kind, args),
+            _ => { return None; }
+        };
+        let node = |v: &Value| v.as_u64().map(|id| NodeId(id as usize));
+        let connection = |v: &Value| match v.as_array() {
+            Some(&[ref from, ref output, ref to, ref input]) => Some(Connection {
+                from: node(from)?, output: output.as_u64()? as usize,
+                to: node(to)?, input: input.as_u64()? as usize
+            }),
+            _ => None
+        };
+
+        let command = match &kind[..] {
+            "add" => Command::AddNode(args.as_str()?.to_owned()),
+            "remove" => Command::RemoveNode(node(args)?),
+            "connect" => Command::Connect(connection(args)?),
+            "feedback" => Command::ConnectFeedback(connection(args)?),
+            "disconnect" => Command::Disconnect(connection(args)?),
+            "set" => match args.as_array() {
+                Some(&[ref id, ref index, ref value]) => {
+                    Command::SetParam(node(id)?, index.as_u64()? as usize, read_value(value)?)
+                },
+                _ => { return None; }
+            },
+            _ => { return None; }
+        };
+        Some(Entry::Apply(command))
+    }
+}
+
+impl fmt::Display for Entry {
+    /// Write the entry on a single line.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let node = |id: NodeId| Value::number(id.0);
+        let connection = |c: &Connection| Value::Array(vec![
+            node(c.from), Value::number(c.output), node(c.to), Value::number(c.input)
+        ]);
+
+        let (kind, args) = match *self {
+            Entry::Undo => return write!(f, "\"undo\""),
+            Entry::Redo => return write!(f, "\"redo\""),
+            Entry::Begin => return write!(f, "\"begin\""),
+            Entry::End => return write!(f, "\"end\""),
+            Entry::Apply(Command::AddNode(ref uuid)) => ("add", Value::String(uuid.clone())),
+            Entry::Apply(Command::RemoveNode(id)) => ("remove", node(id)),
+            Entry::Apply(Command::Connect(ref c)) => ("connect", connection(c)),
+            Entry::Apply(Command::ConnectFeedback(ref c)) => ("feedback", connection(c)),
+            Entry::Apply(Command::Disconnect(ref c)) => ("disconnect", connection(c)),
+            Entry::Apply(Command::SetParam(id, index, ref value)) => {
+                ("set", Value::Array(vec![node(id), Value::number(index), write_value(value)]))
+            }
+        };
+        write!(f, "{}", Value::Object(vec![(kind.to_owned(), args)]).to_compact())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// Entries of a [`History`](struct.History.html), in the order they
+/// happened.
+pub struct Journal(pub Vec<Entry>);
+
+impl Journal {
+    /// Read a journal, one entry per line. A malformed last line, e.g. of
+    /// an interrupted write, is ignored; malformed lines before it are
+    /// errors, and their index is returned.
+    pub fn parse(text: &str) -> Result<Self, usize> {
+        let lines = text.lines().filter(|line| !line.trim().is_empty()).collect::<Vec<_>>();
+        let mut entries = Vec::with_capacity(lines.len());
+        for (i, line) in lines.iter().enumerate() {
+            match Entry::parse(line) {
+                Some(entry) => entries.push(entry),
+                None if i + 1 == lines.len() => {},
+                None => { return Err(i); }
+            }
+        }
+        Ok(Journal(entries))
+    }
+
+    /// Apply the entries onto `graph`, which should be the graph of a
+    /// fresh core so node ids match, and get the resulting history.
+    pub fn replay(&self, graph: &mut Graph, factories: &mut [&mut Factory])
+        -> GraphResult<History> {
+        let mut history = History::default();
+        for entry in self.0.iter() {
+            match *entry {
+                Entry::Apply(ref command) => {
+                    history.apply(graph, factories, command.clone())?;
+                },
+                Entry::Undo => { history.undo(graph)?; },
+                Entry::Redo => { history.redo(graph)?; },
+                Entry::Begin => history.begin(),
+                Entry::End => history.end()
+            }
+        }
+        Ok(history)
+    }
+}
+
+impl fmt::Display for Journal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.iter().map(|entry| writeln!(f, "{}", entry)).collect()
+    }
+}
+
+#[derive(Default)]
+/// Undo and redo stacks of edits of a graph. Each step is a group of edits
+/// which are undone together.
+///
+/// # Examples
+/// ```
+/// use overcore::{Core, CoreConfig};
+/// use overcore::buffer::control::ParamValue;
+/// use overcore::graph::Graph;
+/// use overcore::history::{Command, History};
+/// use overcore::plugins::core::{CoreFactory, CorePlugin, Mixer};
+///
+/// let core = Core::new(CoreConfig::default());
+/// let mut factory = CoreFactory::new(&core);
+/// let mut graph = Graph::new(&core);
+/// let mut history = History::default();
+///
+/// let add = Command::AddNode(Mixer::get_uuid().to_owned());
+/// let mixer = history.apply(&mut graph, &mut [&mut factory], add).unwrap().unwrap();
+/// let set = Command::SetParam(mixer, 0, ParamValue::Float(-6.));
+/// history.apply(&mut graph, &mut [], set).unwrap();
+///
+/// history.undo(&mut graph).unwrap();
+/// assert_eq!(graph.plugin(mixer).unwrap().get_param(0), Some(ParamValue::Float(0.)));
+/// history.undo(&mut graph).unwrap();
+/// assert!(graph.plugin(mixer).is_err());
+/// ```
+pub struct History {
+    undo: Vec<Vec<Edit>>,
+    redo: Vec<Vec<Edit>>,
+    // the open group and it's nesting depth
+    group: Vec<Edit>,
+    depth: usize,
+    journal: Journal
+}
+
+impl History {
+    /// Apply `command` to `graph`. Nodes are created from the first of
+    /// `factories` which provides their uuid. On success, the redo stack is
+    /// cleared and the id of the added node, if any, is returned.
+    pub fn apply(&mut self, graph: &mut Graph, factories: &mut [&mut Factory],
+                 command: Command) -> GraphResult<Option<NodeId>> {
+        let (edit, added) = match command {
+            Command::AddNode(ref uuid) => {
+                let factory = factories.iter_mut()
+                    .find(|f| f.get_plugins().iter().any(|desc| desc.uuid == *uuid))
+                    .ok_or(GraphError::UnknownPlugin(uuid.clone()))?;
+                let id = graph.create_node(&mut **factory, uuid)?;
+                (Edit::Added(id, None), Some(id))
+            },
+            Command::RemoveNode(id) => (Edit::Removed(id, Some(take(graph, id)?)), None),
+            Command::Connect(connection) => {
+                graph.connect(connection)?;
+                (Edit::Connected(connection, false), None)
+            },
+            Command::ConnectFeedback(connection) => {
+                graph.connect_feedback(connection)?;
+                (Edit::Connected(connection, true), None)
+            },
+            Command::Disconnect(connection) => {
+                let params = snapshot(graph, &[connection.from, connection.to]);
+                let feedback = graph.is_feedback(connection);
+                graph.disconnect(connection)?;
+                (Edit::Disconnected(connection, feedback, params), None)
+            },
+            Command::SetParam(id, index, ref value) => {
+                let old = graph.plugin(id)?.get_param(index)
+                    .ok_or(GraphError::Plugin(id, PluginError::InvalidArgument))?;
+                set_param(graph, id, index, value.clone())?;
+                (Edit::ParamSet(id, index, old, value.clone()), None)
+            }
+        };
+
+        self.redo.clear();
+        self.group.push(edit);
+        if self.depth == 0 {
+            self.undo.push(self.group.drain(..).collect());
+        }
+        self.journal.0.push(Entry::Apply(command));
+        Ok(added)
+    }
+
+    /// Start a group; commands applied until the matching [`end()`] are
+    /// undone in one step. Groups may be nested.
+    ///
+    /// [`end()`]: #method.end
+    pub fn begin(&mut self) {
+        self.depth += 1;
+        self.journal.0.push(Entry::Begin);
+    }
+
+    /// End the current group.
+    pub fn end(&mut self) {
+        if self.depth == 0 {
+            return;
+        }
+        self.depth -= 1;
+        if self.depth == 0 && !self.group.is_empty() {
+            self.undo.push(self.group.drain(..).collect());
+        }
+        self.journal.0.push(Entry::End);
+    }
+
+    /// Undo the last step, ending any open groups first. Returns `false` if
+    /// there is nothing to undo. If an edit fails to revert, the ones
+    /// reverted before it are applied again and the step is kept.
+    pub fn undo(&mut self, graph: &mut Graph) -> GraphResult<bool> {
+        while self.depth > 0 {
+            self.end();
+        }
+        let mut step = match self.undo.pop() {
+            Some(step) => step,
+            None => { return Ok(false); }
+        };
+        for i in (0..step.len()).rev() {
+            if let Err(e) = step[i].revert(graph) {
+                for edit in step[i + 1..].iter_mut() {
+                    let _ = edit.reapply(graph);
+                }
+                self.undo.push(step);
+                return Err(e);
+            }
+        }
+        self.redo.push(step);
+        self.journal.0.push(Entry::Undo);
+        Ok(true)
+    }
+
+    /// Redo the last undone step. Returns `false` if there is nothing to
+    /// redo. Like [`undo()`](#method.undo), a failed step is rolled back
+    /// and kept.
+    pub fn redo(&mut self, graph: &mut Graph) -> GraphResult<bool> {
+        let mut step = match self.redo.pop() {
+            Some(step) => step,
+            None => { return Ok(false); }
+        };
+        for i in 0..step.len() {
+            if let Err(e) = step[i].reapply(graph) {
+                for edit in step[..i].iter_mut().rev() {
+                    let _ = edit.revert(graph);
+                }
+                self.redo.push(step);
+                return Err(e);
+            }
+        }
+        self.undo.push(step);
+        self.journal.0.push(Entry::Redo);
+        Ok(true)
+    }
+
+    #[inline]
+    /// Check whether there is a step to undo.
+    pub fn can_undo(&self) -> bool { !self.undo.is_empty() || !self.group.is_empty() }
+
+    #[inline]
+    /// Check whether there is a step to redo.
+    pub fn can_redo(&self) -> bool { !self.redo.is_empty() }
+
+    #[inline]
+    /// Get the journal of everything done.
+    pub fn journal(&self) -> &Journal { &self.journal }
+}
+
+#[cfg(test)]
+mod tests {
+    use Core;
+    use plugins::core::{CoreFactory, CorePlugin, FunctionGenerator, Mixer};
+    use super::*;
+
+    fn session(core: &Core) -> (Graph, History, NodeId, NodeId) {
+        let mut factory = CoreFactory::new(core);
+        let mut graph = Graph::new(core);
+        let mut history = History::default();
+
+        let mut apply = |graph: &mut Graph, history: &mut History, command| {
+            history.apply(graph, &mut [&mut factory], command).unwrap()
+        };
+        history.begin();
+        let uuid = FunctionGenerator::get_uuid().to_owned();
+        let generator = apply(&mut graph, &mut history, Command::AddNode(uuid)).unwrap();
+        let uuid = Mixer::get_uuid().to_owned();
+        let mixer = apply(&mut graph, &mut history, Command::AddNode(uuid)).unwrap();
+        let connection = Connection { from: generator, output: 0, to: mixer, input: 0 };
+        apply(&mut graph, &mut history, Command::Connect(connection));
+        history.end();
+        apply(&mut graph, &mut history, Command::SetParam(mixer, 1, ParamValue::Float(-3.)));
+        (graph, history, generator, mixer)
+    }
+
+    #[test]
+    fn test_history_undo_redo() {
+        let core = Core::new(Default::default());
+        let (mut graph, mut history, generator, mixer) = session(&core);
+
+        history.apply(&mut graph, &mut [], Command::RemoveNode(generator)).unwrap();
+        assert!(graph.connections().is_empty());
+
+        // the removed node comes back with it's connection and parameters
+        history.undo(&mut graph).unwrap();
+        assert_eq!(graph.connections().len(), 1);
+        assert_eq!(graph.plugin(mixer).unwrap().get_param(1), Some(ParamValue::Float(-3.)));
+
+        history.undo(&mut graph).unwrap();
+        assert_eq!(graph.plugin(mixer).unwrap().get_param(1), Some(ParamValue::Float(0.)));
+        history.undo(&mut graph).unwrap();
+        assert_eq!(graph.nodes().count(), 0);
+        assert_eq!(history.undo(&mut graph), Ok(false));
+
+        history.redo(&mut graph).unwrap();
+        assert_eq!(graph.nodes().collect::<Vec<_>>(), [generator, mixer]);
+        assert_eq!(graph.connections().len(), 1);
+        assert!(history.can_redo());
+
+        let set = Command::SetParam(mixer, 0, ParamValue::Float(100.));
+        assert!(history.apply(&mut graph, &mut [], set).is_err());
+        assert!(history.can_redo());
+    }
+
+    #[test]
+    fn test_history_failed_step() {
+        let core = Core::new(Default::default());
+        let (mut graph, mut history, generator, mixer) = session(&core);
+        let connection = Connection { from: generator, output: 0, to: mixer, input: 0 };
+
+        history.begin();
+        history.apply(&mut graph, &mut [], Command::Disconnect(connection)).unwrap();
+        history.apply(&mut graph, &mut [], Command::SetParam(mixer, 0, ParamValue::Float(-6.)))
+            .unwrap();
+        history.end();
+
+        // the connection is made behind the history, so it can't be undone
+        graph.connect(connection).unwrap();
+        assert_eq!(history.undo(&mut graph), Err(GraphError::AlreadyConnected));
+        assert_eq!(graph.plugin(mixer).unwrap().get_param(0), Some(ParamValue::Float(-6.)));
+        assert!(!history.can_redo());
+
+        graph.disconnect(connection).unwrap();
+        assert_eq!(history.undo(&mut graph), Ok(true));
+        assert_eq!(graph.plugin(mixer).unwrap().get_param(0), Some(ParamValue::Float(0.)));
+
+        graph.disconnect(connection).unwrap();
+        assert_eq!(history.redo(&mut graph), Err(GraphError::NotConnected));
+        assert!(history.can_redo());
+        graph.connect(connection).unwrap();
+        assert_eq!(history.redo(&mut graph), Ok(true));
+        assert!(graph.connections().is_empty());
+    }
+
+    #[test]
+    fn test_history_journal_replay() {
+        let core = Core::new(Default::default());
+        let (mut graph, mut history, _, mixer) = session(&core);
+        history.undo(&mut graph).unwrap();
+        history.undo(&mut graph).unwrap();
+        history.redo(&mut graph).unwrap();
+        history.apply(&mut graph, &mut [], Command::SetParam(mixer, 0, ParamValue::Float(-1.)))
+            .unwrap();
+
+        // an interrupted write of the last line is ignored
+        let text = format!("{}{{\"set\": [1,", history.journal());
+        let journal = Journal::parse(&text).unwrap();
+        assert_eq!(&journal, history.journal());
+
+        let mut factory = CoreFactory::new(&core);
+        let mut replayed = Graph::new(&core);
+        let mut restored = journal.replay(&mut replayed, &mut [&mut factory]).unwrap();
+        assert_eq!(replayed.connections(), graph.connections());
+        assert_eq!(replayed.plugin(mixer).unwrap().get_param(0), Some(ParamValue::Float(-1.)));
+
+        // the undo history is restored too
+        restored.undo(&mut replayed).unwrap();
+        restored.undo(&mut replayed).unwrap();
+        assert_eq!(replayed.nodes().count(), 0);
+        assert_eq!(Journal::parse("\"undo\"\nbad\n\"redo\""), Err(1));
+    }
+}
@@ -1,11 +1,80 @@
-use std::ops::{Index, Deref};
+use std::ops::{Index, Deref, Add, Sub, Mul};
 use buffer::audio::Sample;
 
 const TWO_PI: f64 = 2. * ::std::f64::consts::PI;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Interpolation { Floor, Nearest, Linear, Poly, Spline }
 
-pub struct Interpolator(Interpolation, Box<[Sample]>);
+impl Interpolation {
+    /// Interpolate the value at `x` of a sequence which it's known values
+    /// at integer indexes are given by `y`. `Poly` is a 4-point, 3rd-order
+    /// Lagrange polynomial, and `Spline` is a Catmull-Rom spline, which
+    /// unlike `Poly` has a continuous slope at known points.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::interpolate::Interpolation;
+    ///
+    /// let ramp = |i: isize| i as f64 * 2.;
+    /// assert_eq!(Interpolation::Floor.apply(1.75, &ramp), 2.);
+    /// assert_eq!(Interpolation::Linear.apply(1.75, &ramp), 3.5);
+    /// assert_eq!(Interpolation::Poly.apply(1.75, &ramp), 3.5);
+    /// assert_eq!(Interpolation::Spline.apply(1.75, &ramp), 3.5);
+    ///
+    /// let square = |i: isize| (i * i) as f64;
+    /// assert_eq!(Interpolation::Spline.apply(2., &square), 4.);
+    /// assert_eq!(Interpolation::Spline.apply(2.5, &square), 6.25);
+    /// ```
+    pub fn apply<T, F>(&self, x: f64, y: F) -> T
+        where T: Copy + From<f64> + Add<Output = T> + Sub<Output = T> +
+                 Mul<Output = T>, F: Fn(isize) -> T {
+        let (x_l, x_d) = (x.floor(), x - x.floor());
+        let i = x_l as isize;
+
+        match *self {
+            Interpolation::Floor => y(i),
+            Interpolation::Nearest => y(x.round() as isize),
+            Interpolation::Linear => {
+                let (y_l, y_h) = (y(i), y(i + 1));
+                y_l + T::from(x_d) * (y_h - y_l)
+            },
+            Interpolation::Poly => {
+                let (d_p, d_m, d_mm) = (x_d + 1., x_d - 1., x_d - 2.);
+                let c = [
+                    -x_d * d_m * d_mm / 6., d_p * d_m * d_mm / 2.,
+                    -d_p * x_d * d_mm / 2., d_p * x_d * d_m / 6.
+                ];
+                y(i - 1) * c[0].into() + y(i) * c[1].into() +
+                    y(i + 1) * c[2].into() + y(i + 2) * c[3].into()
+            },
+            Interpolation::Spline => {
+                let (d_2, d_3) = (x_d * x_d, x_d * x_d * x_d);
+                let c = [
+                    (-x_d + 2. * d_2 - d_3) / 2., (2. - 5. * d_2 + 3. * d_3) / 2.,
+                    (x_d + 4. * d_2 - 3. * d_3) / 2., (d_3 - d_2) / 2.
+                ];
+                y(i - 1) * c[0].into() + y(i) * c[1].into() +
+                    y(i + 1) * c[2].into() + y(i + 2) * c[3].into()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Domain of the function tabulated by an [`Interpolator`].
+/// [`Interpolator`]: struct.Interpolator.html
+pub enum Domain {
+    /// The function is periodic with period of 2pi.
+    Periodic,
+
+    /// The function is known in the closed range of given minimum and
+    /// maximum, and is constant out of it.
+    Range(f64, f64)
+}
+
+#[derive(Debug, Clone)]
+pub struct Interpolator(Interpolation, Box<[Sample]>, Domain);
 
 impl Interpolator {
     /// Create new function interpolator.
@@ -26,9 +95,52 @@ impl Interpolator {
         let known = (0..n).map(|i| i as f64 * TWO_PI / n as f64)
             .map(f).collect::<Vec<_>>().into_boxed_slice();
 
-        Self { 0: i, 1: known }
+        Self { 0: i, 1: known, 2: Domain::Periodic }
     }
 
+    /// Create new function interpolator in range [`min`, `max`].
+    ///
+    /// `f` is called exactly `n` times, on evenly spaced points of the range
+    /// including both ends. Out of the range, f(x) is f(min) or f(max).
+    ///
+    /// # Panics
+    /// Panics if n < 2 or the range is empty.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::interpolate::{Interpolation, Interpolator};
+    ///
+    /// let clip = |x: f64| x.max(-1.).min(1.);
+    /// let interpolator = Interpolator::with_range(Interpolation::Linear, 5, -2., 2., clip);
+    ///
+    /// assert_eq!(interpolator.f(0.5), 0.5);
+    /// assert_eq!(interpolator.f(1.5), 1.);
+    /// assert_eq!(interpolator.f(-10.), -1.);
+    /// ```
+    pub fn with_range<F>(i: Interpolation, n: usize, min: f64, max: f64, f: F)
+        -> Self where F: FnMut(f64) -> Sample {
+        assert!(n >= 2 && max > min);
+
+        let step = (max - min) / (n - 1) as f64;
+        let known = (0..n).map(|i| min + i as f64 * step).map(f);
+        Self::from_points(i, min, max, known.collect::<Vec<_>>().into_boxed_slice())
+    }
+
+    /// Create new interpolator from known `points`, which are evenly spaced
+    /// in range [`min`, `max`] including both ends.
+    ///
+    /// # Panics
+    /// Panics if there are less than 2 points or the range is empty.
+    pub fn from_points(i: Interpolation, min: f64, max: f64, points: Box<[Sample]>)
+        -> Self {
+        assert!(points.len() >= 2 && max > min);
+        Self { 0: i, 1: points, 2: Domain::Range(min, max) }
+    }
+
+    #[inline]
+    /// Get domain of the interpolated function.
+    pub fn domain(&self) -> Domain { self.2 }
+
     /// Interpolate f(x).
     ///
     /// # Examples
@@ -42,29 +154,28 @@ impl Interpolator {
     /// assert_eq!(interpolator.f(FRAC_PI_4), 0.5);
     /// ```
     pub fn f(&self, x: f64) -> Sample {
-        let x = x * self.1.len() as f64 / TWO_PI;
-
-        match self.0 {
-            Interpolation::Floor => self[x.floor() as isize],
-            Interpolation::Nearest => self[x.round() as isize],
-            Interpolation::Linear => {
-                let x_l = x.floor();
-                let y_l = self[x_l as isize];
-                let y_h = self[x_l as isize + 1];
-                let (x_d, y_d) = (x - x_l, y_h - y_l);
-                y_l + x_d * y_d
-            },
-            _ => unimplemented!()
-        }
+        let x = match self.2 {
+            Domain::Periodic => x * self.1.len() as f64 / TWO_PI,
+            Domain::Range(min, max) => {
+                let x = x.max(min).min(max);
+                (x - min) * (self.1.len() - 1) as f64 / (max - min)
+            }
+        };
+        self.0.apply(x, |i| self[i])
     }
 }
 
 impl Index<isize> for Interpolator {
     type Output = Sample;
 
-    /// Get f(i) like f is a periodic function.
+    /// Get f(i) like f is a periodic function, or f of the nearest known
+    /// point if the domain is a range.
     fn index(&self, i: isize) -> &Sample {
         let (mut i, len) = (i, self.1.len() as isize);
+        if let Domain::Range(..) = self.2 {
+            return &self.1[i.max(0).min(len - 1) as usize];
+        }
+
         loop {
             if i >= len {
                 i -= len;
@@ -139,4 +250,42 @@ mod tests {
         assert_eq!(int.f(-FRAC_PI_4), -0.5);
         assert_eq!(int.f(5. * FRAC_PI_4), -0.5);
     }
+
+    #[test]
+    fn test_interpolator_f_poly() {
+        use std::f64::consts::FRAC_PI_4;
+        let int = Interpolator::new(Interpolation::Poly, 4, |x| x.sin());
+
+        assert!((int.f(2. * FRAC_PI_4) - 1.).abs() < 1e-10);
+        assert!((int.f(FRAC_PI_4) - 0.625).abs() < 1e-10);
+        assert!((int.f(-FRAC_PI_4) + 0.625).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_interpolator_f_spline() {
+        use std::f64::consts::FRAC_PI_4;
+        let int = Interpolator::new(Interpolation::Spline, 4, |x| x.sin());
+
+        assert!((int.f(2. * FRAC_PI_4) - 1.).abs() < 1e-10);
+        assert!((int.f(FRAC_PI_4) - 0.625).abs() < 1e-10);
+        assert!((int.f(-FRAC_PI_4) + 0.625).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_interpolator_range() {
+        let int = Interpolator::with_range(Interpolation::Linear, 3, -1., 1., |x| x * x);
+
+        assert_eq!(int.domain(), Domain::Range(-1., 1.));
+        assert_eq!(int[-5], 1.);
+        assert_eq!(int[1], 0.);
+        assert_eq!(int[5], 1.);
+
+        assert_eq!(int.f(-0.5), 0.5);
+        assert_eq!(int.f(1.), 1.);
+        assert_eq!(int.f(3.), 1.);
+
+        let int = Interpolator::from_points(Interpolation::Nearest, 0., 2., box [0., 1., 4.]);
+        assert_eq!(int.f(1.4), 1.);
+        assert_eq!(int.f(1.6), 4.);
+    }
 }
@@ -0,0 +1,329 @@
+//! The JSON Module
+//!
+//! A minimal JSON reader and writer for project, preset and journal files. Numbers
+//! are kept as their text, so integers are not rounded through `f64`, and
+//! keys of objects keep their order.
+
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Boolean(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>)
+}
+
+impl Value {
+    /// Make a number of `value`. Floats are written in their shortest form
+    /// which parses back to the same value. JSON has no NaN or infinities,
+    /// so they are written as `null`.
+    pub fn number<T: fmt::Debug>(value: T) -> Self {
+        let number = format!("{:?}", value);
+        match &*number {
+            "NaN" | "inf" | "-inf" => Value::Null,
+            _ => Value::Number(number)
+        }
+    }
+
+    /// Get value of `key`, if `self` is an object.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match *self {
+            Value::Object(ref fields) => fields.iter().find(|f| f.0 == key).map(|f| &f.1),
+            _ => None
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        if let Value::Boolean(b) = *self { Some(b) } else { None }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        if let Value::String(ref s) = *self { Some(s) } else { None }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        if let Value::Array(ref a) = *self { Some(a) } else { None }
+    }
+
+    pub fn as_object(&self) -> Option<&[(String, Value)]> {
+        if let Value::Object(ref o) = *self { Some(o) } else { None }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        if let Value::Number(ref n) = *self { n.parse().ok() } else { None }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        if let Value::Number(ref n) = *self { n.parse().ok() } else { None }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        if let Value::Number(ref n) = *self { n.parse().ok() } else { None }
+    }
+
+    /// Parse a whole document. On failure, the byte offset of the error is
+    /// returned.
+    pub fn parse(text: &str) -> Result<Self, usize> {
+        let mut parser = Parser { text: text.as_bytes(), position: 0, depth: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.position == text.len() { Ok(value) } else { Err(parser.position) }
+    }
+
+    /// Write the value on a single line, without whitespace.
+    pub fn to_compact(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out, None);
+        out
+    }
+
+    /// Write the value to `out`, indented by `indent` levels, or on a
+    /// single line if it's `None`.
+    fn write(&self, out: &mut String, indent: Option<usize>) {
+        let pad = |out: &mut String, indent: Option<usize>| match indent {
+            Some(indent) => for _ in 0..indent { out.push_str("  "); },
+            None => {}
+        };
+        let (open, separator, close) = match indent {
+            Some(_) => ("\n", ",\n", "\n"),
+            None => ("", ",", "")
+        };
+        let inner = indent.map(|i| i + 1);
+
+        match *self {
+            Value::Null => out.push_str("null"),
+            Value::Boolean(b) => out.push_str(if b { "true" } else { "false" }),
+            Value::Number(ref n) => out.push_str(n),
+            Value::String(ref s) => write_string(out, s),
+            Value::Array(ref items) if items.is_empty() => out.push_str("[]"),
+            Value::Array(ref items) => {
+                out.push('[');
+                out.push_str(open);
+                for (i, item) in items.iter().enumerate() {
+                    pad(out, inner);
+                    item.write(out, inner);
+                    out.push_str(if i + 1 < items.len() { separator } else { close });
+                }
+                pad(out, indent);
+                out.push(']');
+            },
+            Value::Object(ref fields) if fields.is_empty() => out.push_str("{}"),
+            Value::Object(ref fields) => {
+                out.push('{');
+                out.push_str(open);
+                for (i, &(ref key, ref value)) in fields.iter().enumerate() {
+                    pad(out, inner);
+                    write_string(out, key);
+                    out.push_str(if indent.is_some() { ": " } else { ":" });
+                    value.write(out, inner);
+                    out.push_str(if i + 1 < fields.len() { separator } else { close });
+                }
+                pad(out, indent);
+                out.push('}');
+            }
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::new();
+        self.write(&mut out, Some(0));
+        f.write_str(&out)
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); },
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+}
+
+/// Deepest nesting of arrays and objects which is parsed, so the parser's
+/// recursion can't overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+struct Parser<'a> {
+    text: &'a [u8],
+    position: usize,
+    depth: usize
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.text.get(self.position).map_or(false, |c| b" \t\r\n".contains(c)) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, token: &[u8]) -> Result<(), usize> {
+        if self.text[self.position..].starts_with(token) {
+            self.position += token.len();
+            Ok(())
+        } else {
+            Err(self.position)
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, usize> {
+        self.skip_whitespace();
+        match self.text.get(self.position) {
+            Some(&b'n') => self.expect(b"null").map(|_| Value::Null),
+            Some(&b't') => self.expect(b"true").map(|_| Value::Boolean(true)),
+            Some(&b'f') => self.expect(b"false").map(|_| Value::Boolean(false)),
+            Some(&b'"') => self.string().map(Value::String),
+            Some(&b'[') => self.nested(Parser::array),
+            Some(&b'{') => self.nested(Parser::object),
+            Some(&c) if c == b'-' || (c as char).is_digit(10) => {
+                let start = self.position;
+                while self.text.get(self.position)
+                    .map_or(false, |&c| b"+-.eE".contains(&c) || (c as char).is_digit(10)) {
+                    self.position += 1;
+                }
+                let number = String::from_utf8_lossy(&self.text[start..self.position]);
+                match number.parse::<f64>() {
+                    Ok(_) => Ok(Value::Number(number.into_owned())),
+                    Err(_) => Err(start)
+                }
+            },
+            _ => Err(self.position)
+        }
+    }
+
+    /// Parse an array or object with `parse`, failing if it's nested deeper
+    /// than `MAX_DEPTH`.
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Value, usize>)
+        -> Result<Value, usize> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.position);
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn array(&mut self) -> Result<Value, usize> {
+        self.position += 1;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.expect(b"]").is_ok() {
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            if self.expect(b"]").is_ok() { return Ok(Value::Array(items)); }
+            self.expect(b",")?;
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, usize> {
+        self.position += 1;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.expect(b"}").is_ok() {
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(b":")?;
+            fields.push((key, self.value()?));
+            self.skip_whitespace();
+            if self.expect(b"}").is_ok() { return Ok(Value::Object(fields)); }
+            self.expect(b",")?;
+        }
+    }
+
+    fn string(&mut self) -> Result<String, usize> {
+        self.expect(b"\"")?;
+        let mut bytes = Vec::new();
+        loop {
+            let c = *self.text.get(self.position).ok_or(self.position)?;
+            self.position += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let escape = *self.text.get(self.position).ok_or(self.position)?;
+                    self.position += 1;
+                    let c = match escape {
+                        b'"' => '"', b'\\' => '\\', b'/' => '/',
+                        b'n' => '\n', b'r' => '\r', b't' => '\t',
+                        b'b' => '\u{8}', b'f' => '\u{c}',
+                        b'u' => {
+                            let start = self.position;
+                            let hex = self.text.get(start..start + 4).ok_or(start)?;
+                            self.position += 4;
+                            let code = ::std::str::from_utf8(hex).ok()
+                                .and_then(|h| u32::from_str_radix(h, 16).ok());
+                            code.and_then(::std::char::from_u32).ok_or(start)?
+                        },
+                        _ => { return Err(self.position - 1); }
+                    };
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                },
+                c => bytes.push(c)
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_round_trip() {
+        let value = Value::Object(vec![
+            ("name".to_owned(), Value::String("a \"b\"\n".to_owned())),
+            ("big".to_owned(), Value::number(u64::max_value())),
+            ("float".to_owned(), Value::number(0.1f64)),
+            ("list".to_owned(), Value::Array(vec![Value::Null, Value::Boolean(true)])),
+            ("empty".to_owned(), Value::Object(vec![]))
+        ]);
+        let parsed = Value::parse(&value.to_string()).unwrap();
+        assert_eq!(parsed, value);
+        assert!(!value.to_compact().contains('\n'));
+        assert_eq!(Value::parse(&value.to_compact()).unwrap(), value);
+        assert_eq!(parsed.get("big").and_then(Value::as_u64), Some(u64::max_value()));
+        assert_eq!(parsed.get("float").and_then(Value::as_f64), Some(0.1));
+
+        assert_eq!(Value::parse(" [1, -2.5e3, \"\\u00e9\"] ").unwrap(), Value::Array(vec![
+            Value::number(1), Value::Number("-2.5e3".to_owned()), Value::String("é".to_owned())
+        ]));
+        assert_eq!(Value::parse("{\"a\" 1}"), Err(5));
+        assert_eq!(Value::parse("[1] x"), Err(4));
+
+        let infinite = Value::Array(vec![
+            Value::number(::std::f64::NAN), Value::number(::std::f64::NEG_INFINITY)
+        ]);
+        assert_eq!(Value::parse(&infinite.to_string()).unwrap(),
+                   Value::Array(vec![Value::Null, Value::Null]));
+    }
+
+    #[test]
+    fn test_json_depth() {
+        let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(Value::parse(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(Value::parse(&nested(MAX_DEPTH + 1)), Err(MAX_DEPTH));
+        // far deeper than the stack of a recursive parser allows
+        assert_eq!(Value::parse(&nested(1 << 20)), Err(MAX_DEPTH));
+    }
+}
@@ -10,19 +10,51 @@ extern crate sample;
 
 pub mod hardconf;
 pub mod buffer;
+pub mod codec;
+pub mod fft;
 pub mod interpolate;
+mod json;
 pub mod meta;
+#[macro_use]
 pub mod plugins;
+pub mod resample;
+pub mod theory;
+pub mod tuning;
 
 pub mod graph;
+pub mod history;
+pub mod project;
+mod pool;
+pub mod queue;
+pub mod realtime;
+pub mod subgraph;
 
 use self::graph::Graph;
+use self::tuning::SharedTuning;
 
 #[derive(Debug, Clone)]
 pub struct CoreConfig {
     pub sample_rate: u32,
     pub buffer_size: usize,
-    pub pool_preallocate: usize
+    pub pool_preallocate: usize,
+    /// Number of threads a graph is processed on, including the render
+    /// thread.
+    pub threads: usize,
+    pub tuning: SharedTuning,
+    pub tempo: f64
+}
+
+impl Default for CoreConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44100,
+            buffer_size: 512,
+            pool_preallocate: 0,
+            threads: 1,
+            tuning: tuning::default_tuning(),
+            tempo: 120.
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -50,5 +82,21 @@ impl Core {
         self.status = CoreStatus::Initialized;
     }
 
+    /// Get the graph of plugins, if the core is initialized.
+    pub fn graph(&self) -> Option<&Graph> { self.graph.as_ref() }
+
+    /// Get the graph of plugins mutably, if the core is initialized.
+    pub fn graph_mut(&mut self) -> Option<&mut Graph> { self.graph.as_mut() }
+
     pub fn start(&self) {}
+
+    /// Stop processing, letting plugins of the graph finish their run. See
+    /// [`Graph::stop()`](graph/struct.Graph.html#method.stop).
+    pub fn stop(&mut self) -> graph::GraphResult<()> {
+        self.status = CoreStatus::Idle;
+        match self.graph {
+            Some(ref mut graph) => graph.stop(),
+            None => Ok(())
+        }
+    }
 }
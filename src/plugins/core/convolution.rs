@@ -0,0 +1,404 @@
+use std::iter;
+use buffer::Buffer;
+use buffer::prelude::*;
+use buffer::control::ParamValue;
+use codec::AudioFile;
+use fft::{Fft, Complex};
+use plugins::prelude::*;
+use plugins::special::Effect;
+use {Core, CoreConfig};
+use super::CorePlugin;
+
+const PARAM_MIX: usize = 0;
+const PARAM_GAIN: usize = 1;
+
+const RANGES: [PluginParamRange; 2] = [
+    PluginParamRange::Float(0., 1.),
+    PluginParamRange::Float(-48., 24.)
+];
+
+fn zeros<T: Clone + Default>(length: usize) -> Box<[T]> {
+    iter::repeat(T::default()).take(length).collect::<Vec<_>>().into_boxed_slice()
+}
+
+/// A stereo convolver using uniformly partitioned overlap-save FFT
+/// convolution. Each channel of the input is convolved with the same
+/// channel of the impulse response.
+///
+/// Input is processed in blocks, so the output is delayed by exactly one
+/// block, regardless of how frames are fed to it.
+pub struct Convolver {
+    block: usize,
+    fft: Fft,
+    // spectra of impulse response partitions, for each channel
+    filters: [Vec<Box<[Complex]>>; 2],
+    // frequency domain delay line of input spectra, for each channel
+    spectra: [Vec<Box<[Complex]>>; 2],
+    head: usize,
+    // last input frames filling an FFT, last computed output block
+    input: [Box<[Sample]>; 2],
+    output: [Box<[Sample]>; 2],
+    position: usize,
+    scratch: Box<[Complex]>
+}
+
+impl Convolver {
+    /// Create a convolver for `impulse` with partitions of `block` frames.
+    /// `block` may be of any size; the FFT is padded to the next power of
+    /// two of twice the block.
+    ///
+    /// # Panics
+    /// Panics if `block` is 0.
+    pub fn new(impulse: &[Frame], block: usize) -> Self {
+        assert_ne!(block, 0);
+
+        let size = (2 * block).next_power_of_two();
+        let fft = Fft::new(size);
+        let partitions = (impulse.len() + block - 1) / block;
+
+        let mut filters = [Vec::new(), Vec::new()];
+        for p in 0..partitions {
+            let part = &impulse[p * block..impulse.len().min((p + 1) * block)];
+            for channel in 0..2 {
+                let mut spectrum = zeros::<Complex>(size);
+                for (c, frame) in spectrum.iter_mut().zip(part.iter()) {
+                    *c = frame[channel].into();
+                }
+                fft.forward(&mut spectrum);
+                filters[channel].push(spectrum);
+            }
+        }
+
+        let spectra = (0..partitions).map(|_| zeros(size)).collect::<Vec<_>>();
+        Self {
+            block, fft, filters,
+            spectra: [spectra.clone(), spectra],
+            head: 0,
+            input: [zeros(size), zeros(size)],
+            output: [zeros(block), zeros(block)],
+            position: 0,
+            scratch: zeros(size)
+        }
+    }
+
+    #[inline]
+    /// Get latency in frames, which is the block size.
+    pub fn latency(&self) -> usize { self.block }
+
+    /// Process a single `frame`, returning the convolved frame and the
+    /// input, both delayed by [`latency()`](#method.latency).
+    #[inline]
+    pub fn process(&mut self, frame: Frame) -> (Frame, Frame) {
+        let (i, block) = (self.position, self.block);
+        // the last block of input is being filled, the one before is dry
+        let delayed = self.input[0].len() - 2 * block + i;
+        let wet = (self.output[0][i], self.output[1][i]).into();
+        let dry = (self.input[0][delayed], self.input[1][delayed]).into();
+
+        self.input[0][delayed + block] = frame[0];
+        self.input[1][delayed + block] = frame[1];
+
+        self.position += 1;
+        if self.position == block {
+            self.position = 0;
+            self.process_block();
+        }
+        (wet, dry)
+    }
+
+    fn process_block(&mut self) {
+        let (block, partitions) = (self.block, self.spectra[0].len());
+        if partitions == 0 {
+            for channel in 0..2 {
+                self.shift(channel);
+            }
+            return;
+        }
+
+        for channel in 0..2 {
+            {
+                let spectrum = &mut self.spectra[channel][self.head];
+                for (c, s) in spectrum.iter_mut().zip(self.input[channel].iter()) {
+                    *c = (*s).into();
+                }
+                self.fft.forward(spectrum);
+            }
+
+            self.scratch.iter_mut().for_each(|c| *c = Complex::default());
+            for p in 0..partitions {
+                let x = &self.spectra[channel][(self.head + partitions - p) % partitions];
+                let h = &self.filters[channel][p];
+                for ((y, x), h) in self.scratch.iter_mut().zip(x.iter()).zip(h.iter()) {
+                    *y = *y + *x * *h;
+                }
+            }
+            self.fft.inverse(&mut self.scratch);
+
+            let output = self.output[channel].iter_mut();
+            let size = self.scratch.len();
+            for (o, y) in output.zip(self.scratch[size - block..].iter()) {
+                *o = y.re;
+            }
+
+            self.shift(channel);
+        }
+
+        self.head = (self.head + 1) % partitions;
+    }
+
+    /// Drop the oldest block of input of `channel`, making room for the
+    /// next one.
+    fn shift(&mut self, channel: usize) {
+        let (input, block) = (&mut self.input[channel], self.block);
+        for i in block..input.len() {
+            input[i - block] = input[i];
+        }
+    }
+
+    /// Clear the internal state, keeping the impulse response.
+    pub fn reset(&mut self) {
+        for channel in 0..2 {
+            self.spectra[channel].iter_mut()
+                .for_each(|s| s.iter_mut().for_each(|c| *c = Complex::default()));
+            self.input[channel].iter_mut().for_each(|s| *s = 0.);
+            self.output[channel].iter_mut().for_each(|s| *s = 0.);
+        }
+        self.position = 0;
+        self.head = 0;
+    }
+}
+
+/// A convolution effect, applying an impulse response (e.g. of a room or a
+/// cabinet). The latency is one block of the core's buffer size.
+///
+/// The impulse response is kept at it's own sample rate, and resampled to
+/// the core's sample rate whenever it changes.
+pub struct Convolution {
+    // the impulse response as given, and resampled to `sample_rate`
+    source: AudioFile,
+    impulse: AudioBuffer,
+    sample_rate: u32,
+    mix: f64,
+    gain: f64,
+    convolver: Convolver
+}
+
+impl Convolution {
+    fn block(buffer_size: usize) -> usize { buffer_size.max(1) }
+
+    /// Use `impulse` as the impulse response, at the core's sample rate.
+    /// The default impulse response is a unit impulse, which leaves audio
+    /// unchanged.
+    pub fn set_impulse_response(&mut self, impulse: AudioBuffer) {
+        let sample_rate = self.sample_rate;
+        self.load(AudioFile { sample_rate, channels: 2, frames: impulse });
+    }
+
+    /// Use `source` as the impulse response, resampling it to the core's
+    /// sample rate.
+    fn load(&mut self, source: AudioFile) {
+        self.impulse = source.resample(self.sample_rate).frames;
+        self.convolver = Convolver::new(&self.impulse, self.convolver.block);
+        self.source = source;
+    }
+}
+
+impl Effect for Convolution {
+    fn process_audio(&mut self, audio: &mut AudioBuffer) -> PluginResult<()> {
+        let gain = 10f64.powf(self.gain / 20.);
+        let (dry, wet) = (Frame::from(1. - self.mix), Frame::from(self.mix * gain));
+        for frame in audio.iter_mut() {
+            let (convolved, input) = self.convolver.process(*frame);
+            *frame = input * dry + convolved * wet;
+        }
+        Ok(())
+    }
+}
+
+impl_effect_for!(Convolution, {
+    fn core_changed(&mut self, config: &CoreConfig) {
+        let block = Self::block(config.buffer_size);
+        if config.sample_rate != self.sample_rate {
+            self.sample_rate = config.sample_rate;
+            self.convolver.block = block;
+            let source = self.source.clone();
+            self.load(source);
+        } else if block != self.convolver.block {
+            self.convolver = Convolver::new(&self.impulse, block);
+        }
+    }
+
+    fn latency(&self) -> usize { self.convolver.latency() }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.source.encode().unwrap_or_default()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> PluginResult<()> {
+        let file = AudioFile::decode(state).map_err(|_| PluginError::InvalidArgument)?;
+        self.load(file);
+        Ok(())
+    }
+
+    fn get_params(&self) -> PluginParamsDesc {
+        box [
+            PluginParamDesc {
+                name: "Mix".to_owned(),
+                kind: PluginParamKind::Float(0., 1.),
+                default: ParamValue::Float(1.)
+            },
+            PluginParamDesc {
+                name: "Gain".to_owned(),
+                kind: PluginParamKind::Float(-48., 24.),
+                default: ParamValue::Float(0.)
+            }
+        ]
+    }
+
+    fn get_param(&self, index: usize) -> Option<ParamValue> {
+        match index {
+            PARAM_MIX => Some(ParamValue::Float(self.mix)),
+            PARAM_GAIN => Some(ParamValue::Float(self.gain)),
+            _ => None
+        }
+    }
+
+    fn param_range(&self, index: usize) -> Option<PluginParamRange> {
+        RANGES.get(index).cloned()
+    }
+
+    fn set_param(&mut self, index: usize, value: ParamValue) -> PluginResult<()> {
+        self.check_param(index, &value)?;
+
+        match (index, value) {
+            (PARAM_MIX, ParamValue::Float(m)) => { self.mix = m },
+            (PARAM_GAIN, ParamValue::Float(g)) => { self.gain = g },
+            _ => unreachable!("checked by the range")
+        }
+        Ok(())
+    }
+});
+
+impl CorePlugin for Convolution {
+    fn new(core: &Core) -> Self {
+        let mut impulse = AudioBuffer::with_length(1);
+        impulse[0] = (1.).into();
+        let block = Self::block(core.config.buffer_size);
+        let convolver = Convolver::new(&impulse, block);
+        let sample_rate = core.config.sample_rate;
+        let source = AudioFile { sample_rate, channels: 2, frames: impulse.clone() };
+        Self { source, impulse, sample_rate, mix: 1., gain: 0., convolver }
+    }
+
+    fn get_uuid() -> &'static str { "a93d0f57-e2c1-4b8e-96f4-1c7b5d28e3a0" }
+
+    fn get_desc(id: usize) -> PluginDesc {
+        PluginDesc::default().with_id(id).with_uuid(Self::get_uuid())
+            .with_name("Convolution")
+            .with_category("Effect.Reverb")
+            .with_description("partitioned FFT convolution with an impulse response.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noise(length: usize, seed: u32) -> AudioBuffer {
+        // a small LCG, so the tests are deterministic
+        let mut state = seed;
+        let mut next = move || {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 8) as f64 / (1 << 24) as f64 * 2. - 1.
+        };
+
+        let mut audio = AudioBuffer::with_length(length);
+        for frame in audio.iter_mut() {
+            *frame = (next(), next()).into();
+        }
+        audio
+    }
+
+    fn direct(input: &[Frame], impulse: &[Frame]) -> Vec<Frame> {
+        (0..input.len()).map(|n| {
+            let mut sum = Frame::default();
+            for (k, h) in impulse.iter().enumerate().take(n + 1) {
+                sum += input[n - k] * *h;
+            }
+            sum
+        }).collect()
+    }
+
+    fn assert_convolves(impulse: &AudioBuffer, block: usize) {
+        let input = noise(1000, 7);
+        let expected = direct(&input, impulse);
+
+        let mut convolver = Convolver::new(impulse, block);
+        let output = input.iter().chain(iter::repeat(&Frame::default()).take(block))
+            .map(|f| convolver.process(*f)).collect::<Vec<_>>();
+
+        for (n, e) in expected.iter().enumerate() {
+            let (wet, dry) = output[n + block];
+            assert!((wet - *e).abs().0.iter().all(|d| *d < 1e-9));
+            assert_eq!(dry, input[n]);
+        }
+    }
+
+    #[test]
+    fn test_convolver_against_direct() {
+        assert_convolves(&noise(1, 1), 16);
+        assert_convolves(&noise(16, 2), 16);
+        assert_convolves(&noise(100, 3), 16);
+        assert_convolves(&noise(300, 4), 64);
+        assert_convolves(&noise(100, 5), 100);
+        assert_convolves(&noise(50, 6), 3);
+    }
+
+    #[test]
+    fn test_convolution_plugin() {
+        let config = CoreConfig { buffer_size: 100, ..Default::default() };
+        let mut convolution = Convolution::new(&Core::new(config));
+        assert_eq!(convolution.latency(), 100);
+
+        let mut impulse = AudioBuffer::with_length(3);
+        impulse[2] = (0.5, -1.).into();
+        convolution.set_impulse_response(impulse);
+
+        let mut audio = AudioBuffer::with_length(256);
+        audio[0] = (1.).into();
+        convolution.process_audio(&mut audio).unwrap();
+        for (i, frame) in audio.iter().enumerate() {
+            let expected = if i == 102 { (0.5, -1.).into() } else { Frame::default() };
+            assert!((*frame - expected).abs().0.iter().all(|d| *d < 1e-12));
+        }
+
+        let config = CoreConfig { buffer_size: 64, ..Default::default() };
+        convolution.core_changed(&config);
+        assert_eq!(convolution.latency(), 64);
+    }
+
+    #[test]
+    fn test_convolution_sample_rate() {
+        let config = CoreConfig { sample_rate: 22050, buffer_size: 16, ..Default::default() };
+        let mut convolution = Convolution::new(&Core::new(config));
+        let mut impulse = AudioBuffer::with_length(8);
+        impulse[3] = (1.).into();
+        convolution.set_impulse_response(impulse.clone());
+
+        // the response is as long in time at twice the rate
+        let config = CoreConfig { sample_rate: 44100, buffer_size: 16, ..Default::default() };
+        convolution.core_changed(&config);
+        let mut audio = AudioBuffer::with_length(64);
+        audio[0] = (1.).into();
+        convolution.process_audio(&mut audio).unwrap();
+        let peak = (0..audio.len()).max_by(|&a, &b| {
+            audio[a][0].abs().partial_cmp(&audio[b][0].abs()).unwrap()
+        });
+        assert_eq!(peak, Some(16 + 6));
+
+        // the state keeps the response at it's own rate
+        let state = AudioFile::decode(&convolution.save_state()).unwrap();
+        assert_eq!(state.sample_rate, 22050);
+        assert_eq!(&state.frames[..], &impulse[..]);
+    }
+}
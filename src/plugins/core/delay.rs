@@ -0,0 +1,243 @@
+use buffer::prelude::*;
+use buffer::audio::DelayLine;
+use buffer::control::ParamValue;
+use interpolate::Interpolation;
+use plugins::prelude::*;
+use plugins::special::Effect;
+use {Core, CoreConfig};
+use super::CorePlugin;
+
+/// Maximum delay time in seconds.
+const MAX_TIME: f64 = 4.;
+
+const PARAM_TIME: usize = 0;
+const PARAM_SYNC: usize = 1;
+const PARAM_FEEDBACK: usize = 2;
+const PARAM_MIX: usize = 3;
+const PARAM_PING_PONG: usize = 4;
+
+const RANGES: [PluginParamRange; 5] = [
+    PluginParamRange::Float(1., MAX_TIME * 1000.),
+    PluginParamRange::Enum(10),
+    PluginParamRange::Float(0., 0.95),
+    PluginParamRange::Float(0., 1.),
+    PluginParamRange::Boolean
+];
+
+/// Note values which the delay time can be synced to, as names and lengths
+/// in beats.
+const SYNC: &'static [(&'static str, f64)] = &[
+    ("Off", 0.),
+    ("1/1", 4.), ("1/2", 2.), ("1/4", 1.), ("1/8", 0.5), ("1/16", 0.25),
+    ("1/4 Dotted", 1.5), ("1/8 Dotted", 0.75),
+    ("1/4 Triplet", 2. / 3.), ("1/8 Triplet", 1. / 3.)
+];
+
+/// A feedback delay. The delay time is either free (in milliseconds) or
+/// synced to the tempo of the core. In ping-pong mode, the input is summed
+/// to mono and the echoes bounce between the channels.
+pub struct Delay {
+    time: f64,
+    sync: usize,
+    feedback: f64,
+    mix: f64,
+    ping_pong: bool,
+    sample_rate: f64,
+    tempo: f64,
+    // cached delay in frames
+    delay: f64,
+    line: DelayLine
+}
+
+impl Delay {
+    fn update(&mut self) {
+        let seconds = match SYNC[self.sync].1 {
+            beats if beats > 0. => beats * 60. / self.tempo,
+            _ => self.time / 1000.
+        };
+        self.delay = seconds.min(MAX_TIME) * self.sample_rate;
+    }
+
+    /// Get current delay time in frames.
+    pub fn delay(&self) -> f64 { self.delay }
+}
+
+impl Effect for Delay {
+    fn process_audio(&mut self, audio: &mut AudioBuffer) -> PluginResult<()> {
+        let (dry, wet) = (Frame::from(1. - self.mix), Frame::from(self.mix));
+        let feedback = Frame::from(self.feedback);
+
+        for frame in audio.iter_mut() {
+            let delayed = self.line.read(self.delay - 1., Interpolation::Poly);
+
+            let input = if self.ping_pong {
+                let [left, right] = frame.0;
+                let echo: Frame = (delayed[1], delayed[0]).into();
+                Frame::from(((left + right) / 2., 0.)) + echo * feedback
+            } else {
+                *frame + delayed * feedback
+            };
+            self.line.push(input);
+
+            *frame = *frame * dry + delayed * wet;
+        }
+        Ok(())
+    }
+}
+
+impl_effect_for!(Delay, {
+    fn core_changed(&mut self, config: &CoreConfig) {
+        let sample_rate = config.sample_rate as f64;
+        if sample_rate != self.sample_rate {
+            self.line = DelayLine::new((MAX_TIME * sample_rate) as usize + 2);
+        }
+        self.sample_rate = sample_rate;
+        self.tempo = config.tempo;
+        self.update();
+    }
+
+    fn get_params(&self) -> PluginParamsDesc {
+        let sync = SYNC.iter().map(|&(name, _)| name.to_owned());
+        box [
+            PluginParamDesc {
+                name: "Time".to_owned(),
+                kind: PluginParamKind::Float(1., MAX_TIME * 1000.),
+                default: ParamValue::Float(250.)
+            },
+            PluginParamDesc {
+                name: "Sync".to_owned(),
+                kind: PluginParamKind::Enum(sync.collect::<Vec<_>>().into_boxed_slice()),
+                default: ParamValue::Index(0)
+            },
+            PluginParamDesc {
+                name: "Feedback".to_owned(),
+                kind: PluginParamKind::Float(0., 0.95),
+                default: ParamValue::Float(0.4)
+            },
+            PluginParamDesc {
+                name: "Mix".to_owned(),
+                kind: PluginParamKind::Float(0., 1.),
+                default: ParamValue::Float(0.5)
+            },
+            PluginParamDesc {
+                name: "Ping-Pong".to_owned(),
+                kind: PluginParamKind::Boolean,
+                default: ParamValue::Boolean(false)
+            }
+        ]
+    }
+
+    fn get_param(&self, index: usize) -> Option<ParamValue> {
+        match index {
+            PARAM_TIME => Some(ParamValue::Float(self.time)),
+            PARAM_SYNC => Some(ParamValue::Index(self.sync)),
+            PARAM_FEEDBACK => Some(ParamValue::Float(self.feedback)),
+            PARAM_MIX => Some(ParamValue::Float(self.mix)),
+            PARAM_PING_PONG => Some(ParamValue::Boolean(self.ping_pong)),
+            _ => None
+        }
+    }
+
+    fn param_range(&self, index: usize) -> Option<PluginParamRange> {
+        RANGES.get(index).cloned()
+    }
+
+    fn set_param(&mut self, index: usize, value: ParamValue) -> PluginResult<()> {
+        self.check_param(index, &value)?;
+
+        match (index, value) {
+            (PARAM_TIME, ParamValue::Float(t)) => { self.time = t },
+            (PARAM_SYNC, ParamValue::Index(s)) => { self.sync = s },
+            (PARAM_FEEDBACK, ParamValue::Float(f)) => { self.feedback = f },
+            (PARAM_MIX, ParamValue::Float(m)) => { self.mix = m },
+            (PARAM_PING_PONG, ParamValue::Boolean(p)) => {
+                self.ping_pong = p;
+                self.line.clear();
+            },
+            _ => unreachable!("checked by the range")
+        }
+
+        self.update();
+        Ok(())
+    }
+});
+
+impl CorePlugin for Delay {
+    fn new(core: &Core) -> Self {
+        let sample_rate = core.config.sample_rate as f64;
+        let mut delay = Self {
+            time: 250., sync: 0, feedback: 0.4, mix: 0.5, ping_pong: false,
+            sample_rate, tempo: core.config.tempo, delay: 0.,
+            line: DelayLine::new((MAX_TIME * sample_rate) as usize + 2)
+        };
+        delay.update();
+        delay
+    }
+
+    fn get_uuid() -> &'static str { "b6d2e9a0-4c7f-4a18-8e3b-71f05c2d9a46" }
+
+    fn get_desc(id: usize) -> PluginDesc {
+        PluginDesc::default().with_id(id).with_uuid(Self::get_uuid())
+            .with_name("Delay")
+            .with_category("Effect.Delay")
+            .with_description("feedback delay with tempo sync and ping-pong.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use buffer::Buffer;
+
+    fn delay() -> Delay {
+        let config = CoreConfig { sample_rate: 1000, ..Default::default() };
+        let mut delay = Delay::new(&Core::new(config));
+        delay.set_param(PARAM_TIME, ParamValue::Float(10.)).unwrap();
+        delay
+    }
+
+    fn impulse(delay: &mut Delay, length: usize) -> AudioBuffer {
+        let mut audio = AudioBuffer::with_length(length);
+        audio[0] = (1., 0.5).into();
+        delay.process_audio(&mut audio).unwrap();
+        audio
+    }
+
+    #[test]
+    fn test_delay_echoes() {
+        let mut delay = delay();
+        let audio = impulse(&mut delay, 25);
+
+        assert_eq!(audio[0], (0.5, 0.25).into());
+        assert_eq!(audio[10], (0.5, 0.25).into());
+        assert_eq!(audio[20], (0.2, 0.1).into());
+        assert_eq!(audio[5], Frame::default());
+    }
+
+    #[test]
+    fn test_delay_ping_pong() {
+        let mut delay = delay();
+        delay.set_param(PARAM_PING_PONG, ParamValue::Boolean(true)).unwrap();
+        let audio = impulse(&mut delay, 25);
+
+        assert_eq!(audio[10], (0.375, 0.).into());
+        assert_eq!(audio[20].0[0], 0.);
+        assert!((audio[20].0[1] - 0.15).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_delay_sync() {
+        let mut delay = delay();
+        assert_eq!(delay.delay(), 10.);
+
+        delay.set_param(PARAM_SYNC, ParamValue::Index(3)).unwrap();
+        assert_eq!(delay.delay(), 500.);
+
+        let config = CoreConfig { sample_rate: 1000, tempo: 60., ..Default::default() };
+        delay.core_changed(&config);
+        assert_eq!(delay.delay(), 1000.);
+
+        delay.set_param(PARAM_SYNC, ParamValue::Index(1)).unwrap();
+        assert_eq!(delay.delay(), MAX_TIME * 1000.);
+    }
+}
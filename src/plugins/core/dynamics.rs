@@ -0,0 +1,578 @@
+use std::collections::VecDeque;
+use buffer::prelude::*;
+use buffer::audio::DelayLine;
+use buffer::control::ParamValue;
+use interpolate::Interpolation;
+use plugins::prelude::*;
+use {Core, CoreConfig};
+use super::CorePlugin;
+
+/// Maximum lookahead in milliseconds.
+const MAX_LOOKAHEAD: f64 = 10.;
+
+/// Averaging time of RMS detection in milliseconds.
+const RMS_TIME: f64 = 10.;
+
+#[inline]
+fn to_db(amplitude: f64) -> f64 { 20. * amplitude.max(1e-10).log10() }
+
+#[inline]
+fn to_amplitude(db: f64) -> f64 { 10f64.powf(db / 20.) }
+
+#[inline]
+fn coefficient(ms: f64, sample_rate: f64) -> f64 {
+    if ms <= 0. { 0. } else { (-1000. / (ms * sample_rate)).exp() }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Kind of a [`Dynamics`](struct.Dynamics.html) processor.
+pub enum DynamicsKind {
+    /// Reduces gain of levels above threshold by ratio.
+    Compressor,
+
+    /// A compressor with infinite ratio, hard knee and lookahead, so the
+    /// level never exceeds the threshold.
+    Limiter,
+
+    /// Downward expander; reduces gain of levels below threshold by ratio.
+    Gate
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Level detection method of a [`Dynamics`](struct.Dynamics.html).
+pub enum Detection { Peak, Rms }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Param {
+    Threshold, Ratio, Attack, Release, Knee, Makeup, Detection, Lookahead
+}
+
+impl Param {
+    fn of(kind: DynamicsKind) -> &'static [Param] {
+        match kind {
+            DynamicsKind::Compressor => &[
+                Param::Threshold, Param::Ratio, Param::Attack, Param::Release,
+                Param::Knee, Param::Makeup, Param::Detection
+            ],
+            DynamicsKind::Limiter => &[
+                Param::Threshold, Param::Release, Param::Lookahead, Param::Makeup
+            ],
+            DynamicsKind::Gate => &[
+                Param::Threshold, Param::Ratio, Param::Attack, Param::Release,
+                Param::Knee, Param::Detection
+            ]
+        }
+    }
+
+    fn default(&self, kind: DynamicsKind) -> ParamValue {
+        match (*self, kind) {
+            (Param::Threshold, DynamicsKind::Limiter) => ParamValue::Float(-1.),
+            (Param::Threshold, DynamicsKind::Gate) => ParamValue::Float(-40.),
+            (Param::Threshold, _) => ParamValue::Float(-12.),
+            (Param::Ratio, DynamicsKind::Gate) => ParamValue::Float(10.),
+            (Param::Ratio, _) => ParamValue::Float(4.),
+            (Param::Attack, _) => ParamValue::Float(10.),
+            (Param::Release, _) => ParamValue::Float(100.),
+            (Param::Knee, DynamicsKind::Compressor) => ParamValue::Float(6.),
+            (Param::Knee, _) => ParamValue::Float(0.),
+            (Param::Makeup, _) => ParamValue::Float(0.),
+            (Param::Detection, _) => ParamValue::Index(0),
+            (Param::Lookahead, _) => ParamValue::Float(5.)
+        }
+    }
+
+    fn range(&self) -> PluginParamRange {
+        match *self {
+            Param::Detection => PluginParamRange::Enum(2),
+            _ => self.kind().range()
+        }
+    }
+
+    fn kind(&self) -> PluginParamKind {
+        match *self {
+            Param::Threshold => PluginParamKind::Float(-80., 0.),
+            Param::Ratio => PluginParamKind::Float(1., 20.),
+            Param::Attack => PluginParamKind::Float(0., 200.),
+            Param::Release => PluginParamKind::Float(1., 2000.),
+            Param::Knee => PluginParamKind::Float(0., 24.),
+            Param::Makeup => PluginParamKind::Float(0., 24.),
+            Param::Detection => PluginParamKind::Enum(
+                box ["Peak".to_owned(), "RMS".to_owned()]),
+            Param::Lookahead => PluginParamKind::Float(0., MAX_LOOKAHEAD)
+        }
+    }
+
+    fn desc(&self, kind: DynamicsKind) -> PluginParamDesc {
+        let name = match *self {
+            Param::Threshold => "Threshold",
+            Param::Ratio => "Ratio",
+            Param::Attack => "Attack",
+            Param::Release => "Release",
+            Param::Knee => "Knee",
+            Param::Makeup => "Makeup",
+            Param::Detection => "Detection",
+            Param::Lookahead => "Lookahead"
+        };
+        PluginParamDesc { name: name.to_owned(), kind: self.kind(), default: self.default(kind) }
+    }
+}
+
+/// A dynamics processor. It has an audio input and an optional sidechain
+/// audio input; if the sidechain is connected, the level is detected from
+/// it instead of the audio input.
+///
+/// Core plugins [`Compressor`], [`Limiter`] and [`Gate`] are thin wrappers
+/// around it.
+/// [`Compressor`]: struct.Compressor.html
+/// [`Limiter`]: struct.Limiter.html
+/// [`Gate`]: struct.Gate.html
+pub struct Dynamics {
+    kind: DynamicsKind,
+    threshold: f64,
+    ratio: f64,
+    attack: f64,
+    release: f64,
+    knee: f64,
+    makeup: f64,
+    detection: Detection,
+    lookahead: f64,
+    sample_rate: f64,
+    // peak envelope or mean square of the detector
+    level: f64,
+    // current gain in dB
+    gain: f64,
+    // audio delayed by lookahead, and pending gains as (frame number, gain)
+    line: DelayLine,
+    pending: VecDeque<(usize, f64)>,
+    frame: usize
+}
+
+impl Dynamics {
+    /// Create a processor of `kind` for `sample_rate`, with default
+    /// parameters.
+    pub fn new(kind: DynamicsKind, sample_rate: f64) -> Self {
+        let lookahead = (MAX_LOOKAHEAD * sample_rate / 1000.) as usize + 1;
+        let mut dynamics = Self {
+            kind, threshold: 0., ratio: 1., attack: 0., release: 0., knee: 0.,
+            makeup: 0., detection: Detection::Peak, lookahead: 0.,
+            sample_rate, level: 0., gain: 0.,
+            line: DelayLine::new(lookahead),
+            pending: VecDeque::with_capacity(lookahead + 1),
+            frame: 0
+        };
+
+        for (index, param) in Param::of(kind).iter().enumerate() {
+            dynamics.set_param(index, param.default(kind)).unwrap();
+        }
+        dynamics
+    }
+
+    #[inline]
+    /// Get kind of the processor.
+    pub fn kind(&self) -> DynamicsKind { self.kind }
+
+    /// Get latency caused by lookahead, in frames.
+    pub fn lookahead(&self) -> usize {
+        match self.kind {
+            DynamicsKind::Limiter => (self.lookahead * self.sample_rate / 1000.) as usize,
+            _ => 0
+        }
+    }
+
+    /// Compute the static gain in dB to apply on `level` dB.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::plugins::core::{Dynamics, DynamicsKind};
+    ///
+    /// let compressor = Dynamics::new(DynamicsKind::Compressor, 44100.);
+    /// // default threshold is -12dB with ratio 4:1 and knee of 6dB
+    /// assert_eq!(compressor.curve(-24.), 0.);
+    /// assert_eq!(compressor.curve(0.), -9.);
+    /// ```
+    pub fn curve(&self, level: f64) -> f64 {
+        let (over, knee) = (level - self.threshold, self.knee);
+        match self.kind {
+            DynamicsKind::Compressor => {
+                let slope = 1. / self.ratio - 1.;
+                if 2. * over.abs() <= knee && knee > 0. {
+                    slope * (over + knee / 2.).powi(2) / (2. * knee)
+                } else if over > 0. {
+                    slope * over
+                } else {
+                    0.
+                }
+            },
+            DynamicsKind::Limiter => if over > 0. { -over } else { 0. },
+            DynamicsKind::Gate => {
+                let slope = self.ratio - 1.;
+                if 2. * over.abs() <= knee && knee > 0. {
+                    -slope * (over - knee / 2.).powi(2) / (2. * knee)
+                } else if over < 0. {
+                    slope * over
+                } else {
+                    0.
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn detect(&mut self, key: Frame) -> f64 {
+        let [left, right] = key.0;
+        match (self.kind, self.detection) {
+            // the limiter holds the peaks itself
+            (DynamicsKind::Limiter, _) => to_db(left.abs().max(right.abs())),
+            (_, Detection::Peak) => {
+                let c = coefficient(self.release, self.sample_rate);
+                let peak = left.abs().max(right.abs());
+                self.level = peak.max(self.level * c);
+                to_db(self.level)
+            },
+            (_, Detection::Rms) => {
+                let c = coefficient(RMS_TIME, self.sample_rate);
+                let square = (left * left + right * right) / 2.;
+                self.level = square + c * (self.level - square);
+                to_db(self.level.sqrt())
+            }
+        }
+    }
+
+    /// Get the minimum gain of the lookahead window, after pushing `gain`.
+    #[inline]
+    fn hold(&mut self, gain: f64) -> f64 {
+        let window = self.lookahead();
+        while self.pending.back().map_or(false, |&(_, g)| g >= gain) {
+            self.pending.pop_back();
+        }
+        self.pending.push_back((self.frame, gain));
+        while self.pending.front().map_or(false, |&(f, _)| f + window < self.frame) {
+            self.pending.pop_front();
+        }
+        self.frame += 1;
+        self.pending.front().map_or(gain, |&(_, g)| g)
+    }
+
+    /// Process a single `frame`, detecting the level from `key`.
+    pub fn process_frame(&mut self, frame: Frame, key: Frame) -> Frame {
+        let level = self.detect(key);
+        let target = self.curve(level);
+
+        let (frame, target) = if let DynamicsKind::Limiter = self.kind {
+            let delay = self.lookahead() as f64;
+            self.line.push(frame);
+            let target = self.hold(target);
+            // attack is instant on the held gain, which is already early
+            if target < self.gain { self.gain = target; }
+            (self.line.read(delay, Interpolation::Floor), target)
+        } else {
+            (frame, target)
+        };
+
+        let opening = target > self.gain;
+        let attacking = opening == (self.kind == DynamicsKind::Gate);
+        let time = if attacking { self.attack } else { self.release };
+        let c = coefficient(time, self.sample_rate);
+        self.gain = target + c * (self.gain - target);
+
+        frame * to_amplitude(self.gain + self.makeup).into()
+    }
+
+    /// Get current gain reduction in dB.
+    pub fn reduction(&self) -> f64 { -self.gain }
+
+    /// Clear the detector and the lookahead state.
+    pub fn reset(&mut self) {
+        self.level = 0.;
+        self.gain = 0.;
+        self.line.clear();
+        self.pending.clear();
+        self.frame = 0;
+    }
+}
+
+impl Plugin for Dynamics {
+    fn core_changed(&mut self, config: &CoreConfig) {
+        let sample_rate = config.sample_rate as f64;
+        if sample_rate != self.sample_rate {
+            let lookahead = (MAX_LOOKAHEAD * sample_rate / 1000.) as usize + 1;
+            self.line = DelayLine::new(lookahead);
+            self.pending = VecDeque::with_capacity(lookahead + 1);
+            self.sample_rate = sample_rate;
+            self.reset();
+        }
+    }
+
+    fn latency(&self) -> usize { self.lookahead() }
+
+    fn get_params(&self) -> PluginParamsDesc {
+        let params = Param::of(self.kind).iter().map(|p| p.desc(self.kind));
+        params.collect::<Vec<_>>().into_boxed_slice()
+    }
+
+    fn get_param(&self, index: usize) -> Option<ParamValue> {
+        Param::of(self.kind).get(index).map(|param| match *param {
+            Param::Threshold => ParamValue::Float(self.threshold),
+            Param::Ratio => ParamValue::Float(self.ratio),
+            Param::Attack => ParamValue::Float(self.attack),
+            Param::Release => ParamValue::Float(self.release),
+            Param::Knee => ParamValue::Float(self.knee),
+            Param::Makeup => ParamValue::Float(self.makeup),
+            Param::Detection => ParamValue::Index(match self.detection {
+                Detection::Peak => 0, Detection::Rms => 1
+            }),
+            Param::Lookahead => ParamValue::Float(self.lookahead)
+        })
+    }
+
+    fn param_range(&self, index: usize) -> Option<PluginParamRange> {
+        Param::of(self.kind).get(index).map(Param::range)
+    }
+
+    fn set_param(&mut self, index: usize, value: ParamValue) -> PluginResult<()> {
+        self.check_param(index, &value)?;
+        let param = Param::of(self.kind)[index];
+
+        match (param, value) {
+            (Param::Threshold, ParamValue::Float(t)) => { self.threshold = t },
+            (Param::Ratio, ParamValue::Float(r)) => { self.ratio = r },
+            (Param::Attack, ParamValue::Float(a)) => { self.attack = a },
+            (Param::Release, ParamValue::Float(r)) => { self.release = r },
+            (Param::Knee, ParamValue::Float(k)) => { self.knee = k },
+            (Param::Makeup, ParamValue::Float(m)) => { self.makeup = m },
+            (Param::Detection, ParamValue::Index(d)) => {
+                self.detection = if d == 0 { Detection::Peak } else { Detection::Rms };
+            },
+            (Param::Lookahead, ParamValue::Float(l)) => {
+                self.lookahead = l;
+                self.pending.clear();
+            },
+            _ => unreachable!("checked by the range")
+        }
+        Ok(())
+    }
+
+    fn get_io_descriptor(&self) -> PluginIoDesc {
+        PluginIoDesc {
+            mode: PluginIoMode::Complex(PluginComplexIo {
+                inputs: box [PluginIoKind::Audio, PluginIoKind::Audio],
+                outputs: box [PluginIoKind::Audio]
+            }),
+            inputs: box ["Audio".to_owned(), "Sidechain".to_owned()],
+            outputs: box ["Audio".to_owned()]
+        }
+    }
+
+    fn process(&mut self, inputs: &PluginIo, outputs: &mut PluginIo)
+        -> PluginResult<()> {
+        let output = match outputs.get_mut(0) {
+            Some(&mut PluginIoBuffer::Audio(ref mut audio)) => audio,
+            Some(&mut PluginIoBuffer::Disconnected) => { return Ok(()); },
+            _ => { return Err(PluginError::InvalidArgument); }
+        };
+
+        let input = match inputs.get(0) {
+            Some(&PluginIoBuffer::Audio(ref audio)) => audio,
+            Some(&PluginIoBuffer::Disconnected) => {
+                output.iter_mut().for_each(|f| *f = Default::default());
+                return Ok(());
+            },
+            _ => { return Err(PluginError::InvalidArgument); }
+        };
+
+        match inputs.get(1) {
+            Some(&PluginIoBuffer::Audio(ref sidechain)) => {
+                let frames = output.iter_mut().zip(input.iter().zip(sidechain.iter()));
+                for (o, (&i, &key)) in frames {
+                    *o = self.process_frame(i, key);
+                }
+            },
+            Some(&PluginIoBuffer::Control(_)) => {
+                return Err(PluginError::InvalidArgument);
+            },
+            _ => {
+                for (o, &i) in output.iter_mut().zip(input.iter()) {
+                    *o = self.process_frame(i, i);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+macro_rules! dynamics_plugin {
+    ($(#[$attr:meta])* $t:ident, $kind:ident, $uuid:expr, $desc:expr) => (
+        $(#[$attr])*
+        pub struct $t(pub Dynamics);
+
+        impl Plugin for $t {
+            fn core_changed(&mut self, config: &CoreConfig) {
+                self.0.core_changed(config)
+            }
+
+            fn get_params(&self) -> PluginParamsDesc { self.0.get_params() }
+
+            fn get_param(&self, index: usize) -> Option<ParamValue> {
+                self.0.get_param(index)
+            }
+
+            fn set_param(&mut self, index: usize, value: ParamValue)
+                -> PluginResult<()> {
+                self.0.set_param(index, value)
+            }
+
+            fn param_range(&self, index: usize) -> Option<PluginParamRange> {
+                self.0.param_range(index)
+            }
+
+            fn latency(&self) -> usize { self.0.latency() }
+
+            fn get_io_descriptor(&self) -> PluginIoDesc {
+                self.0.get_io_descriptor()
+            }
+
+            fn process(&mut self, inputs: &PluginIo, outputs: &mut PluginIo)
+                -> PluginResult<()> {
+                self.0.process(inputs, outputs)
+            }
+        }
+
+        impl CorePlugin for $t {
+            fn new(core: &Core) -> Self {
+                let sample_rate = core.config.sample_rate as f64;
+                $t(Dynamics::new(DynamicsKind::$kind, sample_rate))
+            }
+
+            fn get_uuid() -> &'static str { $uuid }
+
+            fn get_desc(id: usize) -> PluginDesc {
+                PluginDesc::default().with_id(id).with_uuid(Self::get_uuid())
+                    .with_name(stringify!($t))
+                    .with_category("Effect.Dynamics")
+                    .with_description($desc)
+            }
+        }
+    )
+}
+
+dynamics_plugin!(
+    /// A compressor with optional sidechain.
+    Compressor, Compressor, "0d7c4b1e-6f38-4a92-a5e1-39b8f2c06d57",
+    "compressor with peak or RMS detection and sidechain input.");
+
+dynamics_plugin!(
+    /// A lookahead brickwall limiter with optional sidechain.
+    Limiter, Limiter, "c2f95a68-1e4d-47b3-9a0c-8d63e7b41f25",
+    "lookahead limiter with sidechain input.");
+
+dynamics_plugin!(
+    /// A noise gate (downward expander) with optional sidechain.
+    Gate, Gate, "74a1e8d0-b52c-4f96-83d7-e6095c3a2b18",
+    "noise gate with peak or RMS detection and sidechain input.");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use buffer::Buffer;
+
+    const RATE: f64 = 48000.;
+
+    fn sine(amplitude: f64, length: usize) -> AudioBuffer {
+        let mut audio = AudioBuffer::with_length(length);
+        for (i, frame) in audio.iter_mut().enumerate() {
+            let phase = 2. * ::std::f64::consts::PI * 1000. * i as f64 / RATE;
+            *frame = (amplitude * phase.sin()).into();
+        }
+        audio
+    }
+
+    fn run(plugin: &mut Plugin, input: AudioBuffer, sidechain: Option<AudioBuffer>)
+        -> AudioBuffer {
+        let length = input.len();
+        let sidechain = sidechain.map_or(PluginIoBuffer::Disconnected,
+                                         PluginIoBuffer::Audio);
+        let inputs: PluginIo = box [PluginIoBuffer::Audio(input), sidechain];
+        let mut outputs: PluginIo = box [
+            PluginIoBuffer::Audio(AudioBuffer::with_length(length))
+        ];
+        plugin.process(&inputs, &mut outputs).unwrap();
+
+        match outputs[0] {
+            PluginIoBuffer::Audio(ref audio) => audio.clone(),
+            _ => unreachable!()
+        }
+    }
+
+    fn peak(frames: &[Frame]) -> f64 {
+        frames.iter().fold(0., |max, f| max.max(f[0].abs()).max(f[1].abs()))
+    }
+
+    #[test]
+    fn test_dynamics_curve() {
+        let mut compressor = Dynamics::new(DynamicsKind::Compressor, RATE);
+        compressor.set_param(4, ParamValue::Float(0.)).unwrap();
+        assert_eq!(compressor.curve(-12.), 0.);
+        assert_eq!(compressor.curve(-4.), -6.);
+
+        // soft knee is continuous at it's edges
+        compressor.set_param(4, ParamValue::Float(12.)).unwrap();
+        assert_eq!(compressor.curve(-18.), 0.);
+        assert!((compressor.curve(-6.) + 4.5).abs() < 1e-10);
+        assert!(compressor.curve(-12.) < 0.);
+
+        let gate = Dynamics::new(DynamicsKind::Gate, RATE);
+        assert_eq!(gate.curve(-30.), 0.);
+        assert_eq!(gate.curve(-45.), -45.);
+
+        let limiter = Dynamics::new(DynamicsKind::Limiter, RATE);
+        assert_eq!(limiter.curve(3.), -4.);
+    }
+
+    #[test]
+    fn test_compressor_detection() {
+        let core = Core::new(CoreConfig { sample_rate: RATE as u32, ..Default::default() });
+        let mut compressor = Compressor::new(&core);
+
+        let output = run(&mut compressor, sine(1., 9600), None);
+        let peak_reduced = peak(&output[4800..]);
+        assert!((to_db(peak_reduced) + 9.).abs() < 0.5);
+
+        compressor.0.reset();
+        compressor.set_param(6, ParamValue::Index(1)).unwrap();
+        let output = run(&mut compressor, sine(1., 9600), None);
+        // RMS of the sine is -3dB, so it's compressed less
+        assert!(peak(&output[4800..]) > peak_reduced);
+    }
+
+    #[test]
+    fn test_limiter_ceiling() {
+        let core = Core::new(CoreConfig { sample_rate: RATE as u32, ..Default::default() });
+        let mut limiter = Limiter::new(&core);
+        let latency = limiter.0.lookahead();
+        assert_eq!(latency, 240);
+
+        let mut input = sine(0.1, 4800);
+        for frame in input[2400..2500].iter_mut() {
+            *frame = *frame * (40.).into();
+        }
+
+        let output = run(&mut limiter, input.clone(), None);
+        assert!(to_db(peak(&output)) <= -1. + 1e-9);
+        // the signal is delayed by lookahead
+        assert_eq!(output[100 + latency], input[100]);
+    }
+
+    #[test]
+    fn test_gate_sidechain() {
+        let core = Core::new(CoreConfig { sample_rate: RATE as u32, ..Default::default() });
+        let mut gate = Gate::new(&core);
+        assert_eq!(gate.get_io_descriptor().inputs.len(), 2);
+
+        let output = run(&mut gate, sine(0.001, 4800), None);
+        assert!(peak(&output[2400..]) < 1e-6);
+
+        gate.0.reset();
+        let output = run(&mut gate, sine(0.001, 4800), Some(sine(1., 4800)));
+        assert!((peak(&output[2400..]) - 0.001).abs() < 1e-5);
+    }
+}
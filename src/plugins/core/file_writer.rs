@@ -0,0 +1,254 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::str;
+use buffer::control::ParamValue;
+use codec::{CodecResult, Encoding, WavWriter};
+use plugins::prelude::*;
+use plugins::WaitablePlugin;
+use {Core, CoreConfig};
+use super::CorePlugin;
+
+const PARAM_FORMAT: usize = 0;
+const PARAM_DITHER: usize = 1;
+
+const RANGES: [PluginParamRange; 2] = [
+    PluginParamRange::Enum(3),
+    PluginParamRange::Boolean
+];
+
+const FORMATS: &'static [(&'static str, Encoding)] = &[
+    ("16-bit", Encoding::Int16),
+    ("24-bit", Encoding::Int24),
+    ("32-bit Float", Encoding::Float32)
+];
+
+/// Writes it's audio input to a WAV file. As a [`WaitablePlugin`] it never
+/// blocks, so an engine waiting on it renders faster than realtime.
+///
+/// Format and dither parameters are applied to the next created file. The
+/// path of the file is the state of the plugin: loading a path as the state
+/// starts a new file there, and an empty state finishes the current one.
+/// Each [`wait()`] completes the file written so far.
+///
+/// [`WaitablePlugin`]: ../trait.WaitablePlugin.html
+/// [`wait()`]: ../trait.WaitablePlugin.html#method.wait
+pub struct FileWriter {
+    writer: Option<WavWriter<BufWriter<File>>>,
+    path: Option<PathBuf>,
+    format: usize,
+    dither: bool,
+    sample_rate: u32
+}
+
+impl FileWriter {
+    /// Start writing to a new file at `path`, finishing the current one.
+    pub fn create<P: AsRef<Path>>(&mut self, path: P) -> CodecResult<()> {
+        self.finish()?;
+        let file = BufWriter::new(File::create(&path)?);
+        let writer = WavWriter::new(file, self.sample_rate, FORMATS[self.format].1)?;
+        self.writer = Some(writer.with_dither(self.dither));
+        self.path = Some(path.as_ref().to_owned());
+        Ok(())
+    }
+
+    /// Finish writing the current file, if any.
+    pub fn finish(&mut self) -> CodecResult<()> {
+        self.path = None;
+        match self.writer.take() {
+            Some(writer) => writer.finish().map(|_| ()),
+            None => Ok(())
+        }
+    }
+
+    #[inline]
+    /// Check whether a file is being written.
+    pub fn is_writing(&self) -> bool { self.writer.is_some() }
+}
+
+impl Plugin for FileWriter {
+    fn core_changed(&mut self, config: &CoreConfig) {
+        self.sample_rate = config.sample_rate;
+    }
+
+    fn get_params(&self) -> PluginParamsDesc {
+        let formats = FORMATS.iter().map(|&(name, _)| name.to_owned());
+        box [
+            PluginParamDesc {
+                name: "Format".to_owned(),
+                kind: PluginParamKind::Enum(formats.collect::<Vec<_>>().into_boxed_slice()),
+                default: ParamValue::Index(1)
+            },
+            PluginParamDesc {
+                name: "Dither".to_owned(),
+                kind: PluginParamKind::Boolean,
+                default: ParamValue::Boolean(true)
+            }
+        ]
+    }
+
+    fn get_param(&self, index: usize) -> Option<ParamValue> {
+        match index {
+            PARAM_FORMAT => Some(ParamValue::Index(self.format)),
+            PARAM_DITHER => Some(ParamValue::Boolean(self.dither)),
+            _ => None
+        }
+    }
+
+    fn param_range(&self, index: usize) -> Option<PluginParamRange> {
+        RANGES.get(index).cloned()
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let path = self.path.as_ref().and_then(|p| p.to_str());
+        path.map(|p| p.as_bytes().to_vec()).unwrap_or_default()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> PluginResult<()> {
+        if state.is_empty() {
+            return self.finish().map_err(|_| PluginError::InternalError);
+        }
+        let path = str::from_utf8(state).map_err(|_| PluginError::InvalidArgument)?;
+        self.create(path).map_err(|_| PluginError::InvalidArgument)
+    }
+
+    fn set_param(&mut self, index: usize, value: ParamValue) -> PluginResult<()> {
+        self.check_param(index, &value)?;
+
+        match (index, value) {
+            (PARAM_FORMAT, ParamValue::Index(f)) => { self.format = f },
+            (PARAM_DITHER, ParamValue::Boolean(d)) => { self.dither = d },
+            _ => unreachable!("checked by the range")
+        }
+        Ok(())
+    }
+
+    fn get_io_descriptor(&self) -> PluginIoDesc {
+        PluginIoDesc {
+            mode: PluginIoMode::Complex(PluginComplexIo {
+                inputs: box [PluginIoKind::Audio],
+                outputs: box []
+            }),
+            inputs: box ["Audio".to_owned()],
+            outputs: box []
+        }
+    }
+
+    fn process(&mut self, inputs: &PluginIo, _outputs: &mut PluginIo)
+        -> PluginResult<()> {
+        let input = match inputs.get(0) {
+            Some(&PluginIoBuffer::Audio(ref audio)) => audio,
+            Some(&PluginIoBuffer::Disconnected) => { return Ok(()); },
+            _ => { return Err(PluginError::InvalidArgument); }
+        };
+
+        let failed = match self.writer {
+            Some(ref mut writer) => writer.write(input).is_err(),
+            None => false
+        };
+
+        // the file is left as is, but no more writes are tried
+        if failed {
+            self.writer = None;
+            return Err(PluginError::InternalError);
+        }
+        Ok(())
+    }
+
+    fn terminate(&mut self) {
+        let _ = self.finish();
+    }
+
+    fn as_waitable(&mut self) -> Option<&mut WaitablePlugin> { Some(self) }
+}
+
+impl WaitablePlugin for FileWriter {
+    fn wait(&mut self) -> PluginResult<()> {
+        match self.writer {
+            Some(ref mut writer) => writer.flush().map_err(|_| PluginError::InternalError),
+            None => Ok(())
+        }
+    }
+}
+
+impl CorePlugin for FileWriter {
+    fn new(core: &Core) -> Self {
+        let sample_rate = core.config.sample_rate;
+        Self { writer: None, path: None, format: 1, dither: true, sample_rate }
+    }
+
+    fn get_uuid() -> &'static str { "5c2f8e61-3a94-4b7d-a0e2-71d9c4f6b853" }
+
+    fn get_desc(id: usize) -> PluginDesc {
+        PluginDesc::default().with_id(id).with_uuid(Self::get_uuid())
+            .with_name("File Writer")
+            .with_category("Output.File")
+            .with_description("renders it's input to a WAV file.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use buffer::Buffer;
+    use buffer::audio::AudioBuffer;
+    use codec::AudioFile;
+    use graph::{Connection, Graph};
+    use plugins::core::{CoreFactory, FunctionGenerator};
+    use super::*;
+
+    #[test]
+    fn test_file_writer() {
+        let core = Core::new(CoreConfig { sample_rate: 48000, ..Default::default() });
+        let mut plugin = FileWriter::new(&core);
+        plugin.set_param(PARAM_FORMAT, ParamValue::Index(2)).unwrap();
+        plugin.wait().unwrap();
+
+        let path = env::temp_dir().join("overcore-test-file-writer.wav");
+        plugin.create(&path).unwrap();
+        assert!(plugin.is_writing());
+
+        let mut audio = AudioBuffer::with_length(4);
+        audio[1] = (0.5, -0.5).into();
+        let inputs: PluginIo = box [PluginIoBuffer::Audio(audio)];
+        let mut outputs: PluginIo = box [];
+        plugin.process(&inputs, &mut outputs).unwrap();
+        plugin.process(&inputs, &mut outputs).unwrap();
+        plugin.terminate();
+        assert!(!plugin.is_writing());
+
+        let file = AudioFile::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(file.sample_rate, 48000);
+        assert_eq!(file.frames.len(), 8);
+        assert_eq!(file.frames[5], (0.5, -0.5).into());
+    }
+
+    #[test]
+    fn test_file_writer_in_graph() {
+        let core = Core::new(CoreConfig { buffer_size: 4, ..Default::default() });
+        let mut factory = CoreFactory::new(&core);
+        let mut graph = Graph::new(&core);
+        let generator = graph.create_node(&mut factory, FunctionGenerator::get_uuid()).unwrap();
+        let writer = graph.create_node(&mut factory, FileWriter::get_uuid()).unwrap();
+        graph.connect(Connection { from: generator, output: 0, to: writer, input: 0 }).unwrap();
+
+        // the path is given as the state, as a loaded project does
+        let path = env::temp_dir().join("overcore-test-file-writer-graph.wav");
+        let state = path.to_str().unwrap().as_bytes();
+        graph.plugin_mut(writer).unwrap().load_state(state).unwrap();
+        assert_eq!(graph.plugin(writer).unwrap().save_state(), state);
+
+        graph.process().unwrap();
+        graph.process().unwrap();
+        // the header is complete once stopped, though the file is open
+        graph.stop().unwrap();
+        let file = AudioFile::open(&path).unwrap();
+        assert_eq!(file.frames.len(), 8);
+
+        graph.plugin_mut(writer).unwrap().load_state(&[]).unwrap();
+        assert_eq!(graph.plugin(writer).unwrap().save_state(), Vec::<u8>::new());
+        fs::remove_file(&path).unwrap();
+    }
+}
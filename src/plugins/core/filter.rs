@@ -0,0 +1,378 @@
+use std::f64::consts::PI;
+use buffer::prelude::*;
+use buffer::control::ParamValue;
+use plugins::prelude::*;
+use plugins::special::Effect;
+use {Core, CoreConfig};
+use super::CorePlugin;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Response type of a [`Biquad`](struct.Biquad.html).
+pub enum FilterKind { LowPass, HighPass, BandPass, Notch, LowShelf, HighShelf, Peak }
+
+impl FilterKind {
+    /// Get all kinds, ordered by their index.
+    pub fn all() -> &'static [FilterKind] {
+        &[
+            FilterKind::LowPass, FilterKind::HighPass, FilterKind::BandPass,
+            FilterKind::Notch, FilterKind::LowShelf, FilterKind::HighShelf,
+            FilterKind::Peak
+        ]
+    }
+
+    /// Get a human readable name of the kind.
+    pub fn name(&self) -> &'static str {
+        match self {
+            &FilterKind::LowPass   => "Low Pass",
+            &FilterKind::HighPass  => "High Pass",
+            &FilterKind::BandPass  => "Band Pass",
+            &FilterKind::Notch     => "Notch",
+            &FilterKind::LowShelf  => "Low Shelf",
+            &FilterKind::HighShelf => "High Shelf",
+            &FilterKind::Peak      => "Peak"
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A stereo biquad filter, implemented in transposed direct form II with
+/// coefficients from RBJ's audio EQ cookbook.
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [Frame; 2]
+}
+
+impl Biquad {
+    /// Create a filter of `kind` with corner (or center) frequency `freq`,
+    /// quality factor `q` and `gain` in dB (only used by shelf and peak
+    /// filters) for `sample_rate`.
+    pub fn new(kind: FilterKind, freq: f64, q: f64, gain: f64,
+               sample_rate: f64) -> Self {
+        let mut biquad = Self { b: [1., 0., 0.], a: [0., 0.], z: Default::default() };
+        biquad.set(kind, freq, q, gain, sample_rate);
+        biquad
+    }
+
+    /// Update coefficients, keeping the filter state. See
+    /// [`new()`](#method.new).
+    pub fn set(&mut self, kind: FilterKind, freq: f64, q: f64, gain: f64,
+               sample_rate: f64) {
+        let freq = freq.min(sample_rate * 0.49).max(1.);
+        let w = 2. * PI * freq / sample_rate;
+        let (cos, alpha) = (w.cos(), w.sin() / (2. * q));
+        let amp = 10f64.powf(gain / 40.);
+
+        let (b, a) = match kind {
+            FilterKind::LowPass => {
+                let b1 = 1. - cos;
+                ([b1 / 2., b1, b1 / 2.], [1. + alpha, -2. * cos, 1. - alpha])
+            },
+            FilterKind::HighPass => {
+                let b1 = 1. + cos;
+                ([b1 / 2., -b1, b1 / 2.], [1. + alpha, -2. * cos, 1. - alpha])
+            },
+            FilterKind::BandPass => {
+                ([alpha, 0., -alpha], [1. + alpha, -2. * cos, 1. - alpha])
+            },
+            FilterKind::Notch => {
+                ([1., -2. * cos, 1.], [1. + alpha, -2. * cos, 1. - alpha])
+            },
+            FilterKind::Peak => {
+                ([1. + alpha * amp, -2. * cos, 1. - alpha * amp],
+                 [1. + alpha / amp, -2. * cos, 1. - alpha / amp])
+            },
+            FilterKind::LowShelf => {
+                let s = 2. * amp.sqrt() * alpha;
+                ([amp * ((amp + 1.) - (amp - 1.) * cos + s),
+                  2. * amp * ((amp - 1.) - (amp + 1.) * cos),
+                  amp * ((amp + 1.) - (amp - 1.) * cos - s)],
+                 [(amp + 1.) + (amp - 1.) * cos + s,
+                  -2. * ((amp - 1.) + (amp + 1.) * cos),
+                  (amp + 1.) + (amp - 1.) * cos - s])
+            },
+            FilterKind::HighShelf => {
+                let s = 2. * amp.sqrt() * alpha;
+                ([amp * ((amp + 1.) + (amp - 1.) * cos + s),
+                  -2. * amp * ((amp - 1.) + (amp + 1.) * cos),
+                  amp * ((amp + 1.) + (amp - 1.) * cos - s)],
+                 [(amp + 1.) - (amp - 1.) * cos + s,
+                  2. * ((amp - 1.) - (amp + 1.) * cos),
+                  (amp + 1.) - (amp - 1.) * cos - s])
+            }
+        };
+
+        self.b = [b[0] / a[0], b[1] / a[0], b[2] / a[0]];
+        self.a = [a[1] / a[0], a[2] / a[0]];
+    }
+
+    #[inline]
+    /// Filter a single frame.
+    pub fn process(&mut self, x: Frame) -> Frame {
+        let [b0, b1, b2] = self.b;
+        let [a1, a2] = self.a;
+
+        let y = x * b0.into() + self.z[0];
+        self.z[0] = x * b1.into() - y * a1.into() + self.z[1];
+        self.z[1] = x * b2.into() - y * a2.into();
+        y
+    }
+
+    /// Clear the filter state.
+    pub fn reset(&mut self) {
+        self.z = Default::default();
+    }
+
+    /// Get magnitude of the frequency response at `freq`.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::plugins::core::{Biquad, FilterKind};
+    /// let biquad = Biquad::new(FilterKind::LowPass, 1000., 0.5f64.sqrt(), 0., 48000.);
+    /// assert!((biquad.magnitude(0., 48000.) - 1.).abs() < 1e-9);
+    /// ```
+    pub fn magnitude(&self, freq: f64, sample_rate: f64) -> f64 {
+        let w = 2. * PI * freq / sample_rate;
+        // evaluate polynomials of z^-1 = e^-jw as (real, imaginary)
+        let eval = |c: [f64; 3]| {
+            let re = c[0] + c[1] * w.cos() + c[2] * (2. * w).cos();
+            let im = -c[1] * w.sin() - c[2] * (2. * w).sin();
+            (re * re + im * im).sqrt()
+        };
+        eval(self.b) / eval([1., self.a[0], self.a[1]])
+    }
+
+    /// Get group delay of the filter at DC, in samples. It's only defined
+    /// for filters which pass DC.
+    pub fn dc_delay(&self) -> f64 {
+        let delay = |c: [f64; 3]| (c[1] + 2. * c[2]) / (c[0] + c[1] + c[2]);
+        delay(self.b) - delay([1., self.a[0], self.a[1]])
+    }
+}
+
+const PARAM_KIND: usize = 0;
+const PARAM_CUTOFF: usize = 1;
+const PARAM_Q: usize = 2;
+const PARAM_GAIN: usize = 3;
+
+const RANGES: [PluginParamRange; 4] = [
+    PluginParamRange::Enum(7),
+    PluginParamRange::Float(10., 22000.),
+    PluginParamRange::Float(0.1, 20.),
+    PluginParamRange::Float(-24., 24.)
+];
+
+/// A biquad filter plugin, with kind, cutoff, Q and gain parameters.
+pub struct Filter {
+    kind: FilterKind,
+    cutoff: f64,
+    q: f64,
+    gain: f64,
+    sample_rate: f64,
+    biquad: Biquad
+}
+
+impl Filter {
+    fn update(&mut self) {
+        let (kind, cutoff, q, gain) = (self.kind, self.cutoff, self.q, self.gain);
+        self.biquad.set(kind, cutoff, q, gain, self.sample_rate);
+    }
+}
+
+impl Effect for Filter {
+    fn process_audio(&mut self, audio: &mut AudioBuffer) -> PluginResult<()> {
+        for frame in audio.iter_mut() {
+            *frame = self.biquad.process(*frame);
+        }
+        Ok(())
+    }
+}
+
+impl_effect_for!(Filter, {
+    fn core_changed(&mut self, config: &CoreConfig) {
+        self.sample_rate = config.sample_rate as f64;
+        self.update();
+    }
+
+    fn get_params(&self) -> PluginParamsDesc {
+        let kinds = FilterKind::all().iter().map(|k| k.name().to_owned());
+        box [
+            PluginParamDesc {
+                name: "Type".to_owned(),
+                kind: PluginParamKind::Enum(kinds.collect::<Vec<_>>().into_boxed_slice()),
+                default: ParamValue::Index(0)
+            },
+            PluginParamDesc {
+                name: "Cutoff".to_owned(),
+                kind: PluginParamKind::Float(10., 22000.),
+                default: ParamValue::Float(1000.)
+            },
+            PluginParamDesc {
+                name: "Q".to_owned(),
+                kind: PluginParamKind::Float(0.1, 20.),
+                default: ParamValue::Float(0.5f64.sqrt())
+            },
+            PluginParamDesc {
+                name: "Gain".to_owned(),
+                kind: PluginParamKind::Float(-24., 24.),
+                default: ParamValue::Float(0.)
+            }
+        ]
+    }
+
+    fn get_param(&self, index: usize) -> Option<ParamValue> {
+        match index {
+            PARAM_KIND => {
+                FilterKind::all().iter().position(|k| *k == self.kind)
+                    .map(ParamValue::Index)
+            },
+            PARAM_CUTOFF => Some(ParamValue::Float(self.cutoff)),
+            PARAM_Q => Some(ParamValue::Float(self.q)),
+            PARAM_GAIN => Some(ParamValue::Float(self.gain)),
+            _ => None
+        }
+    }
+
+    fn param_range(&self, index: usize) -> Option<PluginParamRange> {
+        RANGES.get(index).cloned()
+    }
+
+    fn set_param(&mut self, index: usize, value: ParamValue) -> PluginResult<()> {
+        self.check_param(index, &value)?;
+
+        match (index, value) {
+            (PARAM_KIND, ParamValue::Index(i)) => { self.kind = FilterKind::all()[i] },
+            (PARAM_CUTOFF, ParamValue::Float(f)) => { self.cutoff = f },
+            (PARAM_Q, ParamValue::Float(q)) => { self.q = q },
+            (PARAM_GAIN, ParamValue::Float(g)) => { self.gain = g },
+            _ => unreachable!("checked by the range")
+        }
+
+        self.update();
+        Ok(())
+    }
+});
+
+impl CorePlugin for Filter {
+    fn new(core: &Core) -> Self {
+        let (kind, cutoff, q, gain) = (FilterKind::LowPass, 1000., 0.5f64.sqrt(), 0.);
+        let sample_rate = core.config.sample_rate as f64;
+        let biquad = Biquad::new(kind, cutoff, q, gain, sample_rate);
+        Self { kind, cutoff, q, gain, sample_rate, biquad }
+    }
+
+    fn get_uuid() -> &'static str { "3c9b5f2e-8d1a-4e47-a6f0-5b2d7c91e034" }
+
+    fn get_desc(id: usize) -> PluginDesc {
+        PluginDesc::default().with_id(id).with_uuid(Self::get_uuid())
+            .with_name("Filter")
+            .with_category("Effect.Filter")
+            .with_description("biquad low/high/band pass, notch, shelf and peak filter.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use buffer::Buffer;
+
+    const RATE: f64 = 48000.;
+
+    fn db(magnitude: f64) -> f64 { 20. * magnitude.log10() }
+
+    fn biquad(kind: FilterKind, q: f64, gain: f64) -> Biquad {
+        Biquad::new(kind, 1000., q, gain, RATE)
+    }
+
+    #[test]
+    fn test_biquad_low_pass() {
+        let filter = biquad(FilterKind::LowPass, 0.5f64.sqrt(), 0.);
+        assert!(db(filter.magnitude(10., RATE)).abs() < 0.01);
+        assert!((db(filter.magnitude(1000., RATE)) + 3.01).abs() < 0.01);
+        assert!(db(filter.magnitude(10000., RATE)) < -38.);
+    }
+
+    #[test]
+    fn test_biquad_high_pass() {
+        let filter = biquad(FilterKind::HighPass, 0.5f64.sqrt(), 0.);
+        assert!(db(filter.magnitude(100., RATE)) < -38.);
+        assert!((db(filter.magnitude(1000., RATE)) + 3.01).abs() < 0.01);
+        assert!(db(filter.magnitude(20000., RATE)).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_biquad_band_pass_and_notch() {
+        let filter = biquad(FilterKind::BandPass, 2., 0.);
+        assert!(db(filter.magnitude(1000., RATE)).abs() < 0.01);
+        assert!(db(filter.magnitude(100., RATE)) < -20.);
+
+        let filter = biquad(FilterKind::Notch, 2., 0.);
+        assert!(filter.magnitude(1000., RATE) < 1e-6);
+        assert!(db(filter.magnitude(100., RATE)).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_biquad_peak_and_shelves() {
+        let filter = biquad(FilterKind::Peak, 1., 6.);
+        assert!((db(filter.magnitude(1000., RATE)) - 6.).abs() < 0.01);
+        assert!(db(filter.magnitude(20., RATE)).abs() < 0.1);
+
+        let filter = biquad(FilterKind::LowShelf, 0.5f64.sqrt(), -12.);
+        assert!((db(filter.magnitude(10., RATE)) + 12.).abs() < 0.05);
+        assert!((db(filter.magnitude(1000., RATE)) + 6.).abs() < 0.05);
+        assert!(db(filter.magnitude(20000., RATE)).abs() < 0.05);
+
+        let filter = biquad(FilterKind::HighShelf, 0.5f64.sqrt(), 12.);
+        assert!(db(filter.magnitude(10., RATE)).abs() < 0.05);
+        assert!((db(filter.magnitude(1000., RATE)) - 6.).abs() < 0.05);
+        assert!((db(filter.magnitude(20000., RATE)) - 12.).abs() < 0.05);
+    }
+
+    /// Measure amplitude of a sine with frequency `freq` passed through the
+    /// filter plugin, after it's settled.
+    fn measure(filter: &mut Filter, freq: f64) -> Frame {
+        let mut audio = AudioBuffer::with_length(RATE as usize / 4);
+        for (i, frame) in audio.iter_mut().enumerate() {
+            let x = (2. * PI * freq * i as f64 / RATE).sin();
+            *frame = (x, -x).into();
+        }
+
+        filter.process_audio(&mut audio).unwrap();
+        filter.biquad.reset();
+
+        let settled = audio.iter().skip(audio.len() / 2);
+        settled.fold(Frame::default(), |max, frame| {
+            let frame = frame.abs();
+            (max[0].max(frame[0]), max[1].max(frame[1])).into()
+        })
+    }
+
+    #[test]
+    fn test_filter_plugin() {
+        let config = CoreConfig { sample_rate: RATE as u32, ..Default::default() };
+        let mut filter = Filter::new(&Core::new(config));
+
+        filter.set_param(PARAM_CUTOFF, ParamValue::Float(2000.)).unwrap();
+        for &freq in [100., 2000., 8000.].iter() {
+            let expected = filter.biquad.magnitude(freq, RATE);
+            let [left, right] = measure(&mut filter, freq).0;
+            assert!((left - expected).abs() < 0.01);
+            assert!((right - expected).abs() < 0.01);
+        }
+
+        let invalid = filter.set_param(PARAM_Q, ParamValue::Float(100.));
+        assert_eq!(invalid, Err(PluginError::InvalidArgument));
+        assert_eq!(filter.get_param(PARAM_Q), Some(ParamValue::Float(0.5f64.sqrt())));
+    }
+
+    #[test]
+    fn test_filter_core_changed() {
+        let mut filter = Filter::new(&Core::new(Default::default()));
+        let before = filter.biquad.clone();
+
+        let config = CoreConfig { sample_rate: 96000, ..Default::default() };
+        filter.core_changed(&config);
+        assert!(filter.biquad != before);
+        assert!((db(filter.biquad.magnitude(1000., 96000.)) + 3.01).abs() < 0.01);
+    }
+}
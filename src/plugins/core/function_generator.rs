@@ -1,17 +1,62 @@
 use std::f64::consts::PI;
 use std::collections::HashMap;
 use buffer::prelude::*;
+use buffer::control::ParamValue;
 use plugins::prelude::*;
-use Core;
+use plugins::preset::Preset;
+use plugins::special::Generator;
+use tuning::SharedTuning;
+use {Core, CoreConfig};
 use super::CorePlugin;
 
+const PARAM_WAVEFORM: usize = 0;
+const PARAM_LEVEL: usize = 1;
+
+const RANGES: [PluginParamRange; 2] = [
+    PluginParamRange::Enum(4),
+    PluginParamRange::Float(-48., 12.)
+];
+
+const DEFAULTS: [ParamValue; 2] = [ParamValue::Index(0), ParamValue::Float(0.)];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Wave shape of a [`FunctionGenerator`](struct.FunctionGenerator.html).
+pub enum Waveform { Sine, Square, Saw, Triangle }
+
+impl Waveform {
+    /// Get all waveforms, ordered by their index.
+    pub fn all() -> &'static [Waveform] {
+        &[Waveform::Sine, Waveform::Square, Waveform::Saw, Waveform::Triangle]
+    }
+
+    /// Get a human readable name of the waveform.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Waveform::Sine => "Sine",
+            Waveform::Square => "Square",
+            Waveform::Saw => "Saw",
+            Waveform::Triangle => "Triangle"
+        }
+    }
+
+    /// Get value of the wave at `phase`, in [0, 2π).
+    fn at(&self, phase: f64) -> f64 {
+        match *self {
+            Waveform::Sine => phase.sin(),
+            Waveform::Square => if phase < PI { 1. } else { -1. },
+            Waveform::Saw => phase / PI - 1.,
+            Waveform::Triangle => 1. - 2. * (phase / PI - 1.).abs()
+        }
+    }
+}
+
 #[inline]
-fn calculate(position: f64, state: &mut NoteState) -> Frame {
+fn calculate(step: f64, waveform: Waveform, state: &mut NoteState) -> Frame {
     const TWO_PI: f64 = 2. * PI;
 
-    let frame: Frame = state.phase.sin().into();
+    let frame: Frame = waveform.at(state.phase).into();
 
-    let phase = state.phase + TWO_PI * position * state.freq;
+    let phase = state.phase + TWO_PI * step * state.freq;
 
     state.phase = if phase >= TWO_PI { phase - TWO_PI } else { phase };
 
@@ -19,6 +64,7 @@ fn calculate(position: f64, state: &mut NoteState) -> Frame {
 }
 
 struct NoteState {
+    name: NoteName,
     params: NoteParams,
     phase: f64,
     // cache to note frequency
@@ -27,83 +73,150 @@ struct NoteState {
     velocities: Frame
 }
 
-pub struct FunctionGenerator(HashMap<NoteName, NoteState>, f64);
+/// Generates a naive (not band limited) wave for each note.
+pub struct FunctionGenerator {
+    notes: HashMap<NoteRef, NoteState>,
+    sample_rate: f64,
+    tuning: SharedTuning,
+    waveform: Waveform,
+    level: f64
+}
 
 impl FunctionGenerator {
+    /// Use `tuning` instead of the core's tuning for new notes.
+    pub fn set_tuning(&mut self, tuning: SharedTuning) {
+        self.tuning = tuning;
+    }
+
     #[inline]
     fn apply_moment(&mut self, moment: &Moment) {
         if let &Some(ref events) = moment {
             for event in events.iter() {
                 match event {
-                    &Event::NoteOn(ref note, _) => {
+                    &Event::NoteOn(ref note, reference) => {
                         let state = NoteState {
+                            name: note.name.clone(),
                             params: note.params.clone(),
                             phase: 0.,
-                            freq: note.freq(),
+                            freq: note.freq_with(&*self.tuning),
                             velocities: note.params.velocities().into()
                         };
-                        self.0.insert(note.name.clone(), state);
+                        // notes which are not mapped by the tuning are muted
+                        if state.freq.is_finite() {
+                            self.notes.insert(reference, state);
+                        }
                     },
-                    &Event::NoteSet(ref name, ref param) => {
-                        if let Some(state) = self.0.get_mut(name) {
+                    &Event::NoteSet(ref reference, ref param) => {
+                        if let Some(state) = self.notes.get_mut(reference) {
                             state.params.apply(param);
                             if let &NoteParam::Cents(c) = param {
-                                state.freq = name.detune(c)
+                                state.freq = state.name.detune_with(&*self.tuning, c)
                             } else {
                                 state.velocities = state.params.velocities().into();
                             }
                         }
                     },
-                    &Event::NoteOff(ref name) => {
-                        self.0.remove(name);
+                    &Event::NoteOff(ref reference) => {
+                        self.notes.remove(reference);
                     },
-                    &Event::Panic => {
-                        self.0.clear()
+                    &Event::ParamSet(index, ref value) => {
+                        let _ = self.set_param(index, value.clone());
                     },
-                    _ => {}
+                    &Event::ParamReset(index) => {
+                        if let Some(value) = self.param_default(index) {
+                            let _ = self.set_param(index, value);
+                        }
+                    },
+                    &Event::Panic => {
+                        self.notes.clear()
+                    }
                 }
             }
         }
     }
 }
 
-impl Plugin for FunctionGenerator {
-    fn get_io_descriptor(&self) -> PluginIoDesc {
-        use std::convert::TryFrom;
-        TryFrom::try_from("Control:C|Wave:A").unwrap()
-    }
+impl Generator for FunctionGenerator {
+    fn generate(&mut self, control: Option<&ControlBuffer>,
+                audio: &mut AudioBuffer) -> PluginResult<()> {
+        let step = 1. / self.sample_rate;
 
-    fn process(&mut self, inputs: &PluginIo, outputs: &mut PluginIo)
-        -> PluginResult<()> {
-        if inputs.len() != 1 || outputs.len() != 1 {
-            return Err(PluginError::InvalidArgument);
-        }
+        for (i, frame) in audio.iter_mut().enumerate() {
+            if let Some(control) = control {
+                self.apply_moment(&control[i]);
+            }
 
-        if let PluginIoBuffer::Control(ref control) = inputs[0] {
-            if let PluginIoBuffer::Audio(ref mut audio) = outputs[1] {
-                let items = control.iter().zip(audio.iter_mut());
+            let (waveform, level) = (self.waveform, Frame::from(10f64.powf(self.level / 20.)));
+            *frame = self.notes.values_mut()
+                .map(|state| calculate(step, waveform, state))
+                .sum::<Frame>() * level;
+        }
 
-                for (i, (moment, frame)) in items.enumerate() {
-                    self.apply_moment(moment);
+        Ok(())
+    }
+}
 
-                    let pos = i as f64 / self.1;
+impl_generator_for!(FunctionGenerator, {
+    fn core_changed(&mut self, config: &CoreConfig) {
+        self.sample_rate = config.sample_rate as f64;
+        self.tuning = config.tuning.clone();
+    }
 
-                    *frame = self.0.values_mut()
-                        .map(|state| calculate(pos, state))
-                        .sum();
-                }
+    fn get_params(&self) -> PluginParamsDesc {
+        let waveforms = Waveform::all().iter().map(|w| w.name().to_owned());
+        box [
+            PluginParamDesc {
+                name: "Waveform".to_owned(),
+                kind: PluginParamKind::Enum(waveforms.collect::<Vec<_>>().into_boxed_slice()),
+                default: ParamValue::Index(0)
+            },
+            PluginParamDesc {
+                name: "Level".to_owned(),
+                kind: PluginParamKind::Float(-48., 12.),
+                default: ParamValue::Float(0.)
             }
+        ]
+    }
+
+    fn get_param(&self, index: usize) -> Option<ParamValue> {
+        match index {
+            PARAM_WAVEFORM => {
+                let index = Waveform::all().iter().position(|w| *w == self.waveform);
+                index.map(ParamValue::Index)
+            },
+            PARAM_LEVEL => Some(ParamValue::Float(self.level)),
+            _ => None
         }
+    }
+
+    fn param_range(&self, index: usize) -> Option<PluginParamRange> {
+        RANGES.get(index).cloned()
+    }
+
+    fn param_default(&self, index: usize) -> Option<ParamValue> {
+        DEFAULTS.get(index).cloned()
+    }
+
+    fn set_param(&mut self, index: usize, value: ParamValue) -> PluginResult<()> {
+        self.check_param(index, &value)?;
 
+        match (index, value) {
+            (PARAM_WAVEFORM, ParamValue::Index(w)) => { self.waveform = Waveform::all()[w] },
+            (PARAM_LEVEL, ParamValue::Float(l)) => { self.level = l },
+            _ => unreachable!("checked by the range")
+        }
         Ok(())
     }
-}
+});
 
 impl CorePlugin for FunctionGenerator {
     fn new(core: &Core) -> Self {
         Self {
-            0: HashMap::new(),
-            1: core.config.sample_rate as f64
+            notes: HashMap::new(),
+            sample_rate: core.config.sample_rate as f64,
+            tuning: core.config.tuning.clone(),
+            waveform: Waveform::Sine,
+            level: 0.
         }
     }
 
@@ -114,4 +227,68 @@ impl CorePlugin for FunctionGenerator {
             .with_name("Function Generator")
             .with_description("generates multiple waves.")
     }
+
+    fn get_presets() -> Vec<Preset> {
+        let preset = |name, waveform: Waveform, level| Preset::new(name, Self::get_uuid())
+            .with_param("Waveform", ParamValue::Index(waveform as usize))
+            .with_param("Level", ParamValue::Float(level));
+        vec![
+            preset("Pure Sine", Waveform::Sine, 0.),
+            preset("Hollow Square", Waveform::Square, -12.),
+            preset("Bright Saw", Waveform::Saw, -9.),
+            preset("Soft Triangle", Waveform::Triangle, -3.)
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buffer::Buffer;
+    use super::*;
+
+    fn render(plugin: &mut FunctionGenerator, control: ControlBuffer) -> Vec<Sample> {
+        let mut outputs: PluginIo = box [PluginIoBuffer::Audio(AudioBuffer::with_length(4))];
+        let inputs: PluginIo = box [PluginIoBuffer::Control(control)];
+        plugin.process(&inputs, &mut outputs).unwrap();
+        match outputs[0] {
+            PluginIoBuffer::Audio(ref audio) => audio.iter().map(|f| f[0]).collect(),
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_function_generator_param_events() {
+        // a quarter of the sample rate, so the phase steps by π/2
+        let mut config = CoreConfig { sample_rate: 1760, ..Default::default() };
+        config.tuning = ::std::sync::Arc::new(::tuning::EqualTemperament::with_pitch(440.));
+        let mut plugin = FunctionGenerator::new(&Core::new(config));
+
+        let preset = FunctionGenerator::get_presets().into_iter()
+            .find(|p| p.name == "Hollow Square").unwrap();
+        let note = Note { name: NoteName::from_midi(69), params: Default::default() };
+        let mut control = ControlBuffer::with_length(4);
+        control[0] = Some(box [Event::NoteOn(note, NoteRef(0))]);
+        control[2] = Some(preset.events(&plugin).unwrap());
+
+        let output = render(&mut plugin, control);
+        // the peak of the sine, then the low half of the quieter square
+        let ratio = output[3] / output[1];
+        assert!((ratio + 10f64.powf(-12. / 20.)).abs() < 1e-9);
+        assert_eq!(plugin.get_param(PARAM_WAVEFORM), Some(ParamValue::Index(1)));
+    }
+
+    #[test]
+    fn test_function_generator_tuning() {
+        let mut config = CoreConfig { sample_rate: 1760, ..Default::default() };
+        config.tuning = ::std::sync::Arc::new(::tuning::EqualTemperament::with_pitch(880.));
+        let mut plugin = FunctionGenerator::new(&Core::new(Default::default()));
+        plugin.core_changed(&config);
+
+        // A4 is tuned to half the sample rate, so the sine steps by π
+        let note = Note { name: NoteName::from_midi(69), params: Default::default() };
+        let mut control = ControlBuffer::with_length(4);
+        control[0] = Some(box [Event::NoteOn(note, NoteRef(0))]);
+        let output = render(&mut plugin, control);
+        assert!(output.iter().all(|s| s.abs() < 1e-9));
+    }
 }
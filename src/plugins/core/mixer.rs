@@ -0,0 +1,309 @@
+use buffer::Buffer;
+use buffer::prelude::*;
+use buffer::control::ParamValue;
+use plugins::prelude::*;
+use Core;
+use super::CorePlugin;
+
+const PARAM_MASTER: usize = 0;
+const PARAMS_PER_CHANNEL: usize = 4;
+const PARAM_GAIN: usize = 0;
+const PARAM_PANNING: usize = 1;
+const PARAM_MUTE: usize = 2;
+const PARAM_SOLO: usize = 3;
+
+#[inline]
+fn amplitude(db: f64) -> f64 { 10f64.powf(db / 20.) }
+
+#[derive(Debug, Clone, Default)]
+struct Channel {
+    /// gain in dB
+    gain: f64,
+    panning: f64,
+    mute: bool,
+    solo: bool,
+    connected: bool
+}
+
+impl Channel {
+    #[inline]
+    fn gains(&self) -> Frame {
+        Frame::from(amplitude(self.gain)).pan(self.panning)
+    }
+}
+
+/// A summing bus. Each connected input is a channel with it's own gain,
+/// panning, mute and solo parameters, and all channels are summed to the
+/// master output.
+///
+/// There is always a spare input after the channels; connecting it adds a
+/// new channel, and disconnecting the last channels removes them. In both
+/// cases [`io_changed()`][0] returns [`PluginError::InvalidArgument`][1] so
+/// the host fetches the new IO descriptor and parameters.
+/// [0]: ../trait.Plugin.html#method.io_changed
+/// [1]: ../enum.PluginError.html#variant.InvalidArgument
+pub struct Mixer {
+    channels: Vec<Channel>,
+    /// master gain in dB
+    master: f64
+}
+
+impl Mixer {
+    #[inline]
+    /// Get number of channels, excluding the spare input.
+    pub fn channels(&self) -> usize { self.channels.len() }
+
+    fn param_desc(name: String, kind: PluginParamKind, default: ParamValue)
+        -> PluginParamDesc {
+        PluginParamDesc { name, kind, default }
+    }
+}
+
+impl Plugin for Mixer {
+    fn get_params(&self) -> PluginParamsDesc {
+        let mut params = vec![Self::param_desc("Master".to_owned(),
+            PluginParamKind::Float(-60., 12.), ParamValue::Float(0.))];
+
+        for i in 1..self.channels.len() + 1 {
+            params.push(Self::param_desc(format!("Gain {}", i),
+                PluginParamKind::Float(-60., 12.), ParamValue::Float(0.)));
+            params.push(Self::param_desc(format!("Pan {}", i),
+                PluginParamKind::Float(-100., 100.), ParamValue::Float(0.)));
+            params.push(Self::param_desc(format!("Mute {}", i),
+                PluginParamKind::Boolean, ParamValue::Boolean(false)));
+            params.push(Self::param_desc(format!("Solo {}", i),
+                PluginParamKind::Boolean, ParamValue::Boolean(false)));
+        }
+
+        params.into_boxed_slice()
+    }
+
+    fn get_param(&self, index: usize) -> Option<ParamValue> {
+        if index == PARAM_MASTER {
+            return Some(ParamValue::Float(self.master));
+        }
+
+        let (channel, param) = ((index - 1) / PARAMS_PER_CHANNEL,
+                                (index - 1) % PARAMS_PER_CHANNEL);
+        self.channels.get(channel).map(|channel| match param {
+            PARAM_GAIN => ParamValue::Float(channel.gain),
+            PARAM_PANNING => ParamValue::Float(channel.panning),
+            PARAM_MUTE => ParamValue::Boolean(channel.mute),
+            _ => ParamValue::Boolean(channel.solo)
+        })
+    }
+
+    fn param_range(&self, index: usize) -> Option<PluginParamRange> {
+        if index == PARAM_MASTER {
+            return Some(PluginParamRange::Float(-60., 12.));
+        }
+        if index > self.channels.len() * PARAMS_PER_CHANNEL {
+            return None;
+        }
+
+        Some(match (index - 1) % PARAMS_PER_CHANNEL {
+            PARAM_GAIN => PluginParamRange::Float(-60., 12.),
+            PARAM_PANNING => PluginParamRange::Float(-100., 100.),
+            _ => PluginParamRange::Boolean
+        })
+    }
+
+    fn set_param(&mut self, index: usize, value: ParamValue) -> PluginResult<()> {
+        self.check_param(index, &value)?;
+
+        if index == PARAM_MASTER {
+            if let ParamValue::Float(gain) = value { self.master = gain; }
+            return Ok(());
+        }
+
+        let channel = &mut self.channels[(index - 1) / PARAMS_PER_CHANNEL];
+        match ((index - 1) % PARAMS_PER_CHANNEL, value) {
+            (PARAM_GAIN, ParamValue::Float(g)) => { channel.gain = g },
+            (PARAM_PANNING, ParamValue::Float(p)) => { channel.panning = p },
+            (PARAM_MUTE, ParamValue::Boolean(m)) => { channel.mute = m },
+            (PARAM_SOLO, ParamValue::Boolean(s)) => { channel.solo = s },
+            _ => unreachable!("checked by the range")
+        }
+        Ok(())
+    }
+
+    fn io_changed(&mut self, change: PluginIoChange) -> PluginResult<()> {
+        if change.is_output {
+            return if change.index == 0 { Ok(()) }
+                   else { Err(PluginError::InvalidArgument) };
+        }
+
+        let count = self.channels.len();
+        match (change.index, change.status) {
+            (i, _) if i > count => Err(PluginError::InvalidArgument),
+            (i, Some(PluginIoKind::Audio)) if i == count => {
+                self.channels.push(Channel { connected: true, ..Default::default() });
+                Err(PluginError::InvalidArgument)
+            },
+            (i, Some(_)) if i == count => Err(PluginError::InvalidArgument),
+            (i, None) if i == count => Ok(()),
+            (i, status) => {
+                self.channels[i].connected = status.is_some();
+
+                while self.channels.last().map_or(false, |c| !c.connected) {
+                    self.channels.pop();
+                }
+
+                if self.channels.len() == count { Ok(()) }
+                else { Err(PluginError::InvalidArgument) }
+            }
+        }
+    }
+
+    fn get_io_descriptor(&self) -> PluginIoDesc {
+        let count = self.channels.len() + 1;
+        let inputs = (1..count + 1).map(|i| format!("Input {}", i));
+        PluginIoDesc {
+            mode: PluginIoMode::Complex(PluginComplexIo {
+                inputs: vec![PluginIoKind::Audio; count].into_boxed_slice(),
+                outputs: box [PluginIoKind::Audio]
+            }),
+            inputs: inputs.collect::<Vec<_>>().into_boxed_slice(),
+            outputs: box ["Master".to_owned()]
+        }
+    }
+
+    fn process(&mut self, inputs: &PluginIo, outputs: &mut PluginIo)
+        -> PluginResult<()> {
+        let output = match outputs.get_mut(0) {
+            Some(&mut PluginIoBuffer::Audio(ref mut audio)) => audio,
+            Some(&mut PluginIoBuffer::Disconnected) => { return Ok(()); },
+            _ => { return Err(PluginError::InvalidArgument); }
+        };
+
+        output.clear();
+
+        let solo = self.channels.iter().any(|c| c.solo);
+        for (channel, input) in self.channels.iter().zip(inputs.iter()) {
+            if channel.mute || (solo && !channel.solo) {
+                continue;
+            }
+
+            if let &PluginIoBuffer::Audio(ref audio) = input {
+                let gains = channel.gains();
+                for (o, i) in output.iter_mut().zip(audio.iter()) {
+                    *o += *i * gains;
+                }
+            }
+        }
+
+        output.gain(amplitude(self.master));
+        Ok(())
+    }
+}
+
+impl CorePlugin for Mixer {
+    fn new(_core: &Core) -> Self {
+        Self { channels: Vec::new(), master: 0. }
+    }
+
+    fn get_uuid() -> &'static str { "8f0e6a43-27c5-4b1d-9e8a-d6c1f5a3b072" }
+
+    fn get_desc(id: usize) -> PluginDesc {
+        PluginDesc::default().with_id(id).with_uuid(Self::get_uuid())
+            .with_name("Mixer")
+            .with_category("Mixer")
+            .with_description("sums inputs with gain, panning, mute and solo.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connect(mixer: &mut Mixer, index: usize, connected: bool)
+        -> PluginResult<()> {
+        let status = if connected { Some(PluginIoKind::Audio) } else { None };
+        mixer.io_changed(PluginIoChange { is_output: false, index, status })
+    }
+
+    fn mixer(channels: usize) -> Mixer {
+        let mut mixer = Mixer::new(&Core::new(Default::default()));
+        for i in 0..channels {
+            assert_eq!(connect(&mut mixer, i, true), Err(PluginError::InvalidArgument));
+        }
+        mixer
+    }
+
+    fn run(mixer: &mut Mixer, frames: &[Frame]) -> Frame {
+        let mut inputs = Vec::new();
+        for frame in frames.iter() {
+            let mut audio = AudioBuffer::with_length(1);
+            audio[0] = *frame;
+            inputs.push(PluginIoBuffer::Audio(audio));
+        }
+        inputs.push(PluginIoBuffer::Disconnected);
+
+        let inputs = inputs.into_boxed_slice();
+        let mut outputs: PluginIo = box [
+            PluginIoBuffer::Audio(AudioBuffer::with_length(1))
+        ];
+        mixer.process(&inputs, &mut outputs).unwrap();
+
+        match outputs[0] {
+            PluginIoBuffer::Audio(ref audio) => audio[0],
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_mixer_io() {
+        let mut mixer = mixer(3);
+        assert_eq!(mixer.channels(), 3);
+        assert_eq!(mixer.get_io_descriptor().inputs.len(), 4);
+        assert_eq!(mixer.get_params().len(), 1 + 3 * PARAMS_PER_CHANNEL);
+
+        // disconnecting a middle channel keeps it
+        assert_eq!(connect(&mut mixer, 1, false), Ok(()));
+        assert_eq!(mixer.channels(), 3);
+
+        // disconnecting the last one removes all trailing disconnected ones
+        assert_eq!(connect(&mut mixer, 2, false), Err(PluginError::InvalidArgument));
+        assert_eq!(mixer.channels(), 1);
+
+        assert_eq!(connect(&mut mixer, 1, false), Ok(()));
+        assert_eq!(connect(&mut mixer, 5, true), Err(PluginError::InvalidArgument));
+        assert_eq!(mixer.channels(), 1);
+    }
+
+    #[test]
+    fn test_mixer_sum() {
+        let mut mixer = mixer(2);
+        let output = run(&mut mixer, &[(1., 0.5).into(), (0.25, 0.5).into()]);
+        assert_eq!(output, (1.25, 1.).into());
+
+        mixer.set_param(PARAM_MASTER, ParamValue::Float(-6.)).unwrap();
+        let output = run(&mut mixer, &[(1., 0.5).into(), (0.25, 0.5).into()]);
+        assert!((output[0] - 1.25 * amplitude(-6.)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_mixer_channel_params() {
+        let mut mixer = mixer(2);
+        let frames = [Frame::from(1.), Frame::from(0.5)];
+
+        mixer.set_param(1 + PARAM_GAIN, ParamValue::Float(-12.)).unwrap();
+        mixer.set_param(5 + PARAM_PANNING, ParamValue::Float(-100.)).unwrap();
+        let output = run(&mut mixer, &frames);
+        let expected = Frame::from(amplitude(-12.)) + Frame::from(0.5).pan(-100.);
+        assert!((output[0] - expected[0]).abs() < 1e-9);
+        assert!((output[1] - expected[1]).abs() < 1e-9);
+
+        mixer.set_param(5 + PARAM_SOLO, ParamValue::Boolean(true)).unwrap();
+        let output = run(&mut mixer, &frames);
+        assert_eq!(output, Frame::from(0.5).pan(-100.));
+
+        mixer.set_param(5 + PARAM_MUTE, ParamValue::Boolean(true)).unwrap();
+        assert_eq!(run(&mut mixer, &frames), Frame::default());
+
+        assert_eq!(mixer.get_param(5 + PARAM_MUTE), Some(ParamValue::Boolean(true)));
+        assert_eq!(mixer.get_param(9), None);
+        let invalid = mixer.set_param(1 + PARAM_PANNING, ParamValue::Boolean(true));
+        assert_eq!(invalid, Err(PluginError::InvalidArgument));
+    }
+}
@@ -1,9 +1,31 @@
 use Core;
 use super::prelude::*;
 use super::{Factory, FactoryDesc};
+use super::preset::Preset;
 
 mod function_generator;
-pub use self::function_generator::FunctionGenerator;
+pub use self::function_generator::{FunctionGenerator, Waveform};
+mod filter;
+pub use self::filter::{Filter, Biquad, FilterKind};
+mod mixer;
+pub use self::mixer::Mixer;
+mod delay;
+pub use self::delay::Delay;
+mod reverb;
+pub use self::reverb::Reverb;
+mod dynamics;
+pub use self::dynamics::{Dynamics, DynamicsKind, Detection};
+pub use self::dynamics::{Compressor, Limiter, Gate};
+mod convolution;
+pub use self::convolution::{Convolver, Convolution};
+mod oversampler;
+pub use self::oversampler::{Oversampler, Oversampled};
+mod waveshaper;
+pub use self::waveshaper::{Curve, Waveshaper};
+mod sampler;
+pub use self::sampler::Sampler;
+mod file_writer;
+pub use self::file_writer::FileWriter;
 
 pub trait CorePlugin: Plugin {
     fn new(core: &Core) -> Self;
@@ -11,6 +33,9 @@ pub trait CorePlugin: Plugin {
     fn get_uuid() -> &'static str;
 
     fn get_desc(id: usize) -> PluginDesc;
+
+    /// Get the presets shipped with the plugin.
+    fn get_presets() -> Vec<Preset> { Vec::new() }
 }
 
 /// A factory which holds core plugins
@@ -31,13 +56,89 @@ impl<'a> Factory for CoreFactory<'a> {
     }
 
     fn get_plugins(&self) -> Box<[PluginDesc]> {
-        box [ FunctionGenerator::get_desc(0) ]
+        box [
+            FunctionGenerator::get_desc(0),
+            Filter::get_desc(1),
+            Mixer::get_desc(2),
+            Delay::get_desc(3),
+            Reverb::get_desc(4),
+            Compressor::get_desc(5),
+            Limiter::get_desc(6),
+            Gate::get_desc(7),
+            Convolution::get_desc(8),
+            Waveshaper::get_desc(9),
+            Sampler::get_desc(10),
+            FileWriter::get_desc(11)
+        ]
     }
 
     fn create_plugin(&mut self, id: usize) -> PluginResult<Box<Plugin>> {
         match id {
             0 => Ok(box FunctionGenerator::new(&self.core)),
+            1 => Ok(box Filter::new(&self.core)),
+            2 => Ok(box Mixer::new(&self.core)),
+            3 => Ok(box Delay::new(&self.core)),
+            4 => Ok(box Reverb::new(&self.core)),
+            5 => Ok(box Compressor::new(&self.core)),
+            6 => Ok(box Limiter::new(&self.core)),
+            7 => Ok(box Gate::new(&self.core)),
+            8 => Ok(box Convolution::new(&self.core)),
+            9 => Ok(box Waveshaper::new(&self.core)),
+            10 => Ok(box Sampler::new(&self.core)),
+            11 => Ok(box FileWriter::new(&self.core)),
             _ => Err(PluginError::InvalidArgument)
         }
     }
+
+    fn get_presets(&self) -> Box<[Preset]> {
+        let presets = [
+            FunctionGenerator::get_presets(),
+            Filter::get_presets(),
+            Mixer::get_presets(),
+            Delay::get_presets(),
+            Reverb::get_presets(),
+            Compressor::get_presets(),
+            Limiter::get_presets(),
+            Gate::get_presets(),
+            Convolution::get_presets(),
+            Waveshaper::get_presets(),
+            Sampler::get_presets(),
+            FileWriter::get_presets()
+        ];
+        presets.concat().into_boxed_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_core_param_ranges() {
+        let core = Core::new(Default::default());
+        let mut factory = CoreFactory::new(&core);
+        for desc in factory.get_plugins().iter() {
+            let plugin = factory.create_plugin(desc.id).unwrap();
+            let params = plugin.get_params();
+            for (i, param) in params.iter().enumerate() {
+                assert_eq!(plugin.param_range(i), Some(param.kind.range()), "{}", param.name);
+                assert_eq!(plugin.check_param(i, &param.default), Ok(()), "{}", param.name);
+                assert_eq!(plugin.param_default(i), Some(param.default.clone()), "{}", param.name);
+            }
+            assert_eq!(plugin.param_range(params.len()), None, "{}", desc.name);
+            assert_eq!(plugin.param_default(params.len()), None, "{}", desc.name);
+        }
+    }
+
+    #[test]
+    fn test_core_presets() {
+        let core = Core::new(Default::default());
+        let mut factory = CoreFactory::new(&core);
+        let plugins = factory.get_plugins();
+        for preset in factory.get_presets().iter() {
+            let desc = plugins.iter().find(|desc| desc.uuid == preset.uuid).unwrap();
+            let mut plugin = factory.create_plugin(desc.id).unwrap();
+            assert_eq!(preset.apply(&mut *plugin), Ok(()), "{}", preset.name);
+        }
+    }
 }
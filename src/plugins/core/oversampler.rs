@@ -0,0 +1,517 @@
+use std::f64::consts::PI;
+use buffer::Buffer;
+use buffer::prelude::*;
+use buffer::control::ParamValue;
+use plugins::prelude::*;
+use CoreConfig;
+use super::{Biquad, FilterKind};
+
+/// Number of biquads of each anti-aliasing filter.
+const SECTIONS: usize = 4;
+
+/// Cutoff of the anti-aliasing filters, relative to the base sample rate.
+const CUTOFF: f64 = 0.45;
+
+fn lowpass(factor: usize, sample_rate: f64) -> Vec<Biquad> {
+    // butterworth filter of order 2 * SECTIONS, as cascaded biquads
+    (0..SECTIONS).map(|k| {
+        let angle = (2 * k + 1) as f64 * PI / (4 * SECTIONS) as f64;
+        let q = 1. / (2. * angle.cos());
+        let rate = sample_rate * factor as f64;
+        Biquad::new(FilterKind::LowPass, CUTOFF * sample_rate, q, 0., rate)
+    }).collect()
+}
+
+/// Runs a nonlinear process at a multiple of the sample rate, to reduce
+/// aliasing. Frames are upsampled by zero stuffing and downsampled by
+/// decimation, both through 8th order butterworth low-pass filters.
+#[derive(Clone)]
+pub struct Oversampler {
+    factor: usize,
+    up: Vec<Biquad>,
+    down: Vec<Biquad>
+}
+
+impl Oversampler {
+    /// Create an oversampler by `factor` for base `sample_rate`.
+    ///
+    /// # Panics
+    /// Panics if `factor` is 0.
+    pub fn new(factor: usize, sample_rate: f64) -> Self {
+        assert_ne!(factor, 0);
+        let up = lowpass(factor, sample_rate);
+        Self { factor, down: up.clone(), up }
+    }
+
+    #[inline]
+    /// Get oversampling factor.
+    pub fn factor(&self) -> usize { self.factor }
+
+    /// Process a single `frame` by running `f` on `factor` frames at the
+    /// higher sample rate.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::plugins::core::Oversampler;
+    ///
+    /// let mut oversampler = Oversampler::new(1, 44100.);
+    /// let frame = oversampler.process((0.5).into(), |f| f * (2.).into());
+    /// assert_eq!(frame, (1.).into());
+    /// ```
+    pub fn process<F>(&mut self, frame: Frame, mut f: F) -> Frame
+        where F: FnMut(Frame) -> Frame {
+        if self.factor == 1 {
+            return f(frame);
+        }
+
+        let mut output = Frame::default();
+        for k in 0..self.factor {
+            let stuffed = if k == 0 { frame * (self.factor as f64).into() }
+                          else { Frame::default() };
+            let upsampled = self.up.iter_mut().fold(stuffed, |x, b| b.process(x));
+            output = self.down.iter_mut().fold(f(upsampled), |x, b| b.process(x));
+        }
+        output
+    }
+
+    /// Upsample `input` into `output`, which should be `factor` times
+    /// longer.
+    pub fn upsample(&mut self, input: &[Frame], output: &mut [Frame]) {
+        if self.factor == 1 {
+            return output.copy_from_slice(input);
+        }
+
+        let gain = Frame::from(self.factor as f64);
+        for (i, frame) in output.iter_mut().enumerate() {
+            let stuffed = if i % self.factor == 0 { input[i / self.factor] * gain }
+                          else { Frame::default() };
+            *frame = self.up.iter_mut().fold(stuffed, |x, b| b.process(x));
+        }
+    }
+
+    /// Downsample `input` into `output`, which should be `factor` times
+    /// shorter.
+    pub fn downsample(&mut self, input: &[Frame], output: &mut [Frame]) {
+        if self.factor == 1 {
+            return output.copy_from_slice(input);
+        }
+
+        for (i, frame) in input.iter().enumerate() {
+            let filtered = self.down.iter_mut().fold(*frame, |x, b| b.process(x));
+            if i % self.factor == self.factor - 1 {
+                output[i / self.factor] = filtered;
+            }
+        }
+    }
+
+    /// Get delay of the filters at low frequencies, in frames of the base
+    /// sample rate.
+    pub fn latency(&self) -> f64 {
+        if self.factor == 1 {
+            return 0.;
+        }
+        let delay = self.up.iter().chain(self.down.iter()).map(Biquad::dc_delay);
+        delay.sum::<f64>() / self.factor as f64
+    }
+
+    /// Clear the filters state.
+    pub fn reset(&mut self) {
+        self.up.iter_mut().chain(self.down.iter_mut()).for_each(Biquad::reset);
+    }
+}
+
+/// Make `high` a buffer like `buffer`, `factor` times longer. Audio is
+/// upsampled by `oversampler` if given, and events are placed at the first
+/// frame of their moment.
+fn stretch(buffer: &PluginIoBuffer, high: &mut PluginIoBuffer, factor: usize,
+           oversampler: Option<&mut Oversampler>) {
+    match *buffer {
+        PluginIoBuffer::Audio(ref audio) => {
+            let length = audio.len() * factor;
+            let fits = match *high {
+                PluginIoBuffer::Audio(ref high) => high.len() == length,
+                _ => false
+            };
+            if !fits {
+                *high = PluginIoBuffer::Audio(AudioBuffer::with_length(length));
+            }
+            if let (Some(oversampler), &mut PluginIoBuffer::Audio(ref mut high)) =
+                (oversampler, high) {
+                oversampler.upsample(audio, high);
+            }
+        },
+        PluginIoBuffer::Control(ref control) => {
+            let length = control.len() * factor;
+            let fits = match *high {
+                PluginIoBuffer::Control(ref high) => high.len() == length,
+                _ => false
+            };
+            if !fits {
+                *high = PluginIoBuffer::Control(ControlBuffer::with_length(length));
+            }
+            if let PluginIoBuffer::Control(ref mut high) = *high {
+                for (i, moment) in high.iter_mut().enumerate() {
+                    *moment = if i % factor == 0 { control[i / factor].clone() } else { None };
+                }
+            }
+        },
+        PluginIoBuffer::Disconnected => { *high = PluginIoBuffer::Disconnected; }
+    }
+}
+
+/// Write `high` back to `buffer`. Audio is downsampled by `oversampler` and
+/// events of each `factor` moments are moved, or merged if more than one of
+/// them has events.
+fn shrink(high: &mut PluginIoBuffer, buffer: &mut PluginIoBuffer, factor: usize,
+          oversampler: &mut Oversampler) {
+    match (high, buffer) {
+        (&mut PluginIoBuffer::Audio(ref high), &mut PluginIoBuffer::Audio(ref mut audio)) => {
+            oversampler.downsample(high, audio);
+        },
+        (&mut PluginIoBuffer::Control(ref mut high),
+         &mut PluginIoBuffer::Control(ref mut control)) => {
+            for (moment, moments) in control.iter_mut().zip(high.chunks_mut(factor)) {
+                *moment = None;
+                for events in moments.iter_mut().filter_map(Option::take) {
+                    *moment = match moment.take() {
+                        None => Some(events),
+                        Some(merged) => {
+                            let mut merged = merged.into_vec();
+                            merged.extend(events.into_vec());
+                            Some(merged.into_boxed_slice())
+                        }
+                    };
+                }
+            }
+        },
+        _ => {}
+    }
+}
+
+/// Runs any plugin at a multiple of the core's sample rate. The plugin sees
+/// the higher sample rate and buffer size through [`core_changed()`], and
+/// it's audio buffers are resampled by [`Oversampler`]s on each cycle.
+///
+/// Buffers of outputs are upsampled too if there are no inputs, as in the
+/// inplace mode. Latency of the filters is added to the plugin's.
+///
+/// Filters and buffers at the higher rate are made for the plugin's ports
+/// in [`core_changed()`] and [`io_changed()`], so processing doesn't
+/// allocate.
+///
+/// [`io_changed()`]: ../trait.Plugin.html#method.io_changed
+///
+/// [`core_changed()`]: ../trait.Plugin.html#method.core_changed
+/// [`Oversampler`]: struct.Oversampler.html
+///
+/// # Examples
+/// ```
+/// use overcore::CoreConfig;
+/// use overcore::plugins::core::{CorePlugin, Oversampled, Waveshaper};
+/// # use overcore::Core;
+/// # let core = Core::new(Default::default());
+///
+/// let config = CoreConfig::default();
+/// let waveshaper = Oversampled::new(Waveshaper::new(&core), 4, &config);
+/// assert_eq!(waveshaper.factor(), 4);
+/// ```
+pub struct Oversampled<P: Plugin> {
+    plugin: P,
+    factor: usize,
+    config: CoreConfig,
+    /// latency of the filters, in frames of the base sample rate
+    filters: f64,
+    inputs: Vec<Oversampler>,
+    outputs: Vec<Oversampler>,
+    // buffers at the higher sample rate
+    high_inputs: PluginIo,
+    high_outputs: PluginIo
+}
+
+impl<P: Plugin> Oversampled<P> {
+    /// Run `plugin` at `factor` times the sample rate of `config`.
+    ///
+    /// # Panics
+    /// Panics if `factor` is 0.
+    pub fn new(plugin: P, factor: usize, config: &CoreConfig) -> Self {
+        assert_ne!(factor, 0);
+        let mut oversampled = Self {
+            plugin, factor,
+            config: config.clone(),
+            filters: 0.,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            high_inputs: box [],
+            high_outputs: box []
+        };
+        oversampled.core_changed(config);
+        oversampled
+    }
+
+    #[inline]
+    /// Get oversampling factor.
+    pub fn factor(&self) -> usize { self.factor }
+
+    #[inline]
+    /// Get the wrapped plugin.
+    pub fn inner(&self) -> &P { &self.plugin }
+
+    #[inline]
+    /// Get the wrapped plugin mutably.
+    pub fn inner_mut(&mut self) -> &mut P { &mut self.plugin }
+
+    /// Unwrap the plugin.
+    pub fn into_inner(self) -> P { self.plugin }
+
+    fn oversampler(&self) -> Oversampler {
+        Oversampler::new(self.factor, self.config.sample_rate as f64)
+    }
+
+    /// Make filters and buffers at the higher sample rate for ports of the
+    /// plugin which don't have them.
+    fn prepare(&mut self) {
+        let desc = self.plugin.get_io_descriptor();
+        let (inputs, outputs) = match desc.mode {
+            PluginIoMode::Inplace(ref io) => (&[][..], &io.buffers[..]),
+            PluginIoMode::Complex(ref io) => (&io.inputs[..], &io.outputs[..])
+        };
+
+        let length = self.config.buffer_size * self.factor;
+        let buffers = |kinds: &[PluginIoKind]| kinds.iter().map(|kind| match *kind {
+            PluginIoKind::Control => PluginIoBuffer::Control(ControlBuffer::with_length(length)),
+            _ => PluginIoBuffer::Audio(AudioBuffer::with_length(length))
+        }).collect::<Vec<_>>().into_boxed_slice();
+        if self.high_inputs.len() != inputs.len() {
+            self.high_inputs = buffers(inputs);
+        }
+        if self.high_outputs.len() != outputs.len() {
+            self.high_outputs = buffers(outputs);
+        }
+
+        let oversampler = self.oversampler();
+        self.filters = oversampler.latency();
+        self.inputs.resize(inputs.len(), oversampler.clone());
+        self.outputs.resize(outputs.len(), oversampler);
+    }
+}
+
+impl<P: Plugin> Plugin for Oversampled<P> {
+    fn initialize(&mut self) -> PluginResult<()> { self.plugin.initialize() }
+
+    fn core_changed(&mut self, config: &CoreConfig) {
+        self.config = config.clone();
+        let factor = self.factor;
+        self.plugin.core_changed(&CoreConfig {
+            sample_rate: config.sample_rate * factor as u32,
+            buffer_size: config.buffer_size * factor,
+            ..config.clone()
+        });
+
+        self.inputs.clear();
+        self.outputs.clear();
+        self.high_inputs = box [];
+        self.high_outputs = box [];
+        self.prepare();
+    }
+
+    fn get_params(&self) -> PluginParamsDesc { self.plugin.get_params() }
+
+    fn get_param(&self, index: usize) -> Option<ParamValue> {
+        self.plugin.get_param(index)
+    }
+
+    fn set_param(&mut self, index: usize, value: ParamValue) -> PluginResult<()> {
+        self.plugin.set_param(index, value)
+    }
+
+    fn param_range(&self, index: usize) -> Option<PluginParamRange> {
+        self.plugin.param_range(index)
+    }
+
+    fn param_allocates(&self, index: usize) -> bool { self.plugin.param_allocates(index) }
+
+    fn param_default(&self, index: usize) -> Option<ParamValue> {
+        self.plugin.param_default(index)
+    }
+
+    fn io_changed(&mut self, change: PluginIoChange) -> PluginResult<()> {
+        let result = self.plugin.io_changed(change);
+        self.prepare();
+        result
+    }
+
+    /// Get delay of the resampling filters and the plugin itself.
+    fn latency(&self) -> usize {
+        let plugin = self.plugin.latency() as f64 / self.factor as f64;
+        (self.filters + plugin).round() as usize
+    }
+
+    fn get_io_descriptor(&self) -> PluginIoDesc { self.plugin.get_io_descriptor() }
+
+    fn process(&mut self, inputs: &PluginIo, outputs: &mut PluginIo)
+        -> PluginResult<()> {
+        let factor = self.factor;
+        while self.inputs.len() < inputs.len() {
+            let oversampler = self.oversampler();
+            self.inputs.push(oversampler);
+        }
+        while self.outputs.len() < outputs.len() {
+            let oversampler = self.oversampler();
+            self.outputs.push(oversampler);
+        }
+        if self.high_inputs.len() != inputs.len() {
+            self.high_inputs = vec![PluginIoBuffer::Disconnected; inputs.len()].into_boxed_slice();
+        }
+        if self.high_outputs.len() != outputs.len() {
+            self.high_outputs = vec![PluginIoBuffer::Disconnected; outputs.len()].into_boxed_slice();
+        }
+
+        for (i, buffer) in inputs.iter().enumerate() {
+            stretch(buffer, &mut self.high_inputs[i], factor, Some(&mut self.inputs[i]));
+        }
+        for (i, buffer) in outputs.iter().enumerate() {
+            let oversampler = if inputs.is_empty() { Some(&mut self.outputs[i]) } else { None };
+            stretch(buffer, &mut self.high_outputs[i], factor, oversampler);
+        }
+
+        let result = self.plugin.process(&self.high_inputs, &mut self.high_outputs);
+
+        for (i, buffer) in outputs.iter_mut().enumerate() {
+            shrink(&mut self.high_outputs[i], buffer, factor, &mut self.outputs[i]);
+        }
+        result
+    }
+
+    fn terminate(&mut self) { self.plugin.terminate() }
+}
+
+#[cfg(test)]
+mod tests {
+    use plugins::special::Effect;
+    use super::*;
+
+    fn sine(freq: f64, i: usize) -> Frame {
+        (2. * PI * freq * i as f64 / 48000.).sin().into()
+    }
+
+    fn amplitude<F: FnMut(Frame) -> Frame>(oversampler: &mut Oversampler,
+                                            freq: f64, mut f: F) -> f64 {
+        let frames = (0..9600).map(|i| oversampler.process(sine(freq, i), &mut f));
+        frames.skip(4800).fold(0., |max, frame| max.max(frame[0].abs()))
+    }
+
+    #[test]
+    fn test_oversampler_passband() {
+        for &factor in [2, 4].iter() {
+            let mut oversampler = Oversampler::new(factor, 48000.);
+            let gain = amplitude(&mut oversampler, 1000., |f| f);
+            assert!((gain - 1.).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_oversampler_aliasing() {
+        // squaring a 15kHz sine makes 30kHz, which aliases to 18kHz at 48kHz
+        let square = |f: Frame| f * f - (0.5).into();
+
+        let aliased = amplitude(&mut Oversampler::new(1, 48000.), 15000., &square);
+        let filtered = amplitude(&mut Oversampler::new(4, 48000.), 15000., &square);
+        assert!(aliased > 0.4);
+        assert!(filtered < 0.01);
+    }
+
+    /// Passes audio through, recording the config it's given.
+    struct Through(CoreConfig, usize);
+
+    impl Effect for Through {
+        fn process_audio(&mut self, audio: &mut AudioBuffer) -> PluginResult<()> {
+            self.1 = audio.len();
+            Ok(())
+        }
+    }
+
+    impl_effect_for!(Through, {
+        fn core_changed(&mut self, config: &CoreConfig) { self.0 = config.clone(); }
+    });
+
+    #[test]
+    fn test_oversampled_plugin() {
+        let config = CoreConfig { sample_rate: 48000, buffer_size: 256, ..Default::default() };
+        let mut plugin = Oversampled::new(Through(Default::default(), 0), 4, &config);
+        assert_eq!(plugin.inner().0.sample_rate, 192000);
+        assert_eq!(plugin.inner().0.buffer_size, 1024);
+
+        let latency = plugin.latency();
+        assert!(latency > 0);
+
+        let input = (0..5120).map(|i| sine(200., i)).collect::<Vec<_>>();
+        let (inputs, mut output): (PluginIo, _) = (box [], Vec::new());
+        for block in input.chunks(256) {
+            let mut audio = AudioBuffer::with_length(block.len());
+            audio.copy_from_slice(block);
+            let mut outputs: PluginIo = box [PluginIoBuffer::Audio(audio)];
+            plugin.process(&inputs, &mut outputs).unwrap();
+            match outputs[0] {
+                PluginIoBuffer::Audio(ref audio) => output.extend_from_slice(audio),
+                _ => unreachable!()
+            }
+        }
+        assert_eq!(plugin.inner().1, 1024);
+
+        for i in 1000..5120 {
+            assert!((output[i][0] - input[i - latency][0]).abs() < 0.02);
+        }
+    }
+
+    /// Moves events of it's control input to the next frame of it's output.
+    struct Shift;
+
+    impl Plugin for Shift {
+        fn get_io_descriptor(&self) -> PluginIoDesc {
+            PluginIoDesc {
+                mode: PluginIoMode::Complex(PluginComplexIo {
+                    inputs: box [PluginIoKind::Control],
+                    outputs: box [PluginIoKind::Control]
+                }),
+                inputs: box ["In".to_owned()],
+                outputs: box ["Out".to_owned()]
+            }
+        }
+
+        fn process(&mut self, inputs: &PluginIo, outputs: &mut PluginIo)
+            -> PluginResult<()> {
+            if let (&PluginIoBuffer::Control(ref input),
+                    &mut PluginIoBuffer::Control(ref mut output)) = (&inputs[0], &mut outputs[0]) {
+                output[0] = None;
+                for i in 1..output.len() {
+                    output[i] = input[i - 1].clone();
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_oversampled_events() {
+        use buffer::control::{ControlBuffer, Event};
+        let config = CoreConfig { buffer_size: 4, ..Default::default() };
+        let mut plugin = Oversampled::new(Shift, 2, &config);
+
+        let mut control = ControlBuffer::with_length(4);
+        control[0] = Some(box [Event::Panic]);
+        control[2] = Some(box [Event::ParamReset(0)]);
+        let inputs: PluginIo = box [PluginIoBuffer::Control(control)];
+        let mut outputs: PluginIo = box [PluginIoBuffer::Control(ControlBuffer::with_length(4))];
+        plugin.process(&inputs, &mut outputs).unwrap();
+
+        // a frame at the higher rate is still in the same moment
+        match outputs[0] {
+            PluginIoBuffer::Control(ref output) => {
+                let events = output.iter().map(|m| m.as_ref().map(|e| e.to_vec()));
+                assert_eq!(events.collect::<Vec<_>>(),
+                           [Some(vec![Event::Panic]), None, Some(vec![Event::ParamReset(0)]), None]);
+            },
+            _ => unreachable!()
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use std::iter;
+use buffer::prelude::*;
+use buffer::audio::DelayLine;
+use buffer::control::ParamValue;
+use interpolate::Interpolation;
+use plugins::prelude::*;
+use plugins::special::Effect;
+use {Core, CoreConfig};
+use super::CorePlugin;
+
+// Freeverb tunings, in frames at 44.1kHz
+const COMBS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASSES: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+const TUNING_RATE: f64 = 44100.;
+
+const INPUT_GAIN: f64 = 0.015;
+const WET_GAIN: f64 = 3.;
+const ALLPASS_FEEDBACK: f64 = 0.5;
+const SCALE_ROOM: f64 = 0.28;
+const OFFSET_ROOM: f64 = 0.7;
+const SCALE_DAMPING: f64 = 0.4;
+
+/// Maximum pre-delay in milliseconds.
+const MAX_PRE_DELAY: f64 = 500.;
+
+const PARAM_ROOM_SIZE: usize = 0;
+const PARAM_DAMPING: usize = 1;
+const PARAM_PRE_DELAY: usize = 2;
+const PARAM_MIX: usize = 3;
+
+const RANGES: [PluginParamRange; 4] = [
+    PluginParamRange::Float(0., 1.),
+    PluginParamRange::Float(0., 1.),
+    PluginParamRange::Float(0., MAX_PRE_DELAY),
+    PluginParamRange::Float(0., 1.)
+];
+
+fn silence(length: usize) -> Box<[Sample]> {
+    iter::repeat(0.).take(length).collect::<Vec<_>>().into_boxed_slice()
+}
+
+/// A low-passed feedback comb filter.
+struct Comb {
+    buffer: Box<[Sample]>,
+    index: usize,
+    store: Sample
+}
+
+impl Comb {
+    fn new(length: usize) -> Self {
+        Self { buffer: silence(length), index: 0, store: 0. }
+    }
+
+    #[inline]
+    fn process(&mut self, input: Sample, feedback: f64, damping: f64) -> Sample {
+        let output = self.buffer[self.index];
+        self.store = output * (1. - damping) + self.store * damping;
+        self.buffer[self.index] = input + self.store * feedback;
+
+        self.index += 1;
+        if self.index == self.buffer.len() { self.index = 0; }
+        output
+    }
+}
+
+/// A Schroeder allpass filter.
+struct Allpass {
+    buffer: Box<[Sample]>,
+    index: usize
+}
+
+impl Allpass {
+    fn new(length: usize) -> Self {
+        Self { buffer: silence(length), index: 0 }
+    }
+
+    #[inline]
+    fn process(&mut self, input: Sample) -> Sample {
+        let delayed = self.buffer[self.index];
+        self.buffer[self.index] = input + delayed * ALLPASS_FEEDBACK;
+
+        self.index += 1;
+        if self.index == self.buffer.len() { self.index = 0; }
+        delayed - input
+    }
+}
+
+/// Parallel combs followed by serial allpasses, for a single channel.
+struct Tank {
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>
+}
+
+impl Tank {
+    fn new(sample_rate: f64, spread: usize) -> Self {
+        let scale = |length: usize| {
+            (((length + spread) as f64 * sample_rate / TUNING_RATE) as usize).max(1)
+        };
+        Self {
+            combs: COMBS.iter().map(|&l| Comb::new(scale(l))).collect(),
+            allpasses: ALLPASSES.iter().map(|&l| Allpass::new(scale(l))).collect()
+        }
+    }
+
+    #[inline]
+    fn process(&mut self, input: Sample, feedback: f64, damping: f64) -> Sample {
+        let mut output = self.combs.iter_mut()
+            .fold(0., |sum, comb| sum + comb.process(input, feedback, damping));
+        for allpass in self.allpasses.iter_mut() {
+            output = allpass.process(output);
+        }
+        output
+    }
+}
+
+/// A stereo algorithmic reverb, based on Freeverb.
+pub struct Reverb {
+    room_size: f64,
+    damping: f64,
+    pre_delay: f64,
+    mix: f64,
+    sample_rate: f64,
+    tanks: [Tank; 2],
+    line: DelayLine
+}
+
+impl Reverb {
+    fn allocate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.tanks = [Tank::new(sample_rate, 0), Tank::new(sample_rate, STEREO_SPREAD)];
+        self.line = DelayLine::new((MAX_PRE_DELAY * sample_rate / 1000.) as usize);
+    }
+
+    /// Silence the reverb tail.
+    pub fn reset(&mut self) {
+        let sample_rate = self.sample_rate;
+        self.allocate(sample_rate);
+    }
+}
+
+impl Effect for Reverb {
+    fn process_audio(&mut self, audio: &mut AudioBuffer) -> PluginResult<()> {
+        let feedback = self.room_size * SCALE_ROOM + OFFSET_ROOM;
+        let damping = self.damping * SCALE_DAMPING;
+        let pre_delay = self.pre_delay * self.sample_rate / 1000.;
+        let (dry, wet) = (1. - self.mix, self.mix * WET_GAIN);
+
+        for frame in audio.iter_mut() {
+            self.line.push(*frame);
+            let delayed = self.line.read(pre_delay, Interpolation::Floor);
+            let input = (delayed[0] + delayed[1]) * INPUT_GAIN;
+
+            let left = self.tanks[0].process(input, feedback, damping);
+            let right = self.tanks[1].process(input, feedback, damping);
+
+            *frame = *frame * dry.into() + Frame::from((left, right)) * wet.into();
+        }
+        Ok(())
+    }
+}
+
+impl_effect_for!(Reverb, {
+    fn core_changed(&mut self, config: &CoreConfig) {
+        let sample_rate = config.sample_rate as f64;
+        if sample_rate != self.sample_rate {
+            self.allocate(sample_rate);
+        }
+    }
+
+    fn get_params(&self) -> PluginParamsDesc {
+        box [
+            PluginParamDesc {
+                name: "Room Size".to_owned(),
+                kind: PluginParamKind::Float(0., 1.),
+                default: ParamValue::Float(0.5)
+            },
+            PluginParamDesc {
+                name: "Damping".to_owned(),
+                kind: PluginParamKind::Float(0., 1.),
+                default: ParamValue::Float(0.5)
+            },
+            PluginParamDesc {
+                name: "Pre-Delay".to_owned(),
+                kind: PluginParamKind::Float(0., MAX_PRE_DELAY),
+                default: ParamValue::Float(0.)
+            },
+            PluginParamDesc {
+                name: "Mix".to_owned(),
+                kind: PluginParamKind::Float(0., 1.),
+                default: ParamValue::Float(0.3)
+            }
+        ]
+    }
+
+    fn get_param(&self, index: usize) -> Option<ParamValue> {
+        match index {
+            PARAM_ROOM_SIZE => Some(ParamValue::Float(self.room_size)),
+            PARAM_DAMPING => Some(ParamValue::Float(self.damping)),
+            PARAM_PRE_DELAY => Some(ParamValue::Float(self.pre_delay)),
+            PARAM_MIX => Some(ParamValue::Float(self.mix)),
+            _ => None
+        }
+    }
+
+    fn param_range(&self, index: usize) -> Option<PluginParamRange> {
+        RANGES.get(index).cloned()
+    }
+
+    fn set_param(&mut self, index: usize, value: ParamValue) -> PluginResult<()> {
+        self.check_param(index, &value)?;
+
+        match (index, value) {
+            (PARAM_ROOM_SIZE, ParamValue::Float(r)) => { self.room_size = r },
+            (PARAM_DAMPING, ParamValue::Float(d)) => { self.damping = d },
+            (PARAM_PRE_DELAY, ParamValue::Float(p)) => { self.pre_delay = p },
+            (PARAM_MIX, ParamValue::Float(m)) => { self.mix = m },
+            _ => unreachable!("checked by the range")
+        }
+        Ok(())
+    }
+});
+
+impl CorePlugin for Reverb {
+    fn new(core: &Core) -> Self {
+        let mut reverb = Self {
+            room_size: 0.5, damping: 0.5, pre_delay: 0., mix: 0.3,
+            sample_rate: 0., tanks: [Tank::new(1., 0), Tank::new(1., 0)],
+            line: DelayLine::new(0)
+        };
+        reverb.allocate(core.config.sample_rate as f64);
+        reverb
+    }
+
+    fn get_uuid() -> &'static str { "5e1a7c3d-902b-4f64-b8d5-c47e0a9f2618" }
+
+    fn get_desc(id: usize) -> PluginDesc {
+        PluginDesc::default().with_id(id).with_uuid(Self::get_uuid())
+            .with_name("Reverb")
+            .with_category("Effect.Reverb")
+            .with_description("stereo algorithmic reverb.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use buffer::Buffer;
+
+    fn reverb() -> Reverb {
+        let config = CoreConfig { sample_rate: 44100, ..Default::default() };
+        let mut reverb = Reverb::new(&Core::new(config));
+        reverb.set_param(PARAM_MIX, ParamValue::Float(1.)).unwrap();
+        reverb
+    }
+
+    fn impulse_response(reverb: &mut Reverb, length: usize) -> AudioBuffer {
+        let mut audio = AudioBuffer::with_length(length);
+        audio[0] = (1.).into();
+        reverb.process_audio(&mut audio).unwrap();
+        audio
+    }
+
+    fn energy(frames: &[Frame]) -> f64 {
+        frames.iter().map(|f| f[0] * f[0] + f[1] * f[1]).sum()
+    }
+
+    #[test]
+    fn test_reverb_impulse_response() {
+        let ir = impulse_response(&mut reverb(), 44100);
+
+        // nothing comes out before the shortest comb of each channel
+        assert!(ir[..1116].iter().all(|f| f[0] == 0.));
+        assert!(ir[..1139].iter().all(|f| f[1] == 0.));
+        assert!(ir[1116][0] != 0. && ir[1139][1] != 0.);
+
+        // the tail decays
+        assert!(energy(&ir[2000..12000]) > energy(&ir[32000..42000]));
+
+        // and it's deterministic
+        let again = impulse_response(&mut reverb(), 44100);
+        assert!(ir.iter().zip(again.iter()).all(|(a, b)| a == b));
+    }
+
+    #[test]
+    fn test_reverb_params() {
+        let mut reverb = reverb();
+        reverb.set_param(PARAM_PRE_DELAY, ParamValue::Float(100.)).unwrap();
+        let ir = impulse_response(&mut reverb, 6000);
+        assert!(ir[..4410 + 1116].iter().all(|f| f[0] == 0.));
+        assert!(ir[4410 + 1116][0] != 0.);
+
+        let (mut small, mut large) = (self::reverb(), self::reverb());
+        small.set_param(PARAM_ROOM_SIZE, ParamValue::Float(0.1)).unwrap();
+        large.set_param(PARAM_ROOM_SIZE, ParamValue::Float(0.9)).unwrap();
+        let small = impulse_response(&mut small, 44100);
+        let large = impulse_response(&mut large, 44100);
+        assert!(energy(&small[22050..]) < energy(&large[22050..]));
+
+        reverb.set_param(PARAM_MIX, ParamValue::Float(0.)).unwrap();
+        reverb.reset();
+        let ir = impulse_response(&mut reverb, 6000);
+        assert_eq!(ir[0], (1.).into());
+        assert_eq!(energy(&ir[1..]), 0.);
+    }
+}
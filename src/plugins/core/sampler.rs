@@ -0,0 +1,346 @@
+use std::path::Path;
+use buffer::Buffer;
+use buffer::prelude::*;
+use buffer::control::ParamValue;
+use codec::{AudioFile, CodecResult};
+use interpolate::Interpolation;
+use plugins::prelude::*;
+use plugins::special::{Instrument, Voices};
+use tuning::SharedTuning;
+use {Core, CoreConfig};
+use super::CorePlugin;
+
+const PARAM_ROOT: usize = 0;
+const PARAM_TRACKING: usize = 1;
+const PARAM_START: usize = 2;
+const PARAM_END: usize = 3;
+const PARAM_LOOP: usize = 4;
+const PARAM_LOOP_START: usize = 5;
+
+const RANGES: [PluginParamRange; 6] = [
+    PluginParamRange::Unsigned(0, 127),
+    PluginParamRange::Boolean,
+    PluginParamRange::Float(0., 1.),
+    PluginParamRange::Float(0., 1.),
+    PluginParamRange::Boolean,
+    PluginParamRange::Float(0., 1.)
+];
+
+const DEFAULTS: [ParamValue; 6] = [
+    ParamValue::Unsigned(60),
+    ParamValue::Boolean(true),
+    ParamValue::Float(0.),
+    ParamValue::Float(1.),
+    ParamValue::Boolean(false),
+    ParamValue::Float(0.)
+];
+
+pub struct Voice {
+    // position in frames of the resampled audio
+    position: f64,
+    step: f64
+}
+
+/// Plays an audio file on each note. The file is resampled to the sample
+/// rate of the core on load, and played back faster or slower relative to
+/// the root note. Start, end and loop points are relative to the length of
+/// the file.
+pub struct Sampler {
+    voices: Voices<Voice>,
+    file: Option<AudioFile>,
+    // the file at the core's sample rate
+    frames: AudioBuffer,
+    sample_rate: u32,
+    tuning: SharedTuning,
+    root: u64,
+    tracking: bool,
+    start: f64,
+    end: f64,
+    looping: bool,
+    loop_start: f64
+}
+
+impl Sampler {
+    /// Play `file`, replacing the current one.
+    pub fn load(&mut self, file: AudioFile) {
+        self.voices.clear();
+        self.frames = file.resample(self.sample_rate).frames;
+        self.file = Some(file);
+    }
+
+    /// Read and play the file at `path`.
+    pub fn open<P: AsRef<Path>>(&mut self, path: P) -> CodecResult<()> {
+        AudioFile::open(path).map(|file| self.load(file))
+    }
+
+    /// Get the file being played.
+    pub fn file(&self) -> Option<&AudioFile> { self.file.as_ref() }
+
+    fn step(&self, note: &Note) -> f64 {
+        if !self.tracking {
+            return 1.;
+        }
+        let root = NoteName::from_midi(self.root as u8).detune_with(&*self.tuning, 0.);
+        note.freq_with(&*self.tuning) / root
+    }
+
+    /// Get start, end and loop start points in frames.
+    fn points(&self) -> (f64, f64, f64) {
+        let len = self.frames.len() as f64;
+        let end = self.end * len;
+        let start = (self.start * len).min(end);
+        let loop_start = (self.loop_start * len).max(start).min(end);
+        (start, end, loop_start)
+    }
+}
+
+impl Instrument for Sampler {
+    type Voice = Voice;
+
+    fn voices(&mut self) -> &mut Voices<Voice> { &mut self.voices }
+
+    fn start(&mut self, note: &Note) -> Voice {
+        let (position, end, _) = self.points();
+        let step = self.step(note);
+        // notes which are not mapped by the tuning are muted
+        if step.is_finite() { Voice { position, step } }
+        else { Voice { position: end, step: 0. } }
+    }
+
+    fn update(&mut self, voice: &mut Voice, note: &Note) {
+        let step = self.step(note);
+        if step.is_finite() {
+            voice.step = step;
+        }
+    }
+
+    fn render(&mut self, voice: &mut Voice, note: &Note) -> Option<Frame> {
+        let (_, end, loop_start) = self.points();
+
+        if voice.position >= end {
+            if !self.looping || end - loop_start < 1. {
+                return None;
+            }
+            voice.position = loop_start + (voice.position - end) % (end - loop_start);
+        }
+
+        let last = self.frames.len() as isize - 1;
+        let frames = &self.frames;
+        let frame: Frame = Interpolation::Poly.apply(voice.position, |i| {
+            frames[i.max(0).min(last) as usize]
+        });
+
+        voice.position += voice.step;
+        Some(frame * note.params.velocities() / Frame::from(100.))
+    }
+}
+
+impl_instrument_for!(Sampler, {
+    fn core_changed(&mut self, config: &CoreConfig) {
+        self.tuning = config.tuning.clone();
+        if config.sample_rate != self.sample_rate {
+            self.sample_rate = config.sample_rate;
+            if let Some(file) = self.file.take() {
+                self.load(file);
+            }
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.file.as_ref().and_then(|file| file.encode().ok()).unwrap_or_default()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> PluginResult<()> {
+        if state.is_empty() {
+            self.voices.clear();
+            self.frames = AudioBuffer::with_length(0);
+            self.file = None;
+            return Ok(());
+        }
+        AudioFile::decode(state).map(|file| self.load(file))
+            .map_err(|_| PluginError::InvalidArgument)
+    }
+
+    fn get_params(&self) -> PluginParamsDesc {
+        box [
+            PluginParamDesc {
+                name: "Root Note".to_owned(),
+                kind: PluginParamKind::Unsigned(0, 127),
+                default: ParamValue::Unsigned(60)
+            },
+            PluginParamDesc {
+                name: "Pitch Tracking".to_owned(),
+                kind: PluginParamKind::Boolean,
+                default: ParamValue::Boolean(true)
+            },
+            PluginParamDesc {
+                name: "Start".to_owned(),
+                kind: PluginParamKind::Float(0., 1.),
+                default: ParamValue::Float(0.)
+            },
+            PluginParamDesc {
+                name: "End".to_owned(),
+                kind: PluginParamKind::Float(0., 1.),
+                default: ParamValue::Float(1.)
+            },
+            PluginParamDesc {
+                name: "Loop".to_owned(),
+                kind: PluginParamKind::Boolean,
+                default: ParamValue::Boolean(false)
+            },
+            PluginParamDesc {
+                name: "Loop Start".to_owned(),
+                kind: PluginParamKind::Float(0., 1.),
+                default: ParamValue::Float(0.)
+            }
+        ]
+    }
+
+    fn get_param(&self, index: usize) -> Option<ParamValue> {
+        match index {
+            PARAM_ROOT => Some(ParamValue::Unsigned(self.root)),
+            PARAM_TRACKING => Some(ParamValue::Boolean(self.tracking)),
+            PARAM_START => Some(ParamValue::Float(self.start)),
+            PARAM_END => Some(ParamValue::Float(self.end)),
+            PARAM_LOOP => Some(ParamValue::Boolean(self.looping)),
+            PARAM_LOOP_START => Some(ParamValue::Float(self.loop_start)),
+            _ => None
+        }
+    }
+
+    fn param_range(&self, index: usize) -> Option<PluginParamRange> {
+        RANGES.get(index).cloned()
+    }
+
+    fn param_default(&self, index: usize) -> Option<ParamValue> {
+        DEFAULTS.get(index).cloned()
+    }
+
+    fn set_param(&mut self, index: usize, value: ParamValue) -> PluginResult<()> {
+        self.check_param(index, &value)?;
+
+        match (index, value) {
+            (PARAM_ROOT, ParamValue::Unsigned(r)) => { self.root = r },
+            (PARAM_TRACKING, ParamValue::Boolean(t)) => { self.tracking = t },
+            (PARAM_START, ParamValue::Float(s)) => { self.start = s },
+            (PARAM_END, ParamValue::Float(e)) => { self.end = e },
+            (PARAM_LOOP, ParamValue::Boolean(l)) => { self.looping = l },
+            (PARAM_LOOP_START, ParamValue::Float(s)) => { self.loop_start = s },
+            _ => unreachable!("checked by the range")
+        }
+        Ok(())
+    }
+});
+
+impl CorePlugin for Sampler {
+    fn new(core: &Core) -> Self {
+        Self {
+            voices: Voices::default(),
+            file: None,
+            frames: AudioBuffer::with_length(0),
+            sample_rate: core.config.sample_rate,
+            tuning: core.config.tuning.clone(),
+            root: 60,
+            tracking: true,
+            start: 0.,
+            end: 1.,
+            looping: false,
+            loop_start: 0.
+        }
+    }
+
+    fn get_uuid() -> &'static str { "0b6b3a0e-5f7e-4d2c-9d5e-8c1f4a7b2e39" }
+
+    fn get_desc(id: usize) -> PluginDesc {
+        PluginDesc::default().with_id(id).with_uuid(Self::get_uuid())
+            .with_name("Sampler")
+            .with_category("Instrument.Sampler")
+            .with_description("plays WAV, AIFF and FLAC files on notes.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sampler(sample_rate: u32) -> Sampler {
+        let core = Core::new(CoreConfig { sample_rate, ..Default::default() });
+        let mut sampler = Sampler::new(&core);
+
+        let mut frames = AudioBuffer::with_length(8);
+        for (i, frame) in frames.iter_mut().enumerate() {
+            *frame = (i as f64 + 1.).into();
+        }
+        sampler.load(AudioFile { sample_rate: 100, channels: 1, frames });
+        sampler
+    }
+
+    fn note_on(midi: u8) -> Event {
+        let note = Note { name: NoteName::from_midi(midi), params: Default::default() };
+        Event::NoteOn(note, NoteRef(0))
+    }
+
+    fn render(plugin: &mut Sampler, events: Box<[Event]>, len: usize) -> Vec<Sample> {
+        let mut control = ControlBuffer::with_length(len);
+        control[0] = Some(events);
+        let inputs: PluginIo = box [PluginIoBuffer::Control(control)];
+        let mut outputs: PluginIo = box [PluginIoBuffer::Audio(AudioBuffer::with_length(len))];
+        plugin.process(&inputs, &mut outputs).unwrap();
+        match outputs[0] {
+            PluginIoBuffer::Audio(ref audio) => audio.iter().map(|f| f[0].round()).collect(),
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_sampler_playback() {
+        let mut plugin = sampler(100);
+        assert_eq!(render(&mut plugin, box [note_on(60)], 10),
+                   [1., 2., 3., 4., 5., 6., 7., 8., 0., 0.]);
+
+        // an octave higher plays twice as fast
+        assert_eq!(render(&mut plugin, box [note_on(72)], 5), [1., 3., 5., 7., 0.]);
+
+        plugin.set_param(PARAM_TRACKING, ParamValue::Boolean(false)).unwrap();
+        assert_eq!(render(&mut plugin, box [note_on(72)], 3), [1., 2., 3.]);
+        assert_eq!(render(&mut plugin, box [Event::NoteOff(NoteRef(0))], 2), [0., 0.]);
+    }
+
+    #[test]
+    fn test_sampler_points() {
+        let mut plugin = sampler(100);
+        plugin.set_param(PARAM_START, ParamValue::Float(0.25)).unwrap();
+        plugin.set_param(PARAM_END, ParamValue::Float(0.75)).unwrap();
+        plugin.set_param(PARAM_LOOP, ParamValue::Boolean(true)).unwrap();
+        plugin.set_param(PARAM_LOOP_START, ParamValue::Float(0.5)).unwrap();
+        assert_eq!(render(&mut plugin, box [note_on(60)], 8), [3., 4., 5., 6., 5., 6., 5., 6.]);
+
+        assert!(plugin.set_param(PARAM_ROOT, ParamValue::Unsigned(128)).is_err());
+    }
+
+    #[test]
+    fn test_sampler_resample() {
+        let mut plugin = sampler(200);
+        assert_eq!(plugin.frames.len(), 16);
+        let mut expected = plugin.frames.iter().map(|f| f[0].round()).collect::<Vec<_>>();
+        expected.push(0.);
+        assert_eq!(render(&mut plugin, box [note_on(60)], 17), expected);
+
+        plugin.core_changed(&CoreConfig { sample_rate: 50, ..Default::default() });
+        assert_eq!(plugin.frames.len(), 4);
+        assert_eq!(plugin.file().unwrap().sample_rate, 100);
+
+        // away from it's ends, a band limited file is resampled accurately
+        let sine = |t: f64| (2. * ::std::f64::consts::PI * 5. * t).sin();
+        let mut frames = AudioBuffer::with_length(256);
+        for (i, frame) in frames.iter_mut().enumerate() {
+            *frame = sine(i as f64 / 100.).into();
+        }
+        let mut plugin = sampler(200);
+        plugin.load(AudioFile { sample_rate: 100, channels: 1, frames });
+        assert_eq!(plugin.frames.len(), 512);
+        for (i, frame) in plugin.frames.iter().enumerate().take(432).skip(80) {
+            assert!((frame[0] - sine(i as f64 / 200.)).abs() < 1e-4);
+        }
+    }
+}
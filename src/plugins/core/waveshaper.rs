@@ -0,0 +1,395 @@
+use buffer::prelude::*;
+use buffer::control::ParamValue;
+use interpolate::{Interpolation, Interpolator};
+use plugins::prelude::*;
+use plugins::special::Effect;
+use {Core, CoreConfig};
+use super::{CorePlugin, Oversampler};
+
+/// Range of the tabulated transfer curves. Driven inputs out of it are
+/// clamped.
+const RANGE: f64 = 8.;
+
+/// Number of points of the tabulated transfer curves.
+const POINTS: usize = 4097;
+
+const PARAM_CURVE: usize = 0;
+const PARAM_DRIVE: usize = 1;
+const PARAM_MIX: usize = 2;
+const PARAM_OVERSAMPLING: usize = 3;
+const PARAM_BITS: usize = 4;
+const PARAM_DOWNSAMPLE: usize = 5;
+
+const RANGES: [PluginParamRange; 6] = [
+    PluginParamRange::Enum(5),
+    PluginParamRange::Float(0., 18.),
+    PluginParamRange::Float(0., 1.),
+    PluginParamRange::Enum(3),
+    PluginParamRange::Unsigned(1, 16),
+    PluginParamRange::Unsigned(1, 64)
+];
+
+const OVERSAMPLING: [usize; 3] = [1, 2, 4];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Transfer curve of a [`Waveshaper`](struct.Waveshaper.html).
+pub enum Curve {
+    /// Soft saturation.
+    Tanh,
+
+    /// Clips samples to [-1, 1].
+    HardClip,
+
+    /// Reflects samples out of [-1, 1] back into it.
+    Foldback,
+
+    /// Quantizes samples to the given bit depth.
+    Bitcrush,
+
+    /// A curve drawn by the user. See
+    /// [`Waveshaper::set_custom_curve()`][0].
+    /// [0]: struct.Waveshaper.html#method.set_custom_curve
+    Custom
+}
+
+impl Curve {
+    /// Get all curves, ordered by their index.
+    pub fn all() -> &'static [Curve] {
+        &[Curve::Tanh, Curve::HardClip, Curve::Foldback, Curve::Bitcrush, Curve::Custom]
+    }
+
+    /// Get a human readable name of the curve.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Curve::Tanh => "Tanh",
+            Curve::HardClip => "Hard Clip",
+            Curve::Foldback => "Foldback",
+            Curve::Bitcrush => "Bitcrush",
+            Curve::Custom => "Custom"
+        }
+    }
+}
+
+fn foldback(x: f64) -> f64 {
+    // a triangle wave with period of 4, equal to x in [-1, 1]
+    let phase = (x + 1.) % 4.;
+    let phase = if phase < 0. { phase + 4. } else { phase };
+    if phase < 2. { phase - 1. } else { 3. - phase }
+}
+
+/// A waveshaper, which maps driven samples through a transfer curve. The
+/// curve is tabulated by an [`Interpolator`], and the shaping can be
+/// oversampled to reduce aliasing.
+/// [`Interpolator`]: ../../interpolate/struct.Interpolator.html
+pub struct Waveshaper {
+    curve: Curve,
+    // in dB
+    drive: f64,
+    mix: f64,
+    oversampling: usize,
+    bits: u64,
+    downsample: u64,
+    custom: Box<[Sample]>,
+    sample_rate: f64,
+    table: Interpolator,
+    oversampler: Oversampler,
+    // passes the dry signal through the same filters, to keep it aligned
+    dry: Oversampler,
+    // held frame and it's age, for sample rate reduction
+    held: (Frame, u64)
+}
+
+impl Waveshaper {
+    fn tabulate(&mut self) {
+        self.table = match self.curve {
+            Curve::Tanh => Interpolator::with_range(
+                Interpolation::Linear, POINTS, -RANGE, RANGE, f64::tanh),
+            Curve::HardClip => Interpolator::with_range(
+                Interpolation::Linear, POINTS, -RANGE, RANGE, |x| x.max(-1.).min(1.)),
+            Curve::Foldback => Interpolator::with_range(
+                Interpolation::Linear, POINTS, -RANGE, RANGE, foldback),
+            Curve::Bitcrush => {
+                // nearest of evenly spaced levels in [-1, 1] is quantization
+                let levels = (1 << self.bits) + 1;
+                Interpolator::with_range(Interpolation::Nearest, levels, -1., 1., |x| x)
+            },
+            Curve::Custom => Interpolator::from_points(
+                Interpolation::Linear, -1., 1., self.custom.clone())
+        };
+    }
+
+    /// Set points of the custom curve, evenly spaced in [-1, 1] including
+    /// both ends. The curve is linearly interpolated between them.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::Core;
+    /// use overcore::buffer::Buffer;
+    /// use overcore::buffer::prelude::*;
+    /// use overcore::buffer::control::ParamValue;
+    /// use overcore::plugins::Plugin;
+    /// use overcore::plugins::core::{CorePlugin, Waveshaper};
+    /// use overcore::plugins::special::Effect;
+    ///
+    /// let mut waveshaper = Waveshaper::new(&Core::new(Default::default()));
+    /// waveshaper.set_param(0, ParamValue::Index(4)).unwrap();
+    /// waveshaper.set_custom_curve(Box::new([1., 0., 1.])).unwrap();
+    ///
+    /// let mut audio = AudioBuffer::with_length(1);
+    /// audio[0] = (-0.5, 0.25).into();
+    /// waveshaper.process_audio(&mut audio).unwrap();
+    /// assert_eq!(audio[0], (0.5, 0.25).into());
+    /// ```
+    pub fn set_custom_curve(&mut self, points: Box<[Sample]>) -> PluginResult<()> {
+        if points.len() < 2 {
+            return Err(PluginError::InvalidArgument);
+        }
+        self.custom = points;
+        self.tabulate();
+        Ok(())
+    }
+
+    fn oversample(&mut self) {
+        self.oversampler = Oversampler::new(self.oversampling, self.sample_rate);
+        self.dry = Oversampler::new(self.oversampling, self.sample_rate);
+    }
+
+    fn shape(&mut self, frame: Frame, gain: Frame) -> Frame {
+        let table = &self.table;
+        self.oversampler.process(frame * gain, |f| {
+            (table.f(f[0]), table.f(f[1])).into()
+        })
+    }
+}
+
+impl Effect for Waveshaper {
+    fn process_audio(&mut self, audio: &mut AudioBuffer) -> PluginResult<()> {
+        let (dry, wet) = (Frame::from(1. - self.mix), Frame::from(self.mix));
+        let gain = Frame::from(10f64.powf(self.drive / 20.));
+        for frame in audio.iter_mut() {
+            let (held, age) = self.held;
+            let input = if age == 0 { *frame } else { held };
+            self.held = (input, (age + 1) % self.downsample);
+
+            let shaped = self.shape(input, gain);
+            let delayed = self.dry.process(*frame, |f| f);
+            *frame = delayed * dry + shaped * wet;
+        }
+        Ok(())
+    }
+}
+
+impl_effect_for!(Waveshaper, {
+    fn core_changed(&mut self, config: &CoreConfig) {
+        self.sample_rate = config.sample_rate as f64;
+        self.oversample();
+    }
+
+    fn latency(&self) -> usize { self.oversampler.latency().round() as usize }
+
+    fn get_params(&self) -> PluginParamsDesc {
+        let curves = Curve::all().iter().map(|c| c.name().to_owned());
+        let factors = OVERSAMPLING.iter().map(|f| format!("{}x", f));
+        box [
+            PluginParamDesc {
+                name: "Curve".to_owned(),
+                kind: PluginParamKind::Enum(curves.collect::<Vec<_>>().into_boxed_slice()),
+                default: ParamValue::Index(0)
+            },
+            PluginParamDesc {
+                name: "Drive".to_owned(),
+                kind: PluginParamKind::Float(0., 18.),
+                default: ParamValue::Float(0.)
+            },
+            PluginParamDesc {
+                name: "Mix".to_owned(),
+                kind: PluginParamKind::Float(0., 1.),
+                default: ParamValue::Float(1.)
+            },
+            PluginParamDesc {
+                name: "Oversampling".to_owned(),
+                kind: PluginParamKind::Enum(factors.collect::<Vec<_>>().into_boxed_slice()),
+                default: ParamValue::Index(0)
+            },
+            PluginParamDesc {
+                name: "Bits".to_owned(),
+                kind: PluginParamKind::Unsigned(1, 16),
+                default: ParamValue::Unsigned(8)
+            },
+            PluginParamDesc {
+                name: "Downsample".to_owned(),
+                kind: PluginParamKind::Unsigned(1, 64),
+                default: ParamValue::Unsigned(1)
+            }
+        ]
+    }
+
+    fn get_param(&self, index: usize) -> Option<ParamValue> {
+        match index {
+            PARAM_CURVE => {
+                Curve::all().iter().position(|c| *c == self.curve).map(ParamValue::Index)
+            },
+            PARAM_DRIVE => Some(ParamValue::Float(self.drive)),
+            PARAM_MIX => Some(ParamValue::Float(self.mix)),
+            PARAM_OVERSAMPLING => {
+                OVERSAMPLING.iter().position(|f| *f == self.oversampling)
+                    .map(ParamValue::Index)
+            },
+            PARAM_BITS => Some(ParamValue::Unsigned(self.bits)),
+            PARAM_DOWNSAMPLE => Some(ParamValue::Unsigned(self.downsample)),
+            _ => None
+        }
+    }
+
+    fn param_range(&self, index: usize) -> Option<PluginParamRange> {
+        RANGES.get(index).cloned()
+    }
+
+    /// The curve and oversampling are rebuilt when they're set.
+    fn param_allocates(&self, index: usize) -> bool {
+        match index {
+            PARAM_CURVE | PARAM_OVERSAMPLING | PARAM_BITS => true,
+            _ => false
+        }
+    }
+
+    fn set_param(&mut self, index: usize, value: ParamValue) -> PluginResult<()> {
+        self.check_param(index, &value)?;
+
+        match (index, value) {
+            (PARAM_CURVE, ParamValue::Index(c)) => {
+                self.curve = Curve::all()[c];
+                self.tabulate();
+            },
+            (PARAM_DRIVE, ParamValue::Float(d)) => { self.drive = d },
+            (PARAM_MIX, ParamValue::Float(m)) => { self.mix = m },
+            (PARAM_OVERSAMPLING, ParamValue::Index(f)) => {
+                self.oversampling = OVERSAMPLING[f];
+                self.oversample();
+            },
+            (PARAM_BITS, ParamValue::Unsigned(b)) => {
+                self.bits = b;
+                if self.curve == Curve::Bitcrush { self.tabulate(); }
+            },
+            (PARAM_DOWNSAMPLE, ParamValue::Unsigned(d)) => {
+                self.downsample = d;
+                self.held.1 = 0;
+            },
+            _ => unreachable!("checked by the range")
+        }
+        Ok(())
+    }
+});
+
+impl CorePlugin for Waveshaper {
+    fn new(core: &Core) -> Self {
+        let sample_rate = core.config.sample_rate as f64;
+        let mut waveshaper = Self {
+            curve: Curve::Tanh, drive: 0., mix: 1., oversampling: 1,
+            bits: 8, downsample: 1, custom: box [-1., 1.],
+            sample_rate,
+            table: Interpolator::from_points(Interpolation::Linear, -1., 1., box [-1., 1.]),
+            oversampler: Oversampler::new(1, sample_rate),
+            dry: Oversampler::new(1, sample_rate),
+            held: (Frame::default(), 0)
+        };
+        waveshaper.tabulate();
+        waveshaper
+    }
+
+    fn get_uuid() -> &'static str { "e47b2c91-3a5d-4f08-b6e2-8c0d1f7a5934" }
+
+    fn get_desc(id: usize) -> PluginDesc {
+        PluginDesc::default().with_id(id).with_uuid(Self::get_uuid())
+            .with_name("Waveshaper")
+            .with_category("Effect.Distortion")
+            .with_description("distortion with tabulated transfer curves.")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use buffer::Buffer;
+
+    fn waveshaper(curve: usize, drive: f64) -> Waveshaper {
+        let mut waveshaper = Waveshaper::new(&Core::new(Default::default()));
+        waveshaper.set_param(PARAM_CURVE, ParamValue::Index(curve)).unwrap();
+        waveshaper.set_param(PARAM_DRIVE, ParamValue::Float(drive)).unwrap();
+        waveshaper
+    }
+
+    fn shape(waveshaper: &mut Waveshaper, samples: &[f64]) -> Vec<f64> {
+        let mut audio = AudioBuffer::with_length(samples.len());
+        for (frame, s) in audio.iter_mut().zip(samples.iter()) {
+            *frame = (*s).into();
+        }
+        waveshaper.process_audio(&mut audio).unwrap();
+        audio.iter().map(|f| f[0]).collect()
+    }
+
+    #[test]
+    fn test_waveshaper_curves() {
+        let output = shape(&mut waveshaper(0, 0.), &[0., 0.5, -2.]);
+        assert_eq!(output[0], 0.);
+        assert!((output[1] - 0.5f64.tanh()).abs() < 1e-4);
+        assert!((output[2] + 2f64.tanh()).abs() < 1e-4);
+
+        let output = shape(&mut waveshaper(1, 20. * 2f64.log10()), &[0.25, 0.75, -1.]);
+        assert_eq!(output, vec![0.5, 1., -1.]);
+
+        let output = shape(&mut waveshaper(2, 20. * 2f64.log10()), &[0.25, 0.75, -1.]);
+        assert_eq!(output, vec![0.5, 0.5, 0.]);
+    }
+
+    #[test]
+    fn test_waveshaper_drive() {
+        for &drive in [0.1, 3.3, 7.7, 17.9].iter() {
+            let waveshaper = waveshaper(0, drive);
+            assert_eq!(waveshaper.get_param(PARAM_DRIVE), Some(ParamValue::Float(drive)));
+        }
+    }
+
+    #[test]
+    fn test_waveshaper_bitcrush() {
+        let mut waveshaper = waveshaper(3, 0.);
+        waveshaper.set_param(PARAM_BITS, ParamValue::Unsigned(2)).unwrap();
+        let output = shape(&mut waveshaper, &[0.1, 0.3, -0.8, 1.]);
+        assert_eq!(output, vec![0., 0.5, -1., 1.]);
+
+        waveshaper.set_param(PARAM_DOWNSAMPLE, ParamValue::Unsigned(2)).unwrap();
+        let output = shape(&mut waveshaper, &[0.3, -0.8, -0.8, 1.]);
+        assert_eq!(output, vec![0.5, 0.5, -1., -1.]);
+    }
+
+    #[test]
+    fn test_waveshaper_mix_and_oversampling() {
+        let mut waveshaper = waveshaper(1, 20. * 4f64.log10());
+        waveshaper.set_param(PARAM_MIX, ParamValue::Float(0.5)).unwrap();
+        assert_eq!(shape(&mut waveshaper, &[0.5]), vec![0.75]);
+
+        waveshaper.set_param(PARAM_OVERSAMPLING, ParamValue::Index(2)).unwrap();
+        assert_eq!(waveshaper.get_param(PARAM_OVERSAMPLING), Some(ParamValue::Index(2)));
+        assert_eq!(waveshaper.oversampler.factor(), 4);
+        assert_eq!(waveshaper.set_custom_curve(box [0.]), Err(PluginError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_waveshaper_oversampled_dry() {
+        // hard clip is linear below 1, so any mix of dry and wet is the same
+        let input = (0..256).map(|i| 0.5 * (i as f64 * 0.3).sin()).collect::<Vec<_>>();
+        let mut outputs = [0., 0.3, 1.].iter().map(|&mix| {
+            let mut waveshaper = waveshaper(1, 0.);
+            waveshaper.set_param(PARAM_OVERSAMPLING, ParamValue::Index(2)).unwrap();
+            waveshaper.set_param(PARAM_MIX, ParamValue::Float(mix)).unwrap();
+            shape(&mut waveshaper, &input)
+        }).collect::<Vec<_>>();
+
+        let wet = outputs.pop().unwrap();
+        for output in outputs {
+            for (o, w) in output.iter().zip(wet.iter()) {
+                assert!((o - w).abs() < 1e-6);
+            }
+        }
+    }
+}
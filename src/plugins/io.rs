@@ -117,6 +117,15 @@ impl PluginIoMode {
     pub fn is_valid(&self) -> bool {
         match *self { PluginIoMode::Inplace(ref m) => m.is_valid(), _ => true }
     }
+
+    /// Get kinds of the buffers which the plugin reads, which are all
+    /// buffers in inplace mode.
+    pub fn inputs(&self) -> &[PluginIoKind] {
+        match *self {
+            PluginIoMode::Inplace(ref m) => &m.buffers,
+            PluginIoMode::Complex(ref m) => &m.inputs
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -221,15 +230,15 @@ impl PluginIoBuffer {
 /// [`Plugin::process()`]: trait.Plugin.html#tymethod.process
 pub type PluginIo = Box<[PluginIoBuffer]>;
 
-#[derive(Clone)]
+#[derive(Debug, Clone, PartialEq)]
 /// Represents a change in plugin's input or output buffers status.
 pub struct PluginIoChange {
     /// Indicates whether the change is for an output buffer or not.
-    is_output: bool,
+    pub is_output: bool,
 
     /// The index of corresponding buffer.
-    index: usize,
+    pub index: usize,
 
-    /// The new status for buffer.
-    status: Option<PluginIoKind>
+    /// The new status for buffer. `None` means it's disconnected.
+    pub status: Option<PluginIoKind>
 }
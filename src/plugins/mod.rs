@@ -4,7 +4,9 @@
 //! factory standard.
 
 use std::collections::HashMap;
+use buffer::control::ParamValue;
 use super::hardconf;
+use CoreConfig;
 
 pub mod prelude {
     //! The Plugins Prelude
@@ -18,7 +20,7 @@ pub mod prelude {
     //! ```
 
     pub use super::{PluginError, PluginResult};
-    pub use super::{PluginParamKind, PluginParamDesc};
+    pub use super::{PluginParamKind, PluginParamRange, PluginParamDesc, PluginParamsDesc};
     pub use super::{PluginIoKind, PluginInplaceIo, PluginComplexIo};
     pub use super::{PluginIoMode, PluginIoDesc};
     pub use super::{PluginIoBuffer, PluginIo, PluginIoChange};
@@ -28,8 +30,14 @@ pub mod prelude {
 mod io;
 pub use self::io::*;
 
+#[macro_use]
+pub mod special;
+
 pub mod core;
 
+pub mod preset;
+use self::preset::Preset;
+
 #[derive(Debug, Clone, PartialEq)]
 /// Represents an error in some operation done by a plugin or factory.
 pub enum PluginError {
@@ -68,6 +76,63 @@ pub enum PluginParamKind {
     Boolean
 }
 
+impl PluginParamKind {
+    /// Get the range of values of this kind.
+    pub fn range(&self) -> PluginParamRange {
+        match *self {
+            PluginParamKind::Unsigned(min, max) => PluginParamRange::Unsigned(min, max),
+            PluginParamKind::Signed(min, max) => PluginParamRange::Signed(min, max),
+            PluginParamKind::Float(min, max) => PluginParamRange::Float(min, max),
+            PluginParamKind::Enum(ref names) => PluginParamRange::Enum(names.len()),
+            PluginParamKind::Boolean => PluginParamRange::Boolean
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The valid values of a parameter; a [`PluginParamKind`] without names, so
+/// plugins can keep it in a constant and check values without allocating.
+/// [`PluginParamKind`]: enum.PluginParamKind.html
+pub enum PluginParamRange {
+    Unsigned(u64, u64),
+    Signed(i64, i64),
+    Float(f64, f64),
+    /// Number of possible values.
+    Enum(usize),
+    Boolean
+}
+
+impl PluginParamRange {
+    /// Check whether `value` has the right type and is in this range.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::ParamValue;
+    /// use overcore::plugins::PluginParamRange;
+    ///
+    /// let range = PluginParamRange::Enum(3);
+    /// assert_eq!(range.is_valid(&ParamValue::Index(2)), true);
+    /// assert_eq!(range.is_valid(&ParamValue::Index(3)), false);
+    /// assert_eq!(range.is_valid(&ParamValue::Float(1.)), false);
+    /// ```
+    pub fn is_valid(&self, value: &ParamValue) -> bool {
+        match (*self, value) {
+            (PluginParamRange::Unsigned(min, max), &ParamValue::Unsigned(v)) => {
+                v >= min && v <= max
+            },
+            (PluginParamRange::Signed(min, max), &ParamValue::Signed(v)) => {
+                v >= min && v <= max
+            },
+            (PluginParamRange::Float(min, max), &ParamValue::Float(v)) => {
+                v >= min && v <= max
+            },
+            (PluginParamRange::Enum(count), &ParamValue::Index(i)) => i < count,
+            (PluginParamRange::Boolean, &ParamValue::Boolean(_)) => true,
+            _ => false
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 /// Descriptor for a parameter.
 pub struct PluginParamDesc {
@@ -75,15 +140,42 @@ pub struct PluginParamDesc {
     pub name: String,
 
     /// Type of parameter value.
-    pub kind: PluginParamKind
+    pub kind: PluginParamKind,
+
+    /// Default value of the parameter.
+    pub default: ParamValue
+}
+
+impl PluginParamDesc {
+    /// Check whether `value` has the right type and is in range of this
+    /// parameter.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::ParamValue;
+    /// use overcore::plugins::{PluginParamDesc, PluginParamKind};
+    ///
+    /// let desc = PluginParamDesc {
+    ///     name: "Gain".to_owned(),
+    ///     kind: PluginParamKind::Float(-24., 24.),
+    ///     default: ParamValue::Float(0.)
+    /// };
+    /// assert_eq!(desc.is_valid(&ParamValue::Float(-6.)), true);
+    /// assert_eq!(desc.is_valid(&ParamValue::Float(30.)), false);
+    /// assert_eq!(desc.is_valid(&ParamValue::Boolean(true)), false);
+    /// ```
+    pub fn is_valid(&self, value: &ParamValue) -> bool {
+        self.kind.range().is_valid(value)
+    }
 }
 
 /// List of parameter descriptors. Returned by [`Plugin::get_params()`][0].
 /// [0]: trait.Plugin.html#tymethod.get_params
 pub type PluginParamsDesc = Box<[PluginParamDesc]>;
 
-/// A trait that every plugin implements.
-pub trait Plugin {
+/// A trait that every plugin implements. Plugins are `Send`, so a graph can
+/// be built on one thread and rendered on another.
+pub trait Plugin: Send {
     /// Initialize the plugin. In this method, the plugin should do it's
     /// allocations, set parameter default values, do start-up calculations,
     /// etc.
@@ -97,12 +189,66 @@ pub trait Plugin {
 
     /// This method is called when some core context is changed such as
     /// sample rate. The plugin should handle the event by updating it's
-    /// internal state if needed.
-    fn core_changed(&mut self) {}
+    /// internal state according to `config` if needed.
+    fn core_changed(&mut self, _config: &CoreConfig) {}
 
     /// The plugin should return description of it's parameters.
     fn get_params(&self) -> PluginParamsDesc { box [] }
 
+    /// Get current value of the parameter at `index` of
+    /// [`get_params()`][0], if any.
+    /// [0]: trait.Plugin.html#method.get_params
+    fn get_param(&self, _index: usize) -> Option<ParamValue> { None }
+
+    /// Set value of the parameter at `index` of [`get_params()`][0]. If the
+    /// index or value is not valid according to the parameter descriptor,
+    /// [`PluginError::InvalidArgument`][1] should be returned.
+    /// [0]: trait.Plugin.html#method.get_params
+    /// [1]: enum.PluginError.html#variant.InvalidArgument
+    fn set_param(&mut self, _index: usize, _value: ParamValue)
+        -> PluginResult<()> {
+        Err(PluginError::InvalidArgument)
+    }
+
+    /// Get range of the parameter at `index` of [`get_params()`][0], if
+    /// any. The default implementation takes it from [`get_params()`][0],
+    /// which allocates; plugins should return it from constant data, since
+    /// it's used by [`check_param()`][1] on the render thread.
+    /// [0]: trait.Plugin.html#method.get_params
+    /// [1]: trait.Plugin.html#method.check_param
+    fn param_range(&self, index: usize) -> Option<PluginParamRange> {
+        self.get_params().get(index).map(|desc| desc.kind.range())
+    }
+
+    /// Check whether `value` is valid for the parameter at `index`,
+    /// according to [`param_range()`][0]. It's meant to be the first step
+    /// of [`set_param()`][1].
+    /// [0]: trait.Plugin.html#method.param_range
+    /// [1]: trait.Plugin.html#method.set_param
+    fn check_param(&self, index: usize, value: &ParamValue) -> PluginResult<()> {
+        match self.param_range(index) {
+            Some(range) if range.is_valid(value) => Ok(()),
+            _ => Err(PluginError::InvalidArgument)
+        }
+    }
+
+    /// Get default value of the parameter at `index` of
+    /// [`get_params()`][0], if any. Like [`param_range()`][1], the default
+    /// implementation allocates; it's used to handle
+    /// [`Event::ParamReset`][2] on the render thread.
+    /// [0]: trait.Plugin.html#method.get_params
+    /// [1]: trait.Plugin.html#method.param_range
+    /// [2]: ../buffer/control/enum.Event.html#variant.ParamReset
+    fn param_default(&self, index: usize) -> Option<ParamValue> {
+        self.get_params().get(index).map(|desc| desc.default.clone())
+    }
+
+    /// Get whether setting the parameter at `index` allocates, e.g. to
+    /// rebuild a table, so it shouldn't be set on the render thread. A host
+    /// rendering on another thread sets such parameters on it's control
+    /// thread instead.
+    fn param_allocates(&self, _index: usize) -> bool { false }
+
     /// Called when some IO buffer's status is changed. If the returning
     /// result was [`PluginError::InvalidArgument`][0], the core would call
     /// the [`get_io_descriptor()`][1] method to know the correct buffer
@@ -113,6 +259,25 @@ pub trait Plugin {
         Ok(())
     }
 
+    /// Get delay of the plugin's outputs relative to it's inputs, in
+    /// frames, e.g. caused by lookahead or block processing. The host
+    /// queries it after each render cycle, so a plugin notifies a change
+    /// just by returning the new value; the change is compensated from the
+    /// next cycle.
+    fn latency(&self) -> usize { 0 }
+
+    /// Get internal state of the plugin which is not covered by it's
+    /// parameters (e.g. a loaded file), to be saved with a project. The
+    /// data is opaque to the host.
+    fn save_state(&self) -> Vec<u8> { Vec::new() }
+
+    /// Restore the state returned by [`save_state()`][0]. It's called after
+    /// parameters are set. If `state` can't be read,
+    /// [`PluginError::InvalidArgument`][1] should be returned.
+    /// [0]: trait.Plugin.html#method.save_state
+    /// [1]: enum.PluginError.html#variant.InvalidArgument
+    fn load_state(&mut self, _state: &[u8]) -> PluginResult<()> { Ok(()) }
+
     /// This method is called when the core needs to know the plugin's
     /// buffer configuration. After calling this method, plugin's
     /// [`io_changed()`][0] is called for each buffer to inform the
@@ -151,6 +316,11 @@ pub trait Plugin {
     /// Called before plugin's deallocation if it was initialized
     /// successfully.
     fn terminate(&mut self) {}
+
+    /// Get the plugin as a [`WaitablePlugin`], if it's one, so the host can
+    /// wait for it through a plugin trait object.
+    /// [`WaitablePlugin`]: trait.WaitablePlugin.html
+    fn as_waitable(&mut self) -> Option<&mut WaitablePlugin> { None }
 }
 
 /// A plugin that is waitable would implement this. The engine would call the
@@ -288,4 +458,7 @@ pub trait Factory {
 
     /// Create an instance of the plugin specified by it's factory `id`.
     fn create_plugin(&mut self, id: usize) -> PluginResult<Box<Plugin>>;
+
+    /// Return presets shipped with the factory's plugins.
+    fn get_presets(&self) -> Box<[Preset]> { box [] }
 }
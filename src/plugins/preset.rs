@@ -0,0 +1,357 @@
+//! The Preset Module
+//!
+//! Named sets of parameter values of a plugin, referenced by the plugin's
+//! uuid. Parameters are stored by their names, so presets survive changes
+//! in the order of parameters.
+//!
+//! Presets are kept in a [`PresetBank`], which is saved as a JSON file.
+//! Factories may ship their own presets through
+//! [`Factory::get_presets()`].
+//!
+//! [`PresetBank`]: struct.PresetBank.html
+//! [`Factory::get_presets()`]: ../trait.Factory.html#method.get_presets
+
+use std::{fmt, io, error};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use buffer::control::{Event, ParamValue};
+use json::Value;
+use super::prelude::*;
+
+/// Version of written banks. Banks of later versions are refused.
+pub const VERSION: u64 = 1;
+
+#[derive(Debug)]
+/// An error occurred while reading or writing a preset bank.
+pub enum PresetError {
+    /// Reading or writing the file failed.
+    Io(io::Error),
+
+    /// The text is not valid JSON, at the given byte offset.
+    Syntax(usize),
+
+    /// The bank is written by a later version.
+    Version(u64),
+
+    /// A field is missing or has a wrong type.
+    Malformed(&'static str)
+}
+
+impl fmt::Display for PresetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PresetError::Io(ref e) => write!(f, "io error: {}", e),
+            PresetError::Syntax(offset) => write!(f, "syntax error at byte {}", offset),
+            PresetError::Version(v) => write!(f, "unsupported version {}", v),
+            PresetError::Malformed(s) => write!(f, "malformed: {}", s)
+        }
+    }
+}
+
+impl error::Error for PresetError {
+    fn description(&self) -> &str { "preset error" }
+}
+
+impl From<io::Error> for PresetError {
+    fn from(e: io::Error) -> Self { PresetError::Io(e) }
+}
+
+pub type PresetResult<T> = Result<T, PresetError>;
+
+#[derive(Debug, Clone, PartialEq)]
+/// A named set of parameter values of the plugin of `uuid`.
+///
+/// # Examples
+/// ```
+/// use overcore::buffer::control::ParamValue;
+/// use overcore::plugins::preset::Preset;
+///
+/// let preset = Preset::new("Dark", "some-uuid")
+///     .with_param("Cutoff", ParamValue::Float(200.));
+/// assert_eq!(preset.params.len(), 1);
+/// ```
+pub struct Preset {
+    /// Name of the preset.
+    pub name: String,
+
+    /// UUID of the plugin.
+    pub uuid: String,
+
+    /// Parameter names and values.
+    pub params: Vec<(String, ParamValue)>
+}
+
+impl Preset {
+    /// Create an empty preset.
+    pub fn new(name: &str, uuid: &str) -> Self {
+        Self { name: name.to_owned(), uuid: uuid.to_owned(), params: Vec::new() }
+    }
+
+    /// Set value of parameter `name`.
+    pub fn with_param(mut self, name: &str, value: ParamValue) -> Self {
+        self.params.retain(|p| p.0 != name);
+        self.params.push((name.to_owned(), value));
+        self
+    }
+
+    /// Capture current values of all parameters of `plugin`.
+    pub fn capture(name: &str, uuid: &str, plugin: &Plugin) -> Self {
+        let params = plugin.get_params().iter().enumerate().filter_map(|(i, desc)| {
+            plugin.get_param(i).map(|value| (desc.name.clone(), value))
+        }).collect();
+        Self { name: name.to_owned(), uuid: uuid.to_owned(), params }
+    }
+
+    /// Get index and value of each parameter in `plugin`. Returns
+    /// [`PluginError::InvalidArgument`][0] if a parameter is not found or
+    /// it's value is not valid, so no parameter is set partially.
+    /// [0]: ../enum.PluginError.html#variant.InvalidArgument
+    fn resolve(&self, plugin: &Plugin) -> PluginResult<Vec<(usize, ParamValue)>> {
+        let descs = plugin.get_params();
+        self.params.iter().map(|&(ref name, ref value)| {
+            match descs.iter().position(|desc| desc.name == *name) {
+                Some(index) if descs[index].is_valid(value) => Ok((index, value.clone())),
+                _ => Err(PluginError::InvalidArgument)
+            }
+        }).collect()
+    }
+
+    /// Set parameters of `plugin` immediately.
+    pub fn apply(&self, plugin: &mut Plugin) -> PluginResult<()> {
+        for (index, value) in self.resolve(plugin)? {
+            plugin.set_param(index, value)?;
+        }
+        Ok(())
+    }
+
+    /// Get [`Event::ParamSet`][0]s which set the parameters of `plugin`, to
+    /// be put in a moment of it's control input, so the change is sample
+    /// accurate. Returns [`PluginError::InvalidArgument`][1] if `plugin` has
+    /// no input which takes control buffers, since it would never receive
+    /// them; use [`apply()`](#method.apply) instead.
+    /// [0]: ../../buffer/control/enum.Event.html#variant.ParamSet
+    /// [1]: ../enum.PluginError.html#variant.InvalidArgument
+    pub fn events(&self, plugin: &Plugin) -> PluginResult<Box<[Event]>> {
+        let io = plugin.get_io_descriptor();
+        if io.mode.inputs().iter().all(|kind| *kind == PluginIoKind::Audio) {
+            return Err(PluginError::InvalidArgument);
+        }
+
+        let events = self.resolve(plugin)?.into_iter()
+            .map(|(index, value)| Event::ParamSet(index, value));
+        Ok(events.collect::<Vec<_>>().into_boxed_slice())
+    }
+}
+
+/// Write `value` tagged by it's type, as it's read without a descriptor.
+pub(crate) fn write_value(value: &ParamValue) -> Value {
+    let (kind, value) = match *value {
+        ParamValue::Unsigned(v) => ("unsigned", Value::number(v)),
+        ParamValue::Signed(v) => ("signed", Value::number(v)),
+        ParamValue::Float(v) => ("float", Value::number(v)),
+        ParamValue::Index(v) => ("index", Value::number(v)),
+        ParamValue::Boolean(v) => ("boolean", Value::Boolean(v))
+    };
+    Value::Object(vec![(kind.to_owned(), value)])
+}
+
+pub(crate) fn read_value(value: &Value) -> Option<ParamValue> {
+    match value.as_object() {
+        Some(&[(ref kind, ref value)]) => match &kind[..] {
+            "unsigned" => value.as_u64().map(ParamValue::Unsigned),
+            "signed" => value.as_i64().map(ParamValue::Signed),
+            "float" => value.as_f64().map(ParamValue::Float),
+            "index" => value.as_u64().map(|v| ParamValue::Index(v as usize)),
+            "boolean" => value.as_bool().map(ParamValue::Boolean),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// A collection of presets of any plugins. Names are unique per plugin.
+///
+/// # Examples
+/// ```
+/// use overcore::buffer::control::ParamValue;
+/// use overcore::plugins::preset::{Preset, PresetBank};
+///
+/// let mut bank = PresetBank::default();
+/// bank.insert(Preset::new("Loud", "some-uuid").with_param("Gain", ParamValue::Float(6.)));
+///
+/// let bank = PresetBank::parse(&bank.to_string()).unwrap();
+/// assert_eq!(bank.get("some-uuid", "Loud").unwrap().params.len(), 1);
+/// ```
+pub struct PresetBank(Vec<Preset>);
+
+impl PresetBank {
+    /// Add `preset`, replacing the one of the same plugin and name.
+    pub fn insert(&mut self, preset: Preset) {
+        match self.0.iter().position(|p| p.uuid == preset.uuid && p.name == preset.name) {
+            Some(index) => self.0[index] = preset,
+            None => self.0.push(preset)
+        }
+    }
+
+    /// Remove preset `name` of the plugin of `uuid`.
+    pub fn remove(&mut self, uuid: &str, name: &str) -> Option<Preset> {
+        let index = self.0.iter().position(|p| p.uuid == uuid && p.name == name);
+        index.map(|index| self.0.remove(index))
+    }
+
+    /// Get preset `name` of the plugin of `uuid`.
+    pub fn get(&self, uuid: &str, name: &str) -> Option<&Preset> {
+        self.0.iter().find(|p| p.uuid == uuid && p.name == name)
+    }
+
+    /// Get presets of the plugin of `uuid`, in the order they were added.
+    pub fn presets<'a>(&'a self, uuid: &'a str) -> impl Iterator<Item = &'a Preset> + 'a {
+        self.0.iter().filter(move |p| p.uuid == uuid)
+    }
+
+    /// Read a bank from it's JSON text.
+    pub fn parse(text: &str) -> PresetResult<Self> {
+        let bank = Value::parse(text).map_err(PresetError::Syntax)?;
+
+        let version = bank.get("version").and_then(Value::as_u64)
+            .ok_or(PresetError::Malformed("version"))?;
+        if version > VERSION {
+            return Err(PresetError::Version(version));
+        }
+
+        let presets = bank.get("presets").and_then(Value::as_array)
+            .ok_or(PresetError::Malformed("presets"))?;
+        let mut result = Self::default();
+        for preset in presets.iter() {
+            let string = |key, error| preset.get(key).and_then(Value::as_str)
+                .ok_or(PresetError::Malformed(error));
+            let mut parsed = Preset::new(string("name", "preset name")?,
+                                         string("uuid", "preset uuid")?);
+
+            let params = preset.get("params").and_then(Value::as_object)
+                .ok_or(PresetError::Malformed("preset params"))?;
+            for &(ref name, ref value) in params.iter() {
+                let value = read_value(value).ok_or(PresetError::Malformed("param value"))?;
+                parsed.params.push((name.clone(), value));
+            }
+            result.insert(parsed);
+        }
+        Ok(result)
+    }
+
+    /// Read the bank file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> PresetResult<Self> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        Self::parse(&text)
+    }
+
+    /// Write the bank to a file at `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> PresetResult<()> {
+        File::create(path)?.write_all(self.to_string().as_bytes())?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for PresetBank {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let field = |key: &str, value| (key.to_owned(), value);
+        let presets = self.0.iter().map(|preset| {
+            let params = preset.params.iter()
+                .map(|&(ref name, ref value)| (name.clone(), write_value(value)));
+            Value::Object(vec![
+                field("name", Value::String(preset.name.clone())),
+                field("uuid", Value::String(preset.uuid.clone())),
+                field("params", Value::Object(params.collect()))
+            ])
+        });
+        let bank = Value::Object(vec![
+            field("version", Value::number(VERSION)),
+            field("presets", Value::Array(presets.collect()))
+        ]);
+        write!(f, "{}", bank)
+    }
+}
+
+impl Extend<Preset> for PresetBank {
+    fn extend<T: IntoIterator<Item = Preset>>(&mut self, presets: T) {
+        for preset in presets {
+            self.insert(preset);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Core;
+    use buffer::Buffer;
+    use buffer::audio::AudioBuffer;
+    use buffer::control::ControlBuffer;
+    use plugins::Factory;
+    use plugins::core::{CoreFactory, CorePlugin, FunctionGenerator, Mixer};
+
+    #[test]
+    fn test_preset_capture_apply() {
+        let core = Core::new(Default::default());
+        let mut mixer = Mixer::new(&core);
+        mixer.set_param(0, ParamValue::Float(-6.)).unwrap();
+
+        let preset = Preset::capture("Quiet", Mixer::get_uuid(), &mixer);
+        assert_eq!(preset.params, [("Master".to_owned(), ParamValue::Float(-6.))]);
+
+        let mut other = Mixer::new(&core);
+        assert_eq!(preset.events(&other), Err(PluginError::InvalidArgument));
+        preset.apply(&mut other).unwrap();
+        assert_eq!(other.get_param(0), Some(ParamValue::Float(-6.)));
+
+        let invalid = preset.with_param("Gain 9", ParamValue::Float(0.));
+        assert_eq!(invalid.apply(&mut other), Err(PluginError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_preset_events() {
+        let core = Core::new(Default::default());
+        let mut generator = FunctionGenerator::new(&core);
+        let preset = Preset::new("Quiet", FunctionGenerator::get_uuid())
+            .with_param("Level", ParamValue::Float(-6.));
+        let events = preset.events(&generator).unwrap();
+        assert_eq!(&events[..], &[Event::ParamSet(1, ParamValue::Float(-6.))]);
+
+        let mut outputs: PluginIo = box [PluginIoBuffer::Audio(AudioBuffer::with_length(1))];
+        let mut control = ControlBuffer::with_length(1);
+        control[0] = Some(events);
+        let inputs: PluginIo = box [PluginIoBuffer::Control(control)];
+        generator.process(&inputs, &mut outputs).unwrap();
+        assert_eq!(generator.get_param(1), Some(ParamValue::Float(-6.)));
+
+        let mut control = ControlBuffer::with_length(1);
+        control[0] = Some(box [Event::ParamReset(1)]);
+        let inputs: PluginIo = box [PluginIoBuffer::Control(control)];
+        generator.process(&inputs, &mut outputs).unwrap();
+        assert_eq!(generator.get_param(1), Some(ParamValue::Float(0.)));
+    }
+
+    #[test]
+    fn test_preset_bank() {
+        let core = Core::new(Default::default());
+        let mut bank = PresetBank::default();
+        bank.extend(CoreFactory::new(&core).get_presets().iter().cloned());
+        let uuid = FunctionGenerator::get_uuid();
+        assert!(bank.presets(uuid).count() > 1);
+
+        bank.insert(Preset::new("Mine", uuid).with_param("Level", ParamValue::Float(-3.)));
+        bank.insert(Preset::new("Mine", uuid).with_param("Level", ParamValue::Float(-1.)));
+        let count = bank.presets(uuid).count();
+
+        let parsed = PresetBank::parse(&bank.to_string()).unwrap();
+        assert_eq!(parsed, bank);
+        assert_eq!(parsed.get(uuid, "Mine").unwrap().params[0].1, ParamValue::Float(-1.));
+
+        bank.remove(uuid, "Mine").unwrap();
+        assert_eq!(bank.presets(uuid).count(), count - 1);
+        assert!(PresetBank::parse("{\"version\": 1, \"presets\": [{}]}").is_err());
+    }
+}
@@ -7,6 +7,51 @@
 //! used to categorize plugins. For instance, One can implement it's own
 //! audio effect without implemeting [`Effect`] trait.
 //! [`Effect`]: trait.Effect.html
+//!
+//! Each trait comes with a macro which implements [`Plugin`] for the type,
+//! providing [`get_io_descriptor()`] and [`process()`]. Other methods of
+//! [`Plugin`] may be given to the macro in braces.
+//!
+//! # Examples
+//! ```
+//! #[macro_use]
+//! extern crate overcore;
+//!
+//! use overcore::buffer::Buffer;
+//! use overcore::buffer::prelude::*;
+//! use overcore::plugins::prelude::*;
+//! use overcore::plugins::special::Effect;
+//!
+//! struct Invert;
+//!
+//! impl Effect for Invert {
+//!     fn process_audio(&mut self, audio: &mut AudioBuffer) -> PluginResult<()> {
+//!         audio.iter_mut().for_each(|frame| *frame = -*frame);
+//!         Ok(())
+//!     }
+//! }
+//!
+//! impl_effect_for!(Invert, {
+//!     fn terminate(&mut self) {}
+//! });
+//!
+//! # fn main() {
+//! let mut audio = AudioBuffer::with_length(4);
+//! audio[0] = (1.).into();
+//!
+//! let inputs: PluginIo = Box::new([]);
+//! let mut outputs: PluginIo = Box::new([PluginIoBuffer::Audio(audio)]);
+//! Invert.process(&inputs, &mut outputs).unwrap();
+//!
+//! if let PluginIoBuffer::Audio(ref audio) = outputs[0] {
+//!     assert_eq!(audio[0], (-1.).into());
+//! }
+//! # }
+//! ```
+//!
+//! [`Plugin`]: ../trait.Plugin.html
+//! [`get_io_descriptor()`]: ../trait.Plugin.html#tymethod.get_io_descriptor
+//! [`process()`]: ../trait.Plugin.html#tymethod.process
 
 
 /* Currently we can't do this:
@@ -21,5 +66,430 @@
  * TODO: Replace `impl_*_for` macros with implementors.
  */
 
+use std::mem;
+use buffer::prelude::*;
+use super::prelude::*;
+
+/// An audio effect. It has a single audio buffer which is processed
+/// inplace. Use [`impl_effect_for!`] to implement [`Plugin`] for it.
+///
+/// [`impl_effect_for!`]: ../../macro.impl_effect_for.html
+/// [`Plugin`]: ../trait.Plugin.html
+pub trait Effect {
+    /// Process `audio` in place.
+    fn process_audio(&mut self, audio: &mut AudioBuffer) -> PluginResult<()>;
+}
+
+/// An audio generator. It reads a control input and writes to an audio
+/// output. Use [`impl_generator_for!`] to implement [`Plugin`] for it.
+///
+/// [`impl_generator_for!`]: ../../macro.impl_generator_for.html
+/// [`Plugin`]: ../trait.Plugin.html
+pub trait Generator {
+    /// Render `audio` according to `control`. `control` is `None` if the
+    /// control input is disconnected.
+    fn generate(&mut self, control: Option<&ControlBuffer>,
+                audio: &mut AudioBuffer) -> PluginResult<()>;
+}
+
+/// An instrument, which renders each of it's voices separately. Voices are
+/// started, updated and stopped by the host according to the events of the
+/// control input, and are held in a [`Voices`]. Parameter events of the
+/// control input are applied at their frame, through [`Plugin::set_param()`].
+/// Use [`impl_instrument_for!`] to implement [`Plugin`] for it.
+///
+/// [`Voices`]: struct.Voices.html
+/// [`Plugin::set_param()`]: ../trait.Plugin.html#method.set_param
+/// [`impl_instrument_for!`]: ../../macro.impl_instrument_for.html
+/// [`Plugin`]: ../trait.Plugin.html
+pub trait Instrument {
+    /// State of a single voice.
+    type Voice;
+
+    /// Get voices of the instrument.
+    fn voices(&mut self) -> &mut Voices<Self::Voice>;
+
+    /// Create a voice to play `note`.
+    fn start(&mut self, note: &Note) -> Self::Voice;
+
+    /// Called after a parameter of `note` is changed.
+    fn update(&mut self, _voice: &mut Self::Voice, _note: &Note) {}
+
+    /// Called when `voice` is released. If it returns `true`, the voice is
+    /// kept and rendered (e.g. for a release phase) until [`render()`]
+    /// returns `None`.
+    ///
+    /// [`render()`]: #tymethod.render
+    fn stop(&mut self, _voice: &mut Self::Voice) -> bool { false }
+
+    /// Render next frame of `voice`, which plays `note`. Returning `None`
+    /// means the voice is finished and should be removed.
+    fn render(&mut self, voice: &mut Self::Voice, note: &Note) -> Option<Frame>;
+}
+
+/// Voices of an [`Instrument`], ordered by their age. When the limit of
+/// voices is reached, the oldest voice is stolen.
+///
+/// [`Instrument`]: trait.Instrument.html
+pub struct Voices<V> {
+    limit: usize,
+    voices: Vec<(NoteRef, Note, V)>,
+    released: Vec<NoteRef>
+}
+
+impl<V> Voices<V> {
+    /// Create an empty voice holder for at most `limit` voices.
+    ///
+    /// # Panics
+    /// Panics if `limit` is 0.
+    pub fn new(limit: usize) -> Self {
+        assert_ne!(limit, 0);
+        Self { limit, voices: Vec::new(), released: Vec::new() }
+    }
+
+    #[inline]
+    /// Get number of voices being played.
+    pub fn len(&self) -> usize { self.voices.len() }
+
+    #[inline]
+    /// Get maximum number of voices.
+    pub fn limit(&self) -> usize { self.limit }
+
+    /// Iterate over notes being played.
+    pub fn notes<'a>(&'a self) -> impl Iterator<Item = &'a Note> {
+        self.voices.iter().map(|&(_, ref note, _)| note)
+    }
+
+    fn push(&mut self, reference: NoteRef, note: Note, voice: V) {
+        if self.voices.len() >= self.limit {
+            let (reference, _, _) = self.voices.remove(0);
+            self.released.retain(|&r| r != reference);
+        }
+        self.voices.push((reference, note, voice));
+    }
+
+    fn position(&self, reference: &NoteRef) -> Option<usize> {
+        self.voices.iter().position(|&(ref r, _, _)| r == reference)
+    }
+
+    fn remove(&mut self, index: usize) {
+        let (reference, _, _) = self.voices.remove(index);
+        self.released.retain(|&r| r != reference);
+    }
+
+    /// Clear all voices.
+    pub fn clear(&mut self) {
+        self.voices.clear();
+        self.released.clear();
+    }
+}
+
+impl<V> Default for Voices<V> {
+    /// Create a voice holder with the limit of 64 voices.
+    fn default() -> Self { Self::new(64) }
+}
+
+#[doc(hidden)]
+pub fn effect_io_descriptor() -> PluginIoDesc {
+    PluginIoDesc {
+        mode: PluginIoMode::Inplace(PluginInplaceIo {
+            buffers: box [PluginIoKind::Audio],
+            mapping: box [0]
+        }),
+        inputs: box ["Audio".to_owned()],
+        outputs: box ["Audio".to_owned()]
+    }
+}
+
+#[doc(hidden)]
+pub fn process_effect<T: Effect>(effect: &mut T, _inputs: &PluginIo,
+                                 outputs: &mut PluginIo) -> PluginResult<()> {
+    if outputs.len() != 1 {
+        return Err(PluginError::InvalidArgument);
+    }
+
+    match outputs[0] {
+        PluginIoBuffer::Audio(ref mut audio) => effect.process_audio(audio),
+        PluginIoBuffer::Disconnected => Ok(()),
+        _ => Err(PluginError::InvalidArgument)
+    }
+}
+
+#[doc(hidden)]
+pub fn generator_io_descriptor() -> PluginIoDesc {
+    PluginIoDesc {
+        mode: PluginIoMode::Complex(PluginComplexIo {
+            inputs: box [PluginIoKind::Control],
+            outputs: box [PluginIoKind::Audio]
+        }),
+        inputs: box ["Control".to_owned()],
+        outputs: box ["Audio".to_owned()]
+    }
+}
+
+/// Get buffers of a generator-like plugin, checking their kinds.
+fn generator_buffers<'a>(inputs: &'a PluginIo, outputs: &'a mut PluginIo)
+    -> PluginResult<(Option<&'a ControlBuffer>, Option<&'a mut AudioBuffer>)> {
+    if inputs.len() != 1 || outputs.len() != 1 {
+        return Err(PluginError::InvalidArgument);
+    }
+
+    let control = match inputs[0] {
+        PluginIoBuffer::Control(ref control) => Some(control),
+        PluginIoBuffer::Disconnected => None,
+        _ => { return Err(PluginError::InvalidArgument); }
+    };
+
+    let audio = match outputs[0] {
+        PluginIoBuffer::Audio(ref mut audio) => Some(audio),
+        PluginIoBuffer::Disconnected => None,
+        _ => { return Err(PluginError::InvalidArgument); }
+    };
+
+    Ok((control, audio))
+}
+
+#[doc(hidden)]
+pub fn process_generator<T: Generator>(generator: &mut T, inputs: &PluginIo,
+                                       outputs: &mut PluginIo)
+    -> PluginResult<()> {
+    match generator_buffers(inputs, outputs)? {
+        (control, Some(audio)) => generator.generate(control, audio),
+        (_, None) => Ok(())
+    }
+}
+
+/// Apply events of a moment to voices of `instrument`.
+fn apply_moment<T: Instrument + Plugin>(instrument: &mut T,
+                                        voices: &mut Voices<T::Voice>, moment: &Moment) {
+    let events = match moment { &Some(ref events) => events, &None => return };
+
+    for event in events.iter() {
+        match event {
+            &Event::NoteOn(ref note, reference) => {
+                if let Some(index) = voices.position(&reference) {
+                    voices.remove(index);
+                }
+                let voice = instrument.start(note);
+                voices.push(reference, note.clone(), voice);
+            },
+            &Event::NoteSet(ref reference, ref param) => {
+                if let Some(index) = voices.position(reference) {
+                    let (_, ref mut note, ref mut voice) = voices.voices[index];
+                    note.params.apply(param);
+                    instrument.update(voice, note);
+                }
+            },
+            &Event::NoteOff(ref reference) => {
+                if let Some(index) = voices.position(reference) {
+                    if voices.released.contains(reference) {
+                        continue;
+                    }
+                    if instrument.stop(&mut voices.voices[index].2) {
+                        voices.released.push(*reference);
+                    } else {
+                        voices.remove(index);
+                    }
+                }
+            },
+            &Event::ParamSet(index, ref value) => {
+                let _ = instrument.set_param(index, value.clone());
+            },
+            &Event::ParamReset(index) => {
+                if let Some(value) = instrument.param_default(index) {
+                    let _ = instrument.set_param(index, value);
+                }
+            },
+            &Event::Panic => voices.clear()
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn process_instrument<T: Instrument + Plugin>(instrument: &mut T, inputs: &PluginIo,
+                                                  outputs: &mut PluginIo)
+    -> PluginResult<()> {
+    let (control, mut audio) = generator_buffers(inputs, outputs)?;
+
+    // voices are moved out to be rendered by the instrument itself
+    let mut voices = mem::replace(instrument.voices(), Voices {
+        limit: 1, voices: Vec::new(), released: Vec::new()
+    });
+
+    let len = audio.as_ref().map(|audio| audio.len())
+        .or(control.map(|control| control.len())).unwrap_or(0);
+
+    for i in 0..len {
+        if let Some(control) = control {
+            apply_moment(instrument, &mut voices, &control[i]);
+        }
+
+        let mut frame = Frame::default();
+        let mut index = 0;
+        while index < voices.voices.len() {
+            let (_, ref note, ref mut voice) = voices.voices[index];
+            match instrument.render(voice, note) {
+                Some(f) => { frame += f; index += 1; },
+                None => voices.remove(index)
+            }
+        }
+
+        if let Some(ref mut audio) = audio {
+            audio[i] = frame;
+        }
+    }
+
+    *instrument.voices() = voices;
+    Ok(())
+}
+
+#[macro_export]
+/// Implement [`Plugin`] for an [`Effect`]. Other methods of [`Plugin`] may
+/// be given in braces after the type.
+///
+/// [`Plugin`]: plugins/trait.Plugin.html
+/// [`Effect`]: plugins/special/trait.Effect.html
+macro_rules! impl_effect_for {
+    ($t:ty) => (impl_effect_for!($t, {}););
+    ($t:ty, { $($items:tt)* }) => (
+        impl $crate::plugins::Plugin for $t {
+            $($items)*
+
+            fn get_io_descriptor(&self) -> $crate::plugins::PluginIoDesc {
+                $crate::plugins::special::effect_io_descriptor()
+            }
+
+            fn process(&mut self, inputs: &$crate::plugins::PluginIo,
+                       outputs: &mut $crate::plugins::PluginIo)
+                -> $crate::plugins::PluginResult<()> {
+                $crate::plugins::special::process_effect(self, inputs, outputs)
+            }
+        }
+    )
+}
+
+#[macro_export]
+/// Implement [`Plugin`] for a [`Generator`]. Other methods of [`Plugin`] may
+/// be given in braces after the type.
+///
+/// [`Plugin`]: plugins/trait.Plugin.html
+/// [`Generator`]: plugins/special/trait.Generator.html
+macro_rules! impl_generator_for {
+    ($t:ty) => (impl_generator_for!($t, {}););
+    ($t:ty, { $($items:tt)* }) => (
+        impl $crate::plugins::Plugin for $t {
+            $($items)*
+
+            fn get_io_descriptor(&self) -> $crate::plugins::PluginIoDesc {
+                $crate::plugins::special::generator_io_descriptor()
+            }
+
+            fn process(&mut self, inputs: &$crate::plugins::PluginIo,
+                       outputs: &mut $crate::plugins::PluginIo)
+                -> $crate::plugins::PluginResult<()> {
+                $crate::plugins::special::process_generator(self, inputs, outputs)
+            }
+        }
+    )
+}
+
+#[macro_export]
+/// Implement [`Plugin`] for an [`Instrument`]. Other methods of [`Plugin`]
+/// may be given in braces after the type.
+///
+/// [`Plugin`]: plugins/trait.Plugin.html
+/// [`Instrument`]: plugins/special/trait.Instrument.html
+macro_rules! impl_instrument_for {
+    ($t:ty) => (impl_instrument_for!($t, {}););
+    ($t:ty, { $($items:tt)* }) => (
+        impl $crate::plugins::Plugin for $t {
+            $($items)*
+
+            fn get_io_descriptor(&self) -> $crate::plugins::PluginIoDesc {
+                $crate::plugins::special::generator_io_descriptor()
+            }
+
+            fn process(&mut self, inputs: &$crate::plugins::PluginIo,
+                       outputs: &mut $crate::plugins::PluginIo)
+                -> $crate::plugins::PluginResult<()> {
+                $crate::plugins::special::process_instrument(self, inputs, outputs)
+            }
+        }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use buffer::Buffer;
+
+    /// Plays a constant 1 on each voice for 2 more frames after release.
+    struct Constant(Voices<Option<usize>>);
+
+    impl Instrument for Constant {
+        type Voice = Option<usize>;
+
+        fn voices(&mut self) -> &mut Voices<Self::Voice> { &mut self.0 }
+
+        fn start(&mut self, _note: &Note) -> Self::Voice { None }
+
+        fn stop(&mut self, voice: &mut Self::Voice) -> bool {
+            *voice = Some(2); true
+        }
+
+        fn render(&mut self, voice: &mut Self::Voice, note: &Note)
+            -> Option<Frame> {
+            if let &mut Some(ref mut left) = voice {
+                if *left == 0 { return None; }
+                *left -= 1;
+            }
+            Some((note.params.velocity / 100.).into())
+        }
+    }
+
+    impl_instrument_for!(Constant);
+
+    fn note_on(reference: usize) -> Event {
+        let name = NoteName(NoteAlphabet::A, 4);
+        let note = Note { name, params: Default::default() };
+        Event::NoteOn(note, NoteRef(reference))
+    }
+
+    fn render(plugin: &mut Constant, control: ControlBuffer) -> Vec<Sample> {
+        let len = control.len();
+        let inputs: PluginIo = box [PluginIoBuffer::Control(control)];
+        let audio = AudioBuffer::with_length(len);
+        let mut outputs: PluginIo = box [PluginIoBuffer::Audio(audio)];
+        plugin.process(&inputs, &mut outputs).unwrap();
+        match outputs[0] {
+            PluginIoBuffer::Audio(ref audio) => audio.iter().map(|f| f[0]).collect(),
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_instrument_voices() {
+        let mut plugin = Constant(Voices::new(2));
+        let mut control = ControlBuffer::with_length(8);
+        control[0] = Some(box [note_on(0), note_on(1)]);
+        control[1] = Some(box [note_on(1)]);
+        control[2] = Some(box [Event::NoteSet(NoteRef(0), NoteParam::Velocity(50.))]);
+        control[3] = Some(box [Event::NoteOff(NoteRef(0))]);
+        control[6] = Some(box [Event::Panic]);
+
+        let output = render(&mut plugin, control);
+        assert_eq!(output, [2., 2., 1.5, 1.5, 1.5, 1., 0., 0.]);
+    }
 
+    #[test]
+    fn test_instrument_voice_stealing() {
+        let mut plugin = Constant(Voices::new(2));
+        let mut control = ControlBuffer::with_length(2);
+        control[0] = Some(box [note_on(0), note_on(1), note_on(2)]);
+        control[1] = Some(box [Event::NoteOff(NoteRef(0))]);
 
+        let output = render(&mut plugin, control);
+        assert_eq!(output, [2., 2.]);
+        assert_eq!(plugin.0.len(), 2);
+    }
+}
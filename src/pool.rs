@@ -0,0 +1,155 @@
+//! The Pool Module
+//!
+//! A fixed set of worker threads, which run tasks handed by one owner
+//! thread. Threads are spawned once, when the pool is made, and tasks are
+//! passed through lock-free queues, so handing a task never allocates.
+//! Idle workers, and an owner waiting for them, are parked.
+
+use std::thread::{self, JoinHandle, Thread};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use queue::{queue, Producer, Consumer};
+
+/// A piece of work which can be run on a worker.
+pub trait Task: Send + 'static {
+    fn run(&mut self);
+}
+
+struct Worker<T> {
+    thread: Option<JoinHandle<()>>,
+    // tasks go with the thread to wake when they're done
+    tasks: Producer<(T, Thread)>,
+    done: Consumer<T>,
+    busy: bool
+}
+
+/// A pool of workers, each running one task at a time.
+pub struct Pool<T> {
+    workers: Vec<Worker<T>>,
+    running: Arc<AtomicBool>
+}
+
+impl<T: Task> Pool<T> {
+    /// Make a pool for processing on `threads` threads, including the
+    /// owner's; so `threads - 1` workers are spawned.
+    pub fn new(threads: usize) -> Self {
+        let running = Arc::new(AtomicBool::new(true));
+        let workers = (1..threads.max(1)).map(|_| {
+            let (tasks, mut inbox) = queue::<(T, Thread)>(1);
+            let (mut outbox, done) = queue(1);
+            let running = running.clone();
+            let thread = thread::spawn(move || loop {
+                match inbox.pop() {
+                    Some((mut task, owner)) => {
+                        task.run();
+                        // the owner doesn't hand a task before taking the last one
+                        if outbox.push(task).is_err() { unreachable!(); }
+                        owner.unpark();
+                    },
+                    None if running.load(Ordering::Acquire) => thread::park(),
+                    None => break
+                }
+            });
+            Worker { thread: Some(thread), tasks, done, busy: false }
+        }).collect();
+        Self { workers, running }
+    }
+
+    /// Get number of threads the pool processes on, including the owner's.
+    pub fn threads(&self) -> usize { self.workers.len() + 1 }
+
+    /// Hand `task` to an idle worker, or get it back if none is idle.
+    pub fn submit(&mut self, task: T) -> Result<(), T> {
+        match self.workers.iter_mut().find(|w| !w.busy) {
+            Some(worker) => {
+                if let Err(_) = worker.tasks.push((task, thread::current())) { unreachable!(); }
+                worker.busy = true;
+                worker.thread.as_ref().unwrap().thread().unpark();
+                Ok(())
+            },
+            None => Err(task)
+        }
+    }
+
+    /// Take a task which is run by a worker, if any.
+    pub fn finished(&mut self) -> Option<T> {
+        for worker in self.workers.iter_mut().filter(|w| w.busy) {
+            if let Some(task) = worker.done.pop() {
+                worker.busy = false;
+                return Some(task);
+            }
+        }
+        None
+    }
+
+    /// Take a task which is run by a worker, parking until one is done.
+    /// Returns `None` at once if no worker is busy.
+    pub fn wait(&mut self) -> Option<T> {
+        while self.workers.iter().any(|w| w.busy) {
+            if let Some(task) = self.finished() {
+                return Some(task);
+            }
+            // a worker unparks after handing it's task back, so it's not missed
+            thread::park();
+        }
+        None
+    }
+}
+
+impl<T> Drop for Pool<T> {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Release);
+        for worker in self.workers.iter_mut() {
+            let thread = worker.thread.take().unwrap();
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Square(u64);
+
+    impl Task for Square {
+        fn run(&mut self) { self.0 *= self.0; }
+    }
+
+    #[test]
+    fn test_pool() {
+        let mut pool = Pool::new(3);
+        assert_eq!(pool.threads(), 3);
+
+        let mut results = Vec::new();
+        let mut next = 0;
+        while results.len() < 100 {
+            if next < 100 {
+                if let Err(mut task) = pool.submit(Square(next)) {
+                    task.run();
+                    results.push(task.0);
+                }
+                next += 1;
+            }
+            while let Some(task) = pool.finished() {
+                results.push(task.0);
+            }
+        }
+        results.sort();
+        assert_eq!(results, (0..100).map(|i| i * i).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_pool_wait() {
+        let mut pool = Pool::new(3);
+        assert!(pool.wait().is_none());
+
+        assert!(pool.submit(Square(3)).is_ok());
+        assert!(pool.submit(Square(4)).is_ok());
+        let mut results = vec![pool.wait().unwrap().0, pool.wait().unwrap().0];
+        results.sort();
+        assert_eq!(results, [9, 16]);
+        assert!(pool.wait().is_none());
+    }
+}
@@ -0,0 +1,534 @@
+//! The Project Module
+//!
+//! Saving and loading a whole session: the core config, nodes of the graph
+//! referenced by their plugin uuid, connections by port indices, and every
+//! parameter and internal state of plugins. Projects are JSON documents
+//! with a version field, like this:
+//!
+//! ```json
+//! {
+//!   "version": 2,
+//!   "config": { "sample_rate": 44100, "buffer_size": 512, "threads": 1, ... },
+//!   "nodes": [
+//!     { "uuid": "...", "params": { "Gain": -6.0, "Mode": "Low Pass" } }
+//!   ],
+//!   "connections": [
+//!     { "from": 0, "output": 0, "to": 1, "input": 0 }
+//!   ]
+//! }
+//! ```
+//!
+//! Nodes are referenced by their index in `nodes`. Projects of version 1
+//! reference ports by name instead, which are only found if no other port
+//! of the node has the same name. Feedback connections
+//! have a `"feedback": true` field. Values of enum parameters are written
+//! by name, and plugin states as hex strings.
+//!
+//! The tuning of the config is written by it's [`TuningKind`], either as
+//! `{ "divisions": 12, "reference": "A4", "freq": 440.0 }` for equal
+//! temperaments or as `{ "scale": "...", "mapping": "..." }` with contents
+//! of Scala files. Other tunings can't be saved.
+//!
+//! [`TuningKind`]: ../tuning/enum.TuningKind.html
+
+use std::{fmt, error, mem};
+use std::convert::TryFrom;
+use buffer::control::{NoteName, ParamValue};
+use graph::{Connection, Graph, GraphError, NodeId};
+use json::Value;
+use plugins::prelude::*;
+use plugins::Factory;
+use tuning::{EqualTemperament, KeyboardMapping, ScalaScale, ScalaTuning, Tuning, TuningKind};
+use {Core, CoreConfig};
+
+/// Version of written projects. Projects of later versions are refused.
+pub const VERSION: u64 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+/// An error occurred while saving or loading a project.
+pub enum ProjectError {
+    /// The text is not valid JSON, at the given byte offset.
+    Syntax(usize),
+
+    /// The project is written by a later version.
+    Version(u64),
+
+    /// A field is missing or has a wrong type.
+    Malformed(&'static str),
+
+    /// A connection refers to a port which is not found, by it's name or
+    /// index.
+    NoSuchPort(String),
+
+    /// A connection refers to a port by a name which several ports have.
+    AmbiguousPort(String),
+
+    /// A parameter which is not found, or has an invalid value.
+    InvalidParam(String),
+
+    /// A node is not created from a factory, so it can't be referenced.
+    Anonymous(NodeId),
+
+    /// The tuning of the config is not one of [`TuningKind`]s.
+    ///
+    /// [`TuningKind`]: ../tuning/enum.TuningKind.html
+    Tuning,
+
+    /// Building the graph failed.
+    Graph(GraphError)
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProjectError::Syntax(offset) => write!(f, "syntax error at byte {}", offset),
+            ProjectError::Version(v) => write!(f, "unsupported version {}", v),
+            ProjectError::Malformed(s) => write!(f, "malformed: {}", s),
+            ProjectError::NoSuchPort(ref port) => write!(f, "no port {}", port),
+            ProjectError::AmbiguousPort(ref name) => write!(f, "several ports named {:?}", name),
+            ProjectError::InvalidParam(ref name) => write!(f, "invalid parameter {:?}", name),
+            ProjectError::Anonymous(id) => write!(f, "node {} has no plugin uuid", id.0),
+            ProjectError::Tuning => write!(f, "tuning can't be saved"),
+            ProjectError::Graph(ref e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl error::Error for ProjectError {
+    fn description(&self) -> &str { "project error" }
+}
+
+impl From<GraphError> for ProjectError {
+    fn from(e: GraphError) -> Self { ProjectError::Graph(e) }
+}
+
+pub type ProjectResult<T> = Result<T, ProjectError>;
+
+fn field(key: &str, value: Value) -> (String, Value) { (key.to_owned(), value) }
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    (0..text.len() / 2).map(|i| {
+        text.get(2 * i..2 * i + 2).and_then(|b| u8::from_str_radix(b, 16).ok())
+    }).collect()
+}
+
+/// Get value of a parameter as written in a project.
+pub(crate) fn write_param(desc: &PluginParamDesc, value: &ParamValue) -> Value {
+    match (&desc.kind, value) {
+        (&PluginParamKind::Enum(ref names), &ParamValue::Index(i)) if i < names.len() => {
+            Value::String(names[i].clone())
+        },
+        (_, &ParamValue::Unsigned(v)) => Value::number(v),
+        (_, &ParamValue::Signed(v)) => Value::number(v),
+        (_, &ParamValue::Float(v)) => Value::number(v),
+        (_, &ParamValue::Index(v)) => Value::number(v),
+        (_, &ParamValue::Boolean(v)) => Value::Boolean(v)
+    }
+}
+
+/// Read value of a parameter of `desc`, if it's valid.
+pub(crate) fn read_param(desc: &PluginParamDesc, value: &Value) -> Option<ParamValue> {
+    let value = match desc.kind {
+        PluginParamKind::Unsigned(..) => value.as_u64().map(ParamValue::Unsigned),
+        PluginParamKind::Signed(..) => value.as_i64().map(ParamValue::Signed),
+        PluginParamKind::Float(..) => value.as_f64().map(ParamValue::Float),
+        PluginParamKind::Enum(ref names) => value.as_str()
+            .and_then(|name| names.iter().position(|n| n == name))
+            .map(ParamValue::Index),
+        PluginParamKind::Boolean => value.as_bool().map(ParamValue::Boolean)
+    };
+    value.and_then(|value| if desc.is_valid(&value) { Some(value) } else { None })
+}
+
+/// Write the parameters and state of `plugin`, as fields of it's node.
+pub(crate) fn write_settings(plugin: &Plugin) -> Vec<(String, Value)> {
+    let params = plugin.get_params().iter().enumerate().filter_map(|(i, desc)| {
+        plugin.get_param(i).map(|value| field(&desc.name, write_param(desc, &value)))
+    }).collect();
+
+    let mut settings = vec![field("params", Value::Object(params))];
+    let state = plugin.save_state();
+    if !state.is_empty() {
+        settings.push(field("state", Value::String(hex(&state))));
+    }
+    settings
+}
+
+/// Set the parameters and state written in `node` to `plugin` of node `id`.
+pub(crate) fn read_settings(id: NodeId, plugin: &mut Plugin, node: &Value) -> ProjectResult<()> {
+    if let Some(params) = node.get("params").and_then(Value::as_object) {
+        let descs = plugin.get_params();
+        for &(ref name, ref value) in params.iter() {
+            let invalid = || ProjectError::InvalidParam(name.clone());
+            let index = descs.iter().position(|desc| desc.name == *name)
+                .ok_or_else(&invalid)?;
+            let value = read_param(&descs[index], value).ok_or_else(&invalid)?;
+            plugin.set_param(index, value).map_err(|e| GraphError::Plugin(id, e))?;
+        }
+    }
+
+    if let Some(state) = node.get("state") {
+        let state = state.as_str().and_then(unhex)
+            .ok_or(ProjectError::Malformed("node state"))?;
+        plugin.load_state(&state).map_err(|e| GraphError::Plugin(id, e))?;
+    }
+    Ok(())
+}
+
+/// Read field `key` of `config` as a positive number up to `max`, if it's
+/// there.
+fn positive(config: &Value, key: &'static str, max: u64) -> ProjectResult<Option<u64>> {
+    match config.get(key) {
+        None => Ok(None),
+        Some(value) => match value.as_u64() {
+            Some(v) if v > 0 && v <= max => Ok(Some(v)),
+            _ => Err(ProjectError::Malformed(key))
+        }
+    }
+}
+
+/// Find the port written as `port` in `names`, by it's index or by it's
+/// name, which must be unique.
+fn find_port(names: &[String], port: &Value) -> ProjectResult<Option<usize>> {
+    if let Some(index) = port.as_u64() {
+        return Ok(if index < names.len() as u64 { Some(index as usize) } else { None });
+    }
+
+    let name = port.as_str().ok_or(ProjectError::Malformed("connection port"))?;
+    let mut found = names.iter().enumerate().filter(|&(_, n)| n == name).map(|(i, _)| i);
+    match (found.next(), found.next()) {
+        (_, Some(_)) => Err(ProjectError::AmbiguousPort(name.to_owned())),
+        (index, None) => Ok(index)
+    }
+}
+
+fn write_tuning(tuning: &Tuning) -> ProjectResult<Value> {
+    Ok(match tuning.kind().ok_or(ProjectError::Tuning)? {
+        TuningKind::Equal(tuning) => Value::Object(vec![
+            field("divisions", Value::number(tuning.divisions)),
+            field("reference", Value::String(tuning.reference.to_string())),
+            field("freq", Value::number(tuning.freq))
+        ]),
+        TuningKind::Scala(tuning) => Value::Object(vec![
+            field("scale", Value::String(tuning.scale.to_string())),
+            field("mapping", Value::String(tuning.mapping.to_string()))
+        ])
+    })
+}
+
+fn read_tuning(value: &Value) -> Option<TuningKind> {
+    if let Some(divisions) = value.get("divisions").and_then(Value::as_u64) {
+        let reference = value.get("reference").and_then(Value::as_str)
+            .and_then(|name| NoteName::try_from(name).ok())?;
+        let freq = value.get("freq").and_then(Value::as_f64)?;
+        if divisions == 0 || divisions > u32::max_value() as u64 {
+            return None;
+        }
+        let tuning = EqualTemperament::new(divisions as u32, reference, freq);
+        return Some(TuningKind::Equal(tuning));
+    }
+
+    let text = |key| value.get(key).and_then(Value::as_str);
+    let scale = ScalaScale::try_from(text("scale")?).ok()?;
+    let mapping = KeyboardMapping::try_from(text("mapping")?).ok()?;
+    Some(TuningKind::Scala(ScalaTuning::new(scale, mapping)))
+}
+
+/// Save `graph` with it's config as a project. All nodes must be created
+/// by [`Graph::create_node()`], and the tuning must be one of
+/// [`TuningKind`]s.
+///
+/// [`Graph::create_node()`]: ../graph/struct.Graph.html#method.create_node
+/// [`TuningKind`]: ../tuning/enum.TuningKind.html
+pub fn save(graph: &Graph) -> ProjectResult<String> {
+    let config = graph.config();
+    let config = Value::Object(vec![
+        field("sample_rate", Value::number(config.sample_rate)),
+        field("buffer_size", Value::number(config.buffer_size)),
+        field("pool_preallocate", Value::number(config.pool_preallocate)),
+        field("threads", Value::number(config.threads)),
+        field("tempo", Value::number(config.tempo)),
+        field("tuning", write_tuning(&*config.tuning)?)
+    ]);
+
+    let ids = graph.nodes().collect::<Vec<_>>();
+    let mut nodes = Vec::with_capacity(ids.len());
+    for &id in ids.iter() {
+        let uuid = graph.uuid(id)?.ok_or(ProjectError::Anonymous(id))?;
+        let mut node = vec![field("uuid", Value::String(uuid.to_owned()))];
+        node.extend(write_settings(graph.plugin(id)?));
+        nodes.push(Value::Object(node));
+    }
+
+    let index = |id| Value::number(ids.iter().position(|&i| i == id).unwrap());
+    let mut connections = Vec::with_capacity(graph.connections().len());
+    for connection in graph.connections().iter().chain(graph.feedback().iter()) {
+        let mut fields = vec![
+            field("from", index(connection.from)),
+            field("output", Value::number(connection.output)),
+            field("to", index(connection.to)),
+            field("input", Value::number(connection.input))
+        ];
+        if graph.is_feedback(*connection) {
+            fields.push(field("feedback", Value::Boolean(true)));
+        }
+        connections.push(Value::Object(fields));
+    }
+
+    let project = Value::Object(vec![
+        field("version", Value::number(VERSION)),
+        field("config", config),
+        field("nodes", Value::Array(nodes)),
+        field("connections", Value::Array(connections))
+    ]);
+    Ok(project.to_string())
+}
+
+/// Load a project, creating plugins from the first of `factories` which
+/// provides their uuid.
+///
+/// Connections are made in the saved order, before parameters are set,
+/// since plugins like the mixer add ports and parameters on connection.
+/// Connections to ports which don't exist yet are retried after the rest.
+/// States are loaded last.
+pub fn load(text: &str, factories: &mut [&mut Factory]) -> ProjectResult<Graph> {
+    let project = Value::parse(text).map_err(ProjectError::Syntax)?;
+
+    let version = project.get("version").and_then(Value::as_u64)
+        .ok_or(ProjectError::Malformed("version"))?;
+    if version > VERSION {
+        return Err(ProjectError::Version(version));
+    }
+
+    let mut config = CoreConfig::default();
+    if let Some(c) = project.get("config") {
+        let size = usize::max_value() as u64;
+        if let Some(rate) = positive(c, "sample_rate", u32::max_value() as u64)? {
+            config.sample_rate = rate as u32;
+        }
+        if let Some(length) = positive(c, "buffer_size", size)? {
+            config.buffer_size = length as usize;
+        }
+        if let Some(threads) = positive(c, "threads", size)? {
+            config.threads = threads as usize;
+        }
+        config.pool_preallocate = c.get("pool_preallocate").and_then(Value::as_u64)
+            .map_or(config.pool_preallocate, |v| v as usize);
+        config.tempo = c.get("tempo").and_then(Value::as_f64).unwrap_or(config.tempo);
+        if let Some(tuning) = c.get("tuning") {
+            let tuning = read_tuning(tuning).ok_or(ProjectError::Malformed("tuning"))?;
+            config.tuning = tuning.shared();
+        }
+    }
+    let mut graph = Graph::new(&Core::new(config));
+
+    let nodes = project.get("nodes").and_then(Value::as_array)
+        .ok_or(ProjectError::Malformed("nodes"))?;
+    let mut ids = Vec::with_capacity(nodes.len());
+    for node in nodes.iter() {
+        let uuid = node.get("uuid").and_then(Value::as_str)
+            .ok_or(ProjectError::Malformed("node uuid"))?;
+        let factory = factories.iter_mut()
+            .find(|f| f.get_plugins().iter().any(|desc| desc.uuid == uuid))
+            .ok_or(GraphError::UnknownPlugin(uuid.to_owned()))?;
+        ids.push(graph.create_node(&mut **factory, uuid)?);
+    }
+
+    let connections = project.get("connections").and_then(Value::as_array)
+        .ok_or(ProjectError::Malformed("connections"))?;
+    let mut pending = Vec::with_capacity(connections.len());
+    for connection in connections.iter() {
+        let node = |key| connection.get(key).and_then(Value::as_u64)
+            .and_then(|i| ids.get(i as usize).cloned())
+            .ok_or(ProjectError::Malformed("connection node"));
+        let port = |key| connection.get(key).ok_or(ProjectError::Malformed("connection port"));
+        let feedback = connection.get("feedback").and_then(Value::as_bool).unwrap_or(false);
+        pending.push((node("from")?, port("output")?, node("to")?, port("input")?, feedback));
+    }
+
+    while !pending.is_empty() {
+        let mut missing = None;
+        let count = pending.len();
+        for (from, output, to, input, feedback) in mem::replace(&mut pending, Vec::new()) {
+            let ports = (find_port(&graph.io_descriptor(from)?.outputs, output)?,
+                         find_port(&graph.io_descriptor(to)?.inputs, input)?);
+            let connection = match ports {
+                (Some(output), Some(input)) => Connection { from, output, to, input },
+                (o, _) => {
+                    let port = if o.is_none() { output } else { input };
+                    missing = missing.or(Some(port));
+                    pending.push((from, output, to, input, feedback));
+                    continue;
+                }
+            };
+            if feedback {
+                graph.connect_feedback(connection)?;
+            } else {
+                graph.connect(connection)?;
+            }
+        }
+
+        if pending.len() == count {
+            return Err(ProjectError::NoSuchPort(missing.unwrap().to_string()));
+        }
+    }
+
+    for (node, &id) in nodes.iter().zip(ids.iter()) {
+        read_settings(id, graph.plugin_mut(id)?, node)?;
+    }
+
+    Ok(graph)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use buffer::Buffer;
+    use buffer::audio::AudioBuffer;
+    use buffer::control::NoteAlphabet;
+    use codec::AudioFile;
+    use plugins::core::{CoreFactory, CorePlugin, FunctionGenerator, Mixer, Sampler};
+    use super::*;
+
+    fn factory_graph(core: &Core) -> (Graph, [NodeId; 3]) {
+        let mut factory = CoreFactory::new(core);
+        let mut graph = Graph::new(core);
+        let generator = graph.create_node(&mut factory, FunctionGenerator::get_uuid()).unwrap();
+        let sampler = graph.create_node(&mut factory, Sampler::get_uuid()).unwrap();
+        let mixer = graph.create_node(&mut factory, Mixer::get_uuid()).unwrap();
+        (graph, [generator, sampler, mixer])
+    }
+
+    #[test]
+    fn test_project_round_trip() {
+        let core = Core::new(CoreConfig {
+            sample_rate: 48000, tempo: 96.5, threads: 3, ..Default::default()
+        });
+        let (mut graph, [generator, sampler, mixer]) = factory_graph(&core);
+
+        graph.connect(Connection { from: generator, output: 0, to: mixer, input: 0 }).unwrap();
+        graph.connect(Connection { from: sampler, output: 0, to: mixer, input: 1 }).unwrap();
+        graph.plugin_mut(mixer).unwrap().set_param(6, ParamValue::Float(-0.25)).unwrap();
+
+        let mut frames = AudioBuffer::with_length(3);
+        frames[1] = (0.1, -0.3).into();
+        let state = AudioFile { sample_rate: 48000, channels: 2, frames }.encode().unwrap();
+        graph.plugin_mut(sampler).unwrap().load_state(&state).unwrap();
+
+        let text = save(&graph).unwrap();
+        let mut factory = CoreFactory::new(&core);
+        let loaded = load(&text, &mut [&mut factory]).unwrap();
+
+        assert_eq!(save(&loaded).unwrap(), text);
+        assert_eq!(loaded.config().tempo, 96.5);
+        assert_eq!(loaded.config().threads, 3);
+        assert_eq!(loaded.connections(), graph.connections());
+        assert_eq!(loaded.plugin(mixer).unwrap().get_param(6), Some(ParamValue::Float(-0.25)));
+        assert_eq!(loaded.plugin(sampler).unwrap().save_state(), state);
+    }
+
+    #[test]
+    fn test_project_feedback_round_trip() {
+        let core = Core::new(Default::default());
+        let (mut graph, [generator, sampler, mixer]) = factory_graph(&core);
+
+        graph.connect(Connection { from: generator, output: 0, to: mixer, input: 0 }).unwrap();
+        // the saved input of the sampler only exists after the feedback
+        graph.connect_feedback(Connection { from: mixer, output: 0, to: mixer, input: 1 }).unwrap();
+        graph.connect(Connection { from: sampler, output: 0, to: mixer, input: 2 }).unwrap();
+
+        let text = save(&graph).unwrap();
+        let mut factory = CoreFactory::new(&core);
+        let loaded = load(&text, &mut [&mut factory]).unwrap();
+
+        assert_eq!(save(&loaded).unwrap(), text);
+        assert_eq!(loaded.connections(), graph.connections());
+        assert_eq!(loaded.feedback(), graph.feedback());
+    }
+
+    #[test]
+    fn test_project_tuning() {
+        let tuning = ScalaTuning::just(&NoteName(NoteAlphabet::D, 4), 293.);
+        let core = Core::new(CoreConfig { tuning: Arc::new(tuning.clone()), ..Default::default() });
+        let (graph, _) = factory_graph(&core);
+
+        let mut factory = CoreFactory::new(&core);
+        let loaded = load(&save(&graph).unwrap(), &mut [&mut factory]).unwrap();
+        assert_eq!(loaded.config().tuning.kind(), Some(TuningKind::Scala(tuning)));
+
+        let tuning = EqualTemperament::new(19, NoteName(NoteAlphabet::Cs, 3), 139.5);
+        let core = Core::new(CoreConfig { tuning: Arc::new(tuning.clone()), ..Default::default() });
+        let (graph, _) = factory_graph(&core);
+
+        let loaded = load(&save(&graph).unwrap(), &mut [&mut factory]).unwrap();
+        assert_eq!(loaded.config().tuning.kind(), Some(TuningKind::Equal(tuning)));
+    }
+
+    #[test]
+    fn test_project_errors() {
+        let core = Core::new(Default::default());
+        let mut factory = CoreFactory::new(&core);
+        let mut error = |text: &str| load(text, &mut [&mut factory]).err();
+
+        assert_eq!(error("{\"version\": 1"), Some(ProjectError::Syntax(13)));
+        assert_eq!(error("{\"version\": 3}"), Some(ProjectError::Version(3)));
+        assert_eq!(error("{\"version\": 1, \"nodes\": [{\"uuid\": \"x\"}]}"),
+                   Some(ProjectError::Graph(GraphError::UnknownPlugin("x".to_owned()))));
+
+        let (mut graph, _) = factory_graph(&core);
+        let anonymous = graph.add_node(box Mixer::new(&core)).unwrap();
+        assert_eq!(save(&graph), Err(ProjectError::Anonymous(anonymous)));
+
+        #[derive(Debug)]
+        struct Octaves;
+        impl Tuning for Octaves {
+            fn freq(&self, name: &NoteName, _cents: f64) -> f64 { name.1 as f64 }
+        }
+        let core = Core::new(CoreConfig { tuning: Arc::new(Octaves), ..Default::default() });
+        assert_eq!(save(&Graph::new(&core)), Err(ProjectError::Tuning));
+
+        let text = "{\"version\": 1, \"config\": {\"tuning\": {\"divisions\": 0}}}";
+        assert_eq!(error(text), Some(ProjectError::Malformed("tuning")));
+        let text = "{\"version\": 2, \"config\": {\"sample_rate\": 0}}";
+        assert_eq!(error(text), Some(ProjectError::Malformed("sample_rate")));
+        let text = "{\"version\": 2, \"config\": {\"buffer_size\": 0}}";
+        assert_eq!(error(text), Some(ProjectError::Malformed("buffer_size")));
+    }
+
+    #[test]
+    fn test_project_port_names() {
+        let core = Core::new(Default::default());
+        let (mut graph, [generator, sampler, mixer]) = factory_graph(&core);
+        graph.connect(Connection { from: generator, output: 0, to: mixer, input: 0 }).unwrap();
+        graph.connect(Connection { from: sampler, output: 0, to: mixer, input: 1 }).unwrap();
+
+        // version 1 projects reference ports by name
+        let outputs = graph.io_descriptor(generator).unwrap().outputs.clone();
+        let inputs = graph.io_descriptor(mixer).unwrap().inputs.clone();
+        let text = save(&graph).unwrap();
+        assert!(text.contains("\"output\": 0") && text.contains("\"input\": 1"));
+        let text = text
+            .replace("\"version\": 2", "\"version\": 1")
+            .replace("\"output\": 0", &format!("\"output\": {:?}", outputs[0]))
+            .replace("\"input\": 1", &format!("\"input\": {:?}", inputs[1]));
+        let mut factory = CoreFactory::new(&core);
+        let loaded = load(&text, &mut [&mut factory]).unwrap();
+        assert_eq!(loaded.connections(), graph.connections());
+
+        let names = vec!["Audio".to_owned(), "Audio".to_owned(), "Control".to_owned()];
+        let port = |name: &str| Value::String(name.to_owned());
+        assert_eq!(find_port(&names, &port("Control")), Ok(Some(2)));
+        assert_eq!(find_port(&names, &port("Midi")), Ok(None));
+        assert_eq!(find_port(&names, &port("Audio")),
+                   Err(ProjectError::AmbiguousPort("Audio".to_owned())));
+        assert_eq!(find_port(&names, &Value::number(1)), Ok(Some(1)));
+        assert_eq!(find_port(&names, &Value::number(3)), Ok(None));
+    }
+}
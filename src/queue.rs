@@ -0,0 +1,121 @@
+//! The Queue Module
+//!
+//! A bounded, lock-free queue for passing values from one thread to exactly
+//! one other thread. Neither end ever blocks or allocates, so it's safe to
+//! use on the render thread.
+
+use std::cell::UnsafeCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Shared<T> {
+    slots: Box<[UnsafeCell<Option<T>>]>,
+    /// index of the next slot to read, only written by the consumer
+    head: AtomicUsize,
+    /// index of the next slot to write, only written by the producer
+    tail: AtomicUsize
+}
+
+// A slot is accessed either by the producer, while it's outside of
+// `head..tail`, or by the consumer, while it's inside; never by both.
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    fn next(&self, index: usize) -> usize {
+        if index + 1 == self.slots.len() { 0 } else { index + 1 }
+    }
+}
+
+/// The sending end of a queue.
+pub struct Producer<T>(Arc<Shared<T>>);
+
+/// The receiving end of a queue.
+pub struct Consumer<T>(Arc<Shared<T>>);
+
+/// Make a queue which holds up to `capacity` values.
+///
+/// # Example
+///
+/// ```
+/// use overcore::queue::queue;
+///
+/// let (mut producer, mut consumer) = queue(2);
+/// assert_eq!(producer.push(1), Ok(()));
+/// assert_eq!(producer.push(2), Ok(()));
+/// assert_eq!(producer.push(3), Err(3));
+/// assert_eq!(consumer.pop(), Some(1));
+/// assert_eq!(consumer.pop(), Some(2));
+/// assert_eq!(consumer.pop(), None);
+/// ```
+pub fn queue<T: Send>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    // one slot is always empty, to tell a full queue from an empty one
+    let slots = (0..capacity + 1).map(|_| UnsafeCell::new(None)).collect::<Vec<_>>();
+    let shared = Arc::new(Shared {
+        slots: slots.into_boxed_slice(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0)
+    });
+    (Producer(shared.clone()), Consumer(shared))
+}
+
+impl<T> Producer<T> {
+    /// Append `value` to the queue, or get it back if the queue is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let tail = self.0.tail.load(Ordering::Relaxed);
+        let next = self.0.next(tail);
+        if next == self.0.head.load(Ordering::Acquire) {
+            return Err(value);
+        }
+        unsafe { *self.0.slots[tail].get() = Some(value); }
+        self.0.tail.store(next, Ordering::Release);
+        Ok(())
+    }
+
+    /// Get whether the consumer has taken every value.
+    pub fn is_empty(&self) -> bool {
+        self.0.head.load(Ordering::Acquire) == self.0.tail.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Consumer<T> {
+    /// Take the oldest value of the queue, if any.
+    pub fn pop(&mut self) -> Option<T> {
+        let head = self.0.head.load(Ordering::Relaxed);
+        if head == self.0.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let value = unsafe { (*self.0.slots[head].get()).take() };
+        self.0.head.store(self.0.next(head), Ordering::Release);
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::*;
+
+    #[test]
+    fn test_queue_threads() {
+        let (mut producer, mut consumer) = queue(3);
+        let sender = thread::spawn(move || {
+            for i in 0..10000 {
+                let mut value = box i;
+                while let Err(v) = producer.push(value) {
+                    value = v;
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut expected = 0;
+        while expected < 10000 {
+            match consumer.pop() {
+                Some(value) => { assert_eq!(*value, expected); expected += 1; },
+                None => thread::yield_now()
+            }
+        }
+        sender.join().unwrap();
+        assert_eq!(consumer.pop(), None);
+    }
+}
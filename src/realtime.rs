@@ -0,0 +1,800 @@
+//! The Realtime Module
+//!
+//! Editing a graph on a control thread while it's rendered on another.
+//! [`split()`] takes a graph apart into an [`Editor`], which keeps a copy of
+//! it's layout, and a [`Renderer`], which owns the plugins.
+//!
+//! Edits are checked and compiled into a new plan by the editor, and sent
+//! to the renderer through a lock-free queue. The renderer applies them
+//! together with the new plan at the start of it's next block, so it never
+//! waits for the editor nor sees half of an edit. Whatever the renderer
+//! replaces is given back in one report per update, to be released by the
+//! editor.
+//!
+//! Plugins are only touched by the renderer. When a connection changes the
+//! status of their ports, they're informed at the start of the block the
+//! new plan is used in, so they keep rendering while the graph is edited;
+//! if their IO configuration changes, the renderer reads it for the editor.
+//! Only when a plugin fails with `InvalidArgument`, or a parameter which
+//! allocates is set, the renderer gives it back, and the editor reconfigures
+//! it in [`Editor::update()`], which should be called regularly, before
+//! sending it again. Until then the node is skipped and it's outputs are
+//! silent. Parameters are checked by the editor, against the ranges it has
+//! read from the plugin.
+//!
+//! [`split()`]: fn.split.html
+//! [`Editor`]: struct.Editor.html
+//! [`Renderer`]: struct.Renderer.html
+//! [`Editor::update()`]: struct.Editor.html#method.update
+
+use std::mem;
+use std::collections::VecDeque;
+use buffer::control::ParamValue;
+use graph::{Graph, NodeId, Connection, GraphError, GraphResult};
+use graph::{Change, Job, Layout, Plan, Shape};
+use plugins::prelude::*;
+use plugins::Factory;
+use pool::Pool;
+use queue::{queue, Producer, Consumer};
+use CoreConfig;
+
+type Table = Vec<Option<Box<Plugin>>>;
+
+/// A change of the renderer's plugins. The renderer puts what it replaces
+/// in place of the edit, and gives the edits back.
+enum Edit {
+    /// Move plugins to this larger table; the old one is given back.
+    Grow(Table),
+    Insert(NodeId, Box<Plugin>),
+    /// Give the plugin back, either to be released or reconfigured.
+    Take(NodeId),
+    /// A plugin given back.
+    Taken(NodeId, Box<Plugin>),
+    /// Inform the plugin about the status of a port.
+    Port(NodeId, PluginIoChange),
+    /// The IO configuration of the plugin is changed by informing it; it's
+    /// new shape.
+    Reshaped(NodeId, Box<Shape>),
+    Failed(GraphError),
+    Done
+}
+
+enum Message {
+    /// Apply edits, then swap the plan.
+    Update(Vec<Edit>, Box<Plan>),
+    /// Set a parameter, which is already checked by the editor and doesn't
+    /// allocate.
+    Param(NodeId, usize, ParamValue)
+}
+
+enum Report {
+    /// Applied edits and the replaced plan of an update.
+    Update(Vec<Edit>, Box<Plan>),
+    Invalid(NodeId),
+    Latency(NodeId, usize),
+    Error(GraphError),
+    /// Number of reports which were dropped.
+    Dropped(usize)
+}
+
+/// Reports of the renderer, which are kept while the queue is full. The
+/// overflow never grows: updates are only taken while there is room to
+/// give them back, and other reports which don't fit are dropped and
+/// counted.
+struct Outbox {
+    queue: Producer<Report>,
+    overflow: VecDeque<Report>,
+    limit: usize,
+    dropped: usize
+}
+
+impl Outbox {
+    fn has_room(&self) -> bool { self.overflow.len() < self.limit }
+
+    /// Send `report`, or keep it if there is room. Returns whether it's
+    /// not dropped.
+    fn send(&mut self, report: Report) -> bool {
+        self.flush();
+        let report = if self.overflow.is_empty() {
+            match self.queue.push(report) {
+                Ok(()) => { return true; },
+                Err(report) => report
+            }
+        } else {
+            report
+        };
+
+        if self.has_room() {
+            self.overflow.push_back(report);
+            true
+        } else {
+            self.dropped += 1;
+            false
+        }
+    }
+
+    fn flush(&mut self) {
+        while let Some(report) = self.overflow.pop_front() {
+            if let Err(report) = self.queue.push(report) {
+                self.overflow.push_front(report);
+                return;
+            }
+        }
+        if self.dropped > 0 && self.queue.push(Report::Dropped(self.dropped)).is_ok() {
+            self.dropped = 0;
+        }
+    }
+}
+
+/// Take `graph` apart to edit it on one thread and render it on another.
+/// `capacity` is the number of messages each way which fit in the queues.
+pub fn split(graph: Graph, capacity: usize) -> (Editor, Renderer) {
+    let (config, layout, mut plugins, plan, pool) = graph.split();
+    let (messages, inbox) = queue(capacity);
+    let (reports, outbox) = queue(capacity);
+
+    let length = plugins.len();
+    plugins.reserve(length);
+    let editor = Editor {
+        config,
+        latency: plan.latency(),
+        layout,
+        length: plugins.capacity(),
+        edits: Vec::new(),
+        taking: Vec::new(),
+        ports: Vec::new(),
+        params: Vec::new(),
+        messages,
+        pending: VecDeque::new(),
+        reports: outbox,
+        dropped: 0
+    };
+    let renderer = Renderer {
+        plugins,
+        plan: box plan,
+        pool,
+        messages: inbox,
+        reports: Outbox {
+            queue: reports,
+            overflow: VecDeque::with_capacity(capacity),
+            limit: capacity,
+            dropped: 0
+        }
+    };
+    (editor, renderer)
+}
+
+/// The control side of a split graph. It has the same editing methods as
+/// [`Graph`](../graph/struct.Graph.html), which take effect on the next
+/// block of the renderer.
+pub struct Editor {
+    config: CoreConfig,
+    latency: usize,
+    layout: Layout,
+    /// capacity of the renderer's table of plugins
+    length: usize,
+    edits: Vec<Edit>,
+    /// nodes which their plugin is asked back
+    taking: Vec<NodeId>,
+    /// ports to inform plugins about, when they're given back
+    ports: Vec<(NodeId, bool, usize)>,
+    /// parameters to set when plugins are given back
+    params: Vec<(NodeId, usize, ParamValue)>,
+    messages: Producer<Message>,
+    /// messages which didn't fit in the queue
+    pending: VecDeque<Message>,
+    reports: Consumer<Report>,
+    dropped: usize
+}
+
+impl Editor {
+    /// Get config of the core the graph is processed in.
+    pub fn config(&self) -> &CoreConfig { &self.config }
+
+    /// Add `plugin` as a new node. The plugin is initialized and informed
+    /// of the core config on this thread, before it's sent.
+    pub fn add_node(&mut self, plugin: Box<Plugin>) -> GraphResult<NodeId> {
+        self.insert(plugin, None)
+    }
+
+    /// Create the plugin of `uuid` from `factory` and add it as a new node.
+    pub fn create_node(&mut self, factory: &mut Factory, uuid: &str) -> GraphResult<NodeId> {
+        let id = NodeId(self.layout.nodes.len());
+        let desc = factory.get_plugins().iter().find(|desc| desc.uuid == uuid).cloned()
+            .ok_or(GraphError::UnknownPlugin(uuid.to_owned()))?;
+        let plugin = factory.create_plugin(desc.id).map_err(|e| GraphError::Plugin(id, e))?;
+        self.insert(plugin, Some(desc.uuid))
+    }
+
+    fn insert(&mut self, mut plugin: Box<Plugin>, uuid: Option<String>) -> GraphResult<NodeId> {
+        let id = NodeId(self.layout.nodes.len());
+        plugin.initialize().map_err(|e| GraphError::Plugin(id, e))?;
+        plugin.core_changed(&self.config);
+        self.layout.insert(id, Shape::new(&*plugin, uuid));
+
+        if id.0 >= self.length {
+            self.length = (self.length * 2).max(id.0 + 1);
+            self.edits.push(Edit::Grow(Vec::with_capacity(self.length)));
+        }
+        self.edits.push(Edit::Insert(id, plugin));
+        self.commit();
+        Ok(id)
+    }
+
+    /// Remove node `id` with all of it's connections. It's plugin is
+    /// terminated and dropped in [`update()`](#method.update).
+    pub fn remove_node(&mut self, id: NodeId) -> GraphResult<()> {
+        self.layout.shape(id)?;
+        for connection in self.layout.connections_of(id) {
+            self.layout.remove(connection)?;
+            self.notify(connection);
+        }
+
+        self.layout.nodes[id.0] = None;
+        self.ports.retain(|port| port.0 != id);
+        self.params.retain(|param| param.0 != id);
+        self.take(id);
+        self.commit();
+        Ok(())
+    }
+
+    /// Get ids of all nodes.
+    pub fn nodes<'a>(&'a self) -> impl Iterator<Item = NodeId> + 'a {
+        self.layout.ids()
+    }
+
+    /// Get uuid of the plugin of node `id`, if it was created by
+    /// [`create_node()`](#method.create_node).
+    pub fn uuid(&self, id: NodeId) -> GraphResult<Option<&str>> {
+        self.layout.shape(id).map(|shape| shape.uuid.as_ref().map(|uuid| &uuid[..]))
+    }
+
+    /// Get IO descriptor of node `id`, as last read from it's plugin.
+    pub fn io_descriptor(&self, id: NodeId) -> GraphResult<&PluginIoDesc> {
+        self.layout.shape(id).map(|shape| &shape.desc)
+    }
+
+    /// Get all connections, except feedback connections.
+    pub fn connections(&self) -> &[Connection] { &self.layout.connections }
+
+    /// Get all feedback connections.
+    pub fn feedback(&self) -> &[Connection] { &self.layout.feedback }
+
+    /// Get the total latency of the graph.
+    pub fn latency(&self) -> usize { self.latency }
+
+    /// Get the number of reports the renderer has dropped, since the queue
+    /// was full. Only errors of plugins are lost this way; other changes
+    /// are reported again.
+    pub fn dropped(&self) -> usize { self.dropped }
+
+    /// Add `connection` to the graph.
+    pub fn connect(&mut self, connection: Connection) -> GraphResult<()> {
+        self.layout.check(connection)?;
+        self.layout.connections.push(connection);
+        self.notify(connection);
+        self.commit();
+        Ok(())
+    }
+
+    /// Add `connection` as a feedback connection, which may make a cycle.
+    pub fn connect_feedback(&mut self, connection: Connection) -> GraphResult<()> {
+        self.layout.check_feedback(connection)?;
+        self.layout.feedback.push(connection);
+        self.notify(connection);
+        self.commit();
+        Ok(())
+    }
+
+    /// Remove `connection` from the graph, whether it's a feedback
+    /// connection or not.
+    pub fn disconnect(&mut self, connection: Connection) -> GraphResult<()> {
+        self.layout.remove(connection)?;
+        self.notify(connection);
+        self.commit();
+        Ok(())
+    }
+
+    /// Set parameter `index` of node `id` to `value`. The value is checked
+    /// here; other errors of the plugin are returned by
+    /// [`update()`](#method.update). Parameters which allocate are set on
+    /// this thread, while the plugin is given back.
+    pub fn set_param(&mut self, id: NodeId, index: usize, value: ParamValue)
+        -> GraphResult<()> {
+        let allocating = {
+            let shape = self.layout.shape(id)?;
+            match shape.params.get(index) {
+                Some(range) if range.is_valid(&value) => {},
+                _ => { return Err(GraphError::Plugin(id, PluginError::InvalidArgument)); }
+            }
+            shape.allocating[index]
+        };
+
+        if allocating && !self.taking.contains(&id) {
+            self.take(id);
+            self.commit();
+        }
+        if self.taking.contains(&id) {
+            self.params.push((id, index, value));
+        } else {
+            self.send(Message::Param(id, index, value));
+        }
+        Ok(())
+    }
+
+    /// Inform plugins of both ends of `connection` about it's status, on
+    /// the renderer. Plugins which are asked back are informed when they're
+    /// reconfigured.
+    fn notify(&mut self, connection: Connection) {
+        for &port in [(connection.from, true, connection.output),
+                      (connection.to, false, connection.input)].iter() {
+            let (id, is_output, index) = port;
+            if self.taking.contains(&id) {
+                if !self.ports.contains(&port) {
+                    self.ports.push(port);
+                }
+            } else if let Ok(change) = self.layout.port_change(id, is_output, index) {
+                self.edits.push(Edit::Port(id, change));
+            }
+        }
+    }
+
+    /// Ask back the plugin of node `id`, if it's not asked already.
+    fn take(&mut self, id: NodeId) {
+        if !self.taking.contains(&id) {
+            self.taking.push(id);
+            self.edits.push(Edit::Take(id));
+        }
+    }
+
+    /// Reconfigure a plugin given back by the renderer, and queue sending
+    /// it again. Plugins of removed nodes are terminated instead.
+    fn reconfigure(&mut self, id: NodeId, mut plugin: Box<Plugin>) -> GraphResult<()> {
+        self.taking.retain(|&n| n != id);
+        if self.layout.shape(id).is_err() {
+            plugin.terminate();
+            return Ok(());
+        }
+
+        // the node exists, so the layout doesn't fail below
+        let mut result = Ok(());
+        let ports = self.ports.iter().filter(|port| port.0 == id).cloned().collect::<Vec<_>>();
+        self.ports.retain(|port| port.0 != id);
+        for (_, is_output, index) in ports {
+            let change = self.layout.port_change(id, is_output, index).unwrap();
+            match plugin.io_changed(change) {
+                Ok(()) => {},
+                Err(PluginError::InvalidArgument) => {
+                    self.layout.shape_mut(id).unwrap().update(&*plugin);
+                },
+                Err(e) => if result.is_ok() { result = Err(GraphError::Plugin(id, e)); }
+            }
+        }
+        self.layout.shape_mut(id).unwrap().update(&*plugin);
+
+        let params = self.params.iter().filter(|param| param.0 == id).cloned()
+            .collect::<Vec<_>>();
+        self.params.retain(|param| param.0 != id);
+        for (_, index, value) in params {
+            if let Err(e) = plugin.set_param(index, value) {
+                if result.is_ok() { result = Err(GraphError::Plugin(id, e)); }
+            }
+        }
+
+        self.edits.push(Edit::Insert(id, plugin));
+        result
+    }
+
+    /// Compile the layout, and send it with the queued edits.
+    fn commit(&mut self) {
+        let plan = self.layout.compile(&self.config);
+        self.latency = plan.latency();
+        let edits = mem::replace(&mut self.edits, Vec::new());
+        self.send(Message::Update(edits, box plan));
+    }
+
+    fn send(&mut self, message: Message) {
+        self.pending.push_back(message);
+        self.flush();
+    }
+
+    fn flush(&mut self) {
+        while let Some(message) = self.pending.pop_front() {
+            if let Err(message) = self.messages.push(message) {
+                self.pending.push_front(message);
+                break;
+            }
+        }
+    }
+
+    /// Send messages which didn't fit in the queue, release what the
+    /// renderer is done with, reconfigure plugins it has given back and
+    /// apply changes it has noticed. The first error is returned.
+    pub fn update(&mut self) -> GraphResult<()> {
+        self.flush();
+
+        let mut result = Ok(());
+        let mut changed = false;
+        while let Some(report) = self.reports.pop() {
+            let error = match report {
+                Report::Update(edits, _) => {
+                    let mut error = Ok(());
+                    for edit in edits {
+                        match edit {
+                            Edit::Taken(id, plugin) => {
+                                let reconfigured = self.reconfigure(id, plugin);
+                                error = error.and(reconfigured);
+                                changed = true;
+                            },
+                            Edit::Reshaped(id, shape) => {
+                                if let Ok(old) = self.layout.shape_mut(id) {
+                                    let uuid = old.uuid.take();
+                                    *old = Shape { uuid, ..*shape };
+                                    changed = true;
+                                }
+                            },
+                            Edit::Failed(e) => { error = error.and(Err(e)); },
+                            _ => {}
+                        }
+                    }
+                    error
+                },
+                Report::Invalid(id) => {
+                    if self.layout.shape(id).is_ok() {
+                        self.take(id);
+                        changed = true;
+                    }
+                    Ok(())
+                },
+                Report::Latency(id, latency) => {
+                    if let Ok(shape) = self.layout.shape_mut(id) {
+                        shape.latency = latency;
+                        changed = true;
+                    }
+                    Ok(())
+                },
+                Report::Error(e) => Err(e),
+                Report::Dropped(count) => { self.dropped += count; Ok(()) }
+            };
+            result = result.and(error);
+        }
+
+        if changed {
+            self.commit();
+        }
+        result
+    }
+
+    /// Get whether the renderer has taken every message, and the editor
+    /// has every plugin it has asked back.
+    pub fn is_synced(&self) -> bool {
+        self.pending.is_empty() && self.messages.is_empty() && self.taking.is_empty()
+    }
+}
+
+/// The render side of a split graph. It owns the plugins, and should be
+/// moved to the render thread.
+pub struct Renderer {
+    plugins: Table,
+    plan: Box<Plan>,
+    pool: Pool<Job>,
+    messages: Consumer<Message>,
+    reports: Outbox
+}
+
+impl Renderer {
+    /// Apply messages of the editor, then process one buffer of all nodes.
+    /// Errors of plugins don't stop processing, but the outputs of the
+    /// failed node are cleared and the first error is returned.
+    pub fn process(&mut self) -> GraphResult<()> {
+        self.reports.flush();
+
+        // each message is answered by one report at most
+        while self.reports.has_room() {
+            match self.messages.pop() {
+                Some(Message::Update(mut edits, mut plan)) => {
+                    for edit in edits.iter_mut() {
+                        self.apply(edit);
+                    }
+                    plan.inherit(&mut self.plan);
+                    let old = mem::replace(&mut self.plan, plan);
+                    self.reports.send(Report::Update(edits, old));
+                },
+                Some(Message::Param(id, index, value)) => {
+                    let result = match self.plugins.get_mut(id.0) {
+                        Some(&mut Some(ref mut plugin)) => {
+                            plugin.set_param(index, value).map_err(|e| GraphError::Plugin(id, e))
+                        },
+                        _ => Err(GraphError::NoSuchNode(id))
+                    };
+                    if let Err(e) = result {
+                        self.reports.send(Report::Error(e));
+                    }
+                },
+                None => break
+            }
+        }
+
+        let Renderer { ref mut plugins, ref mut plan, ref mut pool, ref mut reports, .. } = *self;
+        plan.process(plugins, pool, &mut |id, change| reports.send(match change {
+            Change::Invalid(_) => Report::Invalid(id),
+            Change::Latency(latency) => Report::Latency(id, latency)
+        }))
+    }
+
+    fn apply(&mut self, edit: &mut Edit) {
+        *edit = match mem::replace(edit, Edit::Done) {
+            Edit::Grow(mut table) => {
+                table.extend(self.plugins.drain(..));
+                Edit::Grow(mem::replace(&mut self.plugins, table))
+            },
+            Edit::Insert(id, plugin) => {
+                while self.plugins.len() <= id.0 {
+                    self.plugins.push(None);
+                }
+                self.plugins[id.0] = Some(plugin);
+                Edit::Done
+            },
+            Edit::Take(id) => match self.plugins.get_mut(id.0).and_then(Option::take) {
+                Some(plugin) => Edit::Taken(id, plugin),
+                None => Edit::Done
+            },
+            Edit::Port(id, change) => match self.plugins.get_mut(id.0) {
+                Some(&mut Some(ref mut plugin)) => match plugin.io_changed(change) {
+                    Ok(()) => Edit::Done,
+                    Err(PluginError::InvalidArgument) => {
+                        Edit::Reshaped(id, box Shape::new(&**plugin, None))
+                    },
+                    Err(e) => Edit::Failed(GraphError::Plugin(id, e))
+                },
+                _ => Edit::Done
+            },
+            edit => edit
+        };
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        for plugin in self.plugins.iter_mut().filter_map(|p| p.as_mut()) {
+            plugin.terminate();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use buffer::prelude::*;
+    use plugins::core::{CorePlugin, Mixer};
+    use plugins::special::Generator;
+    use {Core, CoreConfig};
+    use super::*;
+
+    /// Generates a constant signal.
+    struct Constant;
+
+    impl Generator for Constant {
+        fn generate(&mut self, _control: Option<&ControlBuffer>,
+                    audio: &mut AudioBuffer) -> PluginResult<()> {
+            audio.iter_mut().for_each(|f| *f = (1.).into());
+            Ok(())
+        }
+    }
+
+    impl_generator_for!(Constant);
+
+    /// Records audio of it's input.
+    struct Capture(Arc<Mutex<Vec<Sample>>>);
+
+    impl Plugin for Capture {
+        fn get_io_descriptor(&self) -> PluginIoDesc {
+            PluginIoDesc {
+                mode: PluginIoMode::Complex(PluginComplexIo {
+                    inputs: box [PluginIoKind::Audio],
+                    outputs: box []
+                }),
+                inputs: box ["Audio".to_owned()],
+                outputs: box []
+            }
+        }
+
+        fn process(&mut self, inputs: &PluginIo, _outputs: &mut PluginIo)
+            -> PluginResult<()> {
+            if let PluginIoBuffer::Audio(ref audio) = inputs[0] {
+                self.0.lock().unwrap().extend(audio.iter().map(|f| f[0]));
+            }
+            Ok(())
+        }
+    }
+
+    fn split_empty() -> (Editor, Renderer) {
+        let core = Core::new(CoreConfig { buffer_size: 8, ..Default::default() });
+        split(Graph::new(&core), 16)
+    }
+
+    #[test]
+    fn test_realtime_edit() {
+        let (mut editor, mut renderer) = split_empty();
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let source = editor.add_node(box Constant).unwrap();
+        let sink = editor.add_node(box Capture(captured.clone())).unwrap();
+        editor.connect(Connection { from: source, output: 0, to: sink, input: 0 }).unwrap();
+        assert!(!editor.is_synced());
+
+        // both ends are informed at the start of the block, and keep rendering
+        renderer.process().unwrap();
+        assert_eq!(&captured.lock().unwrap()[..], &[1.; 8]);
+        editor.update().unwrap();
+        renderer.process().unwrap();
+        assert!(editor.is_synced());
+        assert_eq!(&captured.lock().unwrap()[..], &[1.; 16]);
+
+        // the mixer grows a spare input, which the editor learns on update
+        let mixer = editor.add_node(box Mixer::new(&Core::new(Default::default()))).unwrap();
+        editor.connect(Connection { from: source, output: 0, to: mixer, input: 0 }).unwrap();
+        renderer.process().unwrap();
+        assert_eq!(editor.io_descriptor(mixer).unwrap().inputs.len(), 1);
+        editor.update().unwrap();
+        assert_eq!(editor.io_descriptor(mixer).unwrap().inputs.len(), 2);
+
+        // the removed plugin is released by the editor, not the renderer
+        editor.remove_node(sink).unwrap();
+        renderer.process().unwrap();
+        assert_eq!(Arc::strong_count(&captured), 2);
+        editor.update().unwrap();
+        assert_eq!(Arc::strong_count(&captured), 1);
+        assert_eq!(captured.lock().unwrap().len(), 24);
+
+        // parameters are checked against ranges read from the plugin
+        let invalid = Err(GraphError::Plugin(mixer, PluginError::InvalidArgument));
+        assert_eq!(editor.set_param(mixer, 42, ParamValue::Float(0.)), invalid);
+        assert_eq!(editor.set_param(mixer, 0, ParamValue::Index(0)), invalid);
+        editor.set_param(mixer, 0, ParamValue::Float(-6.)).unwrap();
+        renderer.process().unwrap();
+        editor.update().unwrap();
+    }
+
+    /// Reports a latency growing by a frame on each of it's first blocks.
+    struct Drifting(usize);
+
+    impl Plugin for Drifting {
+        fn get_io_descriptor(&self) -> PluginIoDesc {
+            PluginIoDesc {
+                mode: PluginIoMode::Complex(PluginComplexIo { inputs: box [], outputs: box [] }),
+                inputs: box [],
+                outputs: box []
+            }
+        }
+
+        fn latency(&self) -> usize { self.0 }
+
+        fn process(&mut self, _inputs: &PluginIo, _outputs: &mut PluginIo)
+            -> PluginResult<()> {
+            self.0 = (self.0 + 1).min(10);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_realtime_overflow() {
+        let core = Core::new(CoreConfig { buffer_size: 8, ..Default::default() });
+        let (mut editor, mut renderer) = split(Graph::new(&core), 2);
+        editor.add_node(box Drifting(0)).unwrap();
+        for _ in 0..10 {
+            renderer.process().unwrap();
+        }
+
+        // there is no room to give an update back, so it waits
+        editor.add_node(box Constant).unwrap();
+        renderer.process().unwrap();
+        assert!(!editor.is_synced());
+        editor.update().unwrap();
+        renderer.process().unwrap();
+        assert!(editor.is_synced());
+
+        // dropped changes of latency are reported again
+        for _ in 0..4 {
+            editor.update().unwrap();
+            renderer.process().unwrap();
+        }
+        editor.update().unwrap();
+        assert_eq!(editor.latency(), 10);
+        assert!(editor.dropped() > 0);
+    }
+
+    #[test]
+    fn test_realtime_threads() {
+        let (mut editor, mut renderer) = split_empty();
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let source = editor.add_node(box Constant).unwrap();
+        let sink = editor.add_node(box Capture(captured.clone())).unwrap();
+        let connection = Connection { from: source, output: 0, to: sink, input: 0 };
+
+        let running = Arc::new(AtomicBool::new(true));
+        let render = {
+            let running = running.clone();
+            thread::spawn(move || {
+                while running.load(Ordering::Relaxed) {
+                    renderer.process().unwrap();
+                    thread::yield_now();
+                }
+                renderer.process().unwrap();
+            })
+        };
+
+        for _ in 0..100 {
+            editor.connect(connection).unwrap();
+            editor.disconnect(connection).unwrap();
+            editor.update().unwrap();
+        }
+        editor.connect(connection).unwrap();
+        while !editor.is_synced() {
+            editor.update().unwrap();
+            thread::yield_now();
+        }
+        running.store(false, Ordering::Relaxed);
+        render.join().unwrap();
+        editor.update().unwrap();
+
+        // the sink is never skipped, so every connected block is captured
+        let captured = captured.lock().unwrap();
+        assert!(!captured.is_empty());
+        assert!(captured.iter().all(|&s| s == 1.));
+    }
+
+    /// Records which of it's parameters are set; the first one allocates.
+    struct Table(Arc<Mutex<Vec<usize>>>);
+
+    impl Plugin for Table {
+        fn get_params(&self) -> PluginParamsDesc {
+            let param = |name: &str| PluginParamDesc {
+                name: name.to_owned(),
+                kind: PluginParamKind::Boolean,
+                default: ParamValue::Boolean(false)
+            };
+            box [param("Table"), param("Gain")]
+        }
+
+        fn set_param(&mut self, index: usize, _value: ParamValue) -> PluginResult<()> {
+            self.0.lock().unwrap().push(index);
+            Ok(())
+        }
+
+        fn param_allocates(&self, index: usize) -> bool { index == 0 }
+
+        fn get_io_descriptor(&self) -> PluginIoDesc { Drifting(0).get_io_descriptor() }
+
+        fn process(&mut self, _inputs: &PluginIo, _outputs: &mut PluginIo)
+            -> PluginResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_realtime_allocating_param() {
+        let (mut editor, mut renderer) = split_empty();
+        let set = Arc::new(Mutex::new(Vec::new()));
+        let table = editor.add_node(box Table(set.clone())).unwrap();
+        renderer.process().unwrap();
+        editor.update().unwrap();
+
+        // set on the renderer
+        editor.set_param(table, 1, ParamValue::Boolean(true)).unwrap();
+        assert!(set.lock().unwrap().is_empty());
+        renderer.process().unwrap();
+        assert_eq!(&set.lock().unwrap()[..], &[1]);
+
+        // set on the editor, while the plugin is given back
+        editor.set_param(table, 0, ParamValue::Boolean(true)).unwrap();
+        renderer.process().unwrap();
+        assert_eq!(&set.lock().unwrap()[..], &[1]);
+        editor.update().unwrap();
+        assert_eq!(&set.lock().unwrap()[..], &[1, 0]);
+        renderer.process().unwrap();
+        editor.update().unwrap();
+        assert!(editor.is_synced());
+    }
+}
@@ -0,0 +1,278 @@
+//! The Resample Module
+//!
+//! Sample rate conversion of audio streams, used to play files, record
+//! and bridge devices at rates other than the core's.
+
+use std::f64::consts::PI;
+use buffer::Buffer;
+use buffer::audio::{Frame, AudioBuffer};
+use interpolate::Interpolation;
+
+/// Zero crossings of the sinc kernel on each side.
+const ZERO_CROSSINGS: usize = 32;
+
+/// Resolution of the kernel table, in entries per zero crossing.
+const PHASES: usize = 512;
+
+/// Shape parameter of the kaiser window.
+const BETA: f64 = 8.;
+
+/// Cutoff of the anti-aliasing filter, relative to the lower nyquist.
+const CUTOFF: f64 = 0.92;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Conversion method of a [`Resampler`].
+///
+/// [`Resampler`]: struct.Resampler.html
+pub enum Quality {
+    /// Linear interpolation. Cheap, but aliases.
+    Linear,
+
+    /// Band limited interpolation by a kaiser windowed sinc.
+    Sinc
+}
+
+/// Zeroth order modified bessel function of the first kind.
+fn bessel(x: f64) -> f64 {
+    let (mut sum, mut term) = (1., 1.);
+    for k in 1..32 {
+        term *= (x / (2. * k as f64)).powi(2);
+        sum += term;
+    }
+    sum
+}
+
+/// Tabulate the right half of the windowed sinc.
+fn kernel() -> Box<[f64]> {
+    let length = ZERO_CROSSINGS * PHASES;
+    let mut table: Vec<f64> = (0..length).map(|i| {
+        let x = i as f64 / PHASES as f64;
+        let sinc = if i == 0 { 1. } else { (PI * x).sin() / (PI * x) };
+        let r = x / ZERO_CROSSINGS as f64;
+        sinc * bessel(BETA * (1. - r * r).sqrt()) / bessel(BETA)
+    }).collect();
+    // for interpolating past the last entry
+    table.extend_from_slice(&[0., 0.]);
+    table.into_boxed_slice()
+}
+
+/// Converts a stream of frames from one sample rate to another, by any
+/// ratio.
+///
+/// Input is given in blocks of any length and output frames are produced as
+/// soon as enough input is available, so the output lags behind the input
+/// by the half width of the filter (see [`lookahead()`]).
+///
+/// [`lookahead()`]: #method.lookahead
+///
+/// # Examples
+/// ```
+/// use overcore::resample::{Quality, Resampler};
+///
+/// let mut resampler = Resampler::new(44100., 48000., Quality::Sinc);
+/// let input = vec![(0.5).into(); 441];
+/// let output = resampler.resample(&input);
+/// assert_eq!(output.len(), 480);
+/// ```
+pub struct Resampler {
+    quality: Quality,
+    // input frames per output frame
+    step: f64,
+    cutoff: f64,
+    width: usize,
+    table: Box<[f64]>,
+    history: Vec<Frame>,
+    // position of the next output frame in history
+    position: f64
+}
+
+impl Resampler {
+    /// Create a resampler from sample rate `from` to `to`.
+    ///
+    /// # Panics
+    /// Panics if either rate is not positive.
+    pub fn new(from: f64, to: f64, quality: Quality) -> Self {
+        assert!(from > 0. && to > 0.);
+
+        let step = from / to;
+        let (cutoff, width, table) = match quality {
+            Quality::Linear => (1., 1, Box::<[f64]>::default()),
+            Quality::Sinc => {
+                let cutoff = CUTOFF * (1. / step).min(1.);
+                let width = (ZERO_CROSSINGS as f64 / cutoff).ceil() as usize;
+                (cutoff, width, kernel())
+            }
+        };
+
+        let mut resampler = Self {
+            quality, step, cutoff, width, table,
+            history: Vec::new(),
+            position: 0.
+        };
+        resampler.reset();
+        resampler
+    }
+
+    #[inline]
+    /// Get the conversion method.
+    pub fn quality(&self) -> Quality { self.quality }
+
+    #[inline]
+    /// Get ratio of output rate to input rate.
+    pub fn ratio(&self) -> f64 { 1. / self.step }
+
+    /// Change the ratio of output rate to input rate, keeping the stream
+    /// continuous. The anti-aliasing filter is not updated, so it's meant
+    /// for small adjustments, e.g. to follow a drifting device clock.
+    ///
+    /// # Panics
+    /// Panics if `ratio` is not positive.
+    pub fn set_ratio(&mut self, ratio: f64) {
+        assert!(ratio > 0.);
+        self.step = 1. / ratio;
+    }
+
+    #[inline]
+    /// Get number of input frames needed after the position of an output
+    /// frame before it can be produced.
+    pub fn lookahead(&self) -> usize { self.width }
+
+    /// Convert frames of `input` and append them to `output`.
+    pub fn process(&mut self, input: &[Frame], output: &mut Vec<Frame>) {
+        self.history.extend_from_slice(input);
+
+        while (self.position as usize) + self.width < self.history.len() {
+            let frame = self.interpolate();
+            output.push(frame);
+            self.position += self.step;
+        }
+
+        // drop frames which are not needed anymore
+        let needed = (self.position as usize + 1).saturating_sub(self.width);
+        let unused = needed.min(self.history.len());
+        self.history.drain(..unused);
+        self.position -= unused as f64;
+    }
+
+    /// Append the frames which are held back by the lookahead to `output`,
+    /// as if the input was followed by silence.
+    pub fn flush(&mut self, output: &mut Vec<Frame>) {
+        let silence = vec![Frame::default(); self.width + 1];
+        self.process(&silence, output);
+    }
+
+    /// Clear the stream state.
+    pub fn reset(&mut self) {
+        // the filter reads frames before the stream start as silence
+        self.history.clear();
+        self.history.resize(self.width - 1, Frame::default());
+        self.position = (self.width - 1) as f64;
+    }
+
+    /// Convert a whole buffer as a separate stream. Length of the result is
+    /// the length of `input` scaled by the ratio, rounded.
+    pub fn resample(&mut self, input: &[Frame]) -> AudioBuffer {
+        let length = (input.len() as f64 / self.step).round() as usize;
+
+        self.reset();
+        let mut output = Vec::with_capacity(length + self.width);
+        self.process(input, &mut output);
+        while output.len() < length {
+            self.flush(&mut output);
+        }
+        self.reset();
+
+        let mut audio = AudioBuffer::with_length(length);
+        audio.copy_from_slice(&output[..length]);
+        audio
+    }
+
+    fn interpolate(&self) -> Frame {
+        let history = &self.history;
+
+        if let Quality::Linear = self.quality {
+            return Interpolation::Linear.apply(self.position, |i| history[i as usize]);
+        }
+
+        let center = self.position as usize;
+        let fraction = self.position - center as f64;
+        let scale = self.cutoff * PHASES as f64;
+
+        let (mut sum, mut weights) = (Frame::default(), 0.);
+        for k in (center + 1 - self.width)..(center + self.width + 1) {
+            let distance = (fraction + center as f64 - k as f64).abs() * scale;
+            let index = distance as usize;
+            let weight = match self.table.get(index + 1) {
+                Some(next) => {
+                    let d = distance - index as f64;
+                    self.table[index] + d * (next - self.table[index])
+                },
+                None => 0.
+            };
+            sum += history[k] * weight.into();
+            weights += weight;
+        }
+        // normalized for unity gain at DC
+        sum / weights.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f64, rate: f64, length: usize) -> Vec<Frame> {
+        (0..length).map(|i| (2. * PI * freq * i as f64 / rate).sin().into()).collect()
+    }
+
+    fn error(output: &[Frame], expected: &[Frame]) -> f64 {
+        output.iter().zip(expected.iter()).skip(100).take(output.len() - 200)
+            .fold(0., |max, (a, b)| max.max((a[0] - b[0]).abs()))
+    }
+
+    #[test]
+    fn test_resampler_sinc() {
+        for &(from, to) in [(44100., 48000.), (48000., 44100.), (48000., 96000.)].iter() {
+            let input = sine(1000., from, 4410);
+            let output = Resampler::new(from, to, Quality::Sinc).resample(&input);
+            let expected = sine(1000., to, output.len());
+            assert_eq!(output.len(), (4410. * to / from).round() as usize);
+            assert!(error(&output, &expected) < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_resampler_linear() {
+        let input = sine(100., 44100., 4410);
+        let output = Resampler::new(44100., 32000., Quality::Linear).resample(&input);
+        assert!(error(&output, &sine(100., 32000., output.len())) < 1e-3);
+    }
+
+    #[test]
+    fn test_resampler_anti_aliasing() {
+        // 20kHz is above the nyquist of 22050Hz
+        let input = sine(20000., 48000., 4800);
+        let output = Resampler::new(48000., 22050., Quality::Sinc).resample(&input);
+        let peak = output[100..output.len() - 100].iter()
+            .fold(0., |max: f64, f| max.max(f[0].abs()));
+        assert!(peak < 1e-3);
+    }
+
+    #[test]
+    fn test_resampler_streaming() {
+        let input = sine(440., 44100., 2000);
+        let mut resampler = Resampler::new(44100., 48000., Quality::Sinc);
+        let whole = resampler.resample(&input);
+
+        let mut output = Vec::new();
+        for block in input.chunks(37) {
+            resampler.process(block, &mut output);
+        }
+        resampler.flush(&mut output);
+        assert!(output.len() >= whole.len());
+        assert!(error(&output[..whole.len()], &whole) < 1e-10);
+
+        resampler.set_ratio(1.);
+        assert_eq!(resampler.ratio(), 1.);
+    }
+}
@@ -0,0 +1,568 @@
+//! The Subgraph Module
+//!
+//! A [`Graph`] wrapped as a single plugin, which exposes selected ports and
+//! parameters of it's nodes. Reusable instruments and effect racks are
+//! built this way, and a [`SubgraphFactory`] ships them as ordinary
+//! plugins.
+//!
+//! [`Graph`]: ../graph/struct.Graph.html
+//! [`SubgraphFactory`]: struct.SubgraphFactory.html
+
+use std::str;
+use buffer::control::ParamValue;
+use graph::{port_kinds, Graph, NodeId, Connection, GraphError, GraphResult};
+use json::Value;
+use plugins::prelude::*;
+use plugins::{Factory, FactoryDesc};
+use project::{self, ProjectError};
+use CoreConfig;
+
+/// Passes an input of the subgraph into the graph. The subgraph fills it's
+/// output buffer before each cycle.
+struct Inlet(PluginIoKind);
+
+impl Plugin for Inlet {
+    fn get_io_descriptor(&self) -> PluginIoDesc {
+        PluginIoDesc {
+            mode: PluginIoMode::Complex(PluginComplexIo {
+                inputs: box [],
+                outputs: box [self.0.clone()]
+            }),
+            inputs: box [],
+            outputs: box ["Inlet".to_owned()]
+        }
+    }
+
+    fn process(&mut self, _inputs: &PluginIo, _outputs: &mut PluginIo)
+        -> PluginResult<()> {
+        Ok(())
+    }
+}
+
+/// Passes an output of the graph out of the subgraph. The subgraph reads
+/// it's input buffer after each cycle.
+struct Outlet(PluginIoKind);
+
+impl Plugin for Outlet {
+    fn get_io_descriptor(&self) -> PluginIoDesc {
+        PluginIoDesc {
+            mode: PluginIoMode::Complex(PluginComplexIo {
+                inputs: box [self.0.clone()],
+                outputs: box []
+            }),
+            inputs: box ["Outlet".to_owned()],
+            outputs: box []
+        }
+    }
+
+    fn process(&mut self, _inputs: &PluginIo, _outputs: &mut PluginIo)
+        -> PluginResult<()> {
+        Ok(())
+    }
+}
+
+/// An exposed port, passed through an inlet or outlet node.
+struct Port {
+    node: NodeId,
+    kind: PluginIoKind,
+    name: String
+}
+
+/// An exposed parameter.
+struct Param {
+    node: NodeId,
+    index: usize,
+    name: Option<String>
+}
+
+/// A graph as a plugin. Inputs and outputs of the plugin are passed to the
+/// exposed ports of nodes, and it's parameters are the exposed parameters.
+///
+/// The inner graph is processed on a single thread, and it's compiled
+/// outside of [`process()`], when the subgraph is built, edited, or it's
+/// state or core config is loaded. Latency changes of inner plugins are
+/// reported as the latency of the subgraph right away, but connections
+/// inside are compensated for them once it's compiled again. Errors of
+/// inner plugins don't stop the cycle: outputs of the failed node are
+/// cleared, the rest of the graph is processed as usual, and the first
+/// error is returned.
+///
+/// The state of the subgraph holds parameters and states of all inner
+/// plugins, exposed or not.
+///
+/// # Examples
+/// ```
+/// use overcore::Core;
+/// use overcore::graph::Graph;
+/// use overcore::plugins::prelude::*;
+/// use overcore::plugins::core::{CorePlugin, Filter};
+/// use overcore::subgraph::Subgraph;
+///
+/// let core = Core::new(Default::default());
+/// let mut graph = Graph::new(&core);
+/// let filter = graph.add_node(Box::new(Filter::new(&core))).unwrap();
+///
+/// let mut rack = Subgraph::new(graph);
+/// rack.expose_input(filter, 0, "In").unwrap();
+/// rack.expose_output(filter, 0, "Out").unwrap();
+/// rack.expose_param(filter, 1, Some("Tone")).unwrap();
+///
+/// assert_eq!(&rack.get_io_descriptor().outputs[..], &["Out".to_owned()]);
+/// assert_eq!(rack.get_params()[0].name, "Tone");
+/// ```
+///
+/// [`process()`]: ../plugins/trait.Plugin.html#tymethod.process
+pub struct Subgraph {
+    graph: Graph,
+    inputs: Vec<Port>,
+    outputs: Vec<Port>,
+    params: Vec<Param>,
+    latency: usize
+}
+
+impl Subgraph {
+    /// Wrap `graph`, with nothing exposed.
+    pub fn new(mut graph: Graph) -> Self {
+        graph.set_threads(1);
+        let latency = graph.latency();
+        Self { graph, inputs: Vec::new(), outputs: Vec::new(), params: Vec::new(), latency }
+    }
+
+    /// Get the inner graph.
+    pub fn graph(&self) -> &Graph { &self.graph }
+
+    /// Edit the inner graph with `edit`, and compile it again.
+    pub fn edit<F, R>(&mut self, edit: F) -> R where F: FnOnce(&mut Graph) -> R {
+        let result = edit(&mut self.graph);
+        self.latency = self.graph.latency();
+        result
+    }
+
+    /// Expose `input` of `node` as a new input named `name`, and get it's
+    /// index.
+    pub fn expose_input(&mut self, node: NodeId, input: usize, name: &str)
+        -> GraphResult<usize> {
+        let kind = port_kinds(self.graph.io_descriptor(node)?).0.get(input).cloned()
+            .ok_or(GraphError::NoSuchPort(node, input))?;
+        let inlet = self.graph.add_node(box Inlet(kind.clone()))?;
+        let connection = Connection { from: inlet, output: 0, to: node, input };
+        if let Err(e) = self.graph.connect(connection) {
+            self.graph.remove_node(inlet)?;
+            return Err(e);
+        }
+
+        self.inputs.push(Port { node: inlet, kind, name: name.to_owned() });
+        self.latency = self.graph.latency();
+        Ok(self.inputs.len() - 1)
+    }
+
+    /// Expose `output` of `node` as a new output named `name`, and get it's
+    /// index.
+    pub fn expose_output(&mut self, node: NodeId, output: usize, name: &str)
+        -> GraphResult<usize> {
+        let kind = port_kinds(self.graph.io_descriptor(node)?).1.get(output).cloned()
+            .ok_or(GraphError::NoSuchPort(node, output))?;
+        let outlet = self.graph.add_node(box Outlet(kind.clone()))?;
+        let connection = Connection { from: node, output, to: outlet, input: 0 };
+        if let Err(e) = self.graph.connect(connection) {
+            self.graph.remove_node(outlet)?;
+            return Err(e);
+        }
+
+        self.outputs.push(Port { node: outlet, kind, name: name.to_owned() });
+        self.latency = self.graph.latency();
+        Ok(self.outputs.len() - 1)
+    }
+
+    /// Expose parameter `index` of `node` as a new parameter, and get it's
+    /// index. It keeps it's name, unless `name` is given.
+    pub fn expose_param(&mut self, node: NodeId, index: usize, name: Option<&str>)
+        -> GraphResult<usize> {
+        if index >= self.graph.plugin(node)?.get_params().len() {
+            return Err(GraphError::Plugin(node, PluginError::InvalidArgument));
+        }
+
+        let name = name.map(str::to_owned);
+        self.params.push(Param { node, index, name });
+        Ok(self.params.len() - 1)
+    }
+}
+
+impl Plugin for Subgraph {
+    fn core_changed(&mut self, config: &CoreConfig) {
+        self.graph.core_changed(&CoreConfig { threads: 1, ..config.clone() });
+        self.latency = self.graph.latency();
+    }
+
+    fn get_params(&self) -> PluginParamsDesc {
+        let params = self.params.iter().filter_map(|param| {
+            let plugin = self.graph.plugin(param.node).ok()?;
+            let mut desc = plugin.get_params().get(param.index)?.clone();
+            if let Some(ref name) = param.name {
+                desc.name = name.clone();
+            }
+            Some(desc)
+        });
+        params.collect::<Vec<_>>().into_boxed_slice()
+    }
+
+    fn get_param(&self, index: usize) -> Option<ParamValue> {
+        let param = self.params.get(index)?;
+        self.graph.plugin(param.node).ok()?.get_param(param.index)
+    }
+
+    fn param_range(&self, index: usize) -> Option<PluginParamRange> {
+        let param = self.params.get(index)?;
+        self.graph.plugin(param.node).ok()?.param_range(param.index)
+    }
+
+    fn param_allocates(&self, index: usize) -> bool {
+        self.params.get(index).and_then(|param| self.graph.plugin(param.node).ok()
+            .map(|plugin| plugin.param_allocates(param.index))).unwrap_or(false)
+    }
+
+    fn param_default(&self, index: usize) -> Option<ParamValue> {
+        let param = self.params.get(index)?;
+        self.graph.plugin(param.node).ok()?.param_default(param.index)
+    }
+
+    fn set_param(&mut self, index: usize, value: ParamValue) -> PluginResult<()> {
+        let (node, index) = match self.params.get(index) {
+            Some(param) => (param.node, param.index),
+            None => { return Err(PluginError::InvalidArgument); }
+        };
+        self.graph.set_param(node, index, value).map_err(plugin_error)
+    }
+
+    fn latency(&self) -> usize { self.latency }
+
+    fn save_state(&self) -> Vec<u8> {
+        let nodes = self.graph.nodes().filter_map(|id| self.graph.plugin(id).ok())
+            .map(|plugin| Value::Object(project::write_settings(plugin)));
+        Value::Array(nodes.collect()).to_compact().into_bytes()
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> PluginResult<()> {
+        let value = str::from_utf8(state).ok().and_then(|text| Value::parse(text).ok());
+        let nodes = match value {
+            Some(Value::Array(nodes)) => nodes,
+            _ => { return Err(PluginError::InvalidArgument); }
+        };
+        let ids = self.graph.nodes().collect::<Vec<_>>();
+        if nodes.len() != ids.len() {
+            return Err(PluginError::InvalidArgument);
+        }
+
+        let mut result = Ok(());
+        for (node, &id) in nodes.iter().zip(ids.iter()) {
+            let loaded = self.graph.plugin_mut(id).map_err(ProjectError::from)
+                .and_then(|plugin| project::read_settings(id, plugin, node));
+            let _ = self.graph.refresh(id);
+            if let Err(e) = loaded {
+                result = Err(match e {
+                    ProjectError::Graph(GraphError::Plugin(_, e)) => e,
+                    _ => PluginError::InvalidArgument
+                });
+                break;
+            }
+        }
+        self.latency = self.graph.latency();
+        result
+    }
+
+    fn get_io_descriptor(&self) -> PluginIoDesc {
+        let kinds = |ports: &[Port]| ports.iter().map(|p| p.kind.clone())
+            .collect::<Vec<_>>().into_boxed_slice();
+        let names = |ports: &[Port]| ports.iter().map(|p| p.name.clone())
+            .collect::<Vec<_>>().into_boxed_slice();
+        PluginIoDesc {
+            mode: PluginIoMode::Complex(PluginComplexIo {
+                inputs: kinds(&self.inputs),
+                outputs: kinds(&self.outputs)
+            }),
+            inputs: names(&self.inputs),
+            outputs: names(&self.outputs)
+        }
+    }
+
+    fn process(&mut self, inputs: &PluginIo, outputs: &mut PluginIo)
+        -> PluginResult<()> {
+        if inputs.len() != self.inputs.len() || outputs.len() != self.outputs.len() {
+            return Err(PluginError::InvalidArgument);
+        }
+
+        for (port, input) in self.inputs.iter().zip(inputs.iter()) {
+            self.graph.set_output(port.node, 0, input);
+        }
+        let result = self.graph.process_compiled();
+        for (port, output) in self.outputs.iter().zip(outputs.iter_mut()) {
+            self.graph.get_input(port.node, 0, output);
+        }
+        self.latency = self.graph.compiled_latency();
+        result.map_err(plugin_error)
+    }
+
+    fn terminate(&mut self) {
+        self.graph.terminate();
+    }
+}
+
+/// Get the plugin error of `error`, for errors of the inner graph.
+fn plugin_error(error: GraphError) -> PluginError {
+    match error {
+        GraphError::Plugin(_, e) => e,
+        _ => PluginError::InvalidArgument
+    }
+}
+
+/// Builds a subgraph each time it's plugin is created.
+pub type Builder = Box<FnMut() -> GraphResult<Subgraph>>;
+
+/// A factory of subgraphs, each built by a function.
+///
+/// # Examples
+/// ```
+/// use overcore::{Core, CoreConfig};
+/// use overcore::graph::Graph;
+/// use overcore::plugins::{Factory, FactoryDesc, PluginDesc};
+/// use overcore::plugins::core::{CorePlugin, Reverb};
+/// use overcore::subgraph::{Subgraph, SubgraphFactory};
+///
+/// let desc = FactoryDesc {
+///     uuid: "0c9f6b1e-5d7a-4b8e-a0f3-2e6c4d1b9a57".to_owned(),
+///     name: "Racks".to_owned(),
+///     description: "effect racks.".to_owned()
+/// };
+/// let room = PluginDesc::default().with_name("Room");
+/// let mut factory = SubgraphFactory::new(desc).with_plugin(room, || {
+///     let core = Core::new(CoreConfig::default());
+///     let mut graph = Graph::new(&core);
+///     let reverb = graph.add_node(Box::new(Reverb::new(&core)))?;
+///     let mut room = Subgraph::new(graph);
+///     room.expose_input(reverb, 0, "In")?;
+///     room.expose_output(reverb, 0, "Out")?;
+///     Ok(room)
+/// });
+///
+/// assert_eq!(factory.get_plugins()[0].name, "Room");
+/// let room = factory.create_plugin(0).unwrap();
+/// assert_eq!(room.get_io_descriptor().inputs.len(), 1);
+/// ```
+pub struct SubgraphFactory {
+    desc: FactoryDesc,
+    plugins: Vec<(PluginDesc, Builder)>
+}
+
+impl SubgraphFactory {
+    /// Create an empty factory described by `desc`.
+    pub fn new(desc: FactoryDesc) -> Self {
+        Self { desc, plugins: Vec::new() }
+    }
+
+    /// Add a plugin described by `desc`, built by `builder`. Id of the
+    /// descriptor is set to it's index in the factory.
+    pub fn with_plugin<F>(mut self, desc: PluginDesc, builder: F) -> Self
+        where F: FnMut() -> GraphResult<Subgraph> + 'static {
+        let desc = desc.with_id(self.plugins.len());
+        self.plugins.push((desc, box builder));
+        self
+    }
+}
+
+impl Factory for SubgraphFactory {
+    fn get_descriptor(&self) -> FactoryDesc { self.desc.clone() }
+
+    fn get_plugins(&self) -> Box<[PluginDesc]> {
+        let plugins = self.plugins.iter().map(|&(ref desc, _)| desc.clone());
+        plugins.collect::<Vec<_>>().into_boxed_slice()
+    }
+
+    fn create_plugin(&mut self, id: usize) -> PluginResult<Box<Plugin>> {
+        let builder = &mut self.plugins.get_mut(id).ok_or(PluginError::InvalidArgument)?.1;
+        let subgraph = builder().map_err(plugin_error)?;
+        Ok(box subgraph)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use buffer::Buffer;
+    use buffer::prelude::*;
+    use graph::Graph;
+    use plugins::core::{CorePlugin, Mixer};
+    use {Core, CoreConfig};
+    use super::*;
+
+    fn audio(value: f64) -> PluginIoBuffer {
+        let mut buffer = AudioBuffer::with_length(8);
+        buffer.iter_mut().for_each(|f| *f = value.into());
+        PluginIoBuffer::Audio(buffer)
+    }
+
+    /// Passes it's input through, but fails on every cycle.
+    struct Failing;
+
+    impl Plugin for Failing {
+        fn get_io_descriptor(&self) -> PluginIoDesc {
+            PluginIoDesc {
+                mode: PluginIoMode::Complex(PluginComplexIo {
+                    inputs: box [PluginIoKind::Audio],
+                    outputs: box [PluginIoKind::Audio]
+                }),
+                inputs: box ["In".to_owned()],
+                outputs: box ["Out".to_owned()]
+            }
+        }
+
+        fn process(&mut self, _inputs: &PluginIo, _outputs: &mut PluginIo)
+            -> PluginResult<()> {
+            Err(PluginError::InternalError)
+        }
+    }
+
+    /// Reports one more frame of latency after each cycle, and counts it's
+    /// terminations.
+    struct Drifting(usize, Arc<AtomicUsize>);
+
+    impl Plugin for Drifting {
+        fn get_io_descriptor(&self) -> PluginIoDesc { Failing.get_io_descriptor() }
+
+        fn latency(&self) -> usize { self.0 }
+
+        fn process(&mut self, _inputs: &PluginIo, _outputs: &mut PluginIo)
+            -> PluginResult<()> {
+            self.0 += 1;
+            Ok(())
+        }
+
+        fn terminate(&mut self) { self.1.fetch_add(1, Ordering::SeqCst); }
+    }
+
+    #[test]
+    fn test_subgraph_latency() {
+        let core = Core::new(CoreConfig { buffer_size: 8, ..Default::default() });
+        let terminated = Arc::new(AtomicUsize::new(0));
+        let mut graph = Graph::new(&core);
+        let drifting = graph.add_node(box Drifting(2, terminated.clone())).unwrap();
+        let mixer = graph.add_node(box Mixer::new(&core)).unwrap();
+        graph.connect(Connection { from: drifting, output: 0, to: mixer, input: 0 }).unwrap();
+
+        let mut rack = Subgraph::new(graph);
+        rack.expose_input(drifting, 0, "In").unwrap();
+        rack.expose_output(mixer, 0, "Out").unwrap();
+        assert_eq!(rack.latency(), 2);
+
+        let inputs: PluginIo = box [audio(0.5)];
+        let mut outputs: PluginIo = box [audio(0.)];
+        for latency in 3..6 {
+            rack.process(&inputs, &mut outputs).unwrap();
+            assert_eq!(rack.latency(), latency);
+        }
+
+        // inner plugins are terminated once
+        rack.terminate();
+        assert_eq!(terminated.load(Ordering::SeqCst), 1);
+        drop(rack);
+        assert_eq!(terminated.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_subgraph_state() {
+        let core = Core::new(CoreConfig { buffer_size: 8, ..Default::default() });
+        let build = || {
+            let mut graph = Graph::new(&core);
+            let mixer = graph.add_node(box Mixer::new(&core)).unwrap();
+            let mut bus = Subgraph::new(graph);
+            bus.expose_input(mixer, 0, "In").unwrap();
+            bus.expose_output(mixer, 0, "Out").unwrap();
+            bus.expose_param(mixer, 0, None).unwrap();
+            (bus, mixer)
+        };
+
+        let (mut bus, mixer) = build();
+        bus.set_param(0, ParamValue::Float(-6.)).unwrap();
+        // a parameter which is not exposed
+        bus.edit(|graph| graph.set_param(mixer, 1, ParamValue::Float(-12.))).unwrap();
+
+        let (mut loaded, _) = build();
+        assert_eq!(loaded.load_state(&bus.save_state()), Ok(()));
+        assert_eq!(loaded.get_param(0), Some(ParamValue::Float(-6.)));
+        let gain = loaded.graph().plugin(mixer).unwrap().get_param(1);
+        assert_eq!(gain, Some(ParamValue::Float(-12.)));
+        assert_eq!(loaded.save_state(), bus.save_state());
+
+        assert_eq!(loaded.load_state(b"[]"), Err(PluginError::InvalidArgument));
+        assert_eq!(loaded.load_state(b"{"), Err(PluginError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_subgraph_errors() {
+        let core = Core::new(CoreConfig { buffer_size: 8, threads: 4, ..Default::default() });
+        let mut graph = Graph::new(&core);
+        let mixer = graph.add_node(box Mixer::new(&core)).unwrap();
+        let mut rack = Subgraph::new(graph);
+        assert_eq!(rack.graph().config().threads, 1);
+        rack.expose_input(mixer, 0, "In").unwrap();
+        rack.expose_output(mixer, 0, "Out").unwrap();
+
+        let failing = rack.edit(|graph| graph.add_node(box Failing)).unwrap();
+        let inputs: PluginIo = box [audio(0.5)];
+        let mut outputs: PluginIo = box [audio(0.)];
+        assert_eq!(rack.process(&inputs, &mut outputs), Err(PluginError::InternalError));
+
+        // the rest of the graph is still processed
+        match outputs[0] {
+            PluginIoBuffer::Audio(ref output) => assert!(output[0][0] > 0.),
+            _ => panic!("outputs should be audio")
+        }
+        rack.edit(|graph| graph.remove_node(failing)).unwrap();
+        assert_eq!(rack.process(&inputs, &mut outputs), Ok(()));
+    }
+
+    #[test]
+    fn test_subgraph_mixer() {
+        let core = Core::new(CoreConfig { buffer_size: 8, ..Default::default() });
+        let mut graph = Graph::new(&core);
+        let mixer = graph.add_node(box Mixer::new(&core)).unwrap();
+
+        let mut bus = Subgraph::new(graph);
+        assert_eq!(bus.expose_input(mixer, 0, "Left").unwrap(), 0);
+        assert_eq!(bus.expose_input(mixer, 1, "Right").unwrap(), 1);
+        assert_eq!(bus.expose_input(mixer, 3, "None"), Err(GraphError::NoSuchPort(mixer, 3)));
+        bus.expose_output(mixer, 0, "Out").unwrap();
+        bus.expose_param(mixer, 0, Some("Volume")).unwrap();
+        bus.expose_param(mixer, 1, None).unwrap();
+
+        let desc = bus.get_io_descriptor();
+        assert_eq!(&desc.inputs[..], &["Left".to_owned(), "Right".to_owned()]);
+        let names = bus.get_params().iter().map(|p| p.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names, ["Volume", "Gain 1"]);
+
+        bus.set_param(0, ParamValue::Float(-6.)).unwrap();
+        assert_eq!(bus.get_param(0), Some(ParamValue::Float(-6.)));
+        assert_eq!(bus.set_param(2, ParamValue::Float(0.)), Err(PluginError::InvalidArgument));
+
+        // the same as a mixer alone
+        let mut reference = Mixer::new(&core);
+        for input in 0..2 {
+            let status = Some(PluginIoKind::Audio);
+            let _ = reference.io_changed(PluginIoChange { is_output: false, index: input, status });
+        }
+        reference.set_param(0, ParamValue::Float(-6.)).unwrap();
+
+        let inputs: PluginIo = box [audio(0.5), audio(0.25)];
+        let (mut expected, mut outputs): (PluginIo, PluginIo) = (box [audio(0.)], box [audio(0.)]);
+        reference.process(&inputs, &mut expected).unwrap();
+        bus.process(&inputs, &mut outputs).unwrap();
+
+        match (&outputs[0], &expected[0]) {
+            (&PluginIoBuffer::Audio(ref output), &PluginIoBuffer::Audio(ref expected)) => {
+                assert_eq!(&output[..], &expected[..]);
+                assert!(output[0][0] > 0.);
+            },
+            _ => panic!("outputs should be audio")
+        }
+    }
+}
@@ -0,0 +1,409 @@
+//! The Theory Module
+//!
+//! Music theory helpers on top of [`NoteAlphabet`] and [`NoteName`], such as
+//! intervals, scales and chords. Everything here is in terms of 12 half steps
+//! per octave, regardless of the tuning being used.
+//!
+//! [`NoteAlphabet`]: ../buffer/control/enum.NoteAlphabet.html
+//! [`NoteName`]: ../buffer/control/struct.NoteName.html
+
+use buffer::control::{NoteAlphabet, NoteName};
+
+#[inline]
+/// Get `i` modulo `n`, in range [0, n).
+fn modulo(i: isize, n: isize) -> isize { ((i % n) + n) % n }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// An interval in half steps. Negative intervals are descending.
+pub struct Interval(pub isize);
+
+impl Interval {
+    pub const UNISON: Interval = Interval(0);
+    pub const MINOR_SECOND: Interval = Interval(1);
+    pub const MAJOR_SECOND: Interval = Interval(2);
+    pub const MINOR_THIRD: Interval = Interval(3);
+    pub const MAJOR_THIRD: Interval = Interval(4);
+    pub const PERFECT_FOURTH: Interval = Interval(5);
+    pub const TRITONE: Interval = Interval(6);
+    pub const PERFECT_FIFTH: Interval = Interval(7);
+    pub const MINOR_SIXTH: Interval = Interval(8);
+    pub const MAJOR_SIXTH: Interval = Interval(9);
+    pub const MINOR_SEVENTH: Interval = Interval(10);
+    pub const MAJOR_SEVENTH: Interval = Interval(11);
+    pub const OCTAVE: Interval = Interval(12);
+
+    #[inline]
+    /// Get the interval from `from` to `to`.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::{NoteAlphabet, NoteName};
+    /// use overcore::theory::Interval;
+    ///
+    /// let (c4, g4) = (NoteName(NoteAlphabet::C, 4), NoteName(NoteAlphabet::G, 4));
+    /// assert_eq!(Interval::between(&c4, &g4), Interval::PERFECT_FIFTH);
+    /// assert_eq!(Interval::between(&g4, &c4), Interval(-7));
+    /// ```
+    pub fn between(from: &NoteName, to: &NoteName) -> Self {
+        Interval(to.index() - from.index())
+    }
+
+    #[inline]
+    /// Reduce a compound interval to a simple one, in range [0, 12).
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::theory::Interval;
+    /// assert_eq!(Interval(19).simple(), Interval::PERFECT_FIFTH);
+    /// assert_eq!(Interval(-5).simple(), Interval::PERFECT_FIFTH);
+    /// ```
+    pub fn simple(&self) -> Self {
+        Interval(modulo(self.0, NoteAlphabet::len() as isize))
+    }
+
+    #[inline]
+    /// Get inversion of the simple form of this interval.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::theory::Interval;
+    /// assert_eq!(Interval::MAJOR_THIRD.invert(), Interval::MINOR_SIXTH);
+    /// assert_eq!(Interval::UNISON.invert(), Interval::UNISON);
+    /// ```
+    pub fn invert(&self) -> Self {
+        Interval(-self.0).simple()
+    }
+
+    /// Get short name of the simple form of this interval (e.g. "m3", "P5").
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::theory::Interval;
+    /// assert_eq!(Interval::MINOR_THIRD.name(), "m3");
+    /// assert_eq!(Interval(18).name(), "TT");
+    /// ```
+    pub fn name(&self) -> &'static str {
+        const NAMES: [&'static str; 12] = [
+            "P1", "m2", "M2", "m3", "M3", "P4", "TT", "P5", "m6", "M6", "m7", "M7"
+        ];
+        NAMES[self.simple().0 as usize]
+    }
+}
+
+impl NoteName {
+    #[inline]
+    /// Transpose a new note from self by `interval`.
+    pub fn transpose_by(&self, interval: Interval) -> Self {
+        self.transpose(interval.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Kind of a scale, describing it's steps.
+pub enum ScaleKind {
+    /// Ionian mode.
+    Major,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    /// Natural minor, or aeolian mode.
+    Minor,
+    Locrian,
+    HarmonicMinor,
+    /// Ascending melodic minor.
+    MelodicMinor,
+    MajorPentatonic,
+    MinorPentatonic,
+    Chromatic,
+    /// Custom offsets, made by [`Scale::custom()`].
+    ///
+    /// [`Scale::custom()`]: struct.Scale.html#method.custom
+    Custom(CustomOffsets)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Offsets of degrees of a custom scale from the root in half steps,
+/// ascending in range [0, 12) and starting from 0. They're only made by
+/// [`Scale::custom()`], which checks them.
+///
+/// [`Scale::custom()`]: struct.Scale.html#method.custom
+pub struct CustomOffsets(Box<[u8]>);
+
+impl ScaleKind {
+    /// Get offsets of degrees from the root in half steps.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::theory::ScaleKind;
+    /// assert_eq!(ScaleKind::Dorian.offsets(), &[0, 2, 3, 5, 7, 9, 10]);
+    /// ```
+    pub fn offsets(&self) -> &[u8] {
+        match self {
+            &ScaleKind::Major           => &[0, 2, 4, 5, 7, 9, 11],
+            &ScaleKind::Dorian          => &[0, 2, 3, 5, 7, 9, 10],
+            &ScaleKind::Phrygian        => &[0, 1, 3, 5, 7, 8, 10],
+            &ScaleKind::Lydian          => &[0, 2, 4, 6, 7, 9, 11],
+            &ScaleKind::Mixolydian      => &[0, 2, 4, 5, 7, 9, 10],
+            &ScaleKind::Minor           => &[0, 2, 3, 5, 7, 8, 10],
+            &ScaleKind::Locrian         => &[0, 1, 3, 5, 6, 8, 10],
+            &ScaleKind::HarmonicMinor   => &[0, 2, 3, 5, 7, 8, 11],
+            &ScaleKind::MelodicMinor    => &[0, 2, 3, 5, 7, 9, 11],
+            &ScaleKind::MajorPentatonic => &[0, 2, 4, 7, 9],
+            &ScaleKind::MinorPentatonic => &[0, 3, 5, 7, 10],
+            &ScaleKind::Chromatic       => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+            &ScaleKind::Custom(ref offsets) => &offsets.0
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A scale, made of a root and kind of the scale.
+pub struct Scale {
+    pub root: NoteAlphabet,
+    pub kind: ScaleKind
+}
+
+impl Scale {
+    /// Create a new scale.
+    pub fn new(root: NoteAlphabet, kind: ScaleKind) -> Self {
+        Self { root, kind }
+    }
+
+    /// Create a scale from custom `offsets`. The offsets should be ascending
+    /// in range [0, 12) and start from 0.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::NoteAlphabet;
+    /// use overcore::theory::Scale;
+    ///
+    /// assert!(Scale::custom(NoteAlphabet::C, &[0, 2, 4, 6, 8, 10]).is_ok());
+    /// assert!(Scale::custom(NoteAlphabet::C, &[0, 4, 2]).is_err());
+    /// assert!(Scale::custom(NoteAlphabet::C, &[2, 4]).is_err());
+    /// assert!(Scale::custom(NoteAlphabet::C, &[0, 12]).is_err());
+    /// assert!(Scale::custom(NoteAlphabet::C, &[]).is_err());
+    /// ```
+    pub fn custom(root: NoteAlphabet, offsets: &[u8]) -> Result<Self, ()> {
+        if offsets.first() != Some(&0) {
+            return Err(());
+        }
+
+        let len = NoteAlphabet::len() as u8;
+        let ascending = offsets.windows(2).all(|w| w[0] < w[1]);
+        if !ascending || offsets[offsets.len() - 1] >= len {
+            return Err(());
+        }
+
+        let offsets = offsets.to_vec().into_boxed_slice();
+        Ok(Self::new(root, ScaleKind::Custom(CustomOffsets(offsets))))
+    }
+
+    #[inline]
+    /// Get number of degrees in an octave.
+    pub fn len(&self) -> usize { self.kind.offsets().len() }
+
+    #[inline]
+    /// Check whether `alphabet` is in the scale.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::NoteAlphabet;
+    /// use overcore::theory::{Scale, ScaleKind};
+    ///
+    /// let scale = Scale::new(NoteAlphabet::D, ScaleKind::Major);
+    /// assert_eq!(scale.contains(&NoteAlphabet::Fs), true);
+    /// assert_eq!(scale.contains(&NoteAlphabet::F), false);
+    /// ```
+    pub fn contains(&self, alphabet: &NoteAlphabet) -> bool {
+        let offset = modulo(alphabet.index() as isize - self.root.index() as isize,
+                            NoteAlphabet::len() as isize);
+        self.kind.offsets().contains(&(offset as u8))
+    }
+
+    /// Get note of `degree` (0 is the root) which it's root is in `octave`.
+    /// Degrees out of [0, len) are in the neighbour octaves.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::{NoteAlphabet, NoteName};
+    /// use overcore::theory::{Scale, ScaleKind};
+    ///
+    /// let scale = Scale::new(NoteAlphabet::A, ScaleKind::Minor);
+    /// assert_eq!(scale.degree(2, 3), NoteName(NoteAlphabet::C, 4));
+    /// assert_eq!(scale.degree(7, 3), NoteName(NoteAlphabet::A, 4));
+    /// assert_eq!(scale.degree(-1, 3), NoteName(NoteAlphabet::G, 3));
+    /// ```
+    pub fn degree(&self, degree: isize, octave: i8) -> NoteName {
+        let len = self.len() as isize;
+        let (octaves, degree) = (degree - modulo(degree, len), modulo(degree, len));
+        let offset = self.kind.offsets()[degree as usize] as isize;
+        let octaves = octaves / len * NoteAlphabet::len() as isize;
+        NoteName(self.root.clone(), octave).transpose(octaves + offset)
+    }
+
+    /// Get notes of the scale which it's root is in `octave`.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::NoteAlphabet;
+    /// use overcore::theory::{Scale, ScaleKind};
+    ///
+    /// let scale = Scale::new(NoteAlphabet::G, ScaleKind::MajorPentatonic);
+    /// let notes = scale.notes(4).iter().map(|n| n.to_string()).collect::<Vec<_>>();
+    /// assert_eq!(notes, ["G4", "A4", "B4", "D5", "E5"]);
+    /// ```
+    pub fn notes(&self, octave: i8) -> Vec<NoteName> {
+        (0..self.len() as isize).map(|d| self.degree(d, octave)).collect()
+    }
+
+    /// Get the nearest note in the scale to `name`. When two notes are at the
+    /// same distance, the lower one is chosen.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::{NoteAlphabet, NoteName};
+    /// use overcore::theory::{Scale, ScaleKind};
+    ///
+    /// let scale = Scale::new(NoteAlphabet::C, ScaleKind::MajorPentatonic);
+    /// assert_eq!(scale.snap(&NoteName(NoteAlphabet::E, 4)), NoteName(NoteAlphabet::E, 4));
+    /// assert_eq!(scale.snap(&NoteName(NoteAlphabet::F, 4)), NoteName(NoteAlphabet::E, 4));
+    /// assert_eq!(scale.snap(&NoteName(NoteAlphabet::Fs, 4)), NoteName(NoteAlphabet::G, 4));
+    /// assert_eq!(scale.snap(&NoteName(NoteAlphabet::B, 4)), NoteName(NoteAlphabet::C, 5));
+    /// ```
+    pub fn snap(&self, name: &NoteName) -> NoteName {
+        for distance in 0..NoteAlphabet::len() as isize {
+            for &n in [-distance, distance].iter() {
+                let candidate = name.transpose(n);
+                if self.contains(&candidate.0) {
+                    return candidate;
+                }
+            }
+        }
+        unreachable!("a scale always contains it's root")
+    }
+
+    /// Get notes of a chord built by stacking `len` thirds of the scale on
+    /// `degree`, which it's root is in `octave`.
+    fn stack(&self, degree: isize, octave: i8, len: isize) -> Vec<NoteName> {
+        (0..len).map(|i| self.degree(degree + 2 * i, octave)).collect()
+    }
+
+    /// Get the diatonic triad on `degree`. See [`degree()`](#method.degree).
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::NoteAlphabet;
+    /// use overcore::theory::{Scale, ScaleKind};
+    ///
+    /// let scale = Scale::new(NoteAlphabet::C, ScaleKind::Major);
+    /// let notes = scale.triad(4, 4).iter().map(|n| n.to_string()).collect::<Vec<_>>();
+    /// assert_eq!(notes, ["G4", "B4", "D5"]);
+    /// ```
+    pub fn triad(&self, degree: isize, octave: i8) -> Vec<NoteName> {
+        self.stack(degree, octave, 3)
+    }
+
+    /// Get the diatonic seventh chord on `degree`. See
+    /// [`degree()`](#method.degree).
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::NoteAlphabet;
+    /// use overcore::theory::{Scale, ScaleKind};
+    ///
+    /// let scale = Scale::new(NoteAlphabet::C, ScaleKind::Major);
+    /// let notes = scale.seventh(1, 4).iter().map(|n| n.to_string()).collect::<Vec<_>>();
+    /// assert_eq!(notes, ["D4", "F4", "A4", "C5"]);
+    /// ```
+    pub fn seventh(&self, degree: isize, octave: i8) -> Vec<NoteName> {
+        self.stack(degree, octave, 4)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Kind of a chord, describing it's intervals from the root.
+pub enum ChordKind {
+    Major,
+    Minor,
+    Diminished,
+    Augmented,
+    Sus2,
+    Sus4,
+    Major7,
+    Minor7,
+    Dominant7,
+    MinorMajor7,
+    HalfDiminished7,
+    Diminished7
+}
+
+impl ChordKind {
+    /// Get intervals of chord tones from the root in half steps.
+    pub fn offsets(&self) -> &'static [isize] {
+        match self {
+            &ChordKind::Major           => &[0, 4, 7],
+            &ChordKind::Minor           => &[0, 3, 7],
+            &ChordKind::Diminished      => &[0, 3, 6],
+            &ChordKind::Augmented       => &[0, 4, 8],
+            &ChordKind::Sus2            => &[0, 2, 7],
+            &ChordKind::Sus4            => &[0, 5, 7],
+            &ChordKind::Major7          => &[0, 4, 7, 11],
+            &ChordKind::Minor7          => &[0, 3, 7, 10],
+            &ChordKind::Dominant7       => &[0, 4, 7, 10],
+            &ChordKind::MinorMajor7     => &[0, 3, 7, 11],
+            &ChordKind::HalfDiminished7 => &[0, 3, 6, 10],
+            &ChordKind::Diminished7     => &[0, 3, 6, 9]
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A chord, made of it's root, kind and inversion.
+pub struct Chord {
+    pub root: NoteName,
+    pub kind: ChordKind,
+
+    /// Number of the lowest notes moved an octave higher. 0 means root
+    /// position.
+    pub inversion: usize
+}
+
+impl Chord {
+    /// Create a new chord in root position.
+    pub fn new(root: NoteName, kind: ChordKind) -> Self {
+        Self { root, kind, inversion: 0 }
+    }
+
+    /// Set [`self.inversion`](#structfield.inversion) to `inversion`.
+    pub fn with_inversion(mut self, inversion: usize) -> Self {
+        self.inversion = inversion; self
+    }
+
+    /// Get notes of the chord, from the lowest to the highest.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::{NoteAlphabet, NoteName};
+    /// use overcore::theory::{Chord, ChordKind};
+    ///
+    /// let to_strings = |chord: Chord| {
+    ///     chord.notes().iter().map(|n| n.to_string()).collect::<Vec<_>>()
+    /// };
+    ///
+    /// let chord = Chord::new(NoteName(NoteAlphabet::A, 3), ChordKind::Minor);
+    /// assert_eq!(to_strings(chord.clone()), ["A3", "C4", "E4"]);
+    /// assert_eq!(to_strings(chord.clone().with_inversion(1)), ["C4", "E4", "A4"]);
+    /// assert_eq!(to_strings(chord.with_inversion(4)), ["C5", "E5", "A5"]);
+    /// ```
+    pub fn notes(&self) -> Vec<NoteName> {
+        let offsets = self.kind.offsets();
+        let len = offsets.len();
+        let octave = NoteAlphabet::len() as isize;
+
+        (self.inversion..self.inversion + len).map(|i| {
+            let offset = offsets[i % len] + (i / len) as isize * octave;
+            self.root.transpose(offset)
+        }).collect()
+    }
+}
@@ -0,0 +1,550 @@
+//! The Tuning Module
+//!
+//! Holds tuning systems, which map [`NoteName`]s to frequencies. A
+//! [`Tuning`] is chosen per core through [`CoreConfig::tuning`], and plugins
+//! may override it for themselves.
+//!
+//! [`NoteName`]: ../buffer/control/struct.NoteName.html
+//! [`Tuning`]: trait.Tuning.html
+//! [`CoreConfig::tuning`]: ../struct.CoreConfig.html#structfield.tuning
+
+use std::fmt;
+use std::sync::{Arc, Once, RwLock};
+use std::convert::TryFrom;
+use buffer::control::{NoteAlphabet, NoteName};
+use hardconf;
+
+/// A tuning system which maps note names to frequencies.
+pub trait Tuning: fmt::Debug {
+    /// Get frequency of `name`, detuned by `cents`. Returns `NAN` if `name`
+    /// is not mapped to any frequency.
+    fn freq(&self, name: &NoteName, cents: f64) -> f64;
+
+    /// Get a copy of this tuning as one of the tunings of this module, so it
+    /// can be saved. Other tunings return `None`.
+    fn kind(&self) -> Option<TuningKind> { None }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// One of the tunings of this module.
+pub enum TuningKind {
+    Equal(EqualTemperament),
+    Scala(ScalaTuning)
+}
+
+impl TuningKind {
+    /// Make a shared tuning of this one.
+    pub fn shared(self) -> SharedTuning {
+        match self {
+            TuningKind::Equal(tuning) => Arc::new(tuning),
+            TuningKind::Scala(tuning) => Arc::new(tuning)
+        }
+    }
+}
+
+/// A tuning which can be shared between threads, plugins and the core.
+pub type SharedTuning = Arc<Tuning + Send + Sync>;
+
+/// Get the slot of the default tuning, creating it on the first call.
+fn default_slot() -> &'static RwLock<SharedTuning> {
+    static INIT: Once = Once::new();
+    static mut SLOT: *const RwLock<SharedTuning> = 0 as *const _;
+    unsafe {
+        INIT.call_once(|| {
+            let tuning: SharedTuning = Arc::new(EqualTemperament::default());
+            SLOT = Box::into_raw(box RwLock::new(tuning));
+        });
+        &*SLOT
+    }
+}
+
+/// Get the default tuning, which notes use when they're not given a tuning
+/// (e.g. by [`NoteName::freq()`]), and default core configs start with.
+/// It's 12-TET with A4 tuned to [`hardconf::REFERENCE_PITCH`], unless it's
+/// replaced by [`set_default_tuning()`].
+///
+/// [`NoteName::freq()`]: ../buffer/control/struct.NoteName.html#method.freq
+/// [`hardconf::REFERENCE_PITCH`]: ../hardconf/constant.REFERENCE_PITCH.html
+/// [`set_default_tuning()`]: fn.set_default_tuning.html
+pub fn default_tuning() -> SharedTuning {
+    default_slot().read().unwrap().clone()
+}
+
+/// Replace the default tuning with `tuning`. See
+/// [`default_tuning()`](fn.default_tuning.html).
+///
+/// # Examples
+/// ```
+/// use std::sync::Arc;
+/// use overcore::buffer::control::{NoteAlphabet, NoteName};
+/// use overcore::tuning::{set_default_tuning, EqualTemperament};
+///
+/// set_default_tuning(Arc::new(EqualTemperament::with_pitch(432.)));
+/// assert_eq!(NoteName(NoteAlphabet::A, 4).freq(), 432.);
+/// ```
+pub fn set_default_tuning(tuning: SharedTuning) {
+    *default_slot().write().unwrap() = tuning;
+}
+
+#[inline]
+/// Get key number of `name`, like MIDI note numbers (60 is C4).
+fn key(name: &NoteName) -> isize { name.index() }
+
+#[inline]
+/// Divide `a` by `b`, rounding towards negative infinity. Returns the
+/// quotient and the (positive) remainder.
+fn div_floor(a: isize, b: isize) -> (isize, isize) {
+    let (q, r) = (a / b, a % b);
+    if r < 0 { (q - 1, r + b) } else { (q, r) }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Equal division of the octave (EDO), where each key is one division higher
+/// than the previous one. With 12 divisions, this is the common 12-TET.
+pub struct EqualTemperament {
+    /// Number of divisions in an octave.
+    pub divisions: u32,
+
+    /// The note which it's frequency is given.
+    pub reference: NoteName,
+
+    /// Frequency of `reference`.
+    pub freq: f64
+}
+
+impl EqualTemperament {
+    /// Create 12-TET with A4 tuned to `freq`.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::{NoteAlphabet, NoteName};
+    /// use overcore::tuning::{EqualTemperament, Tuning};
+    ///
+    /// let tuning = EqualTemperament::with_pitch(432.);
+    /// assert_eq!(tuning.freq(&NoteName(NoteAlphabet::A, 5), 0.), 864.);
+    /// ```
+    pub fn with_pitch(freq: f64) -> Self {
+        Self::new(12, NoteName(NoteAlphabet::A, 4), freq)
+    }
+
+    /// Create an EDO with `divisions` divisions, tuning `reference` to
+    /// `freq`.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::{NoteAlphabet, NoteName};
+    /// use overcore::tuning::{EqualTemperament, Tuning};
+    ///
+    /// let tuning = EqualTemperament::new(19, NoteName(NoteAlphabet::C, 4), 256.);
+    /// let octave = NoteName(NoteAlphabet::C, 4).transpose(19);
+    /// assert_eq!(tuning.freq(&octave, 0.), 512.);
+    /// ```
+    pub fn new(divisions: u32, reference: NoteName, freq: f64) -> Self {
+        assert_ne!(divisions, 0);
+        Self { divisions, reference, freq }
+    }
+}
+
+impl Default for EqualTemperament {
+    /// 12-TET with A4 tuned to [`hardconf::REFERENCE_PITCH`].
+    ///
+    /// [`hardconf::REFERENCE_PITCH`]: ../hardconf/constant.REFERENCE_PITCH.html
+    fn default() -> Self { Self::with_pitch(hardconf::REFERENCE_PITCH) }
+}
+
+impl Tuning for EqualTemperament {
+    #[inline]
+    fn freq(&self, name: &NoteName, cents: f64) -> f64 {
+        let divisions = self.divisions as f64;
+        let mut steps = (key(name) - key(&self.reference)) as f64;
+        steps += cents * divisions / 1200.;
+        self.freq * 2f64.powf(steps / divisions)
+    }
+
+    fn kind(&self) -> Option<TuningKind> { Some(TuningKind::Equal(self.clone())) }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A scale, as described by a Scala scale (.scl) file.
+pub struct ScalaScale {
+    /// Description of the scale.
+    pub description: String,
+
+    /// Pitches of degrees 1 to n in cents (degree 0 is always 0 cents). The
+    /// last one is the period of the scale (usually an octave).
+    pitches: Box<[f64]>
+}
+
+impl ScalaScale {
+    /// Create a scale with `pitches` of degrees 1 to n in cents, where the
+    /// last one is the period of the scale.
+    ///
+    /// # Panics
+    /// Panics if `pitches` is empty.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::tuning::ScalaScale;
+    /// let scale = ScalaScale::new("Fifths".to_owned(), Box::new([702., 1200.]));
+    /// assert_eq!(scale.cents(3), 1902.);
+    /// ```
+    pub fn new(description: String, pitches: Box<[f64]>) -> Self {
+        assert!(!pitches.is_empty());
+        Self { description, pitches }
+    }
+
+    /// Create a 12-tone 5-limit just intonation scale.
+    pub fn just() -> Self {
+        let ratios = [
+            (16, 15), (9, 8), (6, 5), (5, 4), (4, 3), (45, 32),
+            (3, 2), (8, 5), (5, 3), (9, 5), (15, 8), (2, 1)
+        ];
+        let pitches = ratios.iter()
+            .map(|&(n, d)| 1200. * (n as f64 / d as f64).log2());
+        Self::new("5-limit just intonation".to_owned(),
+                  pitches.collect::<Vec<_>>().into_boxed_slice())
+    }
+
+    #[inline]
+    /// Get number of degrees in a period.
+    pub fn len(&self) -> usize { self.pitches.len() }
+
+    #[inline]
+    /// Get pitches of degrees 1 to n in cents.
+    pub fn pitches(&self) -> &[f64] { &self.pitches }
+
+    #[inline]
+    /// Get pitch of `degree` in cents. Degrees out of [0, len) are repeated
+    /// by the period.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::tuning::ScalaScale;
+    /// let scale = ScalaScale::just();
+    /// assert_eq!(scale.cents(0), 0.);
+    /// assert_eq!(scale.cents(12), 1200.);
+    /// assert_eq!(scale.cents(-12), -1200.);
+    /// assert_eq!(scale.cents(19).round(), 1902.);
+    /// ```
+    pub fn cents(&self, degree: isize) -> f64 {
+        let (period, degree) = div_floor(degree, self.len() as isize);
+        let cents = if degree == 0 { 0. } else { self.pitches[degree as usize - 1] };
+        cents + period as f64 * self.pitches[self.len() - 1]
+    }
+}
+
+/// Parse a pitch line of a Scala scale file.
+fn parse_pitch(line: &str) -> Option<f64> {
+    let pitch = line.split_whitespace().next()?;
+
+    if pitch.contains('.') {
+        return pitch.parse().ok();
+    }
+
+    let mut ratio = pitch.splitn(2, '/');
+    let n: f64 = ratio.next()?.parse().ok()?;
+    let d: f64 = match ratio.next() { Some(d) => d.parse().ok()?, None => 1. };
+
+    if n > 0. && d > 0. { Some(1200. * (n / d).log2()) } else { None }
+}
+
+/// Get lines of a Scala file, without comments.
+fn scala_lines<'a>(s: &'a str) -> impl Iterator<Item = &'a str> {
+    s.lines().filter(|line| !line.starts_with('!'))
+}
+
+impl<'a> TryFrom<&'a str> for ScalaScale {
+    type Error = ();
+
+    /// Parse contents of a Scala scale (.scl) file.
+    ///
+    /// # Examples
+    /// ```
+    /// #![feature(try_from)]
+    ///
+    /// use std::convert::TryFrom;
+    /// use overcore::tuning::ScalaScale;
+    ///
+    /// let scale = ScalaScale::try_from("! a comment\n\
+    ///                                   Fifths and octave\n\
+    ///                                   2\n\
+    ///                                   3/2\n\
+    ///                                   1200.0 the octave\n").unwrap();
+    /// assert_eq!(scale.description, "Fifths and octave");
+    /// assert_eq!(scale.len(), 2);
+    /// assert_eq!(scale.cents(1).round(), 702.);
+    /// assert_eq!(ScalaScale::try_from("Empty\n0\n"), Err(()));
+    /// ```
+    fn try_from(s: &'a str) -> Result<Self, Self::Error> {
+        let mut lines = scala_lines(s);
+
+        let description = lines.next().ok_or(())?.trim().to_owned();
+        let len: usize = lines.next().ok_or(())?.trim().parse().map_err(|_| ())?;
+
+        let pitches = lines.take(len).map(parse_pitch)
+            .collect::<Option<Vec<_>>>().ok_or(())?;
+
+        if len == 0 || pitches.len() != len {
+            return Err(());
+        }
+
+        Ok(Self { description, pitches: pitches.into_boxed_slice() })
+    }
+}
+
+impl fmt::Display for ScalaScale {
+    /// Write contents of a Scala scale (.scl) file, with pitches in cents.
+    ///
+    /// # Examples
+    /// ```
+    /// #![feature(try_from)]
+    ///
+    /// use std::convert::TryFrom;
+    /// use overcore::tuning::ScalaScale;
+    ///
+    /// let scale = ScalaScale::just();
+    /// assert_eq!(ScalaScale::try_from(&*scale.to_string()), Ok(scale));
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.description.lines().next().unwrap_or(""))?;
+        writeln!(f, "{}", self.len())?;
+        for pitch in self.pitches.iter() {
+            // debug format always has a point, so it's read as cents
+            writeln!(f, "{:?}", pitch)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A mapping from keys to scale degrees, as described by a Scala keyboard
+/// mapping (.kbm) file. Keys are numbered like MIDI notes (60 is C4).
+pub struct KeyboardMapping {
+    /// The key which degree 0 of the scale is mapped to.
+    pub middle: isize,
+
+    /// The key which it's frequency is given.
+    pub reference: isize,
+
+    /// Frequency of `reference`.
+    pub freq: f64,
+
+    /// The degree which is considered as the formal octave. 0 means the
+    /// period of the scale.
+    pub octave: usize,
+
+    /// Scale degrees for each key in a repetition of the mapping, starting
+    /// from `middle`. `None` means the key is not mapped. If it's empty, keys
+    /// are mapped linearly to consecutive degrees.
+    pub mapping: Box<[Option<usize>]>
+}
+
+impl KeyboardMapping {
+    /// Create a linear mapping, with degree 0 on `middle` and `reference`
+    /// tuned to `freq`.
+    pub fn linear(middle: &NoteName, reference: &NoteName, freq: f64) -> Self {
+        let (middle, reference) = (key(middle), key(reference));
+        Self { middle, reference, freq, octave: 0, mapping: box [] }
+    }
+}
+
+impl<'a> TryFrom<&'a str> for KeyboardMapping {
+    type Error = ();
+
+    /// Parse contents of a Scala keyboard mapping (.kbm) file.
+    ///
+    /// # Examples
+    /// ```
+    /// #![feature(try_from)]
+    ///
+    /// use std::convert::TryFrom;
+    /// use overcore::tuning::KeyboardMapping;
+    ///
+    /// let mapping = KeyboardMapping::try_from("! white keys only\n\
+    ///                                          12\n0\n127\n60\n69\n440.0\n7\n\
+    ///                                          0\nx\n1\nx\n2\n3\nx\n4\nx\n5\nx\n6\n").unwrap();
+    /// assert_eq!(mapping.middle, 60);
+    /// assert_eq!(mapping.octave, 7);
+    /// assert_eq!(mapping.mapping[1], None);
+    /// assert_eq!(mapping.mapping[2], Some(1));
+    /// ```
+    fn try_from(s: &'a str) -> Result<Self, Self::Error> {
+        let mut lines = scala_lines(s).map(|line| {
+            line.split_whitespace().next().unwrap_or("")
+        });
+        let mut next = || lines.next().ok_or(());
+
+        let len: usize = next()?.parse().map_err(|_| ())?;
+        let _first: isize = next()?.parse().map_err(|_| ())?;
+        let _last: isize = next()?.parse().map_err(|_| ())?;
+        let middle = next()?.parse().map_err(|_| ())?;
+        let reference = next()?.parse().map_err(|_| ())?;
+        let freq: f64 = next()?.parse().map_err(|_| ())?;
+        let octave = next()?.parse().map_err(|_| ())?;
+
+        let mut mapping = Vec::with_capacity(len);
+        for _ in 0..len {
+            mapping.push(match next()? {
+                "x" => None,
+                degree => Some(degree.parse().map_err(|_| ())?)
+            });
+        }
+
+        if freq <= 0. {
+            return Err(());
+        }
+
+        let mapping = mapping.into_boxed_slice();
+        Ok(Self { middle, reference, freq, octave, mapping })
+    }
+}
+
+impl fmt::Display for KeyboardMapping {
+    /// Write contents of a Scala keyboard mapping (.kbm) file, mapping the
+    /// whole MIDI range.
+    ///
+    /// # Examples
+    /// ```
+    /// #![feature(try_from)]
+    ///
+    /// use std::convert::TryFrom;
+    /// use overcore::buffer::control::{NoteAlphabet, NoteName};
+    /// use overcore::tuning::KeyboardMapping;
+    ///
+    /// let mut mapping = KeyboardMapping::linear(&NoteName(NoteAlphabet::C, 4),
+    ///                                           &NoteName(NoteAlphabet::A, 4), 440.);
+    /// assert_eq!(KeyboardMapping::try_from(&*mapping.to_string()), Ok(mapping.clone()));
+    ///
+    /// mapping.mapping = Box::new([Some(0), None, Some(1)]);
+    /// assert_eq!(KeyboardMapping::try_from(&*mapping.to_string()), Ok(mapping));
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}\n0\n127", self.mapping.len())?;
+        writeln!(f, "{}\n{}\n{:?}\n{}", self.middle, self.reference, self.freq, self.octave)?;
+        for degree in self.mapping.iter() {
+            match *degree {
+                Some(degree) => writeln!(f, "{}", degree)?,
+                None => writeln!(f, "x")?
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A tuning made of a Scala scale and a keyboard mapping.
+pub struct ScalaTuning {
+    /// The scale.
+    pub scale: ScalaScale,
+
+    /// Mapping from keys to the scale degrees.
+    pub mapping: KeyboardMapping
+}
+
+impl ScalaTuning {
+    /// Create a new tuning from `scale` and it's `mapping`.
+    pub fn new(scale: ScalaScale, mapping: KeyboardMapping) -> Self {
+        Self { scale, mapping }
+    }
+
+    /// Create a 5-limit just intonation with `tonic` tuned to `freq`.
+    ///
+    /// # Examples
+    /// ```
+    /// use overcore::buffer::control::{NoteAlphabet, NoteName};
+    /// use overcore::tuning::{ScalaTuning, Tuning};
+    ///
+    /// let tuning = ScalaTuning::just(&NoteName(NoteAlphabet::C, 4), 264.);
+    /// assert_eq!(tuning.freq(&NoteName(NoteAlphabet::G, 4), 0.).round(), 396.);
+    /// assert_eq!(tuning.freq(&NoteName(NoteAlphabet::E, 3), 0.).round(), 165.);
+    /// ```
+    pub fn just(tonic: &NoteName, freq: f64) -> Self {
+        let mapping = KeyboardMapping::linear(tonic, tonic, freq);
+        Self::new(ScalaScale::just(), mapping)
+    }
+
+    /// Get pitch of `key` in cents relative to degree 0, if it's mapped.
+    fn cents(&self, key: isize) -> Option<f64> {
+        let (scale, mapping) = (&self.scale, &self.mapping);
+        let offset = key - mapping.middle;
+
+        if mapping.mapping.is_empty() {
+            return Some(scale.cents(offset));
+        }
+
+        let (repetition, index) = div_floor(offset, mapping.mapping.len() as isize);
+        let degree = mapping.mapping[index as usize]?;
+        let octave = match mapping.octave { 0 => scale.len(), o => o };
+
+        let octave = scale.cents(octave as isize);
+        Some(scale.cents(degree as isize) + repetition as f64 * octave)
+    }
+}
+
+impl Tuning for ScalaTuning {
+    fn freq(&self, name: &NoteName, cents: f64) -> f64 {
+        match (self.cents(key(name)), self.cents(self.mapping.reference)) {
+            (Some(pitch), Some(reference)) => {
+                let cents = pitch - reference + cents;
+                self.mapping.freq * 2f64.powf(cents / 1200.)
+            },
+            _ => ::std::f64::NAN
+        }
+    }
+
+    fn kind(&self) -> Option<TuningKind> { Some(TuningKind::Scala(self.clone())) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(alphabet: NoteAlphabet, octave: i8) -> NoteName {
+        NoteName(alphabet, octave)
+    }
+
+    #[test]
+    fn test_equal_temperament_12() {
+        use hardconf::TWO_POW_ONE_TWELFTH;
+        let tuning = EqualTemperament::default();
+
+        for i in -30..30 {
+            let name = name(NoteAlphabet::A, 4).transpose(i);
+            let expected = 440. * TWO_POW_ONE_TWELFTH.powf(i as f64);
+            assert!((tuning.freq(&name, 0.) - expected).abs() < 1e-9);
+            assert!((tuning.freq(&name, -100.) - expected / TWO_POW_ONE_TWELFTH).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_scala_tuning_linear() {
+        let scale = ScalaScale::try_from("5-EDO\n5\n240.\n480.\n720.\n960.\n2/1\n");
+        let middle = name(NoteAlphabet::C, 4);
+        let mapping = KeyboardMapping::linear(&middle, &middle, 100.);
+        let tuning = ScalaTuning::new(scale.unwrap(), mapping);
+
+        assert_eq!(tuning.freq(&middle, 0.), 100.);
+        assert_eq!(tuning.freq(&middle.transpose(5), 0.), 200.);
+        assert_eq!(tuning.freq(&middle.transpose(-5), 0.), 50.);
+        assert_eq!(tuning.freq(&middle.transpose(-5), 1200.), 100.);
+    }
+
+    #[test]
+    fn test_scala_tuning_mapping() {
+        let scale = ScalaScale::try_from("major\n7\n\
+            9/8\n5/4\n4/3\n3/2\n5/3\n15/8\n2/1\n").unwrap();
+        let mapping = KeyboardMapping::try_from("12\n0\n127\n60\n60\n300.\n7\n\
+            0\nx\n1\nx\n2\n3\nx\n4\nx\n5\nx\n6\n").unwrap();
+        let tuning = ScalaTuning::new(scale, mapping);
+
+        let freq = |alphabet, octave| {
+            (tuning.freq(&name(alphabet, octave), 0.) * 1e6).round() / 1e6
+        };
+        assert_eq!(freq(NoteAlphabet::C, 4), 300.);
+        assert_eq!(freq(NoteAlphabet::E, 4), 375.);
+        assert_eq!(freq(NoteAlphabet::G, 4), 450.);
+        assert_eq!(freq(NoteAlphabet::C, 5), 600.);
+        assert_eq!(freq(NoteAlphabet::A, 3), 250.);
+        assert!(freq(NoteAlphabet::Cs, 4).is_nan());
+    }
+}